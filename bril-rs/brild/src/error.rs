@@ -8,6 +8,8 @@ pub enum BrildError {
     MissingOrUnknownFileExtension(std::path::PathBuf),
     #[error("Function `{0}` declared more than once")]
     DuplicateFunction(String),
+    #[error("Could not load `{0}`: {1}")]
+    LoadError(std::path::PathBuf, bril_rs::conversion::LoadError),
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
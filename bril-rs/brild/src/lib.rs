@@ -12,6 +12,7 @@ pub mod error;
 use std::collections::HashMap;
 use std::fs::{canonicalize, File};
 use std::hash::BuildHasher;
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
 
 use bril2json::parse_abstract_program_from_read;
@@ -171,6 +172,7 @@ pub fn handle_program<S: BuildHasher>(
     // Do mangling
     let mangled_program = AbstractProgram {
         imports: Vec::new(),
+        version: program.version,
         functions: program
             .functions
             .into_iter()
@@ -203,17 +205,20 @@ pub fn do_import<S: BuildHasher>(
     path_map.insert(canonical_path.clone(), None);
 
     // Find the correct parser for this path based on the extension
-    let f: Box<dyn Fn(_) -> AbstractProgram> =
+    let f: Box<dyn Fn(_) -> Result<AbstractProgram, BrildError>> =
         match canonical_path.extension().and_then(std::ffi::OsStr::to_str) {
             Some("bril") => Box::new(|s| {
-                parse_abstract_program_from_read(
+                Ok(parse_abstract_program_from_read(
                     s,
                     true,
                     true,
                     Some(canonical_path.display().to_string()),
-                )
+                ))
+            }),
+            Some("json") => Box::new(|s| {
+                load_abstract_program_from_read(BufReader::new(s))
+                    .map_err(|e| BrildError::LoadError(canonical_path.clone(), e))
             }),
-            Some("json") => Box::new(load_abstract_program_from_read),
             Some(_) | None => {
                 return Err(BrildError::MissingOrUnknownFileExtension(
                     canonical_path.clone(),
@@ -222,7 +227,7 @@ pub fn do_import<S: BuildHasher>(
         };
 
     // Get the AbstractProgram representation of the file
-    let program = f(File::open(canonical_path)?);
+    let program = f(File::open(canonical_path)?)?;
 
     handle_program(path_map, program, canonical_path, libs, is_toplevel)?;
     Ok(())
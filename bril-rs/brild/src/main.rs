@@ -24,6 +24,7 @@ fn main() -> Result<(), BrildError> {
         AbstractProgram {
             imports: Vec::new(),
             functions: Vec::new(),
+            version: None,
         },
         |mut acc, (_, p)| {
             acc.functions.append(&mut p.unwrap().functions);
@@ -19,7 +19,7 @@ fn main() {
     } else {
         std::io::stdin().read_to_string(&mut src).unwrap()
     };
-    let prog = load_program_from_read(src.as_bytes());
+    let prog = load_program_from_read(src.as_bytes()).unwrap();
 
     let context = Context::create();
     let runtime_path = args.runtime.as_ref().map_or("rt.bc", |f| f);
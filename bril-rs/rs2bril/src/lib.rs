@@ -1175,5 +1175,6 @@ pub fn from_file_to_program(
             .collect(),
         #[cfg(feature = "import")]
         imports: vec![],
+        version: None,
     }
 }
@@ -9,13 +9,21 @@ use crate::program::Position;
 #[cfg(feature = "import")]
 use crate::program::Import;
 
+#[cfg(feature = "format")]
+use crate::program::FormatHints;
+
+#[cfg(feature = "metadata")]
+use serde_json::Map;
+
 use serde::{Deserialize, Serialize};
 
-use serde::de::{self, Error, MapAccess, Visitor};
+use serde::de::{self, DeserializeSeed, Error, MapAccess, SeqAccess, Visitor};
 
 use serde::ser::{SerializeMap, Serializer};
 
 /// Equivalent to a file of bril code
+///
+/// Keys of the top-level object other than the fields below, such as experimental additions from other producers, are ignored when deserializing and so are not written back out.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AbstractProgram {
     /// A list of functions declared in the program
@@ -24,10 +32,37 @@ pub struct AbstractProgram {
     #[cfg(feature = "import")]
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub imports: Vec<Import>,
+    /// The version of the Bril specification this program was written against, if it declares one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+impl AbstractProgram {
+    /// Removes the source positions of every function, label, and instruction in this program
+    #[cfg(feature = "position")]
+    pub fn strip_positions(&mut self) {
+        for func in &mut self.functions {
+            func.pos = None;
+            for code in &mut func.instrs {
+                match code {
+                    AbstractCode::Label { pos, .. }
+                    | AbstractCode::Instruction(
+                        AbstractInstruction::Constant { pos, .. }
+                        | AbstractInstruction::Value { pos, .. }
+                        | AbstractInstruction::Effect { pos, .. },
+                    ) => *pos = None,
+                }
+            }
+        }
+    }
 }
 
 impl Display for AbstractProgram {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        #[cfg(feature = "import")]
+        for i in &self.imports {
+            writeln!(f, "{i}")?;
+        }
         for func in &self.functions {
             writeln!(f, "{func}")?;
         }
@@ -54,10 +89,24 @@ pub struct AbstractFunction {
     #[serde(rename = "type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub return_type: Option<AbstractType>,
+
+    /// Whether this function is only a declaration whose body is provided outside of Bril, in which case it has no instructions
+    #[cfg(feature = "external")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub external: bool,
+
+    /// Names for types which can be used in place of them anywhere in this function, and are replaced by the types they stand for when converting to a [`crate::Function`]
+    #[cfg(feature = "alias")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<AbstractTypeAlias>,
 }
 
 impl Display for AbstractFunction {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        #[cfg(feature = "external")]
+        if self.external {
+            write!(f, "extern ")?;
+        }
         write!(f, "@{}", self.name)?;
         if !self.args.is_empty() {
             write!(f, "(")?;
@@ -72,7 +121,15 @@ impl Display for AbstractFunction {
         if let Some(tpe) = self.return_type.as_ref() {
             write!(f, ": {tpe}")?;
         }
+        #[cfg(feature = "external")]
+        if self.external {
+            return write!(f, ";");
+        }
         writeln!(f, " {{")?;
+        #[cfg(feature = "alias")]
+        for alias in &self.aliases {
+            writeln!(f, "  {alias}")?;
+        }
         for instr in &self.instrs {
             writeln!(f, "{instr}")?;
         }
@@ -81,6 +138,25 @@ impl Display for AbstractFunction {
     }
 }
 
+/// A local name for a type within an [`AbstractFunction`]
+/// Example: type intptr = ptr<int>;
+#[cfg(feature = "alias")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AbstractTypeAlias {
+    /// intptr
+    pub name: String,
+    /// ptr<int>
+    #[serde(rename = "type")]
+    pub alias_type: AbstractType,
+}
+
+#[cfg(feature = "alias")]
+impl Display for AbstractTypeAlias {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "type {} = {};", self.name, self.alias_type)
+    }
+}
+
 /// An argument of a function
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#function>
 /// Example: a : int
@@ -101,8 +177,7 @@ impl Display for AbstractArgument {
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#function>
 /// Code is a Label or an Instruction
-#[cfg_attr(not(feature = "float"), derive(Eq))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum AbstractCode {
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#label>
@@ -113,6 +188,10 @@ pub enum AbstractCode {
         #[cfg(feature = "position")]
         #[serde(flatten, skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
     },
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#instruction>
     Instruction(AbstractInstruction),
@@ -120,20 +199,58 @@ pub enum AbstractCode {
 
 impl Display for AbstractCode {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        #[cfg(feature = "format")]
+        for _ in 0..self.format_hints().blank_lines_before {
+            writeln!(f)?;
+        }
         match self {
             Self::Label {
                 label,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
             } => write!(f, ".{label}:"),
             Self::Instruction(instr) => write!(f, "  {instr}"),
+        }?;
+        #[cfg(feature = "format")]
+        if let Some(comment) = &self.format_hints().comment {
+            write!(f, "  # {comment}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "format")]
+impl AbstractCode {
+    /// The [`FormatHints`] recorded for this code
+    #[must_use]
+    pub const fn format_hints(&self) -> &FormatHints {
+        match self {
+            Self::Label { format, .. }
+            | Self::Instruction(
+                AbstractInstruction::Constant { format, .. }
+                | AbstractInstruction::Value { format, .. }
+                | AbstractInstruction::Effect { format, .. },
+            ) => format,
+        }
+    }
+
+    /// Mutable access to the [`FormatHints`] recorded for this code
+    pub const fn format_hints_mut(&mut self) -> &mut FormatHints {
+        match self {
+            Self::Label { format, .. }
+            | Self::Instruction(
+                AbstractInstruction::Constant { format, .. }
+                | AbstractInstruction::Value { format, .. }
+                | AbstractInstruction::Effect { format, .. },
+            ) => format,
         }
     }
 }
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#instruction>
-#[cfg_attr(not(feature = "float"), derive(Eq))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum AbstractInstruction {
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#constant>
@@ -151,6 +268,14 @@ pub enum AbstractInstruction {
         const_type: Option<AbstractType>,
         /// The literal being stored in the variable
         value: Literal,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
+        /// Keys of the instruction which Bril does not define, kept so they are written back out unchanged
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: Map<String, serde_json::Value>,
     },
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#value-operation>
     Value {
@@ -174,6 +299,14 @@ pub enum AbstractInstruction {
         /// Type of variable
         #[serde(rename = "type")]
         op_type: Option<AbstractType>,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
+        /// Keys of the instruction which Bril does not define, kept so they are written back out unchanged
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: Map<String, serde_json::Value>,
     },
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#effect-operation>
     Effect {
@@ -192,6 +325,14 @@ pub enum AbstractInstruction {
         #[cfg(feature = "position")]
         #[serde(flatten, skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
+        /// Keys of the instruction which Bril does not define, kept so they are written back out unchanged
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: Map<String, serde_json::Value>,
     },
 }
 
@@ -205,6 +346,10 @@ impl Display for AbstractInstruction {
                 value,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
+                #[cfg(feature = "metadata")]
+                    extra: _,
             } => match const_type {
                 Some(const_type) => write!(f, "{dest}: {const_type} = {op} {value};"),
                 None => write!(f, "{dest} = {op} {value};"),
@@ -218,6 +363,10 @@ impl Display for AbstractInstruction {
                 labels,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
+                #[cfg(feature = "metadata")]
+                    extra: _,
             } => {
                 match op_type {
                     Some(op_type) => write!(f, "{dest}: {op_type} = {op}")?,
@@ -241,6 +390,10 @@ impl Display for AbstractInstruction {
                 labels,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
+                #[cfg(feature = "metadata")]
+                    extra: _,
             } => {
                 write!(f, "{op}")?;
                 for func in funcs {
@@ -265,6 +418,11 @@ pub enum AbstractType {
     Primitive(String),
     /// For example `ptr<bool>` => `Parameterized("ptr", Box::new(Primitive("bool")))`
     Parameterized(String, Box<Self>),
+    /// For example `array<int, 8>` => `Sized("array", Box::new(Primitive("int")), "8")`. The size is kept as written and only checked when converting to a [`crate::Type`]
+    ///
+    /// In JSON the parameter and size are written as a pair, like `{"array": ["int", 8]}`.
+    #[cfg(feature = "array")]
+    Sized(String, Box<Self>, String),
 }
 
 struct AbstractTypeVisitor {
@@ -306,16 +464,39 @@ impl<'de> Visitor<'de> for AbstractTypeVisitor {
     {
         // While there are entries remaining in the input, add them
         // into our map.
+        #[cfg(feature = "array")]
+        if let Some((key, value)) = access.next_entry::<String, Parameter>()? {
+            return Ok(match value {
+                Parameter::Type(value) => AbstractType::Parameterized(key, Box::new(value)),
+                Parameter::Sized(value, size) => AbstractType::Sized(
+                    key,
+                    Box::new(value),
+                    match size {
+                        serde_json::Value::String(size) => size,
+                        size => size.to_string(),
+                    },
+                ),
+            });
+        }
+        #[cfg(not(feature = "array"))]
         if let Some((key, value)) = access.next_entry()? {
-            Ok(AbstractType::Parameterized(key, value))
-        } else {
-            Err(M::Error::custom(
-                "Expected one value in map for AbstractType",
-            ))
+            return Ok(AbstractType::Parameterized(key, value));
         }
+        Err(M::Error::custom(
+            "Expected one value in map for AbstractType",
+        ))
     }
 }
 
+/// The value of a parameterized type, which is a pair when the type also has a size
+#[cfg(feature = "array")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Parameter {
+    Type(AbstractType),
+    Sized(AbstractType, serde_json::Value),
+}
+
 impl<'de> Deserialize<'de> for AbstractType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -337,6 +518,16 @@ impl Serialize for AbstractType {
                 map.serialize_entry(t, at)?;
                 map.end()
             }
+            #[cfg(feature = "array")]
+            Self::Sized(t, at, size) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                // A size which is a number is written as one, and anything else is kept as a string for conversion to reject
+                match size.parse::<u64>() {
+                    Ok(n) => map.serialize_entry(t, &(at, n))?,
+                    Err(_) => map.serialize_entry(t, &(at, size))?,
+                }
+                map.end()
+            }
         }
     }
 }
@@ -346,6 +537,121 @@ impl Display for AbstractType {
         match self {
             Self::Primitive(t) => write!(f, "{t}"),
             Self::Parameterized(t, at) => write!(f, "{t}<{at}>"),
+            #[cfg(feature = "array")]
+            Self::Sized(t, at, size) => write!(f, "{t}<{at}, {size}>"),
+        }
+    }
+}
+
+/// The kinds of JSON object in a Bril program, for rejecting keys which this build would ignore
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum KnownKeys {
+    Program,
+    Function,
+    Argument,
+    Code,
+    Import,
+    ImportedFunction,
+    Alias,
+    /// Types and constant values, whose contents are checked by conversion instead
+    Any,
+}
+
+impl KnownKeys {
+    fn allows(self, key: &str) -> bool {
+        let position = cfg!(feature = "position") && matches!(key, "pos" | "pos_end" | "src");
+        match self {
+            Self::Program => {
+                matches!(key, "functions" | "version")
+                    || (cfg!(feature = "import") && key == "imports")
+            }
+            Self::Function => {
+                matches!(key, "name" | "args" | "instrs" | "type")
+                    || position
+                    || (cfg!(feature = "external") && key == "external")
+                    || (cfg!(feature = "alias") && key == "aliases")
+            }
+            Self::Argument | Self::Alias => matches!(key, "name" | "type"),
+            // Instructions keep keys they do not know in `extra`, so nothing they have is ignored
+            Self::Code if cfg!(feature = "metadata") => true,
+            Self::Code => {
+                matches!(
+                    key,
+                    "label" | "op" | "dest" | "type" | "args" | "funcs" | "labels" | "value"
+                ) || position
+            }
+            Self::Import => matches!(key, "path" | "functions"),
+            Self::ImportedFunction => matches!(key, "name" | "alias"),
+            Self::Any => true,
+        }
+    }
+
+    /// The kind of the value of ```key``` in an object of this kind
+    const fn child(self, key: &str) -> Self {
+        match (self, key.as_bytes()) {
+            (Self::Program, b"functions") => Self::Function,
+            (Self::Program, b"imports") => Self::Import,
+            (Self::Function, b"args") => Self::Argument,
+            (Self::Function, b"instrs") => Self::Code,
+            (Self::Function, b"aliases") => Self::Alias,
+            (Self::Import, b"functions") => Self::ImportedFunction,
+            _ => Self::Any,
         }
     }
 }
+
+impl<'de> DeserializeSeed<'de> for KnownKeys {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KnownKeys {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("a Bril program")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    /// The elements of an array have the kind of the array itself, like each function of `functions`
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq.next_element_seed(self)?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if !self.allows(&key) {
+                return Err(A::Error::custom(format!("unknown field `{key}`")));
+            }
+            map.next_value_seed(self.child(&key))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+//! Reads a Bril program as JSON from stdin and writes it to stdout as text
+//!
+//! Like `text2json`, the program is written as it was read, without being checked or converted.
+use std::io;
+use std::process::ExitCode;
+
+use bril_rs::load_abstract_program_from_read;
+
+fn main() -> ExitCode {
+    if let Some(arg) = std::env::args().nth(1) {
+        eprintln!("error: unexpected argument `{arg}`");
+        return ExitCode::FAILURE;
+    }
+    match load_abstract_program_from_read(io::stdin().lock()) {
+        Ok(program) => {
+            print!("{program}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
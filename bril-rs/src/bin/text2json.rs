@@ -0,0 +1,52 @@
+//! Reads a Bril program as text from stdin and writes it to stdout as JSON, with its keys in the order of [`bril_rs::to_canonical_json`]
+//!
+//! The program is written as it was parsed, without being checked or converted, so code which relies on type inference or fails checks like those in `test/check` is still translated.
+//! With the `position` feature, `-p` or `--position` keeps the source position of every function, label, and instruction.
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use bril_rs::abstract_program_to_canonical_json;
+use bril_rs::text::parse_program;
+
+fn main() -> ExitCode {
+    #[cfg_attr(not(feature = "position"), allow(unused_variables))]
+    let position = match position_flag() {
+        Ok(position) => position,
+        Err(arg) => {
+            eprintln!("error: unexpected argument `{arg}`");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut input = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut input) {
+        eprintln!("error: {e}");
+        return ExitCode::FAILURE;
+    }
+    #[cfg_attr(not(feature = "position"), allow(unused_mut))]
+    let mut program = match parse_program(&input) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    #[cfg(feature = "position")]
+    if !position {
+        program.strip_positions();
+    }
+    print!("{}", abstract_program_to_canonical_json(&program));
+    ExitCode::SUCCESS
+}
+
+/// Whether `-p` or `--position` was passed, or else the first argument which is not understood
+fn position_flag() -> Result<bool, String> {
+    let mut position = false;
+    for arg in std::env::args().skip(1) {
+        if cfg!(feature = "position") && (arg == "-p" || arg == "--position") {
+            position = true;
+        } else {
+            return Err(arg);
+        }
+    }
+    Ok(position)
+}
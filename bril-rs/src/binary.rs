@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+#[cfg(feature = "format")]
+use crate::FormatHints;
+use crate::{Argument, Code, ConstOps, Function, Instruction, Literal, Program, Type};
+#[cfg(feature = "position")]
+use crate::{ColRow, Position};
+#[cfg(feature = "import")]
+use crate::{Import, ImportedFunction};
+
+/// The bytes every encoding starts with, followed by [`FORMAT_VERSION`]
+const MAGIC: &[u8; 4] = b"BRIL";
+
+/// Bumped whenever the layout changes, so that stale caches are rejected rather than misread
+const FORMAT_VERSION: u8 = 2;
+
+/// The ways that reading a [Program] with [`from_binary`] can fail
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum BinaryError {
+    /// The input does not start with the bytes that [`to_binary`] writes
+    #[error("the input is not a binary Bril program")]
+    BadMagic,
+    /// The input was written by a different version of [`to_binary`]
+    #[error("the input uses version {0} of the binary format, but only version {FORMAT_VERSION} is supported")]
+    UnsupportedVersion(u8),
+    /// The input ended in the middle of the program
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    /// There is more input after the program
+    #[error("{0} unexpected bytes after the program")]
+    TrailingBytes(usize),
+    /// A number is too large for what it counts
+    #[error("a number is out of range")]
+    OutOfRange,
+    /// A string is not valid UTF-8
+    #[error("a string is not valid UTF-8")]
+    InvalidUtf8,
+    /// A string refers past the end of the table of strings
+    #[error("string {0} is not in the table of strings")]
+    UnknownString(u64),
+    /// A tag byte does not stand for any kind of {0}
+    #[error("invalid tag {1} for a {0}")]
+    InvalidTag(&'static str, u8),
+    /// The name of an operation is not one this build knows, which can happen when the feature that adds it is disabled
+    #[error("unknown operation {0}")]
+    UnknownOp(String),
+    /// The program uses a type or literal from the feature {0}, which is disabled
+    #[error("the program needs the `{0}` feature")]
+    MissingFeature(&'static str),
+    /// The extra keys of an instruction are not a JSON object
+    #[error("the metadata of an instruction is not a JSON object")]
+    InvalidMetadata,
+}
+
+/// Encodes ```program``` in a compact binary form which [`from_binary`] reads back
+///
+/// Every string is stored once in a table and referred to by its index, and numbers are stored as variable-length integers, so the result is typically several times smaller than the JSON and much faster to read.
+/// The encoding is lossless: source positions, [`crate::FormatHints`], the extra keys of instructions, and the bits of every float are kept when their features are enabled.
+#[must_use]
+pub fn to_binary(program: &Program) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.program(program);
+
+    let mut out = Vec::with_capacity(w.body.len() + 64);
+    out.extend_from_slice(MAGIC);
+    out.push(FORMAT_VERSION);
+    write_uint(&mut out, w.strings.len() as u64);
+    for s in &w.strings {
+        write_uint(&mut out, s.len() as u64);
+        out.extend_from_slice(s.as_bytes());
+    }
+    out.extend_from_slice(&w.body);
+    out
+}
+
+/// Decodes a program written by [`to_binary`]
+///
+/// Like reading JSON, source positions, imports, and whether a function is external are dropped when their features are disabled, and so are layout hints.
+/// # Errors
+/// Returns a [`BinaryError`] if ```bytes``` is not a program written by this version of [`to_binary`], or uses a type or operation from a disabled feature
+pub fn from_binary(bytes: &[u8]) -> Result<Program, BinaryError> {
+    let rest = bytes.strip_prefix(MAGIC).ok_or(BinaryError::BadMagic)?;
+    let mut r = Reader {
+        bytes: rest,
+        strings: Vec::new(),
+    };
+    let version = r.byte()?;
+    if version != FORMAT_VERSION {
+        return Err(BinaryError::UnsupportedVersion(version));
+    }
+    let count = r.len()?;
+    for _ in 0..count {
+        let len = r.len()?;
+        let s = std::str::from_utf8(r.take(len)?).map_err(|_| BinaryError::InvalidUtf8)?;
+        r.strings.push(s);
+    }
+    let program = r.program()?;
+    if r.bytes.is_empty() {
+        Ok(program)
+    } else {
+        Err(BinaryError::TrailingBytes(r.bytes.len()))
+    }
+}
+
+/// Writes ```n``` seven bits at a time, lowest first, with the top bit of each byte set when more follow
+#[allow(clippy::cast_possible_truncation)]
+fn write_uint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+#[derive(Default)]
+struct Writer {
+    strings: Vec<String>,
+    indices: HashMap<String, u64>,
+    body: Vec<u8>,
+}
+
+impl Writer {
+    fn uint(&mut self, n: u64) {
+        write_uint(&mut self.body, n);
+    }
+
+    fn len(&mut self, n: usize) {
+        self.uint(n as u64);
+    }
+
+    fn str(&mut self, s: &str) {
+        let index = if let Some(index) = self.indices.get(s) {
+            *index
+        } else {
+            let index = self.strings.len() as u64;
+            self.indices.insert(s.to_string(), index);
+            self.strings.push(s.to_string());
+            index
+        };
+        self.uint(index);
+    }
+
+    #[cfg(any(
+        feature = "import",
+        feature = "position",
+        feature = "format",
+        feature = "metadata"
+    ))]
+    fn opt_str(&mut self, s: Option<&str>) {
+        match s {
+            None => self.body.push(0),
+            Some(s) => {
+                self.body.push(1);
+                self.str(s);
+            }
+        }
+    }
+
+    fn strs(&mut self, strs: &[String]) {
+        self.len(strs.len());
+        for s in strs {
+            self.str(s);
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        self.uint(program.version.map_or(0, |v| u64::from(v) + 1));
+        #[cfg(feature = "import")]
+        {
+            self.len(program.imports.len());
+            for import in &program.imports {
+                self.str(&import.path.to_string_lossy());
+                self.len(import.functions.len());
+                for f in &import.functions {
+                    self.str(&f.name);
+                    self.opt_str(f.alias.as_deref());
+                }
+            }
+        }
+        #[cfg(not(feature = "import"))]
+        self.len(0);
+        self.len(program.functions.len());
+        for f in &program.functions {
+            self.function(f);
+        }
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.str(&func.name);
+        self.len(func.args.len());
+        for a in &func.args {
+            self.str(&a.name);
+            self.ty(&a.arg_type);
+        }
+        match &func.return_type {
+            None => self.body.push(0),
+            Some(t) => {
+                self.body.push(1);
+                self.ty(t);
+            }
+        }
+        #[cfg(feature = "external")]
+        self.body.push(u8::from(func.external));
+        #[cfg(not(feature = "external"))]
+        self.body.push(0);
+        #[cfg(feature = "position")]
+        self.pos(func.pos.as_ref());
+        #[cfg(not(feature = "position"))]
+        self.body.push(0);
+        self.len(func.instrs.len());
+        for code in &func.instrs {
+            self.code(code);
+        }
+    }
+
+    fn ty(&mut self, t: &Type) {
+        match t {
+            Type::Int => self.body.push(0),
+            Type::Bool => self.body.push(1),
+            #[cfg(feature = "float")]
+            Type::Float => self.body.push(2),
+            #[cfg(feature = "char")]
+            Type::Char => self.body.push(3),
+            #[cfg(feature = "memory")]
+            Type::Pointer(inner) => {
+                self.body.push(4);
+                self.ty(inner);
+            }
+            #[cfg(feature = "array")]
+            Type::Array(element, size) => {
+                self.body.push(5);
+                self.ty(element);
+                match size {
+                    None => self.body.push(0),
+                    Some(size) => {
+                        self.body.push(1);
+                        self.len(*size);
+                    }
+                }
+            }
+        }
+    }
+
+    fn literal(&mut self, lit: &Literal) {
+        match lit {
+            Literal::Int(i) => {
+                self.body.push(0);
+                // Zigzag encoding keeps small negative numbers short
+                self.uint(((i << 1) ^ (i >> 63)).cast_unsigned());
+            }
+            Literal::Bool(b) => self.body.push(if *b { 2 } else { 1 }),
+            #[cfg(feature = "float")]
+            Literal::Float(x) => {
+                self.body.push(3);
+                self.body.extend_from_slice(&x.to_bits().to_le_bytes());
+            }
+            #[cfg(feature = "char")]
+            Literal::Char(c) => {
+                self.body.push(4);
+                self.uint(u64::from(u32::from(*c)));
+            }
+        }
+    }
+
+    #[cfg(feature = "position")]
+    fn pos(&mut self, pos: Option<&Position>) {
+        let Some(pos) = pos else {
+            self.body.push(0);
+            return;
+        };
+        self.body.push(1);
+        self.uint(pos.pos.row);
+        self.uint(pos.pos.col);
+        match &pos.pos_end {
+            None => self.body.push(0),
+            Some(end) => {
+                self.body.push(1);
+                self.uint(end.row);
+                self.uint(end.col);
+            }
+        }
+        self.opt_str(pos.src.as_deref());
+    }
+
+    /// Writes the position, layout hints, and extra keys which every kind of code ends with
+    #[cfg_attr(not(feature = "position"), allow(unused_variables))]
+    fn trailer(&mut self, code: &Code) {
+        #[cfg(feature = "position")]
+        self.pos(
+            match code {
+                Code::Label { pos, .. } => pos.clone(),
+                Code::Instruction(instr) => instr.get_pos(),
+            }
+            .as_ref(),
+        );
+        #[cfg(not(feature = "position"))]
+        self.body.push(0);
+        #[cfg(feature = "format")]
+        {
+            let hints = code.format_hints();
+            self.len(hints.blank_lines_before);
+            self.opt_str(hints.comment.as_deref());
+        }
+        #[cfg(not(feature = "format"))]
+        self.body.extend_from_slice(&[0, 0]);
+        #[cfg(feature = "metadata")]
+        match code {
+            Code::Instruction(
+                Instruction::Constant { extra, .. }
+                | Instruction::Value { extra, .. }
+                | Instruction::Effect { extra, .. },
+            ) if !extra.is_empty() => {
+                self.opt_str(Some(&serde_json::Value::Object(extra.clone()).to_string()));
+            }
+            _ => self.body.push(0),
+        }
+        #[cfg(not(feature = "metadata"))]
+        self.body.push(0);
+    }
+
+    fn code(&mut self, code: &Code) {
+        match code {
+            Code::Label { label, .. } => {
+                self.body.push(0);
+                self.str(label);
+            }
+            Code::Instruction(Instruction::Constant {
+                dest,
+                const_type,
+                value,
+                ..
+            }) => {
+                self.body.push(1);
+                self.str(dest);
+                self.ty(const_type);
+                self.literal(value);
+            }
+            Code::Instruction(Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op,
+                op_type,
+                ..
+            }) => {
+                self.body.push(2);
+                // Operations are written by name, so that adding one does not renumber the others
+                self.str(&op.to_string());
+                self.str(dest);
+                self.ty(op_type);
+                self.strs(args);
+                self.strs(funcs);
+                self.strs(labels);
+            }
+            Code::Instruction(Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                ..
+            }) => {
+                self.body.push(3);
+                self.str(&op.to_string());
+                self.strs(args);
+                self.strs(funcs);
+                self.strs(labels);
+            }
+        }
+        self.trailer(code);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    strings: Vec<&'a str>,
+}
+
+impl<'a> Reader<'a> {
+    const fn take(&mut self, n: usize) -> Result<&'a [u8], BinaryError> {
+        if n > self.bytes.len() {
+            return Err(BinaryError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BinaryError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, BinaryError> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= u64::from(b & 0x7f)
+                .checked_shl(shift)
+                .ok_or(BinaryError::OutOfRange)?;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(BinaryError::OutOfRange)
+    }
+
+    fn len(&mut self) -> Result<usize, BinaryError> {
+        usize::try_from(self.uint()?).map_err(|_| BinaryError::OutOfRange)
+    }
+
+    fn flag(&mut self, what: &'static str) -> Result<bool, BinaryError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(BinaryError::InvalidTag(what, b)),
+        }
+    }
+
+    fn str(&mut self) -> Result<&'a str, BinaryError> {
+        let index = self.uint()?;
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.strings.get(i).copied())
+            .ok_or(BinaryError::UnknownString(index))
+    }
+
+    fn string(&mut self) -> Result<String, BinaryError> {
+        self.str().map(ToString::to_string)
+    }
+
+    fn opt_string(&mut self) -> Result<Option<String>, BinaryError> {
+        if self.flag("optional string")? {
+            self.string().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, BinaryError> {
+        (0..self.len()?).map(|_| self.string()).collect()
+    }
+
+    fn program(&mut self) -> Result<Program, BinaryError> {
+        let version = match self.uint()? {
+            0 => None,
+            v => Some(u32::try_from(v - 1).map_err(|_| BinaryError::OutOfRange)?),
+        };
+        let imports = (0..self.len()?)
+            .map(|_| self.import())
+            .collect::<Result<Vec<_>, _>>()?;
+        #[cfg(not(feature = "import"))]
+        drop(imports);
+        let functions = (0..self.len()?)
+            .map(|_| self.function())
+            .collect::<Result<_, _>>()?;
+        Ok(Program {
+            functions,
+            #[cfg(feature = "import")]
+            imports,
+            version,
+        })
+    }
+
+    #[cfg(feature = "import")]
+    fn import(&mut self) -> Result<Import, BinaryError> {
+        let path = self.string()?.into();
+        let functions = (0..self.len()?)
+            .map(|_| {
+                Ok(ImportedFunction {
+                    name: self.string()?,
+                    alias: self.opt_string()?,
+                })
+            })
+            .collect::<Result<_, BinaryError>>()?;
+        Ok(Import { functions, path })
+    }
+
+    /// Skips over an import, which is always present in the encoding even when it is dropped here
+    #[cfg(not(feature = "import"))]
+    fn import(&mut self) -> Result<(), BinaryError> {
+        self.str()?;
+        for _ in 0..self.len()? {
+            self.str()?;
+            self.opt_string()?;
+        }
+        Ok(())
+    }
+
+    fn function(&mut self) -> Result<Function, BinaryError> {
+        let name = self.string()?;
+        let args = (0..self.len()?)
+            .map(|_| {
+                Ok(Argument {
+                    name: self.string()?,
+                    arg_type: self.ty()?,
+                })
+            })
+            .collect::<Result<_, BinaryError>>()?;
+        let return_type = if self.flag("return type")? {
+            Some(self.ty()?)
+        } else {
+            None
+        };
+        #[cfg_attr(not(feature = "external"), allow(unused_variables))]
+        let external = self.flag("external flag")?;
+        #[cfg_attr(
+            not(feature = "position"),
+            allow(unused_variables, clippy::let_unit_value)
+        )]
+        let pos = self.pos()?;
+        let instrs = (0..self.len()?)
+            .map(|_| self.code())
+            .collect::<Result<_, _>>()?;
+        Ok(Function {
+            args,
+            instrs,
+            name,
+            #[cfg(feature = "position")]
+            pos,
+            return_type,
+            #[cfg(feature = "external")]
+            external,
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type, BinaryError> {
+        Ok(match self.byte()? {
+            0 => Type::Int,
+            1 => Type::Bool,
+            #[cfg(feature = "float")]
+            2 => Type::Float,
+            #[cfg(not(feature = "float"))]
+            2 => return Err(BinaryError::MissingFeature("float")),
+            #[cfg(feature = "char")]
+            3 => Type::Char,
+            #[cfg(not(feature = "char"))]
+            3 => return Err(BinaryError::MissingFeature("char")),
+            #[cfg(feature = "memory")]
+            4 => Type::Pointer(Box::new(self.ty()?)),
+            #[cfg(not(feature = "memory"))]
+            4 => return Err(BinaryError::MissingFeature("memory")),
+            #[cfg(feature = "array")]
+            5 => {
+                let element = Box::new(self.ty()?);
+                let size = if self.flag("array size")? {
+                    Some(self.len()?)
+                } else {
+                    None
+                };
+                Type::Array(element, size)
+            }
+            #[cfg(not(feature = "array"))]
+            5 => return Err(BinaryError::MissingFeature("array")),
+            b => return Err(BinaryError::InvalidTag("type", b)),
+        })
+    }
+
+    fn literal(&mut self) -> Result<Literal, BinaryError> {
+        Ok(match self.byte()? {
+            0 => {
+                let n = self.uint()?;
+                Literal::Int((n >> 1).cast_signed() ^ -(n & 1).cast_signed())
+            }
+            1 => Literal::Bool(false),
+            2 => Literal::Bool(true),
+            #[cfg(feature = "float")]
+            3 => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                Literal::Float(f64::from_bits(u64::from_le_bytes(bytes)))
+            }
+            #[cfg(not(feature = "float"))]
+            3 => return Err(BinaryError::MissingFeature("float")),
+            #[cfg(feature = "char")]
+            4 => {
+                let c = u32::try_from(self.uint()?).map_err(|_| BinaryError::OutOfRange)?;
+                Literal::Char(char::from_u32(c).ok_or(BinaryError::OutOfRange)?)
+            }
+            #[cfg(not(feature = "char"))]
+            4 => return Err(BinaryError::MissingFeature("char")),
+            b => return Err(BinaryError::InvalidTag("literal", b)),
+        })
+    }
+
+    /// Reads a position, which is always present in the encoding even when it is dropped here
+    #[cfg(feature = "position")]
+    fn pos(&mut self) -> Result<Option<Position>, BinaryError> {
+        if !self.flag("position")? {
+            return Ok(None);
+        }
+        let pos = ColRow {
+            row: self.uint()?,
+            col: self.uint()?,
+        };
+        let pos_end = if self.flag("end position")? {
+            Some(ColRow {
+                row: self.uint()?,
+                col: self.uint()?,
+            })
+        } else {
+            None
+        };
+        Ok(Some(Position {
+            pos,
+            pos_end,
+            src: self.opt_string()?,
+        }))
+    }
+
+    #[cfg(not(feature = "position"))]
+    fn pos(&mut self) -> Result<(), BinaryError> {
+        if self.flag("position")? {
+            self.uint()?;
+            self.uint()?;
+            if self.flag("end position")? {
+                self.uint()?;
+                self.uint()?;
+            }
+            self.opt_string()?;
+        }
+        Ok(())
+    }
+
+    fn code(&mut self) -> Result<Code, BinaryError> {
+        let tag = self.byte()?;
+        let code = match tag {
+            0 => Code::Label {
+                label: self.string()?,
+                #[cfg(feature = "position")]
+                pos: None,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+            },
+            1 => Code::Instruction(Instruction::Constant {
+                dest: self.string()?,
+                op: ConstOps::Const,
+                const_type: self.ty()?,
+                value: self.literal()?,
+                #[cfg(feature = "position")]
+                pos: None,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+                #[cfg(feature = "metadata")]
+                extra: serde_json::Map::new(),
+            }),
+            2 => {
+                let op = self.str()?;
+                let op = op
+                    .parse()
+                    .map_err(|_| BinaryError::UnknownOp(op.to_string()))?;
+                Code::Instruction(Instruction::Value {
+                    op,
+                    dest: self.string()?,
+                    op_type: self.ty()?,
+                    args: self.strings()?,
+                    funcs: self.strings()?,
+                    labels: self.strings()?,
+                    #[cfg(feature = "position")]
+                    pos: None,
+                    #[cfg(feature = "format")]
+                    format: FormatHints::default(),
+                    #[cfg(feature = "metadata")]
+                    extra: serde_json::Map::new(),
+                })
+            }
+            3 => {
+                let op = self.str()?;
+                let op = op
+                    .parse()
+                    .map_err(|_| BinaryError::UnknownOp(op.to_string()))?;
+                Code::Instruction(Instruction::Effect {
+                    op,
+                    args: self.strings()?,
+                    funcs: self.strings()?,
+                    labels: self.strings()?,
+                    #[cfg(feature = "position")]
+                    pos: None,
+                    #[cfg(feature = "format")]
+                    format: FormatHints::default(),
+                    #[cfg(feature = "metadata")]
+                    extra: serde_json::Map::new(),
+                })
+            }
+            b => return Err(BinaryError::InvalidTag("code", b)),
+        };
+        self.trailer(code)
+    }
+
+    /// Reads the position, layout hints, and extra keys that every kind of code ends with into ```code```
+    #[cfg_attr(
+        not(any(feature = "position", feature = "format", feature = "metadata")),
+        allow(unused_mut)
+    )]
+    fn trailer(&mut self, mut code: Code) -> Result<Code, BinaryError> {
+        #[cfg_attr(
+            not(feature = "position"),
+            allow(unused_variables, clippy::let_unit_value)
+        )]
+        let pos = self.pos()?;
+        #[cfg(feature = "position")]
+        match &mut code {
+            Code::Label { pos: p, .. }
+            | Code::Instruction(
+                Instruction::Constant { pos: p, .. }
+                | Instruction::Value { pos: p, .. }
+                | Instruction::Effect { pos: p, .. },
+            ) => *p = pos,
+        }
+        #[cfg_attr(not(feature = "format"), allow(unused_variables))]
+        let blank_lines_before = self.len()?;
+        #[cfg_attr(not(feature = "format"), allow(unused_variables))]
+        let comment = self.opt_string()?;
+        #[cfg(feature = "format")]
+        {
+            *code.format_hints_mut() = FormatHints {
+                blank_lines_before,
+                comment,
+            };
+        }
+        #[cfg_attr(not(feature = "metadata"), allow(unused_variables))]
+        let extra = self.opt_string()?;
+        #[cfg(feature = "metadata")]
+        if let (
+            Some(extra),
+            Code::Instruction(
+                Instruction::Constant { extra: e, .. }
+                | Instruction::Value { extra: e, .. }
+                | Instruction::Effect { extra: e, .. },
+            ),
+        ) = (extra, &mut code)
+        {
+            *e = serde_json::from_str(&extra).map_err(|_| BinaryError::InvalidMetadata)?;
+        }
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_binary, to_binary};
+    use crate::text::parse_and_convert;
+
+    #[test]
+    fn round_trip_and_size() {
+        let program = parse_and_convert(
+            "@main(n: int) {
+  one: int = const 1;
+  i: int = const 0;
+  sum: int = const 0;
+
+.loop:
+  sum: int = add sum i;  # keep a running total
+  i: int = add i one;
+  more: bool = lt i n;
+  br more .loop .done;
+.done:
+  print sum;
+}",
+        )
+        .unwrap();
+        let bytes = to_binary(&program);
+        let decoded = from_binary(&bytes).unwrap();
+        assert_eq!(decoded, program);
+        // Equality ignores layout, so compare the text
+        assert_eq!(decoded.to_string(), program.to_string());
+        // Positions make the JSON large, and they are a few bytes each here
+        assert!(bytes.len() * 3 < serde_json::to_string(&program).unwrap().len());
+    }
+
+    #[test]
+    fn truncated() {
+        let program = parse_and_convert("@main {\n  print;\n}").unwrap();
+        let bytes = to_binary(&program);
+        assert!(from_binary(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::{Code, EffectOps, Instruction, Program, ValueOps};
+
+/// The index of a [`crate::Function`] within [`Program::functions`]
+pub type FuncId = usize;
+
+/// Which functions call which in a [Program]
+///
+/// Calls to functions which are not defined in the program are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CallGraph {
+    callees: Vec<Vec<FuncId>>,
+    callers: Vec<Vec<FuncId>>,
+    names: HashMap<String, FuncId>,
+}
+
+impl CallGraph {
+    /// The functions called by ```func```, in the order of their first call
+    #[must_use]
+    pub fn callees(&self, func: FuncId) -> &[FuncId] {
+        &self.callees[func]
+    }
+
+    /// The functions which call ```func```, in program order
+    #[must_use]
+    pub fn callers(&self, func: FuncId) -> &[FuncId] {
+        &self.callers[func]
+    }
+
+    /// Finds the function named ```name```
+    #[must_use]
+    pub fn function_id(&self, name: &str) -> Option<FuncId> {
+        self.names.get(name).copied()
+    }
+
+    /// Whether ```func``` can call itself, directly or through other functions
+    #[must_use]
+    pub fn is_recursive(&self, func: FuncId) -> bool {
+        let mut visited = vec![false; self.callees.len()];
+        let mut worklist = self.callees[func].clone();
+        while let Some(f) = worklist.pop() {
+            if f == func {
+                return true;
+            }
+            if !std::mem::replace(&mut visited[f], true) {
+                worklist.extend(&self.callees[f]);
+            }
+        }
+        false
+    }
+
+    /// The functions reachable from ```func``` through one or more calls
+    fn reachable(&self, func: FuncId) -> Vec<bool> {
+        let mut reached = vec![false; self.callees.len()];
+        let mut worklist = self.callees[func].clone();
+        while let Some(f) = worklist.pop() {
+            if !std::mem::replace(&mut reached[f], true) {
+                worklist.extend(&self.callees[f]);
+            }
+        }
+        reached
+    }
+
+    /// Topologically sorts the strongly connected components of the graph, breaking ties and ordering functions within a component by their preorder index in a depth-first walk from ```roots```
+    fn sorted(&self, roots: &[FuncId], callers_first: bool) -> Vec<FuncId> {
+        let n = self.callees.len();
+        let mut discovered = vec![usize::MAX; n];
+        let mut next_index = 0;
+        for &root in roots {
+            let mut stack = vec![root];
+            while let Some(f) = stack.pop() {
+                if discovered[f] != usize::MAX {
+                    continue;
+                }
+                discovered[f] = next_index;
+                next_index += 1;
+                stack.extend(self.callees[f].iter().rev());
+            }
+        }
+
+        // Each function's component is named by its earliest discovered member
+        let reach: Vec<Vec<bool>> = (0..n).map(|f| self.reachable(f)).collect();
+        let component: Vec<FuncId> = (0..n)
+            .map(|f| {
+                (0..n)
+                    .filter(|&g| g == f || (reach[f][g] && reach[g][f]))
+                    .min_by_key(|&g| discovered[g])
+                    .unwrap()
+            })
+            .collect();
+
+        let mut edges: Vec<Vec<FuncId>> = vec![Vec::new(); n];
+        let mut in_degree = vec![0; n];
+        for f in 0..n {
+            for &c in &self.callees[f] {
+                let (from, to) = if callers_first {
+                    (component[f], component[c])
+                } else {
+                    (component[c], component[f])
+                };
+                if from != to && !edges[from].contains(&to) {
+                    edges[from].push(to);
+                    in_degree[to] += 1;
+                }
+            }
+        }
+
+        let mut ready: BinaryHeap<Reverse<(usize, FuncId)>> = (0..n)
+            .filter(|&f| component[f] == f && in_degree[f] == 0)
+            .map(|f| Reverse((discovered[f], f)))
+            .collect();
+        let mut order = Vec::with_capacity(n);
+        while let Some(Reverse((_, c))) = ready.pop() {
+            let mut members: Vec<FuncId> = (0..n).filter(|&f| component[f] == c).collect();
+            members.sort_by_key(|&f| discovered[f]);
+            order.extend(members);
+            for &to in &edges[c] {
+                in_degree[to] -= 1;
+                if in_degree[to] == 0 {
+                    ready.push(Reverse((discovered[to], to)));
+                }
+            }
+        }
+        order
+    }
+}
+
+/// Builds the [`CallGraph`] of ```program``` from both value and effect `call`s
+#[must_use]
+pub fn call_graph(program: &Program) -> CallGraph {
+    let names: HashMap<String, FuncId> = program
+        .function_index()
+        .into_iter()
+        .map(|(name, i)| (name.to_string(), i))
+        .collect();
+    let mut calls = vec![Vec::new(); program.functions.len()];
+    let mut called_by = vec![Vec::new(); program.functions.len()];
+    for (i, func) in program.functions.iter().enumerate() {
+        for code in &func.instrs {
+            if let Code::Instruction(
+                Instruction::Value {
+                    op: ValueOps::Call,
+                    funcs,
+                    ..
+                }
+                | Instruction::Effect {
+                    op: EffectOps::Call,
+                    funcs,
+                    ..
+                },
+            ) = code
+            {
+                for &c in funcs.iter().filter_map(|f| names.get(f)) {
+                    if !calls[i].contains(&c) {
+                        calls[i].push(c);
+                        called_by[c].push(i);
+                    }
+                }
+            }
+        }
+    }
+    CallGraph {
+        callees: calls,
+        callers: called_by,
+        names,
+    }
+}
+
+/// Whether [`Program::order_by_call_depth`] puts callers or callees first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallOrder {
+    /// Every function comes before the functions it calls, so the program reads top-down from `@main`
+    CallersFirst,
+    /// Every function comes after the functions it calls
+    CalleesFirst,
+}
+
+impl Program {
+    /// Reorders [`Program::functions`] to follow the call graph in the direction given by ```order```
+    ///
+    /// Where the call graph leaves a choice, functions keep the order of a depth-first walk which starts from `@main`, then any functions it does not reach in program order, and visits callees in the order they are first called.
+    /// Mutually recursive functions are kept together in the order the walk discovers them, so the result is deterministic.
+    pub fn order_by_call_depth(&mut self, order: CallOrder) {
+        let graph = call_graph(self);
+        let main = graph.function_id("main");
+        let roots: Vec<FuncId> = main
+            .into_iter()
+            .chain((0..self.functions.len()).filter(|&f| Some(f) != main))
+            .collect();
+        let ids = graph.sorted(&roots, order == CallOrder::CallersFirst);
+        let mut functions: Vec<_> = std::mem::take(&mut self.functions)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.functions = ids
+            .into_iter()
+            .filter_map(|f| functions[f].take())
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallOrder;
+    use crate::text::parse_and_convert;
+    use crate::Program;
+
+    /// The names of the functions of ```program```, in order
+    fn names(program: &Program) -> Vec<&str> {
+        program.functions.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn order_caller_and_callee() {
+        let mut program = parse_and_convert(
+            "@leaf {
+}
+@helper {
+  call @leaf;
+}
+@main {
+  call @helper;
+}",
+        )
+        .unwrap();
+        program.order_by_call_depth(CallOrder::CallersFirst);
+        assert_eq!(names(&program), ["main", "helper", "leaf"]);
+        program.order_by_call_depth(CallOrder::CalleesFirst);
+        assert_eq!(names(&program), ["leaf", "helper", "main"]);
+    }
+}
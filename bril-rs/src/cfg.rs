@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::interp::Profile;
+use crate::{Code, EffectOps, Function, Instruction};
+
+/// The index of a [`BasicBlock`] within a [`Cfg`]
+pub type BlockId = usize;
+
+/// A maximal straight-line sequence of [Code] from a [Function]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The label which starts this block, if any
+    pub label: Option<String>,
+    /// The indices into [`Function::instrs`] covered by this block, including the leading label
+    pub range: Range<usize>,
+    /// The blocks that control can flow to from the end of this block
+    pub successors: Vec<BlockId>,
+    /// The blocks that control can flow from into this block
+    pub predecessors: Vec<BlockId>,
+}
+
+/// The control-flow graph of a [Function]
+///
+/// Blocks are stored in program order and the entry block is always present, even for an empty function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    /// The basic blocks of the function in program order
+    pub blocks: Vec<BasicBlock>,
+    /// The block where execution of the function begins
+    pub entry: BlockId,
+    labels: HashMap<String, BlockId>,
+    block_of: Vec<BlockId>,
+}
+
+impl Cfg {
+    /// The number of blocks in the graph
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether the graph has no blocks. This is never true for graphs produced by [`build_cfg`]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// The blocks with their ids, in program order
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &BasicBlock)> + '_ {
+        self.blocks.iter().enumerate()
+    }
+
+    /// The successors of ```block```
+    #[must_use]
+    pub fn successors(&self, block: BlockId) -> &[BlockId] {
+        &self.blocks[block].successors
+    }
+
+    /// The predecessors of ```block```
+    #[must_use]
+    pub fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.blocks[block].predecessors
+    }
+
+    /// Finds the block that starts with ```label```
+    #[must_use]
+    pub fn block_by_label(&self, label: &str) -> Option<BlockId> {
+        self.labels.get(label).copied()
+    }
+
+    /// The block containing the code at ```index``` of [`Function::instrs`]. A label belongs to the block it starts
+    /// # Panics
+    /// Panics if ```index``` is not an index into the function this graph was built from
+    #[must_use]
+    pub fn block_of(&self, index: usize) -> BlockId {
+        self.block_of[index]
+    }
+
+    /// The indices into [`Function::instrs`] covered by ```block```, including its leading label
+    #[must_use]
+    pub fn block_range(&self, block: BlockId) -> Range<usize> {
+        self.blocks[block].range.clone()
+    }
+
+    /// The blocks which leave the function, either through a `ret` or by falling off the end
+    pub fn exits(&self) -> impl Iterator<Item = BlockId> + '_ {
+        self.iter()
+            .filter(|(_, b)| b.successors.is_empty())
+            .map(|(i, _)| i)
+    }
+}
+
+/// How many times each edge of a [Cfg] was taken during a profiled run, for guiding optimizations towards hot paths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgProfile {
+    /// The number of times control went along each `(from, to)` edge which was taken at least once
+    pub edges: HashMap<(BlockId, BlockId), u64>,
+}
+
+impl CfgProfile {
+    /// Counts how often each edge of ```cfg```, the graph of ```func```, was taken in ```profile```
+    ///
+    /// An edge is taken when the first code of its target is executed right after the last code of its source.
+    #[must_use]
+    pub fn from_profile(func: &Function, cfg: &Cfg, profile: &Profile) -> Self {
+        let mut edges = HashMap::new();
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let Some(last) = block.range.end.checked_sub(1) else {
+                continue;
+            };
+            for &s in &block.successors {
+                let count = profile.transfers(&func.name, last, cfg.blocks[s].range.start);
+                if count > 0 {
+                    edges.insert((b, s), count);
+                }
+            }
+        }
+        Self { edges }
+    }
+
+    /// How many times control went from ```from``` to ```to```
+    #[must_use]
+    pub fn weight(&self, from: BlockId, to: BlockId) -> u64 {
+        self.edges.get(&(from, to)).copied().unwrap_or(0)
+    }
+
+    /// The fraction of the times control left ```from``` that it went to ```to```, or [None] if it never left ```from```
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn probability(&self, cfg: &Cfg, from: BlockId, to: BlockId) -> Option<f64> {
+        let total: u64 = cfg
+            .successors(from)
+            .iter()
+            .map(|&s| self.weight(from, s))
+            .sum();
+        (total > 0).then(|| self.weight(from, to) as f64 / total as f64)
+    }
+}
+
+const fn is_terminator(op: EffectOps) -> bool {
+    matches!(op, EffectOps::Jump | EffectOps::Branch | EffectOps::Return)
+}
+
+/// Splits ```func``` into [`BasicBlock`]s at labels and after `jmp`, `br`, and `ret` and connects them into a [`Cfg`]
+///
+/// Blocks without a terminator fall through into the next block. Labels which do not exist in the function are ignored when computing successors.
+#[must_use]
+pub fn build_cfg(func: &Function) -> Cfg {
+    let mut blocks = Vec::new();
+    let mut labels = HashMap::new();
+    let mut start = 0;
+    let mut label = None;
+
+    for (i, code) in func.instrs.iter().enumerate() {
+        match code {
+            Code::Label { label: l, .. } => {
+                if i != start || label.is_some() {
+                    blocks.push((label.take(), start..i));
+                }
+                start = i;
+                labels.insert(l.clone(), blocks.len());
+                label = Some(l.clone());
+            }
+            Code::Instruction(Instruction::Effect { op, .. }) if is_terminator(*op) => {
+                blocks.push((label.take(), start..i + 1));
+                start = i + 1;
+            }
+            Code::Instruction(_) => {}
+        }
+    }
+    if start != func.instrs.len() || label.is_some() || blocks.is_empty() {
+        blocks.push((label, start..func.instrs.len()));
+    }
+
+    let mut blocks: Vec<BasicBlock> = blocks
+        .into_iter()
+        .map(|(label, range)| BasicBlock {
+            label,
+            range,
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        })
+        .collect();
+
+    for i in 0..blocks.len() {
+        let last = blocks[i]
+            .range
+            .clone()
+            .rev()
+            .find_map(|idx| match &func.instrs[idx] {
+                Code::Instruction(instr) => Some(instr),
+                Code::Label { .. } => None,
+            });
+        let successors: Vec<BlockId> = match last {
+            Some(Instruction::Effect {
+                op: EffectOps::Jump | EffectOps::Branch,
+                labels: targets,
+                ..
+            }) => {
+                let mut succs = Vec::new();
+                for s in targets.iter().filter_map(|l| labels.get(l).copied()) {
+                    if !succs.contains(&s) {
+                        succs.push(s);
+                    }
+                }
+                succs
+            }
+            Some(Instruction::Effect {
+                op: EffectOps::Return,
+                ..
+            }) => Vec::new(),
+            _ if i + 1 < blocks.len() => vec![i + 1],
+            _ => Vec::new(),
+        };
+        for &s in &successors {
+            blocks[s].predecessors.push(i);
+        }
+        blocks[i].successors = successors;
+    }
+
+    let mut block_of = vec![0; func.instrs.len()];
+    for (b, block) in blocks.iter().enumerate() {
+        for i in block.range.clone() {
+            block_of[i] = b;
+        }
+    }
+
+    Cfg {
+        blocks,
+        entry: 0,
+        labels,
+        block_of,
+    }
+}
+
+/// The indices into [`Function::instrs`] of the instructions of ```func``` which can never execute, in order
+///
+/// These are the instructions of every block that a walk over the control-flow graph from the entry does not reach, such as code after a `ret` or `jmp` before the next label, and loops which nothing outside them jumps into.
+/// Blocks are reached by falling through as well as by jumping, and a `guard` reaches the label it jumps to on failure. Labels themselves are not included.
+#[must_use]
+pub fn unreachable_instructions(func: &Function) -> Vec<usize> {
+    let cfg = build_cfg(func);
+    let mut reached = vec![false; cfg.len()];
+    let mut stack = vec![cfg.entry];
+    reached[cfg.entry] = true;
+    while let Some(b) = stack.pop() {
+        let targets = cfg.block_range(b).flat_map(|i| match &func.instrs[i] {
+            Code::Instruction(Instruction::Effect { labels, .. }) => labels.as_slice(),
+            _ => &[],
+        });
+        let guarded = targets.filter_map(|l| cfg.block_by_label(l));
+        for s in cfg.successors(b).iter().copied().chain(guarded) {
+            if !reached[s] {
+                reached[s] = true;
+                stack.push(s);
+            }
+        }
+    }
+    cfg.iter()
+        .filter(|(b, _)| !reached[*b])
+        .flat_map(|(_, block)| block.range.clone())
+        .filter(|&i| matches!(func.instrs[i], Code::Instruction(_)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{build_cfg, unreachable_instructions, CfgProfile};
+    use crate::interp::run_with_profile;
+    use crate::text::parse_and_convert;
+    use crate::Function;
+
+    /// The `@main` of ```src```, which is in the text format
+    fn main_of(src: &str) -> Function {
+        parse_and_convert(src).unwrap().functions.remove(0)
+    }
+
+    #[test]
+    fn blocks_and_instructions() {
+        let func = main_of(
+            "@main(c: bool) {
+  x: int = const 1;
+  br c .a .b;
+.a:
+  print x;
+.b:
+  jmp .c;
+  print x;
+.c:
+}",
+        );
+        let cfg = build_cfg(&func);
+        let ranges: Vec<_> = (0..cfg.len()).map(|b| cfg.block_range(b)).collect();
+        assert_eq!(ranges, [0..2, 2..4, 4..6, 6..7, 7..8]);
+        for (b, range) in ranges.into_iter().enumerate() {
+            assert!(range.into_iter().all(|i| cfg.block_of(i) == b));
+        }
+        assert_eq!(cfg.blocks[0].successors, [1, 2]);
+        assert_eq!(cfg.blocks[3].predecessors, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn branch_weights_from_profile() {
+        let program = parse_and_convert(
+            "@main {
+  i: int = const 0;
+  one: int = const 1;
+  five: int = const 5;
+.h:
+  i: int = add i one;
+  c: bool = lt i five;
+  br c .h .x;
+.x:
+  print i;
+}",
+        )
+        .unwrap();
+        let (result, profile) = run_with_profile(&program, vec![], io::sink());
+        assert!(result.is_ok());
+        let func = &program.functions[0];
+        let cfg = build_cfg(func);
+        let weights = CfgProfile::from_profile(func, &cfg, &profile);
+        assert_eq!((weights.weight(0, 1), weights.weight(1, 1)), (1, 4));
+        assert_eq!(
+            weights.weight(1, 1) + weights.weight(1, 2),
+            profile.count("main", 6)
+        );
+        assert_eq!(weights.probability(&cfg, 1, 2), Some(0.2));
+        assert_eq!(weights.probability(&cfg, 2, 2), None);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        let func = main_of(
+            "@main(c: bool) {
+  ret;
+  print c;
+.island:
+  jmp .island;
+.after:
+  print c;
+}",
+        );
+        assert_eq!(unreachable_instructions(&func), [1, 3, 5]);
+        let func = main_of(
+            "@main(c: bool) {
+  br c .a .b;
+.a:
+  print c;
+.b:
+  print c;
+}",
+        );
+        assert_eq!(unreachable_instructions(&func), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,520 @@
+use std::fmt::{self, Display, Formatter};
+
+use thiserror::Error;
+
+#[cfg(feature = "ssa")]
+use std::collections::HashSet;
+
+#[cfg(feature = "ssa")]
+use crate::cfg::build_cfg;
+use crate::cfg::{BlockId, Cfg};
+#[cfg(any(feature = "ssa", feature = "speculate"))]
+use crate::ValueOps;
+use crate::{Code, EffectOps, Function, Instruction, Position};
+#[cfg(feature = "speculate")]
+use std::collections::HashMap;
+
+/// A function with a return type which can finish without returning a value
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("function `{func}` can fall off the end of block {block} without returning a value")]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReturnError {
+    /// The name of the offending function
+    pub func: String,
+    /// The block which leaves the function without a `ret`
+    pub block: BlockId,
+    /// The label of ```block```, if it has one
+    pub label: Option<String>,
+}
+
+/// Checks that every path through ```func``` which is reachable from the entry of ```cfg``` ends in a `ret`
+///
+/// Functions without a return type, and external declarations, may always fall off the end. The first offending block in program order is reported.
+/// # Errors
+/// Returns a [`ReturnError`] naming a reachable block which falls off the end of the function
+pub fn check_returns(func: &Function, cfg: &Cfg) -> Result<(), ReturnError> {
+    if func.return_type.is_none() {
+        return Ok(());
+    }
+    #[cfg(feature = "external")]
+    if func.external {
+        return Ok(());
+    }
+    let mut reachable = vec![false; cfg.len()];
+    let mut worklist = vec![cfg.entry];
+    while let Some(b) = worklist.pop() {
+        if !std::mem::replace(&mut reachable[b], true) {
+            worklist.extend(cfg.successors(b));
+        }
+    }
+
+    for b in cfg.exits().filter(|&b| reachable[b]) {
+        let block = &cfg.blocks[b];
+        let returns = block
+            .range
+            .clone()
+            .rev()
+            .find_map(|i| match &func.instrs[i] {
+                Code::Instruction(instr) => Some(instr),
+                Code::Label { .. } => None,
+            })
+            .is_some_and(|instr| {
+                matches!(
+                    instr,
+                    Instruction::Effect {
+                        op: EffectOps::Return,
+                        ..
+                    }
+                )
+            });
+        if !returns {
+            return Err(ReturnError {
+                func: func.name.clone(),
+                block: b,
+                label: block.label.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A suspicious but legal construct found by a lint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// The name of the function containing the construct
+    pub func: String,
+    /// The index into [`Function::instrs`] of the offending code
+    pub index: usize,
+    /// The source position of the offending code, if known
+    pub pos: Option<Position>,
+    /// A description of the problem
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.pos {
+            Some(p) => write!(f, "Line {}, Column {}: ", p.pos.row, p.pos.col)?,
+            None => write!(f, "@{}, instruction {}: ", self.func, self.index)?,
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(feature = "position")]
+pub(crate) fn pos_of(instr: &Instruction) -> Option<Position> {
+    instr.get_pos()
+}
+
+#[cfg(not(feature = "position"))]
+pub(crate) const fn pos_of(_: &Instruction) -> Option<Position> {
+    None
+}
+
+/// Finds the instructions of ```func``` which assign to one of its parameters
+///
+/// Reassigning a parameter is legal Bril, but it is easy to do by accident and hides the argument's original value from the rest of the function. In SSA form it is an error, since the parameter already counts as a definition.
+#[must_use]
+pub fn find_parameter_shadowing(func: &Function) -> Vec<Warning> {
+    func.instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, code)| match code {
+            Code::Instruction(
+                instr @ (Instruction::Constant { dest, .. } | Instruction::Value { dest, .. }),
+            ) if func.args.iter().any(|a| a.name == *dest) => Some(Warning {
+                func: func.name.clone(),
+                index,
+                pos: pos_of(instr),
+                message: format!("parameter `{dest}` is reassigned"),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A way in which a function is not in SSA form, found by [`check_ssa`]
+#[cfg(feature = "ssa")]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum SsaError {
+    /// A variable is assigned by more than one instruction, or is a parameter and also assigned
+    #[error("`{var}` is assigned more than once, again by instruction {index}")]
+    MultipleDefinitions {
+        /// The variable
+        var: String,
+        /// The index into [`Function::instrs`] of the first instruction which assigns it a second time
+        index: usize,
+    },
+    /// A `phi` has a different number of arguments and labels
+    #[error("the phi assigning `{dest}` at instruction {index} has {args} arguments but {labels} labels")]
+    PhiArity {
+        /// The variable the `phi` assigns
+        dest: String,
+        /// The index into [`Function::instrs`] of the `phi`
+        index: usize,
+        /// How many arguments it has
+        args: usize,
+        /// How many labels it has
+        labels: usize,
+    },
+    /// A `phi` names a label which is not the label of a predecessor of its block
+    #[error("the phi assigning `{dest}` at instruction {index} names `.{label}`, which is not a predecessor of its block")]
+    PhiLabel {
+        /// The variable the `phi` assigns
+        dest: String,
+        /// The index into [`Function::instrs`] of the `phi`
+        index: usize,
+        /// The offending label
+        label: String,
+    },
+}
+
+/// Checks that ```func``` is in SSA form
+///
+/// Every variable must have a single definition, where parameters and `phi`s count as definitions, and every `phi` must have one label per argument, each naming a predecessor of the block the `phi` is in. Whether each use is dominated by its definition is not checked. The first problem in program order is reported.
+/// # Errors
+/// Returns the first [`SsaError`] found
+#[cfg(feature = "ssa")]
+pub fn check_ssa(func: &Function) -> Result<(), SsaError> {
+    let cfg = build_cfg(func);
+    let mut defined: HashSet<&str> = func.args.iter().map(|a| a.name.as_str()).collect();
+    for (index, code) in func.instrs.iter().enumerate() {
+        let Code::Instruction(instr) = code else {
+            continue;
+        };
+        if let Instruction::Value {
+            op: ValueOps::Phi,
+            dest,
+            args,
+            labels,
+            ..
+        } = instr
+        {
+            if args.len() != labels.len() {
+                return Err(SsaError::PhiArity {
+                    dest: dest.clone(),
+                    index,
+                    args: args.len(),
+                    labels: labels.len(),
+                });
+            }
+            let preds = cfg.predecessors(cfg.block_of(index));
+            if let Some(label) = labels
+                .iter()
+                .find(|l| cfg.block_by_label(l).is_none_or(|b| !preds.contains(&b)))
+            {
+                return Err(SsaError::PhiLabel {
+                    dest: dest.clone(),
+                    index,
+                    label: label.clone(),
+                });
+            }
+        }
+        if let Some(dest) = instr.dest() {
+            if !defined.insert(dest) {
+                return Err(SsaError::MultipleDefinitions {
+                    var: dest.to_string(),
+                    index,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A misuse of speculative execution found by [`check_speculation`]
+#[cfg(feature = "speculate")]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum SpecError {
+    /// A `commit` or `guard` can run when no `speculate` is in effect
+    #[error("`{op}` at instruction {index} can run outside of speculation")]
+    Outside {
+        /// The operation, `commit` or `guard`
+        op: &'static str,
+        /// The index into [`Function::instrs`] of the operation
+        index: usize,
+        /// Its source position, if known
+        pos: Option<Position>,
+    },
+    /// A `speculate` can reach the end of the function without being committed or aborted by a `guard`
+    #[error("the speculation begun at instruction {index} can reach the end of the function without a `commit`")]
+    Unmatched {
+        /// The index into [`Function::instrs`] of the `speculate`
+        index: usize,
+        /// Its source position, if known
+        pos: Option<Position>,
+    },
+    /// A `call` or `ret` can run during speculation, which the reference interpreter does not allow
+    #[error("`{op}` at instruction {index} can run during speculation")]
+    NotAllowed {
+        /// The operation, `call` or `ret`
+        op: &'static str,
+        /// The index into [`Function::instrs`] of the operation
+        index: usize,
+        /// Its source position, if known
+        pos: Option<Position>,
+    },
+    /// Two paths reach the same code inside different numbers of nested speculations
+    #[error("paths reach instruction {index} inside different numbers of nested speculations")]
+    Mismatched {
+        /// The index into [`Function::instrs`] of the code, usually a label
+        index: usize,
+        /// Its source position, if known
+        pos: Option<Position>,
+    },
+}
+
+#[cfg(all(feature = "speculate", feature = "position"))]
+fn code_pos(code: &Code) -> Option<Position> {
+    match code {
+        Code::Label { pos, .. } => pos.clone(),
+        Code::Instruction(instr) => instr.get_pos(),
+    }
+}
+
+#[cfg(all(feature = "speculate", not(feature = "position")))]
+const fn code_pos(_: &Code) -> Option<Position> {
+    None
+}
+
+/// Checks that every `speculate` of ```func``` is closed by a `commit`, or aborted by a failing `guard`, on every path before the function ends
+///
+/// Speculations may nest, in which case a `commit` or failing `guard` ends the innermost one, but every path to a piece of code must be inside the same number of them. Like the reference interpreter, `commit` and `guard` may only run during speculation, and `call` and `ret` may not. Every `guard` is assumed to be able to both pass and fail, and code which cannot be reached from the start of the function is ignored.
+/// # Errors
+/// Returns the first [`SpecError`] found
+#[cfg(feature = "speculate")]
+pub fn check_speculation(func: &Function) -> Result<(), SpecError> {
+    let labels: HashMap<&str, usize> = func
+        .instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| match c {
+            Code::Label { label, .. } => Some((label.as_str(), i)),
+            Code::Instruction(_) => None,
+        })
+        .collect();
+    let pos_at = |index: usize| code_pos(&func.instrs[index]);
+    // The `speculate`s in effect on entry to each piece of code, innermost last, where the extra slot stands for the end of the function
+    let mut reached: Vec<Option<usize>> = vec![None; func.instrs.len() + 1];
+    let mut worklist: Vec<(usize, Vec<usize>)> = vec![(0, Vec::new())];
+    while let Some((i, mut open)) = worklist.pop() {
+        let Some(code) = func.instrs.get(i) else {
+            if let Some(&index) = open.last() {
+                return Err(SpecError::Unmatched {
+                    index,
+                    pos: pos_at(index),
+                });
+            }
+            continue;
+        };
+        if let Some(depth) = reached[i] {
+            if depth != open.len() {
+                return Err(SpecError::Mismatched {
+                    index: i,
+                    pos: pos_at(i),
+                });
+            }
+            continue;
+        }
+        reached[i] = Some(open.len());
+        let Code::Instruction(instr) = code else {
+            worklist.push((i + 1, open));
+            continue;
+        };
+        let not_allowed = |op| SpecError::NotAllowed {
+            op,
+            index: i,
+            pos: pos_of(instr),
+        };
+        let outside = |op| SpecError::Outside {
+            op,
+            index: i,
+            pos: pos_of(instr),
+        };
+        match instr {
+            Instruction::Value {
+                op: ValueOps::Call, ..
+            }
+            | Instruction::Effect {
+                op: EffectOps::Call,
+                ..
+            } if !open.is_empty() => return Err(not_allowed("call")),
+            Instruction::Effect {
+                op: EffectOps::Return,
+                ..
+            } if !open.is_empty() => return Err(not_allowed("ret")),
+            Instruction::Effect {
+                op: EffectOps::Return,
+                ..
+            } => continue,
+            Instruction::Effect {
+                op: EffectOps::Jump | EffectOps::Branch,
+                labels: targets,
+                ..
+            } => {
+                for t in targets.iter().filter_map(|l| labels.get(l.as_str())) {
+                    worklist.push((*t, open.clone()));
+                }
+                continue;
+            }
+            Instruction::Effect {
+                op: EffectOps::Speculate,
+                ..
+            } => open.push(i),
+            Instruction::Effect {
+                op: EffectOps::Commit,
+                ..
+            } => {
+                let Some(_) = open.pop() else {
+                    return Err(outside("commit"));
+                };
+            }
+            Instruction::Effect {
+                op: EffectOps::Guard,
+                labels: targets,
+                ..
+            } => {
+                let Some((_, outer)) = open.split_last() else {
+                    return Err(outside("guard"));
+                };
+                for t in targets.iter().filter_map(|l| labels.get(l.as_str())) {
+                    worklist.push((*t, outer.to_vec()));
+                }
+            }
+            _ => {}
+        }
+        worklist.push((i + 1, open));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_returns, find_parameter_shadowing, ReturnError};
+    #[cfg(feature = "speculate")]
+    use super::{check_speculation, SpecError};
+    #[cfg(feature = "ssa")]
+    use super::{check_ssa, SsaError};
+    use crate::cfg::build_cfg;
+    use crate::text::parse_and_convert;
+    use crate::Function;
+
+    /// The `@main` of ```src```, which is in the text format
+    fn main_of(src: &str) -> Function {
+        parse_and_convert(src).unwrap().functions.remove(0)
+    }
+
+    #[test]
+    fn missing_return_on_one_branch() {
+        let src = "@main(c: bool): int {
+  one: int = const 1;
+  br c .yes .no;
+.yes:
+  ret one;
+.no:
+  print one;
+}";
+        let func = main_of(src);
+        assert_eq!(
+            check_returns(&func, &build_cfg(&func)),
+            Err(ReturnError {
+                func: "main".to_string(),
+                block: 2,
+                label: Some("no".to_string()),
+            })
+        );
+        let func = main_of(&src.replace("print one;", "ret one;"));
+        assert_eq!(check_returns(&func, &build_cfg(&func)), Ok(()));
+        let func = main_of(&src.replace("): int {", ") {").replace("ret one", "ret"));
+        assert_eq!(check_returns(&func, &build_cfg(&func)), Ok(()));
+    }
+
+    #[test]
+    fn reassigned_parameter() {
+        let func = main_of(
+            "@main(x: int, y: int) {
+  one: int = const 1;
+  x: int = add x one;
+  print x y;
+}",
+        );
+        let warnings = find_parameter_shadowing(&func);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].func.as_str(), warnings[0].index), ("main", 1));
+        assert_eq!(warnings[0].message, "parameter `x` is reassigned");
+        #[cfg(feature = "position")]
+        assert_eq!(warnings[0].pos.as_ref().unwrap().pos.row, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "ssa")]
+    fn ssa_diamond_and_double_assignment() {
+        let diamond = "@main(c: bool) {
+.entry:
+  br c .yes .no;
+.yes:
+  a: int = const 1;
+  jmp .join;
+.no:
+  b: int = const 2;
+  jmp .join;
+.join:
+  x: int = phi a b .yes .no;
+  print x;
+}";
+        assert_eq!(check_ssa(&main_of(diamond)), Ok(()));
+        assert_eq!(
+            check_ssa(&main_of(&diamond.replace("b: int", "a: int"))),
+            Err(SsaError::MultipleDefinitions {
+                var: "a".to_string(),
+                index: 6,
+            })
+        );
+        assert_eq!(
+            check_ssa(&main_of(
+                &diamond.replace("phi a b .yes .no", "phi a b .yes .entry")
+            )),
+            Err(SsaError::PhiLabel {
+                dest: "x".to_string(),
+                index: 9,
+                label: "entry".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "speculate")]
+    fn balanced_speculation_and_stray_commit() {
+        let balanced = "@main(c: bool) {
+  speculate;
+  guard c .abort;
+  commit;
+  ret;
+.abort:
+  print c;
+}";
+        assert_eq!(check_speculation(&main_of(balanced)), Ok(()));
+        let stray = main_of("@main {\n  x: int = const 1;\n  commit;\n}");
+        let error = check_speculation(&stray).unwrap_err();
+        assert!(matches!(
+            &error,
+            SpecError::Outside {
+                op: "commit",
+                index: 1,
+                ..
+            }
+        ));
+        #[cfg(feature = "position")]
+        assert!(matches!(
+            error,
+            SpecError::Outside { pos: Some(pos), .. } if pos.pos.row == 3
+        ));
+        let unmatched = main_of("@main {\n  speculate;\n  print;\n}");
+        assert!(matches!(
+            check_speculation(&unmatched),
+            Err(SpecError::Unmatched { index: 0, .. })
+        ));
+    }
+}
@@ -1,13 +1,21 @@
+#[cfg(feature = "alias")]
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Display;
 
 use crate::{
     AbstractArgument, AbstractCode, AbstractFunction, AbstractInstruction, AbstractProgram,
-    AbstractType, Argument, Code, EffectOps, Function, Instruction, Position, Program, Type,
-    ValueOps,
+    AbstractType, Argument, Code, ColRow, EffectOps, Function, Instruction, Literal, Position,
+    Program, Type, ValueOps, SPEC_VERSION,
 };
 
+#[cfg(feature = "alias")]
+use crate::AbstractTypeAlias;
+
 use thiserror::Error;
 
+use crate::check::pos_of;
+
 // This is a nifty trick to supply a global value for pos when it is not defined
 #[cfg(not(feature = "position"))]
 #[allow(non_upper_case_globals)]
@@ -35,9 +43,72 @@ pub enum ConversionError {
     #[error("Expected an effect operation, found {0}")]
     InvalidEffectOps(String),
 
+    /// The constant {0} has a value which is not a literal of type {1}, such as a `char` of more than one character, a fractional or out-of-range `int`, or a number typed `bool`
+    #[error("The constant {0} does not have a valid value of type {1}")]
+    InvalidConstant(String, String),
+
     /// Missing type signature
     #[error("Missing type signature")]
     MissingType,
+
+    /// The program targets version {0} of Bril, which is newer than the supported version
+    #[error("The program targets version {0} of Bril, but only versions up to {SPEC_VERSION} are supported")]
+    UnsupportedVersion(u32),
+
+    /// The label {0} is defined more than once in the same function
+    #[error("The label .{0} is defined more than once")]
+    DuplicateLabel(String),
+
+    /// An instruction refers to the label {0}, which is not defined in its function
+    #[error("The label .{0} is not defined")]
+    UndefinedLabel(String),
+
+    /// The `phi` assigning {0} has {1} arguments but {2} labels, when it needs one label for each argument
+    #[cfg(feature = "ssa")]
+    #[error("The phi assigning {0} has {1} arguments but {2} labels")]
+    MalformedPhi(String, usize, usize),
+
+    /// The operation {op} was given {found} arguments, but takes exactly {expected}
+    #[error("The {op} operation takes {expected} arguments but was given {found}")]
+    WrongArity {
+        /// The name of the operation
+        op: String,
+        /// How many arguments it takes
+        expected: usize,
+        /// How many it was given
+        found: usize,
+    },
+
+    /// The operation {op} was annotated with type {found}, but always produces a {expected}
+    #[error("The {op} operation produces a {expected}, not {found}")]
+    WrongOpType {
+        /// The name of the operation
+        op: String,
+        /// The type it produces
+        expected: String,
+        /// The type it was annotated with
+        found: String,
+    },
+
+    /// The external function {0} has instructions
+    #[cfg(feature = "external")]
+    #[error("The external function {0} cannot have a body")]
+    ExternalWithBody(String),
+
+    /// The type alias {0} is defined more than once, or has the name of a built-in type
+    #[cfg(feature = "alias")]
+    #[error("The type alias {0} is already defined")]
+    DuplicateAlias(String),
+
+    /// The type alias {0} stands for a type which contains itself
+    #[cfg(feature = "alias")]
+    #[error("The type alias {0} refers to itself")]
+    RecursiveAlias(String),
+
+    /// The array type {0} has a size which is not a non-negative integer
+    #[cfg(feature = "array")]
+    #[error("The size of the array type {0} is not a non-negative integer")]
+    InvalidArraySize(String),
 }
 
 impl ConversionError {
@@ -47,6 +118,10 @@ impl ConversionError {
         PositionalConversionError {
             e: self,
             pos: pos_var,
+            text: None,
+            byte_offset: None,
+            function: None,
+            code: None,
         }
     }
 }
@@ -58,32 +133,188 @@ pub struct PositionalConversionError {
     pub e: ConversionError,
     #[doc(hidden)]
     pub pos: Option<Position>,
+    text: Option<String>,
+    byte_offset: Option<usize>,
+    /// The index of the function and of the code within it that the error was found in, which lets a parser that kept its own offsets find where the error is
+    pub(crate) function: Option<usize>,
+    pub(crate) code: Option<usize>,
 }
 
 impl PositionalConversionError {
     #[doc(hidden)]
     #[must_use]
     pub const fn new(e: ConversionError) -> Self {
-        Self { e, pos: None }
+        Self {
+            e,
+            pos: None,
+            text: None,
+            byte_offset: None,
+            function: None,
+            code: None,
+        }
+    }
+
+    /// Keeps the text of the program this error was found in so that [Display] can show the offending line with a caret under it
+    ///
+    /// This has no effect on errors without a position, or whose position is past the end of ```source```.
+    #[must_use]
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.text = Some(source.to_string());
+        self
+    }
+
+    /// Records the byte offset into the source text that this error was found at, for when the program carries no [Position]s
+    ///
+    /// [Display] reports this as a line and column when [`Self::with_source`] has also been given the text, and as the bare offset otherwise. A [Position] takes precedence when there is one.
+    #[must_use]
+    pub const fn with_byte_offset(mut self, byte_offset: usize) -> Self {
+        self.byte_offset = Some(byte_offset);
+        self
+    }
+
+    /// The byte offset given to [`Self::with_byte_offset`], if any
+    #[must_use]
+    pub const fn byte_offset(&self) -> Option<usize> {
+        self.byte_offset
+    }
+
+    /// Notes the index of the function the error was found in, unless an inner conversion already did
+    pub(crate) const fn in_function(mut self, index: usize) -> Self {
+        if self.function.is_none() {
+            self.function = Some(index);
+        }
+        self
+    }
+
+    /// Notes the index of the code the error was found at within its function, unless an inner conversion already did
+    pub(crate) const fn at_code(mut self, index: usize) -> Self {
+        if self.code.is_none() {
+            self.code = Some(index);
+        }
+        self
+    }
+
+    /// Writes the offending line of the source with a gutter holding its row number and carets under the span from ```start``` to ```end```
+    fn fmt_snippet(
+        f: &mut std::fmt::Formatter<'_>,
+        source: &str,
+        start: ColRow,
+        end: Option<ColRow>,
+    ) -> std::fmt::Result {
+        let Some(line) = usize::try_from(start.row)
+            .ok()
+            .and_then(|row| source.lines().nth(row.checked_sub(1)?))
+        else {
+            return Ok(());
+        };
+        let col = usize::try_from(start.col).unwrap_or(usize::MAX).max(1);
+        // Tabs are kept in the padding so that the carets line up however wide the terminal draws them
+        let padding: String = line
+            .chars()
+            .take(col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = match end {
+            Some(end) if end.row == start.row && end.col > start.col => {
+                usize::try_from(end.col - start.col).unwrap_or(1)
+            }
+            _ => 1,
+        };
+        let gutter = " ".repeat(start.row.to_string().len());
+        write!(
+            f,
+            "\n{gutter} |\n{} | {line}\n{gutter} | {padding}{}",
+            start.row,
+            "^".repeat(width)
+        )
     }
 }
 
+/// The 1-based row and column, counted in characters, of the byte offset ```index``` into ```source```, or [None] if it is not within it
+fn col_row_of(source: &str, index: usize) -> Option<ColRow> {
+    let before = source.get(..index)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some(ColRow {
+        row: before.matches('\n').count() as u64 + 1,
+        col: before[line_start..].chars().count() as u64 + 1,
+    })
+}
+
 impl Display for PositionalConversionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             #[cfg(feature = "position")]
-            Self { e, pos: Some(pos) } => {
-                write!(f, "Line {}, Column {}: {e}", pos.pos.row, pos.pos.col)
+            Self {
+                e,
+                pos: Some(pos),
+                text,
+                ..
+            } => {
+                if pos.pos_end.is_none() && pos.src.is_none() {
+                    write!(f, "Line {}, Column {}: {e}", pos.pos.row, pos.pos.col)?;
+                } else {
+                    write!(f, "{pos}: {e}")?;
+                }
+                text.as_ref().map_or(Ok(()), |text| {
+                    Self::fmt_snippet(f, text, pos.pos, pos.pos_end)
+                })
             }
             #[cfg(not(feature = "position"))]
-            Self { e: _, pos: Some(_) } => {
+            Self { pos: Some(_), .. } => {
                 unreachable!()
             }
-            Self { e, pos: None } => write!(f, "{e}"),
+            Self {
+                e,
+                pos: None,
+                byte_offset: Some(offset),
+                text,
+                ..
+            } => match text
+                .as_ref()
+                .and_then(|text| Some((text, col_row_of(text, *offset)?)))
+            {
+                Some((text, at)) => {
+                    write!(f, "Line {}, Column {}: {e}", at.row, at.col)?;
+                    Self::fmt_snippet(f, text, at, None)
+                }
+                None => write!(f, "Byte {offset}: {e}"),
+            },
+            Self { e, pos: None, .. } => write!(f, "{e}"),
         }
     }
 }
 
+/// The ways that loading a program from JSON with [`crate::load_program_from_read`] and friends can fail
+#[derive(Error, Debug)]
+pub enum LoadError {
+    /// The input could not be read or is not well-formed Bril JSON. The line and column of the problem are available from [`serde_json::Error::line`] and [`serde_json::Error::column`]
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// The input is well-formed Bril JSON but not a valid Bril program, such as one with an unknown operation
+    #[error(transparent)]
+    Conversion(Box<PositionalConversionError>),
+}
+
+impl From<PositionalConversionError> for LoadError {
+    fn from(e: PositionalConversionError) -> Self {
+        Self::Conversion(Box::new(e))
+    }
+}
+
+/// The names of every value operation that this build can parse, which depends on the enabled features
+///
+/// Tools which generate Bril can use this to avoid emitting operations, like `fadd` without the `float` feature, which would fail to convert.
+#[must_use]
+pub const fn supported_value_ops() -> &'static [&'static str] {
+    ValueOps::NAMES
+}
+
+/// The names of every effect operation that this build can parse, as with [`supported_value_ops`]
+#[must_use]
+pub const fn supported_effect_ops() -> &'static [&'static str] {
+    EffectOps::NAMES
+}
+
 impl TryFrom<AbstractProgram> for Program {
     type Error = PositionalConversionError;
     fn try_from(
@@ -91,15 +322,23 @@ impl TryFrom<AbstractProgram> for Program {
             #[cfg(feature = "import")]
             imports,
             functions,
+            version,
         }: AbstractProgram,
     ) -> Result<Self, Self::Error> {
+        if let Some(v) = version.filter(|&v| v > SPEC_VERSION) {
+            return Err(PositionalConversionError::new(
+                ConversionError::UnsupportedVersion(v),
+            ));
+        }
+        let mut converted = Vec::with_capacity(functions.len());
+        for (i, f) in functions.into_iter().enumerate() {
+            converted.push(Function::try_from(f).map_err(|e| e.in_function(i))?);
+        }
         Ok(Self {
             #[cfg(feature = "import")]
             imports,
-            functions: functions
-                .into_iter()
-                .map(std::convert::TryInto::try_into)
-                .collect::<Result<Vec<Function>, _>>()?,
+            version,
+            functions: converted,
         })
     }
 }
@@ -114,18 +353,32 @@ impl TryFrom<AbstractFunction> for Function {
             return_type,
             #[cfg(feature = "position")]
             pos,
+            #[cfg(feature = "external")]
+            external,
+            #[cfg(feature = "alias")]
+            aliases,
         }: AbstractFunction,
     ) -> Result<Self, Self::Error> {
-        Ok(Self {
+        #[cfg(feature = "external")]
+        if external && !instrs.is_empty() {
+            return Err(ConversionError::ExternalWithBody(name).add_pos(pos));
+        }
+        #[cfg(feature = "alias")]
+        let (mut args, mut return_type, mut instrs) = (args, return_type, instrs);
+        #[cfg(feature = "alias")]
+        expand_aliases(&aliases, &mut args, &mut return_type, &mut instrs)
+            .map_err(|e| e.add_pos(pos.clone()))?;
+        let mut codes = Vec::with_capacity(instrs.len());
+        for (i, c) in instrs.into_iter().enumerate() {
+            codes.push(Code::try_from(c).map_err(|e| e.at_code(i))?);
+        }
+        let func = Self {
             args: args
                 .into_iter()
                 .map(std::convert::TryInto::try_into)
                 .collect::<Result<Vec<Argument>, _>>()
                 .map_err(|e| e.add_pos(pos.clone()))?,
-            instrs: instrs
-                .into_iter()
-                .map(std::convert::TryInto::try_into)
-                .collect::<Result<Vec<Code>, _>>()?,
+            instrs: codes,
             name,
             return_type: match return_type {
                 None => None,
@@ -136,10 +389,241 @@ impl TryFrom<AbstractFunction> for Function {
             },
             #[cfg(feature = "position")]
             pos,
-        })
+            #[cfg(feature = "external")]
+            external,
+        };
+        #[cfg(feature = "validate")]
+        func.validate_labels()?;
+        Ok(func)
     }
 }
 
+impl Function {
+    /// Checks that no label of this function is defined twice and that every label an instruction refers to is defined
+    ///
+    /// Conversion from an [`AbstractFunction`] runs this check when the `validate` feature is enabled.
+    /// # Errors
+    /// Returns a [`ConversionError::DuplicateLabel`] at the second definition of a label, or a [`ConversionError::UndefinedLabel`] at the first instruction which refers to a missing label, whichever comes first
+    // The error is the same one conversion returns
+    #[allow(clippy::result_large_err)]
+    pub fn validate_labels(&self) -> Result<(), PositionalConversionError> {
+        let defined: HashSet<&str> = self
+            .instrs
+            .iter()
+            .filter_map(|c| match c {
+                Code::Label { label, .. } => Some(label.as_str()),
+                Code::Instruction(_) => None,
+            })
+            .collect();
+        let mut seen = HashSet::new();
+        for (i, code) in self.instrs.iter().enumerate() {
+            match code {
+                Code::Label {
+                    label,
+                    #[cfg(feature = "position")]
+                    pos,
+                    ..
+                } => {
+                    if !seen.insert(label.as_str()) {
+                        return Err(ConversionError::DuplicateLabel(label.clone())
+                            .add_pos(pos.clone())
+                            .at_code(i));
+                    }
+                }
+                Code::Instruction(
+                    instr
+                    @ (Instruction::Value { labels, .. } | Instruction::Effect { labels, .. }),
+                ) => {
+                    if let Some(l) = labels.iter().find(|l| !defined.contains(l.as_str())) {
+                        return Err(ConversionError::UndefinedLabel(l.clone())
+                            .add_pos(pos_of(instr))
+                            .at_code(i));
+                    }
+                }
+                Code::Instruction(Instruction::Constant { .. }) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Like converting with [`TryFrom`], but reports every argument, return type, and instruction of ```func``` which cannot be converted instead of stopping at the first
+    /// # Errors
+    /// Returns every [`PositionalConversionError`] in the function, in source order
+    pub fn try_from_collecting(
+        AbstractFunction {
+            args,
+            instrs,
+            name,
+            return_type,
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "external")]
+            external,
+            #[cfg(feature = "alias")]
+            aliases,
+        }: AbstractFunction,
+    ) -> Result<Self, Vec<PositionalConversionError>> {
+        let mut errors = Vec::new();
+        #[cfg(feature = "external")]
+        if external && !instrs.is_empty() {
+            errors.push(ConversionError::ExternalWithBody(name.clone()).add_pos(pos.clone()));
+        }
+        // Every use of a broken alias would be reported as an unknown type as well, so stop here
+        #[cfg(feature = "alias")]
+        let (mut args, mut return_type, mut instrs) = (args, return_type, instrs);
+        #[cfg(feature = "alias")]
+        if let Err(e) = expand_aliases(&aliases, &mut args, &mut return_type, &mut instrs) {
+            errors.push(e.add_pos(pos));
+            return Err(errors);
+        }
+        let args: Vec<Argument> = args
+            .into_iter()
+            .filter_map(|a| {
+                a.try_into()
+                    .map_err(|e: ConversionError| errors.push(e.add_pos(pos.clone())))
+                    .ok()
+            })
+            .collect();
+        let return_type = return_type.and_then(|t| {
+            t.try_into()
+                .map_err(|e: ConversionError| errors.push(e.add_pos(pos.clone())))
+                .ok()
+        });
+        let instrs: Vec<Code> = instrs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                Code::try_from(c)
+                    .map_err(|e| errors.push(e.at_code(i)))
+                    .ok()
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let func = Self {
+            args,
+            instrs,
+            name,
+            #[cfg(feature = "position")]
+            pos,
+            return_type,
+            #[cfg(feature = "external")]
+            external,
+        };
+        #[cfg(feature = "validate")]
+        func.validate_labels().map_err(|e| vec![e])?;
+        Ok(func)
+    }
+}
+
+/// Like converting with [`TryFrom`], but reports the errors of every function of ```program``` as with [`Function::try_from_collecting`] instead of stopping at the first
+/// # Errors
+/// Returns every [`PositionalConversionError`] in the program, in source order, each tagged with the function it is in
+pub fn try_into_program_collecting(
+    AbstractProgram {
+        #[cfg(feature = "import")]
+        imports,
+        functions,
+        version,
+    }: AbstractProgram,
+) -> Result<Program, Vec<PositionalConversionError>> {
+    let mut errors = Vec::new();
+    if let Some(v) = version.filter(|&v| v > SPEC_VERSION) {
+        errors.push(PositionalConversionError::new(
+            ConversionError::UnsupportedVersion(v),
+        ));
+    }
+    let mut converted = Vec::with_capacity(functions.len());
+    for (i, f) in functions.into_iter().enumerate() {
+        match Function::try_from_collecting(f) {
+            Ok(f) => converted.push(f),
+            Err(es) => errors.extend(es.into_iter().map(|e| e.in_function(i))),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Program {
+        #[cfg(feature = "import")]
+        imports,
+        version,
+        functions: converted,
+    })
+}
+
+/// Replaces every use of one of ```aliases``` in the types of a function with the type it stands for
+#[cfg(feature = "alias")]
+fn expand_aliases(
+    aliases: &[AbstractTypeAlias],
+    args: &mut [AbstractArgument],
+    return_type: &mut Option<AbstractType>,
+    instrs: &mut [AbstractCode],
+) -> Result<(), ConversionError> {
+    fn resolve(
+        t: &AbstractType,
+        aliases: &HashMap<&str, &AbstractType>,
+        expanding: &mut Vec<String>,
+    ) -> Result<AbstractType, ConversionError> {
+        match t {
+            AbstractType::Primitive(name) => match aliases.get(name.as_str()) {
+                None => Ok(t.clone()),
+                Some(_) if expanding.contains(name) => {
+                    Err(ConversionError::RecursiveAlias(name.clone()))
+                }
+                Some(target) => {
+                    expanding.push(name.clone());
+                    let expanded = resolve(target, aliases, expanding);
+                    expanding.pop();
+                    expanded
+                }
+            },
+            AbstractType::Parameterized(p, inner) => Ok(AbstractType::Parameterized(
+                p.clone(),
+                Box::new(resolve(inner, aliases, expanding)?),
+            )),
+            #[cfg(feature = "array")]
+            AbstractType::Sized(p, inner, size) => Ok(AbstractType::Sized(
+                p.clone(),
+                Box::new(resolve(inner, aliases, expanding)?),
+                size.clone(),
+            )),
+        }
+    }
+
+    if aliases.is_empty() {
+        return Ok(());
+    }
+    let mut by_name = HashMap::new();
+    for alias in aliases {
+        if ["int", "bool", "float", "char"].contains(&alias.name.as_str())
+            || by_name
+                .insert(alias.name.as_str(), &alias.alias_type)
+                .is_some()
+        {
+            return Err(ConversionError::DuplicateAlias(alias.name.clone()));
+        }
+    }
+    let types = args
+        .iter_mut()
+        .map(|a| &mut a.arg_type)
+        .chain(return_type.as_mut())
+        .chain(instrs.iter_mut().filter_map(|c| match c {
+            AbstractCode::Instruction(
+                AbstractInstruction::Constant { const_type, .. }
+                | AbstractInstruction::Value {
+                    op_type: const_type,
+                    ..
+                },
+            ) => const_type.as_mut(),
+            _ => None,
+        }));
+    for t in types {
+        *t = resolve(t, &by_name, &mut Vec::new())?;
+    }
+    Ok(())
+}
+
 impl TryFrom<AbstractArgument> for Argument {
     type Error = ConversionError;
     fn try_from(
@@ -160,10 +644,14 @@ impl TryFrom<AbstractCode> for Code {
                 label,
                 #[cfg(feature = "position")]
                 pos,
+                #[cfg(feature = "format")]
+                format,
             } => Self::Label {
                 label,
                 #[cfg(feature = "position")]
                 pos,
+                #[cfg(feature = "format")]
+                format,
             },
             AbstractCode::Instruction(i) => Self::Instruction(i.try_into()?),
         })
@@ -181,16 +669,58 @@ impl TryFrom<AbstractInstruction> for Instruction {
                 value,
                 #[cfg(feature = "position")]
                 pos,
-            } => Self::Constant {
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            } => {
+                let const_type: Type = const_type
+                    .try_into()
+                    .map_err(|e: ConversionError| e.add_pos(pos.clone()))?;
+                // Integers too large for an `i64` and fractional numbers are only read as floats, so they end up here as well
+                let fits = match const_type {
+                    Type::Int => matches!(value, Literal::Int(_)),
+                    Type::Bool => matches!(value, Literal::Bool(_)),
+                    #[cfg(feature = "float")]
+                    Type::Float => matches!(value, Literal::Float(_) | Literal::Int(_)),
+                    #[cfg(feature = "char")]
+                    Type::Char => matches!(value, Literal::Char(_)),
+                    // No literal is a pointer or an array
+                    #[cfg(feature = "memory")]
+                    Type::Pointer(_) => false,
+                    #[cfg(feature = "array")]
+                    Type::Array(..) => false,
+                };
+                if !fits {
+                    return Err(
+                        ConversionError::InvalidConstant(dest, const_type.to_string()).add_pos(pos),
+                    );
+                }
+                Self::Constant {
+                    dest,
+                    op,
+                    const_type,
+                    value,
+                    #[cfg(feature = "position")]
+                    pos,
+                    #[cfg(feature = "format")]
+                    format,
+                    #[cfg(feature = "metadata")]
+                    extra,
+                }
+            }
+            // A constant whose value cannot be read as a literal, like a multi-character string, is only accepted as a value operation
+            AbstractInstruction::Value {
                 dest,
                 op,
-                const_type: const_type
-                    .try_into()
-                    .map_err(|e: ConversionError| e.add_pos(pos.clone()))?,
-                value,
+                op_type,
                 #[cfg(feature = "position")]
                 pos,
-            },
+                ..
+            } if op == "const" => {
+                let t = op_type.map_or_else(|| "unknown".to_string(), |t| t.to_string());
+                return Err(ConversionError::InvalidConstant(dest, t).add_pos(pos));
+            }
             AbstractInstruction::Value {
                 args,
                 dest,
@@ -200,77 +730,57 @@ impl TryFrom<AbstractInstruction> for Instruction {
                 op_type,
                 #[cfg(feature = "position")]
                 pos,
-            } => Self::Value {
-                args,
-                dest,
-                funcs,
-                labels,
-                op_type: op_type
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            } => {
+                let op_type: Type = op_type
                     .try_into()
-                    .map_err(|e: ConversionError| e.add_pos(pos.clone()))?,
-                #[cfg(feature = "position")]
-                pos: pos.clone(),
-                op: match op.as_ref() {
-                    "add" => ValueOps::Add,
-                    "mul" => ValueOps::Mul,
-                    "div" => ValueOps::Div,
-                    "eq" => ValueOps::Eq,
-                    "lt" => ValueOps::Lt,
-                    "gt" => ValueOps::Gt,
-                    "le" => ValueOps::Le,
-                    "ge" => ValueOps::Ge,
-                    "not" => ValueOps::Not,
-                    "and" => ValueOps::And,
-                    "or" => ValueOps::Or,
-                    "call" => ValueOps::Call,
-                    "id" => ValueOps::Id,
-                    "sub" => ValueOps::Sub,
-                    #[cfg(feature = "ssa")]
-                    "phi" => ValueOps::Phi,
-                    #[cfg(feature = "float")]
-                    "fadd" => ValueOps::Fadd,
-                    #[cfg(feature = "float")]
-                    "fsub" => ValueOps::Fsub,
-                    #[cfg(feature = "float")]
-                    "fmul" => ValueOps::Fmul,
-                    #[cfg(feature = "float")]
-                    "fdiv" => ValueOps::Fdiv,
-                    #[cfg(feature = "float")]
-                    "feq" => ValueOps::Feq,
-                    #[cfg(feature = "float")]
-                    "flt" => ValueOps::Flt,
-                    #[cfg(feature = "float")]
-                    "fgt" => ValueOps::Fgt,
-                    #[cfg(feature = "float")]
-                    "fle" => ValueOps::Fle,
-                    #[cfg(feature = "float")]
-                    "fge" => ValueOps::Fge,
-                    #[cfg(feature = "char")]
-                    "ceq" => ValueOps::Ceq,
-                    #[cfg(feature = "char")]
-                    "clt" => ValueOps::Clt,
-                    #[cfg(feature = "char")]
-                    "cgt" => ValueOps::Cgt,
-                    #[cfg(feature = "char")]
-                    "cle" => ValueOps::Cle,
-                    #[cfg(feature = "char")]
-                    "cge" => ValueOps::Cge,
-                    #[cfg(feature = "char")]
-                    "char2int" => ValueOps::Char2int,
-                    #[cfg(feature = "char")]
-                    "int2char" => ValueOps::Int2char,
-                    #[cfg(feature = "memory")]
-                    "alloc" => ValueOps::Alloc,
-                    #[cfg(feature = "memory")]
-                    "load" => ValueOps::Load,
-                    #[cfg(feature = "memory")]
-                    "ptradd" => ValueOps::PtrAdd,
-                    v => {
-                        return Err(ConversionError::InvalidValueOps(v.to_string()))
-                            .map_err(|e| e.add_pos(pos))
+                    .map_err(|e: ConversionError| e.add_pos(pos.clone()))?;
+                let op = op
+                    .parse()
+                    .map_err(|e: ConversionError| e.add_pos(pos.clone()))?;
+                #[cfg(feature = "ssa")]
+                if op == ValueOps::Phi && args.len() != labels.len() {
+                    return Err(
+                        ConversionError::MalformedPhi(dest, args.len(), labels.len()).add_pos(pos),
+                    );
+                }
+                if let ValueOps::Not | ValueOps::And | ValueOps::Or = op {
+                    let expected = if op == ValueOps::Not { 1 } else { 2 };
+                    if args.len() != expected {
+                        return Err(ConversionError::WrongArity {
+                            op: op.to_string(),
+                            expected,
+                            found: args.len(),
+                        }
+                        .add_pos(pos));
                     }
-                },
-            },
+                    if op_type != Type::Bool {
+                        return Err(ConversionError::WrongOpType {
+                            op: op.to_string(),
+                            expected: Type::Bool.to_string(),
+                            found: op_type.to_string(),
+                        }
+                        .add_pos(pos));
+                    }
+                }
+                Self::Value {
+                    args,
+                    dest,
+                    funcs,
+                    labels,
+                    op_type,
+                    #[cfg(feature = "position")]
+                    pos,
+                    #[cfg(feature = "format")]
+                    format,
+                    #[cfg(feature = "metadata")]
+                    extra,
+                    op,
+                }
+            }
             AbstractInstruction::Effect {
                 args,
                 funcs,
@@ -278,34 +788,21 @@ impl TryFrom<AbstractInstruction> for Instruction {
                 op,
                 #[cfg(feature = "position")]
                 pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
             } => Self::Effect {
                 args,
                 funcs,
                 labels,
                 #[cfg(feature = "position")]
                 pos: pos.clone(),
-                op: match op.as_ref() {
-                    "jmp" => EffectOps::Jump,
-                    "br" => EffectOps::Branch,
-                    "call" => EffectOps::Call,
-                    "ret" => EffectOps::Return,
-                    "print" => EffectOps::Print,
-                    "nop" => EffectOps::Nop,
-                    #[cfg(feature = "memory")]
-                    "store" => EffectOps::Store,
-                    #[cfg(feature = "memory")]
-                    "free" => EffectOps::Free,
-                    #[cfg(feature = "speculate")]
-                    "speculate" => EffectOps::Speculate,
-                    #[cfg(feature = "speculate")]
-                    "commit" => EffectOps::Commit,
-                    #[cfg(feature = "speculate")]
-                    "guard" => EffectOps::Guard,
-                    e => {
-                        return Err(ConversionError::InvalidEffectOps(e.to_string()))
-                            .map_err(|e| e.add_pos(pos))
-                    }
-                },
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+                op: op.parse().map_err(|e: ConversionError| e.add_pos(pos))?,
             },
         })
     }
@@ -334,9 +831,565 @@ impl TryFrom<AbstractType> for Type {
             AbstractType::Parameterized(t, ty) if t == "ptr" => {
                 Self::Pointer(Box::new((*ty).try_into()?))
             }
+            #[cfg(feature = "array")]
+            AbstractType::Parameterized(t, ty) if t == "array" => {
+                Self::Array(Box::new((*ty).try_into()?), None)
+            }
             AbstractType::Parameterized(t, ty) => {
                 return Err(ConversionError::InvalidParameterized(t, ty.to_string()))
             }
+            #[cfg(feature = "array")]
+            AbstractType::Sized(t, ty, size) if t == "array" => match size.parse() {
+                Ok(size) => Self::Array(Box::new((*ty).try_into()?), Some(size)),
+                Err(_) => {
+                    return Err(ConversionError::InvalidArraySize(
+                        AbstractType::Sized(t, ty, size).to_string(),
+                    ))
+                }
+            },
+            #[cfg(feature = "array")]
+            AbstractType::Sized(t, ty, size) => {
+                return Err(ConversionError::InvalidParameterized(
+                    t,
+                    format!("{ty}, {size}"),
+                ))
+            }
         })
     }
 }
+
+impl From<Program> for AbstractProgram {
+    fn from(
+        Program {
+            #[cfg(feature = "import")]
+            imports,
+            functions,
+            version,
+        }: Program,
+    ) -> Self {
+        Self {
+            #[cfg(feature = "import")]
+            imports,
+            version,
+            functions: functions.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<Function> for AbstractFunction {
+    fn from(
+        Function {
+            args,
+            instrs,
+            name,
+            return_type,
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "external")]
+            external,
+        }: Function,
+    ) -> Self {
+        Self {
+            args: args.into_iter().map(Into::into).collect(),
+            instrs: instrs.into_iter().map(Into::into).collect(),
+            name,
+            return_type: return_type.map(Into::into),
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "external")]
+            external,
+            #[cfg(feature = "alias")]
+            aliases: Vec::new(),
+        }
+    }
+}
+
+impl From<Argument> for AbstractArgument {
+    fn from(Argument { name, arg_type }: Argument) -> Self {
+        Self {
+            name,
+            arg_type: arg_type.into(),
+        }
+    }
+}
+
+impl From<Code> for AbstractCode {
+    fn from(c: Code) -> Self {
+        match c {
+            Code::Label {
+                label,
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+            } => Self::Label {
+                label,
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+            },
+            Code::Instruction(i) => Self::Instruction(i.into()),
+        }
+    }
+}
+
+impl From<Instruction> for AbstractInstruction {
+    fn from(i: Instruction) -> Self {
+        match i {
+            Instruction::Constant {
+                dest,
+                op,
+                const_type,
+                value,
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            } => Self::Constant {
+                dest,
+                op,
+                const_type: Some(const_type.into()),
+                value,
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            },
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op,
+                op_type,
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            } => Self::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op: op.to_string(),
+                op_type: Some(op_type.into()),
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            },
+            Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            } => Self::Effect {
+                args,
+                funcs,
+                labels,
+                op: op.to_string(),
+                #[cfg(feature = "position")]
+                pos,
+                #[cfg(feature = "format")]
+                format,
+                #[cfg(feature = "metadata")]
+                extra,
+            },
+        }
+    }
+}
+
+impl From<Type> for AbstractType {
+    fn from(t: Type) -> Self {
+        match t {
+            #[cfg(feature = "memory")]
+            Type::Pointer(t) => Self::Parameterized("ptr".to_string(), Box::new((*t).into())),
+            #[cfg(feature = "array")]
+            Type::Array(t, None) => Self::Parameterized("array".to_string(), Box::new((*t).into())),
+            #[cfg(feature = "array")]
+            Type::Array(t, Some(size)) => {
+                Self::Sized("array".to_string(), Box::new((*t).into()), size.to_string())
+            }
+            t => Self::Primitive(t.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        supported_effect_ops, supported_value_ops, try_into_program_collecting, ConversionError,
+        PositionalConversionError,
+    };
+    use crate::conversion::LoadError;
+    use crate::text::{parse_and_convert, parse_program};
+    use crate::{
+        load_program_from_read, AbstractType, Code, EffectOps, Function, Instruction, Literal,
+        Position, Program, Type, ValueOps,
+    };
+
+    /// Loads a `main` whose only code is the constant ```dest: ty = const value```, with ```ty``` and ```value``` written as JSON
+    fn load_const(ty: &str, value: &str) -> Result<Program, LoadError> {
+        let json = format!(
+            r#"{{"functions": [{{"name": "main", "instrs": [{{"op": "const", "dest": "x", "type": {ty}, "value": {value}}}]}}]}}"#
+        );
+        load_program_from_read(json.as_bytes())
+    }
+
+    fn assert_invalid(ty: &str, value: &str) {
+        match load_const(ty, value) {
+            Err(LoadError::Conversion(e)) => {
+                assert!(
+                    matches!(e.e, ConversionError::InvalidConstant(..)),
+                    "`{value}` as {ty}: {e}"
+                );
+            }
+            other => panic!("`{value}` as {ty} should be an invalid constant, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn int_and_bool_constants() {
+        load_const(r#""int""#, "5").unwrap();
+        load_const(r#""bool""#, "true").unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "float")]
+    fn out_of_range_int() {
+        assert_invalid(r#""int""#, "9223372036854775808");
+    }
+
+    #[test]
+    #[cfg(feature = "float")]
+    fn fractional_int() {
+        assert_invalid(r#""int""#, "1.5");
+    }
+
+    #[test]
+    fn numeric_bool() {
+        assert_invalid(r#""bool""#, "1");
+    }
+
+    #[test]
+    #[cfg(feature = "char")]
+    fn char_constant() {
+        let program = load_const(r#""char""#, r#""a""#).unwrap();
+        assert!(matches!(
+            &program.functions[0].instrs[0],
+            Code::Instruction(Instruction::Constant {
+                value: Literal::Char('a'),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "char")]
+    fn multi_character_char() {
+        assert_invalid(r#""char""#, r#""ab""#);
+    }
+
+    #[test]
+    #[cfg(feature = "char")]
+    fn mismatched_char() {
+        assert_invalid(r#""char""#, "5");
+    }
+
+    #[test]
+    #[cfg(feature = "float")]
+    fn mismatched_float() {
+        assert_invalid(r#""float""#, "true");
+        load_const(r#""float""#, "5").unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn pointer_constant() {
+        assert_invalid(r#"{"ptr": "int"}"#, "5");
+    }
+
+    #[test]
+    fn supported_ops_round_trip() {
+        for name in supported_value_ops() {
+            assert_eq!(name.parse::<ValueOps>().unwrap().to_string(), *name);
+        }
+        for name in supported_effect_ops() {
+            assert_eq!(name.parse::<EffectOps>().unwrap().to_string(), *name);
+        }
+        assert!("fadd2".parse::<ValueOps>().is_err());
+    }
+
+    #[test]
+    fn collect_every_bad_instruction() {
+        let mut program = parse_program(
+            "@main(a: int) {
+  x: int = frob a;
+  y: int = add a a;
+  zap y;
+}",
+        )
+        .unwrap();
+        let errors = Function::try_from_collecting(program.functions.remove(0)).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0].e, ConversionError::InvalidValueOps(op) if op == "frob"));
+        assert!(matches!(&errors[1].e, ConversionError::InvalidEffectOps(op) if op == "zap"));
+        #[cfg(feature = "position")]
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| e.pos.as_ref().unwrap().pos.row)
+                .collect::<Vec<_>>(),
+            [2, 4]
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "alias", feature = "memory"))]
+    fn local_type_alias() {
+        let program = parse_and_convert(
+            "@main(n: int): intptr {
+  type intptr = ptr<int>;
+  p: intptr = alloc n;
+  ret p;
+}",
+        )
+        .unwrap();
+        let ptr = Type::Pointer(Box::new(Type::Int));
+        let main = &program.functions[0];
+        assert_eq!(main.return_type, Some(ptr.clone()));
+        assert!(matches!(
+            &main.instrs[0],
+            Code::Instruction(Instruction::Value { op_type, .. }) if *op_type == ptr
+        ));
+        let recursive = parse_program("@main {\n  type a = ptr<a>;\n  x: a = const 1;\n}").unwrap();
+        assert!(matches!(
+            Program::try_from(recursive).map_err(|e| e.e),
+            Err(ConversionError::RecursiveAlias(a)) if a == "a"
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "import")]
+    fn aliased_imports() {
+        let json = r#"{"imports": [{"path": "lib.json", "functions": [{"name": "sqrt", "alias": "root"}, {"name": "abs", "alias": "magnitude"}]}], "functions": [{"name": "main", "instrs": []}]}"#;
+        let program = load_program_from_read(json.as_bytes()).unwrap();
+        let import = &program.imports[0];
+        assert_eq!(import.path, std::path::Path::new("lib.json"));
+        let names: Vec<_> = import
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.local_name()))
+            .collect();
+        assert_eq!(names, [("sqrt", "root"), ("abs", "magnitude")]);
+        let written = serde_json::to_value(&program).unwrap();
+        assert_eq!(written["imports"][0]["functions"][1]["alias"], "magnitude");
+        let plain = load_program_from_read(&br#"{"functions": []}"#[..]).unwrap();
+        assert!(plain.imports.is_empty());
+        assert!(serde_json::to_value(&plain)
+            .unwrap()
+            .get("imports")
+            .is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "position")]
+    fn snippet_under_error() {
+        let source = "@main {\n  x: int = frob;\n}";
+        let error = |pos| {
+            let mut e = PositionalConversionError::new(ConversionError::InvalidValueOps(
+                "frob".to_string(),
+            ));
+            e.pos = Some(pos);
+            e
+        };
+        let message = ConversionError::InvalidValueOps("frob".to_string()).to_string();
+        assert_eq!(
+            error(Position::new(2, 12)).to_string(),
+            format!("Line 2, Column 12: {message}")
+        );
+        assert_eq!(
+            error(Position::new(2, 12).with_end(2, 16))
+                .with_source(source)
+                .to_string(),
+            format!("2:12-2:16: {message}\n  |\n2 |   x: int = frob;\n  |            ^^^^")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "validate")]
+    fn duplicate_and_undefined_labels() {
+        let convert = |src: &str| {
+            Program::try_from(parse_program(src).unwrap())
+                .map(drop)
+                .map_err(|e| (e.e, e.code))
+        };
+        assert!(matches!(
+            convert("@main {\n.loop:\n  jmp .loop;\n.loop:\n}"),
+            Err((ConversionError::DuplicateLabel(l), Some(2))) if l == "loop"
+        ));
+        assert!(matches!(
+            convert("@main {\n.loop:\n  jmp .missing;\n}"),
+            Err((ConversionError::UndefinedLabel(l), Some(1))) if l == "missing"
+        ));
+        assert!(convert("@main {\n.loop:\n  jmp .loop;\n}").is_ok());
+    }
+
+    #[test]
+    fn byte_offset_of_bad_op() {
+        let source = "@main {\n  x: int = frob;\n}";
+        let Err(crate::text::TextError::Conversion(e)) = parse_and_convert(source) else {
+            panic!("`frob` should not convert");
+        };
+        assert_eq!(e.byte_offset(), Some(10));
+        let message = ConversionError::InvalidValueOps("frob".to_string()).to_string();
+        let error = || {
+            PositionalConversionError::new(ConversionError::InvalidValueOps("frob".to_string()))
+                .with_byte_offset(10)
+        };
+        assert_eq!(error().to_string(), format!("Byte 10: {message}"));
+        assert_eq!(
+            error().with_source(source).to_string(),
+            format!("Line 2, Column 3: {message}\n  |\n2 |   x: int = frob;\n  |   ^")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn nested_pointer_types() {
+        let abstract_type = |text: &str| -> AbstractType { serde_json::from_str(text).unwrap() };
+        for (json, text) in [
+            (r#"{"ptr": "int"}"#, "ptr<int>"),
+            (r#"{"ptr": {"ptr": "bool"}}"#, "ptr<ptr<bool>>"),
+        ] {
+            let parsed = abstract_type(json);
+            assert_eq!(parsed.to_string(), text);
+            let ty = Type::try_from(parsed).unwrap();
+            assert_eq!(ty.to_string(), text);
+            assert_eq!(
+                serde_json::to_value(&ty).unwrap(),
+                serde_json::from_str::<serde_json::Value>(json).unwrap()
+            );
+        }
+        assert_eq!(
+            Type::try_from(abstract_type(r#"{"box": {"ptr": "int"}}"#))
+                .unwrap_err()
+                .to_string(),
+            "Expected a parameterized type like ptr, found box<ptr<int>>"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ssa")]
+    fn phi_arguments_and_labels() {
+        let convert = |phi: &str| {
+            Program::try_from(
+                parse_program(&format!(
+                    "@main(a: int, b: int) {{\n.l:\n  jmp .r;\n.r:\n  x: int = {phi};\n}}"
+                ))
+                .unwrap(),
+            )
+            .map_err(|e| e.e)
+        };
+        assert!(convert("phi a b .l .r").is_ok());
+        assert!(convert("add a b").is_ok());
+        assert!(matches!(
+            convert("phi a b .l"),
+            Err(ConversionError::MalformedPhi(dest, 2, 1)) if dest == "x"
+        ));
+    }
+
+    #[test]
+    fn logical_op_arity_and_type() {
+        let convert = |instr: &str| {
+            Program::try_from(
+                parse_program(&format!("@main(a: bool, b: bool) {{\n  {instr};\n}}")).unwrap(),
+            )
+            .map_err(|e| e.e)
+        };
+        assert!(convert("x: bool = not a").is_ok());
+        assert!(convert("x: bool = and a b").is_ok());
+        assert!(matches!(
+            convert("x: bool = not a b"),
+            Err(ConversionError::WrongArity { op, expected: 1, found: 2 }) if op == "not"
+        ));
+        assert!(matches!(
+            convert("x: bool = and a"),
+            Err(ConversionError::WrongArity { op, expected: 2, found: 1 }) if op == "and"
+        ));
+        assert!(matches!(
+            convert("x: int = or a b"),
+            Err(ConversionError::WrongOpType { op, .. }) if op == "or"
+        ));
+    }
+
+    #[test]
+    fn collect_errors_across_functions() {
+        let program = parse_program(
+            "@f {
+  x: int = frob;
+}
+@g {
+  ret;
+}
+@main(a: wat) {
+  print a;
+}",
+        )
+        .unwrap();
+        let errors = try_into_program_collecting(program).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0].e, ConversionError::InvalidValueOps(op) if op == "frob"));
+        assert!(matches!(&errors[1].e, ConversionError::InvalidPrimitive(t) if t == "wat"));
+        assert_eq!(
+            errors.iter().map(|e| e.function).collect::<Vec<_>>(),
+            [Some(0), Some(2)]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "array")]
+    fn array_types() {
+        let convert =
+            |json: &str| Type::try_from(serde_json::from_str::<AbstractType>(json).unwrap());
+        for (json, ty, text) in [
+            (
+                r#"{"array": "int"}"#,
+                Type::Array(Box::new(Type::Int), None),
+                "array<int>",
+            ),
+            (
+                r#"{"array": ["int", 8]}"#,
+                Type::Array(Box::new(Type::Int), Some(8)),
+                "array<int, 8>",
+            ),
+        ] {
+            let converted = convert(json).unwrap();
+            assert_eq!(converted, ty);
+            assert_eq!(converted.to_string(), text);
+            assert_eq!(
+                serde_json::to_value(&converted).unwrap(),
+                serde_json::from_str::<serde_json::Value>(json).unwrap()
+            );
+        }
+        assert!(matches!(
+            convert(r#"{"array": ["int", -1]}"#),
+            Err(ConversionError::InvalidArraySize(size)) if size == "array<int, -1>"
+        ));
+    }
+}
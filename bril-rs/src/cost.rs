@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::cfg::build_cfg;
+use crate::dominators::dominators;
+use crate::interp::Profile;
+use crate::loops::loop_forest;
+use crate::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+
+/// How much each kind of [Instruction] costs on an abstract machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CostModel {
+    /// `const`
+    pub constant: u32,
+    /// Every value operation other than `call` and memory operations, including `id` and `phi`
+    pub arithmetic: u32,
+    /// `alloc`, `load`, `store`, and `free`
+    pub memory: u32,
+    /// `call`, whether or not it produces a value, not counting the cost of the callee
+    pub call: u32,
+    /// `jmp`, `br`, and `ret`
+    pub control: u32,
+    /// `print`, `nop`, and the speculation operations
+    pub other: u32,
+    /// What the cost of an instruction is multiplied by for each loop enclosing it in [`Function::estimated_cost`]. 1 leaves loops unweighted
+    pub loop_weight: u32,
+}
+
+impl Default for CostModel {
+    /// Memory operations cost a few times as much as arithmetic and calls much more, and loops are not weighted
+    fn default() -> Self {
+        Self {
+            constant: 1,
+            arithmetic: 1,
+            memory: 4,
+            call: 10,
+            control: 1,
+            other: 1,
+            loop_weight: 1,
+        }
+    }
+}
+
+impl Instruction {
+    /// The cost of executing this instruction once under ```model```
+    #[must_use]
+    pub const fn cost(&self, model: &CostModel) -> u32 {
+        match self {
+            Self::Constant { .. } => model.constant,
+            Self::Value {
+                op: ValueOps::Call, ..
+            }
+            | Self::Effect {
+                op: EffectOps::Call,
+                ..
+            } => model.call,
+            #[cfg(feature = "memory")]
+            Self::Value {
+                op: ValueOps::Alloc | ValueOps::Load,
+                ..
+            }
+            | Self::Effect {
+                op: EffectOps::Store | EffectOps::Free,
+                ..
+            } => model.memory,
+            Self::Value { .. } => model.arithmetic,
+            Self::Effect {
+                op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+                ..
+            } => model.control,
+            Self::Effect { .. } => model.other,
+        }
+    }
+}
+
+impl Function {
+    /// The sum of the [`Instruction::cost`] of every instruction of this function, with each multiplied by [`CostModel::loop_weight`] once for every natural loop it is in
+    ///
+    /// This is a static estimate: each loop is assumed to run [`CostModel::loop_weight`] times per entry, every branch arm is counted, and callees are not included. The sum saturates rather than overflowing.
+    #[must_use]
+    pub fn estimated_cost(&self, model: &CostModel) -> u64 {
+        let cfg = build_cfg(self);
+        let forest = loop_forest(&cfg, &dominators(&cfg));
+        (0..cfg.len())
+            .map(|b| {
+                let depth = forest
+                    .innermost_loop_of(b)
+                    .map_or(0, |l| forest.depth(l) + 1);
+                let weight = u32::try_from(depth)
+                    .map_or(u64::MAX, |d| u64::from(model.loop_weight).saturating_pow(d));
+                self.instrs[cfg.block_range(b)]
+                    .iter()
+                    .filter_map(|c| match c {
+                        Code::Instruction(instr) => Some(u64::from(instr.cost(model))),
+                        Code::Label { .. } => None,
+                    })
+                    .fold(0, |sum: u64, c| {
+                        sum.saturating_add(c.saturating_mul(weight))
+                    })
+            })
+            .fold(0, u64::saturating_add)
+    }
+}
+
+/// The cost of the instructions a run of a [Program] executed, split by function
+///
+/// Unlike [`Function::estimated_cost`], this depends only on what actually ran, so it is a deterministic measure of how fast a program is on some input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CostReport {
+    /// The cost of each function over the whole run, not counting the functions it calls. Functions which never ran are left out
+    pub functions: HashMap<String, u64>,
+}
+
+impl CostReport {
+    /// Prices each instruction counted by ```profile``` with ```model```
+    ///
+    /// [`CostModel::loop_weight`] is not used, since the counts already say how often each loop ran. Sums saturate rather than overflowing.
+    #[must_use]
+    pub fn from_profile(program: &Program, profile: &Profile, model: &CostModel) -> Self {
+        let index = program.function_index();
+        let mut functions: HashMap<String, u64> = HashMap::new();
+        for ((func, i), &count) in &profile.counts {
+            let code = index
+                .get(func.as_str())
+                .and_then(|&f| program.functions[f].instrs.get(*i));
+            if let Some(Code::Instruction(instr)) = code {
+                let cost = functions.entry(func.clone()).or_default();
+                *cost = cost.saturating_add(count.saturating_mul(u64::from(instr.cost(model))));
+            }
+        }
+        Self { functions }
+    }
+
+    /// The cost of ```func```, or 0 if it never ran
+    #[must_use]
+    pub fn of(&self, func: &str) -> u64 {
+        self.functions.get(func).copied().unwrap_or(0)
+    }
+
+    /// The cost of the whole run
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.functions
+            .values()
+            .fold(0, |sum, &c| sum.saturating_add(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CostModel;
+    use crate::text::parse_and_convert;
+
+    #[test]
+    fn loops_weighted_by_depth() {
+        let func = parse_and_convert(
+            "@main(c: bool) {
+  x: int = const 1;
+.outer:
+  y: int = add x x;
+.inner:
+  z: int = mul y y;
+  br c .inner .next;
+.next:
+  br c .outer .exit;
+.exit:
+  print x;
+}",
+        )
+        .unwrap()
+        .functions
+        .remove(0);
+        let model = CostModel {
+            loop_weight: 10,
+            ..CostModel::default()
+        };
+        // One instruction outside any loop at each end, two in the outer loop only, and two in both loops
+        assert_eq!(func.estimated_cost(&model), 1 + 2 * 10 + 2 * 100 + 1);
+        assert_eq!(func.estimated_cost(&CostModel::default()), 6);
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cfg::{build_cfg, BlockId, Cfg};
+use crate::dominators::post_dominators;
+#[cfg(feature = "memory")]
+use crate::EffectOps;
+#[cfg(any(feature = "ssa", feature = "memory"))]
+use crate::ValueOps;
+use crate::{Code, Function, Instruction};
+
+/// Records, for every block of a [`Cfg`], the blocks whose branch decides whether it executes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ControlDependenceGraph {
+    deps: Vec<Vec<BlockId>>,
+    dependents: Vec<Vec<BlockId>>,
+}
+
+impl ControlDependenceGraph {
+    /// The blocks that ```block``` is control-dependent on
+    #[must_use]
+    pub fn control_deps(&self, block: BlockId) -> &[BlockId] {
+        &self.deps[block]
+    }
+
+    /// The blocks that are control-dependent on ```block```
+    #[must_use]
+    pub fn dependents(&self, block: BlockId) -> &[BlockId] {
+        &self.dependents[block]
+    }
+}
+
+/// Builds the [`ControlDependenceGraph`] of ```cfg```
+///
+/// A block `y` is control-dependent on `x` when `x` is in the post-dominance frontier of `y`, that is `x` has one successor which leads to `y` on every path and another which may avoid it.
+/// Blocks which execute whenever the function is entered, and blocks which cannot reach an exit, depend on nothing.
+#[must_use]
+pub fn control_dependence(cfg: &Cfg) -> ControlDependenceGraph {
+    let pdom = post_dominators(cfg);
+    let deps: Vec<Vec<BlockId>> = (0..cfg.len())
+        .map(|b| pdom.dominance_frontier(b).to_vec())
+        .collect();
+    let mut dependents = vec![Vec::new(); cfg.len()];
+    for (b, ds) in deps.iter().enumerate() {
+        for &d in ds {
+            dependents[d].push(b);
+        }
+    }
+    ControlDependenceGraph { deps, dependents }
+}
+
+/// The index of an instruction within [`Function::instrs`]
+pub type InstrId = usize;
+
+/// For every instruction, the definitions of each of its arguments that may reach it
+/// Arguments which may come from the function's parameters have no corresponding definition.
+fn reaching_definitions(func: &Function, cfg: &Cfg) -> Vec<Vec<(String, Vec<InstrId>)>> {
+    let defs_of = |block: BlockId| {
+        cfg.blocks[block]
+            .range
+            .clone()
+            .filter_map(|i| match &func.instrs[i] {
+                Code::Instruction(instr) => instr.dest().map(|d| (d, i)),
+                Code::Label { .. } => None,
+            })
+    };
+
+    let mut out: Vec<HashMap<&str, HashSet<InstrId>>> = vec![HashMap::new(); cfg.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 0..cfg.len() {
+            let mut state: HashMap<&str, HashSet<InstrId>> = HashMap::new();
+            for &p in cfg.predecessors(b) {
+                for (var, ds) in &out[p] {
+                    state.entry(var).or_default().extend(ds);
+                }
+            }
+            for (d, i) in defs_of(b) {
+                state.insert(d, HashSet::from([i]));
+            }
+            if state != out[b] {
+                out[b] = state;
+                changed = true;
+            }
+        }
+    }
+
+    let mut result = vec![Vec::new(); func.instrs.len()];
+    for b in 0..cfg.len() {
+        let mut state: HashMap<&str, HashSet<InstrId>> = HashMap::new();
+        for &p in cfg.predecessors(b) {
+            for (var, ds) in &out[p] {
+                state.entry(var).or_default().extend(ds);
+            }
+        }
+        for i in cfg.block_range(b) {
+            if let Code::Instruction(instr) = &func.instrs[i] {
+                result[i] = instr
+                    .args()
+                    .iter()
+                    .map(|a| {
+                        let mut ds: Vec<InstrId> = state
+                            .get(a.as_str())
+                            .map(|ds| ds.iter().copied().collect())
+                            .unwrap_or_default();
+                        ds.sort_unstable();
+                        (a.clone(), ds)
+                    })
+                    .collect();
+                if let Some(d) = instr.dest() {
+                    state.insert(d, HashSet::from([i]));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Links every definition in a [Function] to the instructions which may use its result, and every use back to the definitions which may reach it
+///
+/// Built by [`Function::def_use`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefUseGraph {
+    reaching: Vec<Vec<(String, Vec<InstrId>)>>,
+    uses: Vec<Vec<InstrId>>,
+}
+
+impl DefUseGraph {
+    /// The instructions which may read the result of ```def```, in ascending order
+    #[must_use]
+    pub fn uses(&self, def: InstrId) -> &[InstrId] {
+        &self.uses[def]
+    }
+
+    /// The definitions which may reach argument ```var``` of ```instr```, in ascending order
+    ///
+    /// This is empty when ```var``` is not an argument of ```instr``` or when its value can only come from a function parameter.
+    #[must_use]
+    pub fn defs_of(&self, instr: InstrId, var: &str) -> &[InstrId] {
+        self.reaching[instr]
+            .iter()
+            .find(|(a, _)| a == var)
+            .map_or(&[], |(_, ds)| ds)
+    }
+
+    /// Every definition which may reach any argument of ```instr```
+    pub fn defs(&self, instr: InstrId) -> impl Iterator<Item = InstrId> + '_ {
+        self.reaching[instr]
+            .iter()
+            .flat_map(|(_, ds)| ds.iter().copied())
+    }
+}
+
+impl Function {
+    /// Builds the [`DefUseGraph`] of this function from reaching definitions
+    ///
+    /// Within a block and in SSA form this is precise. Across blocks in non-SSA code a use is linked to every definition that reaches it along some path.
+    #[must_use]
+    pub fn def_use(&self) -> DefUseGraph {
+        let reaching = reaching_definitions(self, &build_cfg(self));
+        let mut uses = vec![Vec::new(); self.instrs.len()];
+        for (i, args) in reaching.iter().enumerate() {
+            for &d in args.iter().flat_map(|(_, ds)| ds) {
+                if !uses[d].contains(&i) {
+                    uses[d].push(i);
+                }
+            }
+        }
+        DefUseGraph { reaching, uses }
+    }
+}
+
+/// Computes the backward slice of ```func``` from ```seed```: every instruction that could affect the value computed by ```seed``` or whether it executes
+///
+/// The slice follows reaching definitions of each argument and the branches that the containing block is control-dependent on, transitively. It always contains ```seed``` itself.
+/// Under the `memory` feature values flowing through the heap are not tracked precisely, so a `load` conservatively depends on every `store` and `call` in the function.
+#[must_use]
+pub fn backward_slice(func: &Function, seed: InstrId) -> HashSet<InstrId> {
+    let cfg = build_cfg(func);
+    let cdg = control_dependence(&cfg);
+    let def_use = func.def_use();
+
+    let terminator = |block: BlockId| {
+        cfg.block_range(block)
+            .rev()
+            .find(|&i| matches!(func.instrs[i], Code::Instruction(_)))
+    };
+
+    let mut slice = HashSet::new();
+    let mut worklist = vec![seed];
+    while let Some(i) = worklist.pop() {
+        if !slice.insert(i) {
+            continue;
+        }
+        if let Code::Label { .. } = func.instrs[i] {
+            continue;
+        }
+        worklist.extend(def_use.defs(i));
+        worklist.extend(
+            cdg.control_deps(cfg.block_of(i))
+                .iter()
+                .filter_map(|&d| terminator(d)),
+        );
+
+        #[cfg(feature = "ssa")]
+        if let Code::Instruction(Instruction::Value {
+            op: ValueOps::Phi, ..
+        }) = &func.instrs[i]
+        {
+            for &p in cfg.predecessors(cfg.block_of(i)) {
+                worklist.extend(cdg.control_deps(p).iter().filter_map(|&d| terminator(d)));
+            }
+        }
+
+        #[cfg(feature = "memory")]
+        if let Code::Instruction(Instruction::Value {
+            op: ValueOps::Load, ..
+        }) = &func.instrs[i]
+        {
+            worklist.extend(func.instrs.iter().enumerate().filter_map(|(j, c)| {
+                matches!(
+                    c,
+                    Code::Instruction(
+                        Instruction::Effect {
+                            op: EffectOps::Store | EffectOps::Call,
+                            ..
+                        } | Instruction::Value {
+                            op: ValueOps::Call,
+                            ..
+                        }
+                    )
+                )
+                .then_some(j)
+            }));
+        }
+    }
+    slice
+}
+
+/// The total latency of the longest chain of instructions in ```block``` where each one uses a result of the one before, with each instruction costing ```latency```
+///
+/// Only dependencies inside ```block``` count: arguments defined before it are available from the start, and labels are ignored. An instruction depends on the latest earlier definition of each of its arguments.
+/// Under the `memory` feature a `load` also depends on every earlier `store` and `call`, like in [`backward_slice`].
+/// This is a lower bound on how long ```block``` takes to run with unlimited parallelism, so comparing it across schedules of the same block shows which exposes more parallelism.
+pub fn critical_path_length(block: &[Code], latency: impl Fn(&Instruction) -> usize) -> usize {
+    // The length of the longest chain ending at each instruction
+    let mut finish: Vec<usize> = Vec::with_capacity(block.len());
+    let mut last_def: HashMap<&str, usize> = HashMap::new();
+    #[cfg(feature = "memory")]
+    let mut writes: Vec<usize> = Vec::new();
+    for (i, code) in block.iter().enumerate() {
+        let Code::Instruction(instr) = code else {
+            finish.push(0);
+            continue;
+        };
+        #[allow(unused_mut)]
+        let mut start = instr
+            .args()
+            .iter()
+            .filter_map(|a| last_def.get(a.as_str()))
+            .map(|&d| finish[d])
+            .max()
+            .unwrap_or(0);
+        #[cfg(feature = "memory")]
+        if let Instruction::Value {
+            op: ValueOps::Load, ..
+        } = instr
+        {
+            start = start.max(writes.iter().map(|&w| finish[w]).max().unwrap_or(0));
+        } else if matches!(
+            instr,
+            Instruction::Effect {
+                op: EffectOps::Store | EffectOps::Call,
+                ..
+            } | Instruction::Value {
+                op: ValueOps::Call,
+                ..
+            }
+        ) {
+            writes.push(i);
+        }
+        finish.push(start + latency(instr));
+        if let Some(dest) = instr.dest() {
+            last_def.insert(dest, i);
+        }
+    }
+    finish.into_iter().max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{backward_slice, control_dependence, critical_path_length};
+    use crate::cfg::build_cfg;
+    use crate::text::parse_and_convert;
+    use crate::{Instruction, ValueOps};
+
+    #[test]
+    fn diamond_and_loop() {
+        let program = parse_and_convert(
+            "@main(c: bool, n: int) {
+.entry:
+  br c .then .else;
+.then:
+  x: int = const 1;
+  jmp .join;
+.else:
+  x: int = const 2;
+  jmp .join;
+.join:
+  i: int = const 0;
+.loop:
+  one: int = const 1;
+  i: int = add i one;
+  done: bool = ge i n;
+  br done .exit .loop;
+.exit:
+  print x;
+}",
+        )
+        .unwrap();
+        let cfg = build_cfg(&program.functions[0]);
+        let cdg = control_dependence(&cfg);
+        let block = |label| cfg.block_by_label(label).unwrap();
+
+        let entry = block("entry");
+        assert_eq!(cdg.control_deps(block("then")), [entry]);
+        assert_eq!(cdg.control_deps(block("else")), [entry]);
+        assert_eq!(cdg.dependents(entry), [block("then"), block("else")]);
+        // The loop body runs again only when its own branch goes back
+        assert_eq!(cdg.control_deps(block("loop")), [block("loop")]);
+        for always in ["entry", "join", "exit"] {
+            assert!(cdg.control_deps(block(always)).is_empty(), "{always}");
+        }
+    }
+
+    #[test]
+    fn slice_of_print() {
+        let program = parse_and_convert(
+            "@main(c: bool) {
+  a: int = const 1;
+  b: int = const 2;
+  unused: int = add a b;
+  br c .t .f;
+.t:
+  y: int = id a;
+  jmp .end;
+.f:
+  y: int = id b;
+.end:
+  print y;
+}",
+        )
+        .unwrap();
+        // The `print`, both of the `id`s it may read, their constants, and the branch choosing between them
+        assert_eq!(
+            backward_slice(&program.functions[0], 10),
+            HashSet::from([10, 5, 8, 0, 1, 3])
+        );
+    }
+
+    #[test]
+    fn def_use_chain() {
+        let program = parse_and_convert(
+            "@main(a: int, c: bool) {
+  one: int = const 1;
+  b: int = add a one;
+  d: int = add b b;
+  br c .l .r;
+.l:
+  d: int = const 0;
+.r:
+  print d;
+}",
+        )
+        .unwrap();
+        let graph = program.functions[0].def_use();
+        assert_eq!(graph.uses(0), [1]);
+        assert_eq!(graph.uses(1), [2]);
+        assert_eq!(graph.defs_of(2, "b"), [1]);
+        assert_eq!(graph.defs_of(1, "a"), Vec::<usize>::new());
+        assert_eq!(graph.defs_of(1, "b"), Vec::<usize>::new());
+        // Both definitions of `d` reach the `print` along some path
+        assert_eq!(graph.defs_of(7, "d"), [2, 5]);
+        assert_eq!(graph.uses(5), [7]);
+        assert_eq!(graph.defs(1).collect::<Vec<_>>(), [0]);
+    }
+
+    #[test]
+    fn serial_chain_versus_independent_ops() {
+        let block_of = |src: &str| parse_and_convert(src).unwrap().functions.remove(0).instrs;
+        let unit = |_: &Instruction| 1;
+        let serial = block_of(
+            "@main(a: int) {
+  b: int = add a a;
+  c: int = add b b;
+  d: int = add c c;
+}",
+        );
+        assert_eq!(critical_path_length(&serial, unit), 3);
+        let parallel = block_of(
+            "@main(a: int) {
+  b: int = add a a;
+  c: int = mul a a;
+  d: int = sub a a;
+}",
+        );
+        assert_eq!(critical_path_length(&parallel, unit), 1);
+        // With `mul` costing more, the longest chain is the one through it
+        let latency = |instr: &Instruction| match instr {
+            Instruction::Value {
+                op: ValueOps::Mul, ..
+            } => 4,
+            _ => 1,
+        };
+        assert_eq!(critical_path_length(&parallel, latency), 4);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::cfg::{BlockId, Cfg};
+
+/// Dominance information over the blocks of a [`Cfg`]
+///
+/// Blocks which are unreachable from the root are excluded: they have no immediate dominator, dominate nothing, and have an empty frontier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dominators {
+    root: Option<BlockId>,
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+    frontier: Vec<Vec<BlockId>>,
+    reachable: Vec<bool>,
+}
+
+impl Dominators {
+    /// The immediate dominator of ```block```, which is [None] for the root and for unreachable blocks
+    #[must_use]
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block]
+    }
+
+    /// Whether ```a``` dominates ```b```. Every reachable block dominates itself
+    #[must_use]
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if !self.reachable[a] || !self.reachable[b] {
+            return false;
+        }
+        let mut runner = Some(b);
+        while let Some(r) = runner {
+            if r == a {
+                return true;
+            }
+            runner = self.idom[r];
+        }
+        false
+    }
+
+    /// Whether ```a``` dominates ```b``` and they are not the same block
+    #[must_use]
+    pub fn strictly_dominates(&self, a: BlockId, b: BlockId) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
+    /// The dominance frontier of ```block```
+    #[must_use]
+    pub fn dominance_frontier(&self, block: BlockId) -> &[BlockId] {
+        &self.frontier[block]
+    }
+
+    /// The iterated dominance frontier of ```blocks```, in ascending order
+    ///
+    /// These are the blocks where SSA construction places a phi for a variable assigned in each of ```blocks```.
+    #[must_use]
+    pub fn iterated_dominance_frontier(&self, blocks: &[BlockId]) -> Vec<BlockId> {
+        let mut in_result = vec![false; self.frontier.len()];
+        let mut worklist = blocks.to_vec();
+        while let Some(b) = worklist.pop() {
+            for &f in &self.frontier[b] {
+                if !in_result[f] {
+                    in_result[f] = true;
+                    worklist.push(f);
+                }
+            }
+        }
+        (0..in_result.len()).filter(|&b| in_result[b]).collect()
+    }
+
+    /// The blocks immediately dominated by ```block```, which are its children in the dominator tree
+    #[must_use]
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block]
+    }
+
+    /// The root of the dominator tree. This is [None] for post-dominators, whose root is a virtual exit block
+    #[must_use]
+    pub const fn root(&self) -> Option<BlockId> {
+        self.root
+    }
+
+    /// Whether ```block``` can be reached from the root
+    #[must_use]
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.reachable[block]
+    }
+}
+
+/// Computes [Dominators] for ```cfg``` using the iterative algorithm of Cooper, Harvey, and Kennedy
+#[must_use]
+pub fn dominators(cfg: &Cfg) -> Dominators {
+    let preds: Vec<&[BlockId]> = (0..cfg.len()).map(|b| cfg.predecessors(b)).collect();
+    let succs: Vec<&[BlockId]> = (0..cfg.len()).map(|b| cfg.successors(b)).collect();
+    compute(cfg.entry, &preds, &succs, cfg.len())
+}
+
+/// Computes post-dominators for ```cfg```, which are the dominators of the reversed graph
+///
+/// All exits are joined by a virtual exit block which is the root of the tree, so a block whose immediate post-dominator is the exit reports [None].
+/// Blocks that can never reach an exit, such as the body of an infinite loop, are treated as unreachable.
+#[must_use]
+pub fn post_dominators(cfg: &Cfg) -> Dominators {
+    let exit = cfg.len();
+    let exits: Vec<BlockId> = cfg.exits().collect();
+    let mut preds: Vec<&[BlockId]> = (0..cfg.len()).map(|b| cfg.successors(b)).collect();
+    let mut succs: Vec<&[BlockId]> = (0..cfg.len()).map(|b| cfg.predecessors(b)).collect();
+    let to_exit = [exit];
+    for &e in &exits {
+        preds[e] = &to_exit;
+    }
+    preds.push(&[]);
+    succs.push(&exits);
+
+    let mut doms = compute(exit, &preds, &succs, exit);
+    doms.root = None;
+    doms.reachable.truncate(exit);
+    doms.idom.truncate(exit);
+    doms.children.truncate(exit);
+    doms.frontier.truncate(exit);
+    for idom in &mut doms.idom {
+        if *idom == Some(exit) {
+            *idom = None;
+        }
+    }
+    for f in &mut doms.frontier {
+        f.retain(|&b| b != exit);
+    }
+    doms
+}
+
+/// Walks up the partially built tree from ```a``` and ```b``` until they meet
+fn intersect(
+    idom: &[Option<BlockId>],
+    rpo_index: &[usize],
+    mut a: BlockId,
+    mut b: BlockId,
+) -> BlockId {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+/// ```preds``` and ```succs``` describe a graph over ```0..preds.len()```; only the first ```len``` nodes are treated as real blocks when collecting children.
+fn compute(root: BlockId, preds: &[&[BlockId]], succs: &[&[BlockId]], len: usize) -> Dominators {
+    let n = preds.len();
+
+    // Reverse postorder from the root using an explicit stack
+    let mut order = Vec::with_capacity(n);
+    let mut visited = vec![false; n];
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    while let Some((node, next)) = stack.pop() {
+        if let Some(&s) = succs[node].get(next) {
+            stack.push((node, next + 1));
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, 0));
+            }
+        } else {
+            order.push(node);
+        }
+    }
+    order.reverse();
+    let mut rpo_index = vec![usize::MAX; n];
+    for (i, &b) in order.iter().enumerate() {
+        rpo_index[b] = i;
+    }
+
+    let mut idom: Vec<Option<BlockId>> = vec![None; n];
+    idom[root] = Some(root);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in order.iter().skip(1) {
+            let mut new_idom = None;
+            for &p in preds[b] {
+                if idom[p].is_none() {
+                    continue;
+                }
+                new_idom = Some(new_idom.map_or(p, |cur| intersect(&idom, &rpo_index, p, cur)));
+            }
+            if new_idom.is_some() && idom[b] != new_idom {
+                idom[b] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom[root] = None;
+
+    let mut frontier = vec![Vec::new(); n];
+    for b in 0..n {
+        if !visited[b] {
+            continue;
+        }
+        let reachable_preds: Vec<BlockId> =
+            preds[b].iter().copied().filter(|&p| visited[p]).collect();
+        // The root is also entered from outside the graph, so any edge back to it joins two paths
+        if b != root && reachable_preds.len() < 2 {
+            continue;
+        }
+        for p in reachable_preds {
+            let mut runner = Some(p);
+            while let Some(r) = runner {
+                if Some(r) == idom[b] {
+                    break;
+                }
+                if !frontier[r].contains(&b) {
+                    frontier[r].push(b);
+                }
+                runner = idom[r];
+            }
+        }
+    }
+
+    let mut children = vec![Vec::new(); n];
+    for (b, d) in idom.iter().enumerate().take(len) {
+        if let Some(d) = d {
+            children[*d].push(b);
+        }
+    }
+
+    Dominators {
+        root: Some(root),
+        idom,
+        children,
+        frontier,
+        reachable: visited,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dominators;
+    use crate::cfg::build_cfg;
+    use crate::text::parse_and_convert;
+
+    /// The dominance frontier of each block of the `@main` of ```src```, which is in the text format
+    fn frontiers_of(src: &str) -> Vec<Vec<usize>> {
+        let program = parse_and_convert(src).unwrap();
+        let cfg = build_cfg(&program.functions[0]);
+        let doms = dominators(&cfg);
+        (0..cfg.len())
+            .map(|b| doms.dominance_frontier(b).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn diamond_frontier() {
+        let frontiers = frontiers_of(
+            "@main(c: bool) {
+  br c .l .r;
+.l:
+  jmp .j;
+.r:
+  jmp .j;
+.j:
+  print c;
+}",
+        );
+        assert_eq!(frontiers, [vec![], vec![3], vec![3], vec![]]);
+    }
+
+    #[test]
+    fn loop_frontier() {
+        let frontiers = frontiers_of(
+            "@main(c: bool) {
+  jmp .h;
+.h:
+  br c .b .e;
+.b:
+  jmp .h;
+.e:
+  print c;
+}",
+        );
+        assert_eq!(frontiers, [vec![], vec![1], vec![1], vec![]]);
+    }
+
+    #[test]
+    fn entry_header_frontier() {
+        let frontiers = frontiers_of(
+            "@main(c: bool) {
+.l:
+  br c .l .e;
+.e:
+  print c;
+}",
+        );
+        assert_eq!(frontiers, [vec![0], vec![]]);
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(feature = "ssa")]
+use crate::ValueOps;
+use crate::{Code, EffectOps, Function, Instruction};
+
+const fn is_terminator(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Effect {
+            op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+            ..
+        }
+    )
+}
+
+#[cfg(feature = "ssa")]
+const fn is_phi(code: &Code) -> bool {
+    matches!(
+        code,
+        Code::Instruction(Instruction::Value {
+            op: ValueOps::Phi,
+            ..
+        })
+    )
+}
+
+#[cfg(not(feature = "ssa"))]
+const fn is_phi(_: &Code) -> bool {
+    false
+}
+
+/// Calls ```template``` on every instruction of ```func``` and inserts the [Code] it returns, such as a `print` of the instruction's destination, right after that instruction
+///
+/// Code for a `jmp`, `br`, or `ret` is inserted just before it instead, since nothing after it would run. Code for a `phi` is inserted after the last `phi` at the start of its block so the `phi`s stay together.
+/// ```template``` is only called on the original instructions, and should produce straight-line instructions: a label or terminator would change the control flow of the function.
+pub fn instrument_with_prints(
+    func: &mut Function,
+    template: impl Fn(&Instruction) -> Option<Code>,
+) {
+    let old = std::mem::take(&mut func.instrs);
+    let mut instrs = Vec::with_capacity(old.len());
+    let mut pending = Vec::new();
+    for code in old {
+        if !is_phi(&code) {
+            instrs.append(&mut pending);
+        }
+        match &code {
+            Code::Instruction(instr) if is_terminator(instr) => {
+                instrs.extend(template(instr));
+                instrs.push(code);
+            }
+            Code::Instruction(instr) => {
+                let new = template(instr);
+                instrs.push(code);
+                pending.extend(new);
+            }
+            Code::Label { .. } => instrs.push(code),
+        }
+    }
+    instrs.append(&mut pending);
+    func.instrs = instrs;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::instrument_with_prints;
+    use crate::text::parse_and_convert;
+    use crate::{Code, EffectOps, Instruction, ValueOps};
+
+    #[test]
+    fn print_after_every_add() {
+        let mut func = parse_and_convert(
+            "@main(a: int, c: bool) {
+  x: int = add a a;
+  y: int = mul x x;
+  z: int = add x y;
+  br c .l .r;
+.l:
+.r:
+  ret;
+}",
+        )
+        .unwrap()
+        .functions
+        .remove(0);
+        instrument_with_prints(&mut func, |instr| match instr {
+            Instruction::Value {
+                op: ValueOps::Add,
+                dest,
+                ..
+            } => Some(Code::Instruction(Instruction::effect(
+                EffectOps::Print,
+                [dest.as_str()],
+                Vec::<String>::new(),
+                Vec::<String>::new(),
+            ))),
+            _ => None,
+        });
+        let code: Vec<String> = func
+            .instrs
+            .iter()
+            .map(|c| c.to_string().trim().to_string())
+            .collect();
+        assert_eq!(
+            code,
+            [
+                "x: int = add a a;",
+                "print x;",
+                "y: int = mul x x;",
+                "z: int = add x y;",
+                "print z;",
+                "br c .l .r;",
+                ".l:",
+                ".r:",
+                "ret;",
+            ]
+        );
+    }
+}
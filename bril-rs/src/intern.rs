@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::conversion::PositionalConversionError;
+use crate::{AbstractProgram, Code, Instruction, Program};
+
+/// A name which has been given to an [Interner], standing in for the string when comparing or hashing
+///
+/// Symbols are only meaningful with the [Interner] that made them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// A number unique to this symbol within its [Interner], counting up from `0` in the order names were first interned
+    #[must_use]
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Maps variable, label, and function names to [Symbol]s, giving equal names the same symbol
+///
+/// This is only a lookup table beside a [Program]: the program keeps its names as [String]s, so interning saves no memory and the table holds a second copy of every name. What it gives is cheap comparison and hashing of names by their [Symbol]s.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    names: Vec<Box<str>>,
+    symbols: HashMap<Box<str>, Symbol>,
+}
+
+impl Interner {
+    /// An interner which has no names yet
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The symbol for ```name```, making a new one if it has not been interned before
+    /// # Panics
+    /// Panics if more than [`u32::MAX`] different names are interned
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(u32::try_from(self.names.len()).expect("too many names to intern"));
+        self.names.push(name.into());
+        self.symbols.insert(name.into(), symbol);
+        symbol
+    }
+
+    /// The symbol for ```name``` if it has been interned, without adding it otherwise
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    /// The name that ```symbol``` stands for
+    /// # Panics
+    /// Panics if ```symbol``` was made by a different [Interner]
+    #[must_use]
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.index()]
+    }
+
+    /// How many different names have been interned
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether no names have been interned
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The symbol for the variable ```instr``` assigns, if it assigns one which has been interned
+    #[must_use]
+    pub fn dest(&self, instr: &Instruction) -> Option<Symbol> {
+        instr.dest().and_then(|dest| self.get(dest))
+    }
+
+    /// The symbols for the arguments of ```instr```, in order
+    /// # Panics
+    /// Panics if an argument has not been interned, which cannot happen for instructions of a program from [`intern_program`]
+    #[must_use]
+    pub fn args(&self, instr: &Instruction) -> Vec<Symbol> {
+        self.all(instr.args())
+    }
+
+    /// The symbols for the labels ```instr``` refers to, in order
+    /// # Panics
+    /// Panics if a label has not been interned, which cannot happen for instructions of a program from [`intern_program`]
+    #[must_use]
+    pub fn labels(&self, instr: &Instruction) -> Vec<Symbol> {
+        match instr {
+            Instruction::Constant { .. } => Vec::new(),
+            Instruction::Value { labels, .. } | Instruction::Effect { labels, .. } => {
+                self.all(labels)
+            }
+        }
+    }
+
+    /// The symbols for the functions ```instr``` calls, in order
+    /// # Panics
+    /// Panics if a function has not been interned, which cannot happen for instructions of a program from [`intern_program`]
+    #[must_use]
+    pub fn funcs(&self, instr: &Instruction) -> Vec<Symbol> {
+        match instr {
+            Instruction::Constant { .. } => Vec::new(),
+            Instruction::Value { funcs, .. } | Instruction::Effect { funcs, .. } => self.all(funcs),
+        }
+    }
+
+    fn all(&self, names: &[String]) -> Vec<Symbol> {
+        names
+            .iter()
+            .map(|name| self.get(name).expect("name was not interned"))
+            .collect()
+    }
+
+    /// Interns the name of ```program```'s functions, and every parameter, label, and variable they mention
+    pub fn intern_names(&mut self, program: &Program) {
+        for func in &program.functions {
+            self.intern(&func.name);
+            for arg in &func.args {
+                self.intern(&arg.name);
+            }
+            for code in &func.instrs {
+                match code {
+                    Code::Label { label, .. } => {
+                        self.intern(label);
+                    }
+                    Code::Instruction(Instruction::Constant { dest, .. }) => {
+                        self.intern(dest);
+                    }
+                    Code::Instruction(Instruction::Value {
+                        args,
+                        dest,
+                        funcs,
+                        labels,
+                        ..
+                    }) => {
+                        self.intern(dest);
+                        for name in args.iter().chain(funcs).chain(labels) {
+                            self.intern(name);
+                        }
+                    }
+                    Code::Instruction(Instruction::Effect {
+                        args,
+                        funcs,
+                        labels,
+                        ..
+                    }) => {
+                        for name in args.iter().chain(funcs).chain(labels) {
+                            self.intern(name);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Converts ```program``` like [`Program::try_from`], interning every name in the result with ```interner```
+///
+/// Afterwards the symbols of any instruction of the program can be looked up with [`Interner::dest`], [`Interner::args`], [`Interner::labels`], and [`Interner::funcs`], so that analyses can compare and hash names as numbers. The returned program is what [`Program::try_from`] would give, with its names still as strings.
+/// # Errors
+/// Returns any error of the conversion, in which case ```interner``` is left unchanged
+#[allow(clippy::result_large_err)]
+pub fn intern_program(
+    program: AbstractProgram,
+    interner: &mut Interner,
+) -> Result<Program, PositionalConversionError> {
+    let program = Program::try_from(program)?;
+    interner.intern_names(&program);
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{intern_program, Interner};
+    use crate::text::parse_program;
+    use crate::Code;
+
+    #[test]
+    fn equal_names_share_a_symbol() {
+        let mut interner = Interner::new();
+        let a = interner.intern("a");
+        let b = interner.intern("b");
+        assert_eq!(interner.intern(&String::from("a")), a);
+        assert_ne!(a, b);
+        assert_eq!(interner.resolve(b), "b");
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.get("c"), None);
+    }
+
+    #[test]
+    fn instructions_share_symbols() {
+        let mut interner = Interner::new();
+        let program = intern_program(
+            parse_program(
+                "@main {
+  x: int = const 1;
+  y: int = add x x;
+  print y;
+}",
+            )
+            .unwrap(),
+            &mut interner,
+        )
+        .unwrap();
+        let instrs: Vec<_> = program.functions[0]
+            .instrs
+            .iter()
+            .map(|code| match code {
+                Code::Instruction(instr) => instr,
+                Code::Label { .. } => unreachable!(),
+            })
+            .collect();
+        let x = interner.dest(instrs[0]).unwrap();
+        let y = interner.dest(instrs[1]).unwrap();
+        assert_eq!(interner.args(instrs[1]), [x, x]);
+        assert_eq!(interner.args(instrs[2]), [y]);
+        assert_eq!(interner.get("main"), Some(interner.intern("main")));
+    }
+}
@@ -0,0 +1,2322 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+
+use thiserror::Error;
+
+use crate::check::pos_of;
+use crate::cost::{CostModel, CostReport};
+use crate::{Code, EffectOps, Function, Instruction, Literal, Position, Program, Type, ValueOps};
+
+/// A value computed while interpreting a Bril program
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "float"), derive(Eq))]
+pub enum Value {
+    /// <https://capra.cs.cornell.edu/bril/lang/core.html#types>
+    Int(i64),
+    /// <https://capra.cs.cornell.edu/bril/lang/core.html#types>
+    Bool(bool),
+    /// <https://capra.cs.cornell.edu/bril/lang/float.html#types>
+    #[cfg(feature = "float")]
+    Float(f64),
+    /// <https://capra.cs.cornell.edu/bril/lang/char.html#types>
+    #[cfg(feature = "char")]
+    Char(char),
+    /// <https://capra.cs.cornell.edu/bril/lang/memory.html#types>
+    #[cfg(feature = "memory")]
+    Pointer(Pointer),
+}
+
+/// A reference into an allocation on the interpreter's heap
+#[cfg(feature = "memory")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pointer {
+    /// Which allocation this pointer refers to
+    pub base: usize,
+    /// The index of the element within the allocation
+    pub offset: i64,
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{i}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            #[cfg(feature = "float")]
+            Self::Float(x) if x.is_nan() => write!(f, "NaN"),
+            #[cfg(feature = "float")]
+            Self::Float(x) if x.is_infinite() => {
+                write!(f, "{}Infinity", if x.is_sign_negative() { "-" } else { "" })
+            }
+            #[cfg(feature = "float")]
+            Self::Float(x) => write!(f, "{x:.17}"),
+            #[cfg(feature = "char")]
+            Self::Char(c) => write!(f, "{c}"),
+            #[cfg(feature = "memory")]
+            Self::Pointer(p) => write!(f, "{p:?}"),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Self::Int(i)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+#[cfg(feature = "float")]
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Self::Float(x)
+    }
+}
+
+#[cfg(feature = "char")]
+impl From<char> for Value {
+    fn from(c: char) -> Self {
+        Self::Char(c)
+    }
+}
+
+impl Value {
+    /// Whether this value can be stored in a variable of type ```t```
+    #[must_use]
+    pub const fn has_type(&self, t: &Type) -> bool {
+        matches!(
+            (self, t),
+            (Self::Int(_), Type::Int) | (Self::Bool(_), Type::Bool)
+        ) || self.has_extension_type(t)
+    }
+
+    #[allow(clippy::unused_self)]
+    const fn has_extension_type(&self, t: &Type) -> bool {
+        match (self, t) {
+            #[cfg(feature = "float")]
+            (Self::Float(_), Type::Float) => true,
+            #[cfg(feature = "char")]
+            (Self::Char(_), Type::Char) => true,
+            #[cfg(feature = "memory")]
+            (Self::Pointer(_), Type::Pointer(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn from_literal(lit: &Literal, t: &Type) -> Result<Self, InterpError> {
+        let v = match lit {
+            #[cfg(feature = "float")]
+            #[allow(clippy::cast_precision_loss)]
+            Literal::Int(i) if *t == Type::Float => Self::Float(*i as f64),
+            Literal::Int(i) => Self::Int(*i),
+            Literal::Bool(b) => Self::Bool(*b),
+            #[cfg(feature = "float")]
+            Literal::Float(x) => Self::Float(*x),
+            #[cfg(feature = "char")]
+            Literal::Char(c) => Self::Char(*c),
+        };
+        if v.has_type(t) {
+            Ok(v)
+        } else {
+            Err(InterpError::BadConstant(lit.to_string(), t.clone()))
+        }
+    }
+}
+
+/// The ways that interpreting a Bril program can fail
+#[derive(Error, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum InterpError {
+    /// No function named `main` was found
+    #[error("no main function defined")]
+    NoMainFunction,
+    /// A variable was read before it was assigned
+    #[error("undefined variable `{0}`")]
+    UnboundVariable(String),
+    /// A value had a different type than its use requires
+    #[error("expected `{0}` to hold a value of type `{1}`")]
+    TypeMismatch(String, Type),
+    /// A value was used as a pointer but is not one
+    #[cfg(feature = "memory")]
+    #[error("expected `{0}` to hold a pointer")]
+    ExpectedPointer(String),
+    /// A constant's literal does not fit its declared type
+    #[error("constant `{0}` cannot have type `{1}`")]
+    BadConstant(String, Type),
+    /// A call named a function which does not exist
+    #[error("no function of name `{0}` found")]
+    UnknownFunction(String),
+    /// A function declared as external was called, but no implementation of it was registered with [`Interpreter::register_host_fn`]
+    #[cfg(feature = "external")]
+    #[error("cannot call external function `{0}`")]
+    ExternalCall(String),
+    /// A jump or branch named a label which does not exist
+    #[error("could not find label `{0}`")]
+    MissingLabel(String),
+    /// With [`ArithmeticMode::Checked`], the result of `add`, `sub`, `mul`, or `div` did not fit in 64 bits
+    #[error("integer overflow in {0}")]
+    Overflow(Fault),
+    /// `div` was given a zero divisor
+    #[error("attempt to divide by 0 in {0}")]
+    DivByZero(Fault),
+    /// An instruction or call was given the wrong number of arguments
+    #[error("expected {0} arguments, found {1}")]
+    BadNumArgs(usize, usize),
+    /// An instruction was given the wrong number of labels
+    #[error("expected {0} labels, found {1}")]
+    BadNumLabels(usize, usize),
+    /// An instruction was given the wrong number of functions
+    #[error("expected {0} functions, found {1}")]
+    BadNumFuncs(usize, usize),
+    /// A function returned a value of the wrong type, a function with a return type returned without a value, or a void function returned one
+    #[error("function `{0}` returned the wrong kind of value")]
+    BadReturn(String),
+    /// A function with a return type reached the end of its code without a `ret`. The fault is at the last instruction that ran
+    #[error("reached the end of a function which returns a value in {0}")]
+    Unreachable(Fault),
+    /// A `phi` was executed without having come from one of its labels
+    #[cfg(feature = "ssa")]
+    #[error("phi node has no argument for the previous label")]
+    NoLastLabel,
+    /// `alloc` was given a size that is not positive
+    #[cfg(feature = "memory")]
+    #[error("cannot allocate `{0}` entries")]
+    CannotAllocSize(i64),
+    /// A `load`, `store`, or `free` used a pointer into an allocation which has already been freed
+    #[cfg(feature = "memory")]
+    #[error("use of freed memory in {0}")]
+    UseAfterFree(Fault),
+    /// A pointer that does not refer to live memory was used
+    #[cfg(feature = "memory")]
+    #[error("invalid memory access at base `{0}`, offset `{1}`")]
+    InvalidMemoryAccess(usize, i64),
+    /// `load` read a cell of an allocation which has not been stored to
+    #[cfg(feature = "memory")]
+    #[error("read of uninitialized memory at base `{0}`, offset `{1}`")]
+    UninitializedMemory(usize, i64),
+    /// With [`InterpOptions::zero_init`], `load` read a pointer from a cell which has not been stored to, which would be a null pointer
+    #[cfg(feature = "memory")]
+    #[error("load of a null pointer in {0}")]
+    NullPointer(Fault),
+    /// `free` was given a pointer that is not the start of a live allocation
+    #[cfg(feature = "memory")]
+    #[error("tried to free illegal memory location base: `{0}`, offset: `{1}`. Offset must be 0.")]
+    IllegalFree(usize, i64),
+    /// Memory was still allocated when the program finished
+    #[cfg(feature = "memory")]
+    #[error("some memory locations have not been freed by the end of execution")]
+    MemoryLeak,
+    /// `int2char` was given a value that is not a valid character
+    #[cfg(feature = "char")]
+    #[error("value `{0}` cannot be converted to char")]
+    ToCharError(i64),
+    /// `commit` or `guard` was executed outside of speculation, or a function returned while speculating
+    #[cfg(feature = "speculate")]
+    #[error("invalid use of speculation")]
+    BadSpeculation,
+    /// A `guard` failed outside of speculation, so there was nothing to roll back
+    #[cfg(feature = "speculate")]
+    #[error("guard failed outside of speculation in {0}")]
+    GuardFailed(Fault),
+    /// The arguments given for `@main` do not match its parameters
+    #[error(transparent)]
+    Arg(#[from] ArgError),
+    /// [`Interpreter::step_forward`] was called before [`Interpreter::start`] or after the program finished
+    #[error("the program is not running")]
+    NotRunning,
+    /// With [`InterpOptions::detect_loops`], the state of function {0} was the same twice on reaching label {1}, so it would most likely run forever
+    #[error("`@{0}` seems to loop forever: its state repeated at `.{1}`")]
+    LikelyInfiniteLoop(String, String),
+    /// [`Interpreter::step_back`] was called with no recorded steps left to undo
+    #[error("there is no step to undo")]
+    NothingToUndo,
+    /// Writing out the result of a `print` failed
+    #[error("There has been an io error: `{0:?}`")]
+    Io(#[from] std::io::Error),
+}
+
+/// Where the instruction which caused an [`InterpError`] is in the program
+///
+/// Errors are created with an empty location, which [`Interpreter::step_forward`] fills in before returning them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fault {
+    /// The name of the function the instruction is in
+    pub func: String,
+    /// The index into [`Function::instrs`] of the instruction
+    pub index: usize,
+    /// The source position of that instruction, if it has one
+    pub pos: Option<Position>,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "`@{}`", self.func)?;
+        match &self.pos {
+            Some(pos) => write!(f, " at line {}, column {}", pos.pos.row, pos.pos.col),
+            None => write!(f, " at instruction {}", self.index),
+        }
+    }
+}
+
+impl InterpError {
+    /// The location of the instruction which caused this error, for the kinds of error which are faults of a single instruction
+    #[must_use]
+    pub const fn fault(&self) -> Option<&Fault> {
+        match self {
+            Self::DivByZero(fault) | Self::Overflow(fault) | Self::Unreachable(fault) => {
+                Some(fault)
+            }
+            #[cfg(feature = "memory")]
+            Self::UseAfterFree(fault) | Self::NullPointer(fault) => Some(fault),
+            #[cfg(feature = "speculate")]
+            Self::GuardFailed(fault) => Some(fault),
+            _ => None,
+        }
+    }
+
+    const fn fault_mut(&mut self) -> Option<&mut Fault> {
+        match self {
+            Self::DivByZero(fault) | Self::Overflow(fault) | Self::Unreachable(fault) => {
+                Some(fault)
+            }
+            #[cfg(feature = "memory")]
+            Self::UseAfterFree(fault) | Self::NullPointer(fault) => Some(fault),
+            #[cfg(feature = "speculate")]
+            Self::GuardFailed(fault) => Some(fault),
+            _ => None,
+        }
+    }
+}
+
+/// The ways that arguments for `@main` can fail to match its parameters
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    /// No function named `main` was found
+    #[error("no main function defined")]
+    NoMainFunction,
+    /// The number of arguments does not match the number of parameters
+    #[error("expected {0} arguments, found {1}")]
+    BadNumArgs(usize, usize),
+    /// An argument could not be read as a value of its parameter's type
+    #[error("argument {} ({param}: {expected}): `{raw}` is not a valid `{expected}`", .index + 1)]
+    BadValue {
+        /// The position of the argument, counting from 0
+        index: usize,
+        /// The name of the parameter it was passed for
+        param: String,
+        /// The type of that parameter
+        expected: Type,
+        /// The argument as it was given
+        raw: String,
+    },
+}
+
+/// The set of instructions executed during a run, as indices into [`Function::instrs`] keyed by function name
+/// Labels which control passed through are included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Every executed `(function, index)` pair
+    pub executed: HashSet<(String, usize)>,
+}
+
+impl Coverage {
+    /// Whether the code at ```index``` of ```func``` was executed
+    #[must_use]
+    pub fn contains(&self, func: &str, index: usize) -> bool {
+        self.executed.contains(&(func.to_string(), index))
+    }
+}
+
+/// How often each instruction was executed during a run and where control went from it, recorded by [`Interpreter::record_profile`]
+///
+/// Indices are into [`Function::instrs`] and are keyed by function name. Like [Coverage], labels which control passed through are included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// How many times the code at each `(function, index)` was executed
+    pub counts: HashMap<(String, usize), u64>,
+    /// How many times the code at `to` was executed right after the code at `from` in the same call of the function, for each `(function, from, to)`
+    ///
+    /// The instruction after a `call` follows the `call` itself, so the callee's code is not visible here.
+    pub transfers: HashMap<(String, usize, usize), u64>,
+}
+
+impl Profile {
+    /// How many times the code at ```index``` of ```func``` was executed
+    #[must_use]
+    pub fn count(&self, func: &str, index: usize) -> u64 {
+        self.counts
+            .get(&(func.to_string(), index))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// How many times the code at ```to``` of ```func``` was executed right after the code at ```from```
+    #[must_use]
+    pub fn transfers(&self, func: &str, from: usize, to: usize) -> u64 {
+        self.transfers
+            .get(&(func.to_string(), from, to))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// The values printed by one `print`, recorded by [`Interpreter::record_prints`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "float"), derive(Eq))]
+pub struct PrintEvent {
+    /// The arguments of the `print`, in order
+    pub values: Vec<Value>,
+    /// The name of the function which printed them
+    pub func: String,
+    /// The index into [`Function::instrs`] of the `print`
+    pub index: usize,
+}
+
+/// An assignment to a variable listed in [`InterpOptions::watch`], passed to the callback of [`Interpreter::on_watch`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "float"), derive(Eq))]
+pub struct WatchEvent<'b> {
+    /// The variable which was assigned
+    pub var: &'b str,
+    /// Its new value
+    pub value: Value,
+    /// The name of the function whose frame holds the variable
+    pub func: &'b str,
+    /// The index into [`Function::instrs`] of the instruction which assigned it. For the result of a `call`, this is the `call`
+    pub index: usize,
+    /// The source position of that instruction, if it has one
+    pub pos: Option<Position>,
+}
+
+/// What happened after executing a single instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "float"), derive(Eq))]
+pub enum Step {
+    /// The program has more instructions to run
+    Continue,
+    /// `@main` returned with this value
+    Done(Option<Value>),
+}
+
+#[derive(Clone)]
+struct Frame<'a> {
+    func: &'a Function,
+    /// The index of `func` within [`Program::functions`]
+    index: usize,
+    pc: usize,
+    /// The index of the code this frame executed last
+    prev: Option<usize>,
+    env: HashMap<&'a str, Value>,
+    /// Where the caller wants the return value of this frame stored
+    dest: Option<&'a str>,
+    #[cfg(feature = "ssa")]
+    last_label: Option<&'a str>,
+    #[cfg(feature = "ssa")]
+    current_label: Option<&'a str>,
+    #[cfg(feature = "speculate")]
+    speculating: Vec<HashMap<&'a str, Value>>,
+    loop_check: LoopCheck,
+}
+
+/// Brent's cycle detection over the states of a frame at its labels, which needs only one saved state
+#[derive(Clone, Default)]
+struct LoopCheck {
+    /// The label, state hash, and number of memory writes when the state was saved
+    saved: Option<(usize, u64, u64)>,
+    /// How many labels may be reached before saving a new state, which doubles each time
+    window: u64,
+    reached: u64,
+}
+
+fn hash_value(v: Value, h: &mut impl Hasher) {
+    match v {
+        Value::Int(i) => (0, i).hash(h),
+        Value::Bool(b) => (1, b).hash(h),
+        #[cfg(feature = "float")]
+        Value::Float(x) => (2, x.to_bits()).hash(h),
+        #[cfg(feature = "char")]
+        Value::Char(c) => (3, c).hash(h),
+        #[cfg(feature = "memory")]
+        Value::Pointer(p) => (4, p).hash(h),
+    }
+}
+
+impl Frame<'_> {
+    /// A hash of everything the rest of this frame's execution depends on other than memory, taken at the label at ```index```
+    fn state_hash(&self, index: usize) -> u64 {
+        let mut h = DefaultHasher::new();
+        index.hash(&mut h);
+        // Combine the variables without depending on the map's iteration order
+        let vars = self.env.iter().fold(0u64, |acc, (name, &v)| {
+            let mut vh = DefaultHasher::new();
+            name.hash(&mut vh);
+            hash_value(v, &mut vh);
+            acc.wrapping_add(vh.finish())
+        });
+        vars.hash(&mut h);
+        #[cfg(feature = "ssa")]
+        self.last_label.hash(&mut h);
+        #[cfg(feature = "speculate")]
+        self.speculating.len().hash(&mut h);
+        h.finish()
+    }
+}
+
+impl Frame<'_> {
+    fn get(&self, var: &str) -> Result<Value, InterpError> {
+        self.env
+            .get(var)
+            .copied()
+            .ok_or_else(|| InterpError::UnboundVariable(var.to_string()))
+    }
+
+    // Without the other types the wildcard covers just `bool`, but it is needed once they are enabled
+    #[cfg_attr(
+        not(any(feature = "float", feature = "char", feature = "memory")),
+        allow(clippy::match_wildcard_for_single_variants)
+    )]
+    fn int(&self, var: &str) -> Result<i64, InterpError> {
+        match self.get(var)? {
+            Value::Int(i) => Ok(i),
+            _ => Err(InterpError::TypeMismatch(var.to_string(), Type::Int)),
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "float", feature = "char", feature = "memory")),
+        allow(clippy::match_wildcard_for_single_variants)
+    )]
+    fn bool(&self, var: &str) -> Result<bool, InterpError> {
+        match self.get(var)? {
+            Value::Bool(b) => Ok(b),
+            _ => Err(InterpError::TypeMismatch(var.to_string(), Type::Bool)),
+        }
+    }
+
+    #[cfg(feature = "float")]
+    fn float(&self, var: &str) -> Result<f64, InterpError> {
+        match self.get(var)? {
+            Value::Float(x) => Ok(x),
+            _ => Err(InterpError::TypeMismatch(var.to_string(), Type::Float)),
+        }
+    }
+
+    #[cfg(feature = "char")]
+    fn char(&self, var: &str) -> Result<char, InterpError> {
+        match self.get(var)? {
+            Value::Char(c) => Ok(c),
+            _ => Err(InterpError::TypeMismatch(var.to_string(), Type::Char)),
+        }
+    }
+
+    #[cfg(feature = "memory")]
+    fn pointer(&self, var: &str) -> Result<Pointer, InterpError> {
+        match self.get(var)? {
+            Value::Pointer(p) => Ok(p),
+            _ => Err(InterpError::ExpectedPointer(var.to_string())),
+        }
+    }
+}
+
+const fn check_len(expected: usize, found: usize) -> Result<(), InterpError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(InterpError::BadNumArgs(expected, found))
+    }
+}
+
+/// The value that zero-initialized memory of type ```t``` holds. Pointers and arrays have no such value and stay uninitialized
+#[cfg(feature = "memory")]
+const fn zero(t: &Type) -> Option<Value> {
+    match t {
+        Type::Int => Some(Value::Int(0)),
+        Type::Bool => Some(Value::Bool(false)),
+        #[cfg(feature = "float")]
+        Type::Float => Some(Value::Float(0.0)),
+        #[cfg(feature = "char")]
+        Type::Char => Some(Value::Char('\0')),
+        Type::Pointer(_) => None,
+        #[cfg(feature = "array")]
+        Type::Array(..) => None,
+    }
+}
+
+/// How to undo one change to the [Heap]
+#[cfg(feature = "memory")]
+enum HeapChange {
+    /// Remove the newest allocation
+    Alloc,
+    /// Bring back the allocation which was freed at this base
+    Free(usize, Vec<Option<Value>>),
+    /// Put back what this cell held before a `store`
+    Store(Pointer, Option<Value>),
+}
+
+#[cfg(feature = "memory")]
+#[derive(Default)]
+struct Heap {
+    allocations: Vec<Option<Vec<Option<Value>>>>,
+    zero_init: bool,
+    /// Where changes are logged while a step which may be undone runs
+    changes: Option<Vec<HeapChange>>,
+    /// How many times memory has changed, so that a loop working through memory is not mistaken for one without progress
+    writes: u64,
+}
+
+#[cfg(feature = "memory")]
+impl Heap {
+    /// Allocates ```size``` cells for values of type ```elem```, which start out uninitialized unless zero-initialization is on
+    fn alloc(&mut self, size: i64, elem: &Type) -> Result<Pointer, InterpError> {
+        let len = usize::try_from(size)
+            .ok()
+            .filter(|&l| l > 0)
+            .ok_or(InterpError::CannotAllocSize(size))?;
+        let fill = if self.zero_init { zero(elem) } else { None };
+        self.allocations.push(Some(vec![fill; len]));
+        self.log(HeapChange::Alloc);
+        Ok(Pointer {
+            base: self.allocations.len() - 1,
+            offset: 0,
+        })
+    }
+
+    fn free(&mut self, p: Pointer) -> Result<(), InterpError> {
+        match self.allocations.get_mut(p.base) {
+            Some(a @ Some(_)) if p.offset == 0 => {
+                let cells = a.take().unwrap();
+                self.log(HeapChange::Free(p.base, cells));
+                Ok(())
+            }
+            Some(None) => Err(InterpError::UseAfterFree(Fault::default())),
+            _ => Err(InterpError::IllegalFree(p.base, p.offset)),
+        }
+    }
+
+    fn cell(&mut self, p: Pointer) -> Result<&mut Option<Value>, InterpError> {
+        match self.allocations.get_mut(p.base) {
+            Some(None) => Err(InterpError::UseAfterFree(Fault::default())),
+            a => a
+                .and_then(Option::as_mut)
+                .and_then(|a| usize::try_from(p.offset).ok().and_then(|o| a.get_mut(o)))
+                .ok_or(InterpError::InvalidMemoryAccess(p.base, p.offset)),
+        }
+    }
+
+    /// Reads the value of type ```t``` at ```p```
+    fn load(&mut self, p: Pointer, t: &Type) -> Result<Value, InterpError> {
+        let zero_init = self.zero_init;
+        self.cell(p)?.ok_or_else(|| {
+            if zero_init && matches!(t, Type::Pointer(_)) {
+                InterpError::NullPointer(Fault::default())
+            } else {
+                InterpError::UninitializedMemory(p.base, p.offset)
+            }
+        })
+    }
+
+    fn store(&mut self, p: Pointer, v: Value) -> Result<(), InterpError> {
+        let old = self.cell(p)?.replace(v);
+        self.log(HeapChange::Store(p, old));
+        Ok(())
+    }
+
+    fn log(&mut self, change: HeapChange) {
+        self.writes += 1;
+        if let Some(changes) = self.changes.as_mut() {
+            changes.push(change);
+        }
+    }
+
+    fn undo(&mut self, change: HeapChange) {
+        match change {
+            HeapChange::Alloc => {
+                self.allocations.pop();
+            }
+            HeapChange::Free(base, cells) => self.allocations[base] = Some(cells),
+            HeapChange::Store(p, old) => {
+                if let Ok(cell) = self.cell(p) {
+                    *cell = old;
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allocations.iter().all(Option::is_none)
+    }
+}
+
+/// An interpreter for a [Program] which executes one instruction at a time on an explicit call stack
+///
+/// Frames live on the heap rather than the native stack, so deeply recursive programs do not overflow it.
+/// A `call` runs the callee to completion before the caller's next instruction, and `print` writes to the output immediately, so output appears in exactly the order the program performs it across calls.
+pub struct Interpreter<'a> {
+    program: &'a Program,
+    labels: Vec<HashMap<&'a str, usize>>,
+    functions: HashMap<&'a str, usize>,
+    stack: Vec<Frame<'a>>,
+    #[cfg(feature = "memory")]
+    heap: Heap,
+    coverage: Option<Coverage>,
+    profile: Option<Profile>,
+    prints: Option<Vec<PrintEvent>>,
+    options: InterpOptions,
+    #[cfg(feature = "external")]
+    host_fns: HashMap<String, HostFn<'a>>,
+    watcher: Option<Watcher<'a>>,
+    history: VecDeque<Undo<'a>>,
+}
+
+/// The Rust implementation of an external function, registered with [`Interpreter::register_host_fn`]
+#[cfg(feature = "external")]
+type HostFn<'a> = Box<dyn Fn(&[Value]) -> Option<Value> + 'a>;
+
+/// What [`Interpreter::step_back`] needs to restore the state from before one step
+struct Undo<'a> {
+    /// How many frames were on the stack
+    depth: usize,
+    /// The frame which executed the step, as it was before
+    frame: Frame<'a>,
+    /// What the variable the frame returns into held in its caller, if the frame has a caller and a destination
+    caller_dest: Option<(&'a str, Option<Value>)>,
+    #[cfg(feature = "memory")]
+    heap: Vec<HeapChange>,
+}
+
+/// The callback registered with [`Interpreter::on_watch`]
+type Watcher<'a> = Box<dyn FnMut(&WatchEvent) + 'a>;
+
+/// Settings which change how an [Interpreter] executes a program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct InterpOptions {
+    /// Reuse the caller's frame for calls found by [`is_tail_call`], so tail-recursive programs run in constant space
+    ///
+    /// A tail call is only taken when the caller and callee have the same return type and the caller is not speculating. The caller's `ret` is never executed, so it will not appear in [Coverage].
+    pub tco: bool,
+    /// Fill memory from `alloc` with `0`, `false`, `0.0`, or `'\0'` instead of leaving it uninitialized
+    ///
+    /// Like the reference interpreter, the default is uninitialized memory, where a `load` before any `store` fails with [`InterpError::UninitializedMemory`]. Cells holding pointers are never zero-initialized, and a `load` of one before any `store` fails with [`InterpError::NullPointer`].
+    #[cfg(feature = "memory")]
+    pub zero_init: bool,
+    /// Variables whose assignments are reported to the callback of [`Interpreter::on_watch`], in any function
+    ///
+    /// An assignment is any instruction with the variable as its destination, including a `call` when the callee returns. Parameters are not reported when a call binds them.
+    pub watch: Vec<String>,
+    /// Fail with [`InterpError::LikelyInfiniteLoop`] when a function reaches a label with exactly the same variables, and memory unchanged, as an earlier time it reached that label
+    ///
+    /// The state then repeats forever, so this catches many stuck loops long before they would otherwise be noticed. States are compared by hash, so a collision could in principle stop a program which would have finished, and a loop whose variables keep changing, or which keeps writing to memory even without changing it, is never caught.
+    pub detect_loops: bool,
+    /// How many of the most recent steps [`Interpreter::step_back`] can undo. The default of 0 records nothing
+    ///
+    /// Each recorded step keeps a copy of the variables of the frame which executed it and the memory cells it changed. Output that was printed and [Coverage] are not undone.
+    pub history: usize,
+    /// What `add`, `sub`, `mul`, and `div` do when their result does not fit in 64 bits
+    pub arithmetic: ArithmeticMode,
+}
+
+/// How an [Interpreter] handles integer arithmetic which overflows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArithmeticMode {
+    /// Wrap around in two's complement, as the Bril specification requires
+    #[default]
+    Wrapping,
+    /// Fail with [`InterpError::Overflow`], which helps find programs that overflow by accident
+    Checked,
+}
+
+/// Whether the call at ```index``` of ```func``` is a tail call: its next instruction is a `ret` of exactly the call's result, or a bare `ret` after a call with no result
+///
+/// Labels between the call and the `ret` are skipped.
+#[must_use]
+pub fn is_tail_call(func: &Function, index: usize) -> bool {
+    let dest = match &func.instrs[index] {
+        Code::Instruction(Instruction::Value {
+            op: ValueOps::Call,
+            dest,
+            ..
+        }) => Some(dest),
+        Code::Instruction(Instruction::Effect {
+            op: EffectOps::Call,
+            ..
+        }) => None,
+        _ => return false,
+    };
+    func.instrs[index + 1..]
+        .iter()
+        .find_map(|c| match c {
+            Code::Instruction(instr) => Some(instr),
+            Code::Label { .. } => None,
+        })
+        .is_some_and(|next| match next {
+            Instruction::Effect {
+                op: EffectOps::Return,
+                args,
+                ..
+            } => args.iter().eq(dest),
+            _ => false,
+        })
+}
+
+impl<'a> Interpreter<'a> {
+    /// Prepares to interpret ```program```
+    #[must_use]
+    pub fn new(program: &'a Program) -> Self {
+        Self::with_options(program, InterpOptions::default())
+    }
+
+    /// Prepares to interpret ```program``` with the given [`InterpOptions`]
+    #[must_use]
+    pub fn with_options(program: &'a Program, options: InterpOptions) -> Self {
+        let labels = program
+            .functions
+            .iter()
+            .map(|f| {
+                f.instrs
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| match c {
+                        Code::Label { label, .. } => Some((label.as_str(), i)),
+                        Code::Instruction(_) => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            program,
+            labels,
+            functions: program.function_index(),
+            stack: Vec::new(),
+            #[cfg(feature = "memory")]
+            heap: Heap {
+                zero_init: options.zero_init,
+                ..Heap::default()
+            },
+            coverage: None,
+            profile: None,
+            prints: None,
+            options,
+            #[cfg(feature = "external")]
+            host_fns: HashMap::new(),
+            watcher: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Implements the external function ```name``` with ```f```, replacing any earlier implementation
+    ///
+    /// A `call` to an external declaration of ```name``` checks the arguments against its parameters, passes them to ```f```, and checks the result against its return type. External functions without an implementation fail with [`InterpError::ExternalCall`].
+    #[cfg(feature = "external")]
+    pub fn register_host_fn(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn(&[Value]) -> Option<Value> + 'a,
+    ) {
+        self.host_fns.insert(name.into(), Box::new(f));
+    }
+
+    /// Calls ```f``` on every assignment to a variable in [`InterpOptions::watch`], replacing any earlier callback
+    pub fn on_watch(&mut self, f: impl FnMut(&WatchEvent) + 'a) {
+        self.watcher = Some(Box::new(f));
+    }
+
+    /// Reports the assignment of ```dest``` by the current frame's last executed instruction if ```dest``` is watched
+    fn notify(&mut self, dest: &str) {
+        if !self.options.watch.iter().any(|w| w == dest) {
+            return;
+        }
+        let (Some(watcher), Some(frame)) = (self.watcher.as_mut(), self.stack.last()) else {
+            return;
+        };
+        let Some(&value) = frame.env.get(dest) else {
+            return;
+        };
+        let index = frame.pc - 1;
+        let pos = match &frame.func.instrs[index] {
+            Code::Instruction(instr) => pos_of(instr),
+            Code::Label { .. } => None,
+        };
+        watcher(&WatchEvent {
+            var: dest,
+            value,
+            func: &frame.func.name,
+            index,
+            pos,
+        });
+    }
+
+    /// Starts recording which instructions are executed. The recorded [Coverage] is available from [`Interpreter::coverage`]
+    pub fn record_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    /// The instructions executed so far, if [`Interpreter::record_coverage`] was called
+    #[must_use]
+    pub const fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts counting how often each instruction is executed and where control goes. The recorded [Profile] is available from [`Interpreter::profile`]
+    pub fn record_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    /// The execution counts so far, if [`Interpreter::record_profile`] was called
+    #[must_use]
+    pub const fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Starts recording the values of every `print` as a [`PrintEvent`], as well as writing them to the output. The events are available from [`Interpreter::prints`]
+    pub fn record_prints(&mut self) {
+        self.prints = Some(Vec::new());
+    }
+
+    /// Everything printed so far, in order, if [`Interpreter::record_prints`] was called
+    #[must_use]
+    pub fn prints(&self) -> Option<&[PrintEvent]> {
+        self.prints.as_deref()
+    }
+
+    fn find_function(&self, name: &str) -> Result<usize, InterpError> {
+        self.functions
+            .get(name)
+            .copied()
+            .ok_or_else(|| InterpError::UnknownFunction(name.to_string()))
+    }
+
+    fn push_frame(
+        &mut self,
+        index: usize,
+        args: Vec<Value>,
+        dest: Option<&'a str>,
+    ) -> Result<(), InterpError> {
+        let func = &self.program.functions[index];
+        #[cfg(feature = "external")]
+        if func.external {
+            return Err(InterpError::ExternalCall(func.name.clone()));
+        }
+        check_len(func.args.len(), args.len())?;
+        let mut env = HashMap::new();
+        for (arg, v) in func.args.iter().zip(args) {
+            if !v.has_type(&arg.arg_type) {
+                return Err(InterpError::TypeMismatch(
+                    arg.name.clone(),
+                    arg.arg_type.clone(),
+                ));
+            }
+            env.insert(arg.name.as_str(), v);
+        }
+        self.stack.push(Frame {
+            func,
+            index,
+            pc: 0,
+            prev: None,
+            env,
+            dest,
+            #[cfg(feature = "ssa")]
+            last_label: None,
+            #[cfg(feature = "ssa")]
+            current_label: None,
+            #[cfg(feature = "speculate")]
+            speculating: Vec::new(),
+            loop_check: LoopCheck::default(),
+        });
+        Ok(())
+    }
+
+    /// Runs `@main` with ```args```, writing the output of `print` to ```out```, and returns the value `@main` returns
+    /// # Errors
+    /// Returns an [`InterpError`] if the program does something illegal at runtime. Any output printed before the error has already been written to ```out```.
+    pub fn run<W: Write>(
+        &mut self,
+        args: Vec<Value>,
+        mut out: W,
+    ) -> Result<Option<Value>, InterpError> {
+        self.start(args)?;
+        loop {
+            if let Step::Done(result) = self.step_forward(&mut out)? {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Enters `@main` with ```args``` without executing anything, so the program can be run one instruction at a time with [`Interpreter::step_forward`]
+    /// # Errors
+    /// Returns an [`InterpError`] if there is no `@main` or ```args``` do not match its parameters
+    pub fn start(&mut self, args: Vec<Value>) -> Result<(), InterpError> {
+        let main = *self
+            .functions
+            .get("main")
+            .ok_or(InterpError::NoMainFunction)?;
+        self.stack.clear();
+        self.history.clear();
+        self.push_frame(main, args, None)
+    }
+
+    /// Executes the next instruction of the program entered with [`Interpreter::start`], writing the output of any `print` to ```out```
+    ///
+    /// The step is recorded so that it can be undone when [`InterpOptions::history`] is not 0, even if it fails.
+    /// # Errors
+    /// Returns an [`InterpError`] if the instruction does something illegal, or [`InterpError::NotRunning`] if there is nothing to execute
+    pub fn step_forward<W: Write>(&mut self, mut out: W) -> Result<Step, InterpError> {
+        let Some(frame) = self.stack.last() else {
+            return Err(InterpError::NotRunning);
+        };
+        if self.options.history > 0 {
+            let depth = self.stack.len();
+            let caller_dest = frame
+                .dest
+                .filter(|_| depth > 1)
+                .map(|d| (d, self.stack[depth - 2].env.get(d).copied()));
+            let undo = Undo {
+                depth,
+                frame: frame.clone(),
+                caller_dest,
+                #[cfg(feature = "memory")]
+                heap: Vec::new(),
+            };
+            if self.history.len() == self.options.history {
+                self.history.pop_front();
+            }
+            self.history.push_back(undo);
+            #[cfg(feature = "memory")]
+            {
+                self.heap.changes = Some(Vec::new());
+            }
+        }
+        let step = self.step(&mut out).map_err(|e| self.locate(e));
+        #[cfg(feature = "memory")]
+        if let (Some(changes), Some(undo)) = (self.heap.changes.take(), self.history.back_mut()) {
+            undo.heap = changes;
+        }
+        #[cfg(feature = "memory")]
+        if matches!(step, Ok(Step::Done(_))) && !self.heap.is_empty() {
+            return Err(InterpError::MemoryLeak);
+        }
+        step
+    }
+
+    /// Undoes the most recent step recorded by [`Interpreter::step_forward`], restoring the call stack, variables, and memory to how they were before it
+    /// # Errors
+    /// Returns [`InterpError::NothingToUndo`] if no recorded steps are left
+    pub fn step_back(&mut self) -> Result<(), InterpError> {
+        let undo = self.history.pop_back().ok_or(InterpError::NothingToUndo)?;
+        #[cfg(feature = "memory")]
+        for change in undo.heap.into_iter().rev() {
+            self.heap.undo(change);
+        }
+        self.stack.truncate(undo.depth - 1);
+        if let (Some((dest, old)), Some(caller)) = (undo.caller_dest, self.stack.last_mut()) {
+            match old {
+                Some(v) => caller.env.insert(dest, v),
+                None => caller.env.remove(dest),
+            };
+        }
+        self.stack.push(undo.frame);
+        Ok(())
+    }
+
+    /// Fills in the [Fault] of ```error``` with the instruction that the innermost frame just executed
+    fn locate(&self, mut error: InterpError) -> InterpError {
+        if let (Some(fault), Some(frame)) = (error.fault_mut(), self.stack.last()) {
+            let index = frame.prev.unwrap_or(frame.pc);
+            *fault = Fault {
+                func: frame.func.name.clone(),
+                index,
+                pos: match frame.func.instrs.get(index) {
+                    Some(Code::Instruction(instr)) => pos_of(instr),
+                    _ => None,
+                },
+            };
+        }
+        error
+    }
+
+    /// The function and index into [`Function::instrs`] of the next instruction to execute, if the program is running
+    #[must_use]
+    pub fn location(&self) -> Option<(&'a str, usize)> {
+        self.stack.last().map(|f| (f.func.name.as_str(), f.pc))
+    }
+
+    /// The value of ```var``` in the innermost frame, if it is assigned
+    #[must_use]
+    pub fn variable(&self, var: &str) -> Option<Value> {
+        self.stack.last()?.env.get(var).copied()
+    }
+
+    /// Calls the function named by ```funcs```, replacing the current frame when tail calls are enabled and this call is one
+    fn call(
+        &mut self,
+        funcs: &[String],
+        args: &[String],
+        dest: Option<&'a str>,
+    ) -> Result<Step, InterpError> {
+        if funcs.len() != 1 {
+            return Err(InterpError::BadNumFuncs(1, funcs.len()));
+        }
+        let frame = self.stack.last().unwrap();
+        let vals = args
+            .iter()
+            .map(|a| frame.get(a))
+            .collect::<Result<Vec<_>, _>>()?;
+        let callee = self.find_function(&funcs[0])?;
+        #[cfg(feature = "external")]
+        if self.program.functions[callee].external {
+            return self.call_host(callee, &vals, dest);
+        }
+
+        #[allow(unused_mut)]
+        let mut tail = self.options.tco
+            && frame.func.return_type == self.program.functions[callee].return_type
+            && is_tail_call(frame.func, frame.pc - 1);
+        #[cfg(feature = "speculate")]
+        {
+            tail &= frame.speculating.is_empty();
+        }
+        let dest = if tail {
+            self.stack.pop().unwrap().dest
+        } else {
+            dest
+        };
+        self.push_frame(callee, vals, dest)?;
+        Ok(Step::Continue)
+    }
+
+    /// Calls the registered implementation of the external function at ```index``` and stores its result in the current frame
+    #[cfg(feature = "external")]
+    fn call_host(
+        &mut self,
+        index: usize,
+        args: &[Value],
+        dest: Option<&'a str>,
+    ) -> Result<Step, InterpError> {
+        let func = &self.program.functions[index];
+        let host = self
+            .host_fns
+            .get(&func.name)
+            .ok_or_else(|| InterpError::ExternalCall(func.name.clone()))?;
+        check_len(func.args.len(), args.len())?;
+        for (arg, v) in func.args.iter().zip(args) {
+            if !v.has_type(&arg.arg_type) {
+                return Err(InterpError::TypeMismatch(
+                    arg.name.clone(),
+                    arg.arg_type.clone(),
+                ));
+            }
+        }
+        let value = host(args);
+        match (&func.return_type, &value) {
+            (None, None) => {}
+            (Some(t), Some(v)) if v.has_type(t) => {}
+            _ => return Err(InterpError::BadReturn(func.name.clone())),
+        }
+        if let (Some(dest), Some(v)) = (dest, value) {
+            self.stack.last_mut().unwrap().env.insert(dest, v);
+            self.notify(dest);
+        }
+        Ok(Step::Continue)
+    }
+
+    /// Returns from the current frame. When the outermost frame returns, its value is the result of the program.
+    fn ret(&mut self, value: Option<Value>) -> Result<Step, InterpError> {
+        let frame = self.stack.pop().unwrap();
+        #[cfg(feature = "speculate")]
+        if !frame.speculating.is_empty() {
+            return Err(InterpError::BadSpeculation);
+        }
+        match (&frame.func.return_type, value) {
+            (None, None) => {}
+            (Some(t), Some(v)) if v.has_type(t) => {}
+            _ => return Err(InterpError::BadReturn(frame.func.name.clone())),
+        }
+        match self.stack.last_mut() {
+            None => Ok(Step::Done(value)),
+            Some(caller) => {
+                if let (Some(dest), Some(v)) = (frame.dest, value) {
+                    caller.env.insert(dest, v);
+                    self.notify(dest);
+                }
+                Ok(Step::Continue)
+            }
+        }
+    }
+
+    /// Executes the next instruction, returning `Some` with the program's result once `@main` returns
+    fn step<W: Write>(&mut self, out: &mut W) -> Result<Step, InterpError> {
+        let frame = self.stack.last_mut().unwrap();
+        let func = frame.func;
+        let labels_of = &self.labels[frame.index];
+        let Some(code) = func.instrs.get(frame.pc) else {
+            if func.return_type.is_some() {
+                return Err(InterpError::Unreachable(Fault::default()));
+            }
+            return self.ret(None);
+        };
+        if let Some(c) = self.coverage.as_mut() {
+            c.executed.insert((func.name.clone(), frame.pc));
+        }
+        if let Some(p) = self.profile.as_mut() {
+            *p.counts.entry((func.name.clone(), frame.pc)).or_default() += 1;
+            if let Some(prev) = frame.prev {
+                *p.transfers
+                    .entry((func.name.clone(), prev, frame.pc))
+                    .or_default() += 1;
+            }
+        }
+        frame.prev = Some(frame.pc);
+        frame.pc += 1;
+
+        let instr = match code {
+            Code::Label { label, .. } => {
+                #[cfg(feature = "ssa")]
+                {
+                    frame.last_label = frame.current_label;
+                    frame.current_label = Some(label);
+                }
+                if self.options.detect_loops {
+                    let index = frame.pc - 1;
+                    #[cfg(feature = "memory")]
+                    let writes = self.heap.writes;
+                    #[cfg(not(feature = "memory"))]
+                    let writes = 0;
+                    let state = (index, frame.state_hash(index), writes);
+                    let check = &mut frame.loop_check;
+                    if check.saved == Some(state) {
+                        return Err(InterpError::LikelyInfiniteLoop(
+                            func.name.clone(),
+                            label.clone(),
+                        ));
+                    }
+                    check.reached += 1;
+                    if check.saved.is_none() || check.reached >= check.window {
+                        check.saved = Some(state);
+                        check.window = (check.window * 2).max(1);
+                        check.reached = 0;
+                    }
+                }
+                return Ok(Step::Continue);
+            }
+            Code::Instruction(instr) => instr,
+        };
+
+        match instr {
+            Instruction::Constant {
+                dest,
+                const_type,
+                value,
+                ..
+            } => {
+                frame
+                    .env
+                    .insert(dest, Value::from_literal(value, const_type)?);
+                self.notify(dest);
+            }
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                op: ValueOps::Call,
+                ..
+            } => {
+                return self.call(funcs, args, Some(dest));
+            }
+            Instruction::Value {
+                args,
+                dest,
+                #[cfg(feature = "ssa")]
+                labels,
+                op,
+                op_type,
+                ..
+            } => {
+                #[cfg(feature = "ssa")]
+                if *op == ValueOps::Phi {
+                    if args.len() != labels.len() {
+                        return Err(InterpError::BadNumLabels(args.len(), labels.len()));
+                    }
+                    let arg = frame
+                        .last_label
+                        .and_then(|last| labels.iter().position(|l| l == last))
+                        .map(|i| &args[i])
+                        .ok_or(InterpError::NoLastLabel)?;
+                    // Like the reference interpreter, a phi of an undefined variable undefines its destination
+                    match frame.env.get(arg.as_str()).copied() {
+                        Some(v) => frame.env.insert(dest, v),
+                        None => frame.env.remove(dest.as_str()),
+                    };
+                    self.notify(dest);
+                    return Ok(Step::Continue);
+                }
+                let v = eval(
+                    *op,
+                    args,
+                    frame,
+                    self.options.arithmetic,
+                    #[cfg(feature = "memory")]
+                    op_type,
+                    #[cfg(feature = "memory")]
+                    &mut self.heap,
+                )?;
+                if !v.has_type(op_type) {
+                    return Err(InterpError::TypeMismatch(dest.clone(), op_type.clone()));
+                }
+                frame.env.insert(dest, v);
+                self.notify(dest);
+            }
+            Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                ..
+            } => match op {
+                EffectOps::Jump => {
+                    if labels.len() != 1 {
+                        return Err(InterpError::BadNumLabels(1, labels.len()));
+                    }
+                    frame.pc = jump_target(labels_of, &labels[0])?;
+                }
+                EffectOps::Branch => {
+                    check_len(1, args.len())?;
+                    if labels.len() != 2 {
+                        return Err(InterpError::BadNumLabels(2, labels.len()));
+                    }
+                    let target = if frame.bool(&args[0])? {
+                        &labels[0]
+                    } else {
+                        &labels[1]
+                    };
+                    frame.pc = jump_target(labels_of, target)?;
+                }
+                EffectOps::Call => {
+                    return self.call(funcs, args, None);
+                }
+                EffectOps::Return => {
+                    let value = match args.as_slice() {
+                        [] => None,
+                        [a] => Some(frame.get(a)?),
+                        _ => return Err(InterpError::BadNumArgs(1, args.len())),
+                    };
+                    return self.ret(value);
+                }
+                EffectOps::Print => {
+                    let vals = args
+                        .iter()
+                        .map(|a| frame.get(a))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for (i, v) in vals.iter().enumerate() {
+                        if i != 0 {
+                            write!(out, " ")?;
+                        }
+                        write!(out, "{v}")?;
+                    }
+                    writeln!(out)?;
+                    if let Some(p) = self.prints.as_mut() {
+                        p.push(PrintEvent {
+                            values: vals,
+                            func: func.name.clone(),
+                            index: frame.pc - 1,
+                        });
+                    }
+                }
+                EffectOps::Nop => {}
+                #[cfg(feature = "memory")]
+                EffectOps::Store => {
+                    check_len(2, args.len())?;
+                    let v = frame.get(&args[1])?;
+                    let p = frame.pointer(&args[0])?;
+                    self.heap.store(p, v)?;
+                }
+                #[cfg(feature = "memory")]
+                EffectOps::Free => {
+                    check_len(1, args.len())?;
+                    let p = frame.pointer(&args[0])?;
+                    self.heap.free(p)?;
+                }
+                #[cfg(feature = "speculate")]
+                EffectOps::Speculate => {
+                    frame.speculating.push(frame.env.clone());
+                }
+                #[cfg(feature = "speculate")]
+                EffectOps::Commit => {
+                    frame.speculating.pop().ok_or(InterpError::BadSpeculation)?;
+                }
+                #[cfg(feature = "speculate")]
+                EffectOps::Guard => {
+                    check_len(1, args.len())?;
+                    if labels.len() != 1 {
+                        return Err(InterpError::BadNumLabels(1, labels.len()));
+                    }
+                    if !frame.bool(&args[0])? {
+                        frame.env = frame
+                            .speculating
+                            .pop()
+                            .ok_or_else(|| InterpError::GuardFailed(Fault::default()))?;
+                        frame.pc = jump_target(labels_of, &labels[0])?;
+                    }
+                }
+            },
+        }
+        Ok(Step::Continue)
+    }
+}
+
+fn jump_target(labels: &HashMap<&str, usize>, label: &str) -> Result<usize, InterpError> {
+    labels
+        .get(label)
+        .copied()
+        .ok_or_else(|| InterpError::MissingLabel(label.to_string()))
+}
+
+/// Evaluates a value operation other than `call` and `phi`
+fn eval(
+    op: ValueOps,
+    args: &[String],
+    frame: &Frame,
+    arithmetic: ArithmeticMode,
+    #[cfg(feature = "memory")] op_type: &Type,
+    #[cfg(feature = "memory")] heap: &mut Heap,
+) -> Result<Value, InterpError> {
+    let arity = match op {
+        ValueOps::Not | ValueOps::Id => 1,
+        #[cfg(feature = "char")]
+        ValueOps::Char2int | ValueOps::Int2char => 1,
+        #[cfg(feature = "convert")]
+        ValueOps::Int2float | ValueOps::Float2int => 1,
+        #[cfg(feature = "memory")]
+        ValueOps::Alloc | ValueOps::Load => 1,
+        #[cfg(feature = "select")]
+        ValueOps::Select => 3,
+        _ => 2,
+    };
+    check_len(arity, args.len())?;
+
+    let int_op = |wrapping: fn(i64, i64) -> i64, checked: fn(i64, i64) -> Option<i64>| {
+        let (a, b) = (frame.int(&args[0])?, frame.int(&args[1])?);
+        match arithmetic {
+            ArithmeticMode::Wrapping => Ok(Value::Int(wrapping(a, b))),
+            ArithmeticMode::Checked => checked(a, b)
+                .map(Value::Int)
+                .ok_or_else(|| InterpError::Overflow(Fault::default())),
+        }
+    };
+    let int_cmp = |f: fn(&i64, &i64) -> bool| {
+        Ok(Value::Bool(f(&frame.int(&args[0])?, &frame.int(&args[1])?)))
+    };
+    #[cfg(feature = "float")]
+    let float_op = |f: fn(f64, f64) -> f64| {
+        Ok(Value::Float(f(
+            frame.float(&args[0])?,
+            frame.float(&args[1])?,
+        )))
+    };
+    // Comparisons follow IEEE 754 like the reference interpreter: any comparison involving NaN is false, including `feq` of NaN with itself
+    #[cfg(feature = "float")]
+    let float_cmp = |f: fn(&f64, &f64) -> bool| {
+        Ok(Value::Bool(f(
+            &frame.float(&args[0])?,
+            &frame.float(&args[1])?,
+        )))
+    };
+    #[cfg(feature = "char")]
+    let char_cmp = |f: fn(&char, &char) -> bool| {
+        Ok(Value::Bool(f(
+            &frame.char(&args[0])?,
+            &frame.char(&args[1])?,
+        )))
+    };
+
+    match op {
+        ValueOps::Add => int_op(i64::wrapping_add, i64::checked_add),
+        ValueOps::Sub => int_op(i64::wrapping_sub, i64::checked_sub),
+        ValueOps::Mul => int_op(i64::wrapping_mul, i64::checked_mul),
+        ValueOps::Div => {
+            if frame.int(&args[1])? == 0 {
+                return Err(InterpError::DivByZero(Fault::default()));
+            }
+            int_op(i64::wrapping_div, i64::checked_div)
+        }
+        ValueOps::Eq => int_cmp(i64::eq),
+        ValueOps::Lt => int_cmp(i64::lt),
+        ValueOps::Gt => int_cmp(i64::gt),
+        ValueOps::Le => int_cmp(i64::le),
+        ValueOps::Ge => int_cmp(i64::ge),
+        ValueOps::Not => Ok(Value::Bool(!frame.bool(&args[0])?)),
+        ValueOps::And => Ok(Value::Bool(frame.bool(&args[0])? && frame.bool(&args[1])?)),
+        ValueOps::Or => Ok(Value::Bool(frame.bool(&args[0])? || frame.bool(&args[1])?)),
+        ValueOps::Id => frame.get(&args[0]),
+        ValueOps::Call => unreachable!("calls are handled by the interpreter"),
+        #[cfg(feature = "ssa")]
+        ValueOps::Phi => unreachable!("phis are handled by the interpreter"),
+        #[cfg(feature = "float")]
+        ValueOps::Fadd => float_op(|a, b| a + b),
+        #[cfg(feature = "float")]
+        ValueOps::Fsub => float_op(|a, b| a - b),
+        #[cfg(feature = "float")]
+        ValueOps::Fmul => float_op(|a, b| a * b),
+        // Dividing by zero does not trap: it produces an infinity, or NaN for 0/0
+        #[cfg(feature = "float")]
+        ValueOps::Fdiv => float_op(|a, b| a / b),
+        #[cfg(feature = "float")]
+        ValueOps::Feq => float_cmp(f64::eq),
+        #[cfg(feature = "float")]
+        ValueOps::Flt => float_cmp(f64::lt),
+        #[cfg(feature = "float")]
+        ValueOps::Fgt => float_cmp(f64::gt),
+        #[cfg(feature = "float")]
+        ValueOps::Fle => float_cmp(f64::le),
+        #[cfg(feature = "float")]
+        ValueOps::Fge => float_cmp(f64::ge),
+        #[cfg(feature = "char")]
+        ValueOps::Ceq => char_cmp(char::eq),
+        #[cfg(feature = "char")]
+        ValueOps::Clt => char_cmp(char::lt),
+        #[cfg(feature = "char")]
+        ValueOps::Cgt => char_cmp(char::gt),
+        #[cfg(feature = "char")]
+        ValueOps::Cle => char_cmp(char::le),
+        #[cfg(feature = "char")]
+        ValueOps::Cge => char_cmp(char::ge),
+        #[cfg(feature = "char")]
+        ValueOps::Char2int => Ok(Value::Int(i64::from(u32::from(frame.char(&args[0])?)))),
+        #[cfg(feature = "char")]
+        ValueOps::Int2char => {
+            let i = frame.int(&args[0])?;
+            u32::try_from(i)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Value::Char)
+                .ok_or(InterpError::ToCharError(i))
+        }
+        #[cfg(feature = "convert")]
+        #[allow(clippy::cast_precision_loss)]
+        ValueOps::Int2float => Ok(Value::Float(frame.int(&args[0])? as f64)),
+        // `as` truncates toward zero, saturates at the bounds of i64, and maps NaN to 0
+        #[cfg(feature = "convert")]
+        #[allow(clippy::cast_possible_truncation)]
+        ValueOps::Float2int => Ok(Value::Int(frame.float(&args[0])? as i64)),
+        #[cfg(feature = "select")]
+        ValueOps::Select => {
+            let (a, b) = (frame.get(&args[1])?, frame.get(&args[2])?);
+            Ok(if frame.bool(&args[0])? { a } else { b })
+        }
+        #[cfg(feature = "memory")]
+        ValueOps::Alloc => {
+            let elem = op_type.pointee().unwrap_or(op_type);
+            Ok(Value::Pointer(heap.alloc(frame.int(&args[0])?, elem)?))
+        }
+        #[cfg(feature = "memory")]
+        ValueOps::Load => {
+            let p = frame.pointer(&args[0])?;
+            heap.load(p, op_type)
+        }
+        #[cfg(feature = "memory")]
+        ValueOps::PtrAdd => {
+            let p = frame.pointer(&args[0])?;
+            let offset = frame.int(&args[1])?;
+            Ok(Value::Pointer(Pointer {
+                base: p.base,
+                offset: p.offset.wrapping_add(offset),
+            }))
+        }
+    }
+}
+
+/// Interprets ```program``` starting from `@main` with ```args```, writing the output of `print` to ```out```
+/// # Errors
+/// Returns an [`InterpError`] if the program does something illegal at runtime
+pub fn run<W: Write>(
+    program: &Program,
+    args: Vec<Value>,
+    out: W,
+) -> Result<Option<Value>, InterpError> {
+    Interpreter::new(program).run(args, out)
+}
+
+/// Reads ```raw``` as a value of type ```t``` using the same syntax as Bril literals
+fn parse_arg(raw: &str, t: &Type) -> Option<Value> {
+    match t {
+        Type::Int => raw.parse().ok().map(Value::Int),
+        Type::Bool => raw.parse().ok().map(Value::Bool),
+        #[cfg(feature = "float")]
+        Type::Float => raw.parse().ok().map(Value::Float),
+        #[cfg(feature = "char")]
+        Type::Char => {
+            let mut chars = raw.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(Value::Char(c)),
+                _ => None,
+            }
+        }
+        #[cfg(feature = "memory")]
+        Type::Pointer(_) => None,
+        #[cfg(feature = "array")]
+        Type::Array(..) => None,
+    }
+}
+
+/// Converts ```v``` to a value of type ```t```. Integers are accepted for `float` parameters and one-character strings for `char` parameters
+fn json_arg(v: &serde_json::Value, t: &Type) -> Option<Value> {
+    match t {
+        Type::Int => v.as_i64().map(Value::Int),
+        Type::Bool => v.as_bool().map(Value::Bool),
+        #[cfg(feature = "float")]
+        Type::Float => v.as_f64().map(Value::Float),
+        #[cfg(feature = "char")]
+        Type::Char => v.as_str().and_then(|s| parse_arg(s, t)),
+        #[cfg(feature = "memory")]
+        Type::Pointer(_) => None,
+        #[cfg(feature = "array")]
+        Type::Array(..) => None,
+    }
+}
+
+fn coerce_args<T: ToString>(
+    program: &Program,
+    args: &[T],
+    convert: impl Fn(&T, &Type) -> Option<Value>,
+) -> Result<Vec<Value>, ArgError> {
+    let main = program.main().ok_or(ArgError::NoMainFunction)?;
+    if main.args.len() != args.len() {
+        return Err(ArgError::BadNumArgs(main.args.len(), args.len()));
+    }
+    main.args
+        .iter()
+        .zip(args)
+        .enumerate()
+        .map(|(index, (param, raw))| {
+            convert(raw, &param.arg_type).ok_or_else(|| ArgError::BadValue {
+                index,
+                param: param.name.clone(),
+                expected: param.arg_type.clone(),
+                raw: raw.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses command-line style ```args``` into values for the parameters of `@main` in ```program```
+/// # Errors
+/// Returns an [`ArgError`] if there is no `@main`, the number of arguments is wrong, or an argument is not a valid value of its parameter's type
+pub fn parse_main_args(program: &Program, args: &[String]) -> Result<Vec<Value>, ArgError> {
+    coerce_args(program, args, |raw, t| parse_arg(raw, t))
+}
+
+/// Like [run], but takes the arguments of `@main` as JSON values which are coerced to the type of each parameter
+/// # Errors
+/// Returns [`InterpError::Arg`] if an argument does not fit its parameter, and otherwise any [`InterpError`] raised while running
+pub fn run_with_json_args<W: Write>(
+    program: &Program,
+    args: &[serde_json::Value],
+    out: W,
+) -> Result<Option<Value>, InterpError> {
+    let args = coerce_args(program, args, json_arg)?;
+    run(program, args, out)
+}
+
+/// The exit code reported by [`run_to_exit_code`] when arguments cannot be parsed or the program fails
+pub const ERROR_EXIT_CODE: i32 = 2;
+
+/// Runs ```program``` like a command-line interpreter, parsing ```args``` with [`parse_main_args`] and printing to [`std::io::Stdout`], and returns the code the process should exit with
+///
+/// An `int` returned from `@main` is clamped to `0..=255`; any other return, or none, gives `0`. Errors are reported on [`std::io::Stderr`] and give [`ERROR_EXIT_CODE`].
+#[must_use]
+pub fn run_to_exit_code(program: &Program, args: &[String]) -> i32 {
+    let result = parse_main_args(program, args)
+        .map_err(InterpError::from)
+        .and_then(|args| run(program, args, io::BufWriter::new(io::stdout().lock())));
+    match result {
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(Some(Value::Int(i))) => i.clamp(0, 255) as i32,
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ERROR_EXIT_CODE
+        }
+    }
+}
+
+/// Runs ```program``` with ```args``` parsed by [`parse_main_args`] and hashes what it printed and how it finished into a single fingerprint
+///
+/// The hash is 64-bit FNV-1a over, in order: the length in bytes of everything printed, as 8 little-endian bytes; the printed bytes themselves; and then `ok` followed by the value `@main` returned as it would be printed, or nothing if it returned no value, or `error` followed by the message of the [`InterpError`] the run failed with.
+/// The result only depends on the program's behavior, so it is the same on every platform and every run, and two runs with the same fingerprint printed the same output and finished the same way, barring hash collisions.
+#[must_use]
+pub fn run_fingerprint(program: &Program, args: &[String]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    let mut out = Vec::new();
+    let result = parse_main_args(program, args)
+        .map_err(InterpError::from)
+        .and_then(|args| run(program, args, &mut out));
+    let ending = match result {
+        Ok(Some(v)) => format!("ok{v}"),
+        Ok(None) => "ok".to_string(),
+        Err(e) => format!("error{e}"),
+    };
+    (out.len() as u64)
+        .to_le_bytes()
+        .iter()
+        .chain(&out)
+        .chain(ending.as_bytes())
+        .fold(OFFSET_BASIS, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(PRIME)
+        })
+}
+
+/// Like [run], but also reports which instructions were executed. The [Coverage] is complete even when the run fails.
+pub fn run_with_coverage<W: Write>(
+    program: &Program,
+    args: Vec<Value>,
+    out: W,
+) -> (Result<Option<Value>, InterpError>, Coverage) {
+    let mut interp = Interpreter::new(program);
+    interp.record_coverage();
+    let result = interp.run(args, out);
+    (result, interp.coverage.unwrap_or_default())
+}
+
+/// Like [run], but records the values of each `print` instead of writing them as text. The events are complete even when the run fails.
+///
+/// Comparing the values themselves avoids depending on how they are formatted, such as the number of digits printed for a `float`.
+pub fn run_capturing_prints(
+    program: &Program,
+    args: Vec<Value>,
+) -> (Result<Option<Value>, InterpError>, Vec<PrintEvent>) {
+    let mut interp = Interpreter::new(program);
+    interp.record_prints();
+    let result = interp.run(args, io::sink());
+    (result, interp.prints.unwrap_or_default())
+}
+
+/// Like [run], but also counts how often each instruction was executed and where control went. The [Profile] is complete even when the run fails.
+pub fn run_with_profile<W: Write>(
+    program: &Program,
+    args: Vec<Value>,
+    out: W,
+) -> (Result<Option<Value>, InterpError>, Profile) {
+    let mut interp = Interpreter::new(program);
+    interp.record_profile();
+    let result = interp.run(args, out);
+    (result, interp.profile.unwrap_or_default())
+}
+
+/// Like [run], but discards what is printed and prices the instructions that ran with the default [`CostModel`]
+///
+/// Use [`run_with_profile`] and [`CostReport::from_profile`] for another model. The report covers everything that ran even when the run fails.
+pub fn run_with_cost(
+    program: &Program,
+    args: Vec<Value>,
+) -> (Result<Option<Value>, InterpError>, CostReport) {
+    let (result, profile) = run_with_profile(program, args, io::sink());
+    let report = CostReport::from_profile(program, &profile, &CostModel::default());
+    (result, report)
+}
+
+/// Whether ```a``` and ```b``` are the same result, counting any two NaNs as equal
+fn same_result(a: Option<Value>, b: Option<Value>) -> bool {
+    match (a, b) {
+        #[cfg(feature = "float")]
+        (Some(Value::Float(x)), Some(Value::Float(y))) if x.is_nan() && y.is_nan() => true,
+        _ => a == b,
+    }
+}
+
+/// Checks that ```before``` and ```after``` behave identically when `@main` is run with each of ```arg_sets```, for testing that a transformation preserves semantics
+///
+/// For each set of arguments both programs must print the same output and either return the same value or both fail. The errors of failing runs are not compared, since a transformation may change how a failure is reported.
+/// # Panics
+/// Panics with the arguments and both behaviors when the programs differ on some set of arguments
+pub fn assert_same_behavior(before: &Program, after: &Program, arg_sets: &[Vec<Value>]) {
+    for args in arg_sets {
+        let mut out_before = Vec::new();
+        let res_before = run(before, args.clone(), &mut out_before);
+        let mut out_after = Vec::new();
+        let res_after = run(after, args.clone(), &mut out_after);
+        let out_before = String::from_utf8_lossy(&out_before);
+        let out_after = String::from_utf8_lossy(&out_after);
+        let same = out_before == out_after
+            && match (&res_before, &res_after) {
+                (Ok(a), Ok(b)) => same_result(*a, *b),
+                (Err(_), Err(_)) => true,
+                _ => false,
+            };
+        assert!(
+            same,
+            "programs behave differently with arguments {args:?}\nbefore: {res_before:?}\n{out_before}\nafter: {res_after:?}\n{out_after}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use serde_json::json;
+
+    use super::{
+        assert_same_behavior, parse_main_args, run, run_capturing_prints, run_fingerprint,
+        run_to_exit_code, run_with_cost, run_with_coverage, run_with_json_args, ArgError,
+        ArithmeticMode, Fault, InterpError, InterpOptions, Interpreter, Step, Value,
+        ERROR_EXIT_CODE,
+    };
+    use crate::opt::remove_dead_code;
+    use crate::text::parse_and_convert;
+    use crate::{Program, Type};
+
+    /// Runs ```program``` without arguments, returning what it printed and the error it failed with
+    fn fault_of(program: &Program, options: InterpOptions) -> (String, InterpError) {
+        let mut out = Vec::new();
+        let error = Interpreter::with_options(program, options)
+            .run(vec![], &mut out)
+            .unwrap_err();
+        (String::from_utf8(out).unwrap(), error)
+    }
+
+    /// Checks that ```error``` is located at the instruction at ```index``` of ```func```, which is on line ```row```
+    fn assert_at(error: &InterpError, func: &str, index: usize, row: u64) {
+        let Some(Fault {
+            func: f, index: i, ..
+        }) = error.fault()
+        else {
+            panic!("{error} has no fault");
+        };
+        assert_eq!((f.as_str(), *i), (func, index), "{error}");
+        #[cfg(feature = "position")]
+        assert_eq!(error.fault().unwrap().pos.as_ref().unwrap().pos.row, row);
+        #[cfg(not(feature = "position"))]
+        let _ = row;
+    }
+
+    #[test]
+    fn div_by_zero() {
+        let program = parse_and_convert(
+            "@main {
+  one: int = const 1;
+  zero: int = const 0;
+  print one;
+  x: int = div one zero;
+  print x;
+}",
+        )
+        .unwrap();
+        let (out, error) = fault_of(&program, InterpOptions::default());
+        assert_eq!(out, "1\n");
+        assert!(matches!(error, InterpError::DivByZero(_)), "{error}");
+        assert_at(&error, "main", 3, 5);
+    }
+
+    #[test]
+    fn unreachable() {
+        let program = parse_and_convert(
+            "@main {
+  x: int = call @f;
+}
+@f: int {
+  print;
+}",
+        )
+        .unwrap();
+        let (out, error) = fault_of(&program, InterpOptions::default());
+        assert_eq!(out, "\n");
+        assert!(matches!(error, InterpError::Unreachable(_)), "{error}");
+        assert_at(&error, "f", 0, 5);
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn use_after_free() {
+        let program = parse_and_convert(
+            "@main {
+  one: int = const 1;
+  p: ptr<int> = alloc one;
+  store p one;
+  free p;
+  print one;
+  x: int = load p;
+}",
+        )
+        .unwrap();
+        let (out, error) = fault_of(&program, InterpOptions::default());
+        assert_eq!(out, "1\n");
+        assert!(matches!(error, InterpError::UseAfterFree(_)), "{error}");
+        assert_at(&error, "main", 5, 7);
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn null_pointer() {
+        let program = parse_and_convert(
+            "@main {
+  one: int = const 1;
+  p: ptr<ptr<int>> = alloc one;
+  q: ptr<int> = load p;
+  free p;
+}",
+        )
+        .unwrap();
+        let (_, error) = fault_of(
+            &program,
+            InterpOptions {
+                zero_init: true,
+                ..InterpOptions::default()
+            },
+        );
+        assert!(matches!(error, InterpError::NullPointer(_)), "{error}");
+        assert_at(&error, "main", 2, 4);
+        assert!(matches!(
+            run(&program, vec![], Vec::new()),
+            Err(InterpError::UninitializedMemory(0, 0))
+        ));
+    }
+
+    #[test]
+    fn tail_calls_reuse_frames() {
+        let program = parse_and_convert(
+            "@main {
+  n: int = const 2000000;
+  r: int = call @count n;
+  print r;
+}
+@count(n: int): int {
+  zero: int = const 0;
+  done: bool = eq n zero;
+  br done .end .next;
+.end:
+  ret n;
+.next:
+  one: int = const 1;
+  m: int = sub n one;
+  r: int = call @count m;
+  ret r;
+}",
+        )
+        .unwrap();
+        let mut interp = Interpreter::with_options(
+            &program,
+            InterpOptions {
+                tco: true,
+                ..InterpOptions::default()
+            },
+        );
+        let mut out = Vec::new();
+        interp.start(vec![]).unwrap();
+        let mut depth = 0;
+        while interp.step_forward(&mut out).unwrap() == Step::Continue {
+            depth = depth.max(interp.stack.len());
+        }
+        assert_eq!(depth, 2);
+        assert_eq!(out, b"0\n");
+    }
+
+    #[test]
+    fn coverage_skips_untaken_branch() {
+        let program = parse_and_convert(
+            "@main(c: bool) {
+  br c .yes .no;
+.yes:
+  print c;
+  ret;
+.no:
+  x: int = const 1;
+  print x;
+}",
+        )
+        .unwrap();
+        let (result, coverage) = run_with_coverage(&program, vec![Value::Bool(true)], io::sink());
+        assert!(result.is_ok());
+        assert!((0..=3).all(|i| coverage.contains("main", i)));
+        assert!((4..=6).all(|i| !coverage.contains("main", i)));
+        assert_eq!(coverage.executed.len(), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "convert")]
+    fn conversions_truncate_toward_zero() {
+        let program = parse_and_convert(
+            "@main {
+  a: float = const 2.7;
+  b: float = const -2.7;
+  i: int = float2int a;
+  j: int = float2int b;
+  k: int = const -3;
+  f: float = int2float k;
+  print i j f;
+}",
+        )
+        .unwrap();
+        let (result, prints) = run_capturing_prints(&program, vec![]);
+        assert!(result.is_ok());
+        assert_eq!(
+            prints[0].values,
+            [Value::Int(2), Value::Int(-2), Value::Float(-3.0)]
+        );
+    }
+
+    #[test]
+    fn json_args() {
+        let program = parse_and_convert(
+            "@main(n: int, b: bool) {
+  print n b;
+}",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        run_with_json_args(&program, &[json!(5), json!(true)], &mut out).unwrap();
+        assert_eq!(out, b"5 true\n");
+        assert!(matches!(
+            run_with_json_args(&program, &[json!(5), json!(1)], io::sink()),
+            Err(InterpError::Arg(ArgError::BadValue { index: 1, .. }))
+        ));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let program = parse_and_convert(
+            "@main(n: int): int {
+  ret n;
+}",
+        )
+        .unwrap();
+        let code_for = |n: &str| run_to_exit_code(&program, &[n.to_string()]);
+        assert_eq!(code_for("7"), 7);
+        assert_eq!(code_for("300"), 255);
+        assert_eq!(code_for("-5"), 0);
+        assert_eq!(code_for("seven"), ERROR_EXIT_CODE);
+        let void = parse_and_convert("@main {\n}").unwrap();
+        assert_eq!(run_to_exit_code(&void, &[]), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn uninitialized_memory() {
+        let program = parse_and_convert(
+            "@main {
+  two: int = const 2;
+  one: int = const 1;
+  p: ptr<int> = alloc two;
+  store p one;
+  q: ptr<int> = ptradd p one;
+  x: int = load q;
+  print x;
+  free p;
+}",
+        )
+        .unwrap();
+        assert!(matches!(
+            run(&program, vec![], io::sink()),
+            Err(InterpError::UninitializedMemory(0, 1))
+        ));
+        let mut out = Vec::new();
+        let options = InterpOptions {
+            zero_init: true,
+            ..InterpOptions::default()
+        };
+        Interpreter::with_options(&program, options)
+            .run(vec![], &mut out)
+            .unwrap();
+        assert_eq!(out, b"0\n");
+    }
+
+    #[test]
+    fn watch_loop_counter() {
+        let program = parse_and_convert(
+            "@main {
+  i: int = const 0;
+  one: int = const 1;
+  three: int = const 3;
+.loop:
+  i: int = add i one;
+  c: bool = lt i three;
+  br c .loop .done;
+.done:
+  print i;
+}",
+        )
+        .unwrap();
+        let mut assigned = Vec::new();
+        let options = InterpOptions {
+            watch: vec!["i".to_string()],
+            ..InterpOptions::default()
+        };
+        let mut interp = Interpreter::with_options(&program, options);
+        interp.on_watch(|event| assigned.push((event.value, event.index)));
+        interp.run(vec![], io::sink()).unwrap();
+        drop(interp);
+        assert_eq!(
+            assigned,
+            [
+                (Value::Int(0), 0),
+                (Value::Int(1), 4),
+                (Value::Int(2), 4),
+                (Value::Int(3), 4)
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "memory")]
+    fn step_back_restores_variables_and_memory() {
+        let program = parse_and_convert(
+            "@main {
+  one: int = const 1;
+  two: int = const 2;
+  p: ptr<int> = alloc one;
+  store p one;
+  store p two;
+  x: int = load p;
+  print x;
+  free p;
+}",
+        )
+        .unwrap();
+        let options = InterpOptions {
+            history: 2,
+            ..InterpOptions::default()
+        };
+        let mut interp = Interpreter::with_options(&program, options);
+        let mut out = Vec::new();
+        interp.start(vec![]).unwrap();
+        for _ in 0..5 {
+            interp.step_forward(&mut out).unwrap();
+        }
+        let memory = interp.heap.allocations.clone();
+        interp.step_forward(&mut out).unwrap();
+        assert_eq!(interp.variable("x"), Some(Value::Int(2)));
+
+        interp.step_back().unwrap();
+        assert_eq!(interp.location(), Some(("main", 5)));
+        assert_eq!(interp.variable("x"), None);
+        interp.step_back().unwrap();
+        assert_eq!(interp.location(), Some(("main", 4)));
+        assert_eq!(interp.heap.allocations, [Some(vec![Some(Value::Int(1))])]);
+        assert!(matches!(
+            interp.step_back(),
+            Err(InterpError::NothingToUndo)
+        ));
+
+        interp.step_forward(&mut out).unwrap();
+        assert_eq!(interp.heap.allocations, memory);
+        while interp.step_forward(&mut out).unwrap() == Step::Continue {}
+        assert_eq!(out, b"2\n");
+    }
+
+    #[test]
+    fn fingerprints() {
+        let src = "@main(n: int): int {
+  print n;
+  ret n;
+}";
+        let fingerprint = |src: &str, n: &str| {
+            run_fingerprint(&parse_and_convert(src).unwrap(), &[n.to_string()])
+        };
+        assert_eq!(fingerprint(src, "4"), fingerprint(src, "4"));
+        assert_ne!(fingerprint(src, "4"), fingerprint(src, "5"));
+        // Only behavior counts, not the code that produced it
+        let other = "@main(n: int): int {
+  print n;
+  ret n;
+  ret n;
+}";
+        assert_eq!(fingerprint(src, "4"), fingerprint(other, "4"));
+        // The same output without a return value is a different behavior
+        let void = "@main(n: int) {
+  print n;
+}";
+        assert_ne!(fingerprint(src, "4"), fingerprint(void, "4"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "float", feature = "char"))]
+    fn captured_prints_keep_types() {
+        let program = parse_and_convert(
+            "@main {
+  n: int = const 3;
+  b: bool = const false;
+  f: float = const 0.1;
+  c: char = const 'x';
+  print n b;
+  print f c;
+}",
+        )
+        .unwrap();
+        let (result, prints) = run_capturing_prints(&program, vec![]);
+        assert!(matches!(result, Ok(None)));
+        assert_eq!(prints.len(), 2);
+        assert_eq!(prints[0].values, [Value::Int(3), Value::Bool(false)]);
+        assert_eq!(prints[1].values, [Value::Float(0.1), Value::Char('x')]);
+        assert_eq!((prints[1].func.as_str(), prints[1].index), ("main", 5));
+    }
+
+    #[test]
+    fn bad_second_argument() {
+        let program = parse_and_convert("@main(b: bool, n: int) {\n  print b n;\n}").unwrap();
+        let args = ["true".to_string(), "foo".to_string()];
+        let error = parse_main_args(&program, &args).unwrap_err();
+        assert_eq!(
+            error,
+            ArgError::BadValue {
+                index: 1,
+                param: "n".to_string(),
+                expected: Type::Int,
+                raw: "foo".to_string(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "argument 2 (n: int): `foo` is not a valid `int`"
+        );
+        assert_eq!(
+            parse_main_args(&program, &args[..1]),
+            Err(ArgError::BadNumArgs(2, 1))
+        );
+    }
+
+    #[test]
+    fn stuck_loop() {
+        let program = parse_and_convert(
+            "@main {
+  i: int = const 0;
+  one: int = const 1;
+  ten: int = const 10;
+.h:
+  z: int = mul i one;
+  c: bool = lt i ten;
+  br c .h .x;
+.x:
+  print i;
+}",
+        )
+        .unwrap();
+        let (_, error) = fault_of(
+            &program,
+            InterpOptions {
+                detect_loops: true,
+                ..InterpOptions::default()
+            },
+        );
+        assert!(
+            matches!(&error, InterpError::LikelyInfiniteLoop(func, label) if func == "main" && label == "h"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn cost_drops_after_optimizing() {
+        let mut program = parse_and_convert(
+            "@main {
+  a: int = const 1;
+  b: int = const 2;
+  c: int = call @double a;
+  unused: int = add c b;
+  print c;
+}
+@double(x: int): int {
+  y: int = add x x;
+  ret y;
+}",
+        )
+        .unwrap();
+        let (result, before) = run_with_cost(&program, vec![]);
+        assert!(result.is_ok());
+        assert_eq!((before.of("main"), before.of("double")), (14, 2));
+        assert!(remove_dead_code(&mut program.functions[0]));
+        let (_, after) = run_with_cost(&program, vec![]);
+        assert_eq!((after.of("main"), after.of("double")), (12, 2));
+        assert!(after.total() < before.total());
+    }
+
+    #[test]
+    fn max_times_two() {
+        let program = parse_and_convert(
+            "@main {
+  max: int = const 9223372036854775807;
+  two: int = const 2;
+  x: int = mul max two;
+  print x;
+}",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        run(&program, vec![], &mut out).unwrap();
+        assert_eq!(out, b"-2\n");
+        let (out, error) = fault_of(
+            &program,
+            InterpOptions {
+                arithmetic: ArithmeticMode::Checked,
+                ..InterpOptions::default()
+            },
+        );
+        assert_eq!(out, "");
+        assert!(matches!(error, InterpError::Overflow(_)), "{error}");
+        assert_at(&error, "main", 2, 4);
+    }
+
+    #[test]
+    #[cfg(feature = "external")]
+    fn host_double() {
+        let program = parse_and_convert(
+            "extern @double(x: int): int;
+@main {
+  n: int = const 21;
+  d: int = call @double n;
+  print d;
+  b: bool = const true;
+  e: int = call @double b;
+}",
+        )
+        .unwrap();
+        let mut interp = Interpreter::new(&program);
+        interp.register_host_fn("double", |args| match args {
+            [Value::Int(n)] => Some(Value::Int(n * 2)),
+            _ => None,
+        });
+        let mut out = Vec::new();
+        let error = interp.run(vec![], &mut out).unwrap_err();
+        assert_eq!(out, b"42\n");
+        // Arguments are checked against the declaration before the host function sees them
+        assert!(
+            matches!(&error, InterpError::TypeMismatch(x, Type::Int) if x == "x"),
+            "{error}"
+        );
+        let (_, error) = fault_of(&program, InterpOptions::default());
+        assert!(
+            matches!(&error, InterpError::ExternalCall(f) if f == "double"),
+            "{error}"
+        );
+    }
+
+    /// `@main` printing double its argument, once with `add` and once with `mul`
+    fn doubling_programs() -> (Program, Program) {
+        let add = "@main(n: int) {\n  d: int = add n n;\n  print d;\n}";
+        let mul = "@main(n: int) {\n  two: int = const 2;\n  d: int = mul n two;\n  print d;\n}";
+        (
+            parse_and_convert(add).unwrap(),
+            parse_and_convert(mul).unwrap(),
+        )
+    }
+
+    #[test]
+    fn same_behavior() {
+        let (add, mul) = doubling_programs();
+        assert_same_behavior(&add, &mul, &[vec![Value::Int(0)], vec![Value::Int(-7)]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "programs behave differently")]
+    fn different_behavior() {
+        let (add, _) = doubling_programs();
+        let triple = parse_and_convert(
+            "@main(n: int) {\n  d: int = add n n;\n  d: int = add d n;\n  print d;\n}",
+        )
+        .unwrap();
+        assert_same_behavior(&add, &triple, &[vec![Value::Int(0)], vec![Value::Int(3)]]);
+    }
+}
@@ -5,32 +5,81 @@
 
 /// Provides the unstructured representation of Bril programs
 pub mod abstract_program;
+/// Provides a compact binary encoding of [Program]s
+#[cfg(feature = "binary")]
+pub mod binary;
+/// Provides the call graph of a [Program]
+pub mod callgraph;
+/// Provides basic blocks and the control-flow graph of a [Function]
+pub mod cfg;
+/// Provides static checks of [Program]s beyond what conversion enforces
+pub mod check;
 /// Provides the Error handling and conversion between [`AbstractProgram`] and [Program]
 pub mod conversion;
+/// Provides cost estimates of [Function]s for an abstract machine
+pub mod cost;
+/// Provides control- and data-dependence analyses and program slicing
+pub mod dependence;
+/// Provides dominator and post-dominator trees over a [`cfg::Cfg`]
+pub mod dominators;
+/// Provides helpers for instrumenting [Function]s with extra code
+pub mod instrument;
+/// Provides a side table of [`intern::Symbol`]s for the names in [Program]s
+#[cfg(feature = "intern")]
+pub mod intern;
+/// Provides a reference interpreter for [Program]s
+pub mod interp;
+/// Provides renaming and combining of functions across [Program]s
+pub mod link;
+/// Provides live-variable analysis over a [`cfg::Cfg`]
+pub mod liveness;
+/// Provides natural-loop analysis and loop transformations
+pub mod loops;
+/// Provides optimizations which rewrite [Function]s
+pub mod opt;
 /// Provides the structured representation of Bril programs
 pub mod program;
+/// Provides integer value-range analysis over a [`cfg::Cfg`]
+pub mod ranges;
+/// Provides reading and writing [Program]s as S-expressions
+pub mod sexpr;
+/// Provides a parser for the Bril text format
+pub mod text;
+/// Provides a type checker for [Program]s
+pub mod typecheck;
+/// Provides visitor traits for walking over the code of [Program]s
+pub mod visit;
 pub use abstract_program::*;
 pub use program::*;
 
+use conversion::LoadError;
 use std::io::{self, Write};
 
 // todo Have versions of the output_* functions that take a [std::io::Write]
 // todo possible deprecate/remove the wrapper functions to make the code base cleaner
-// todo Wrap the outputs of serde in an error instead of panic-ing
 
 /// A helper function for parsing a Bril program from ```input``` in JSON format to [Program]
-/// # Panics
-/// Will panic if the input JSON is not well-formed bril JSON
-pub fn load_program_from_read<R: std::io::Read>(mut input: R) -> Program {
-    let mut buffer = String::new();
-    input.read_to_string(&mut buffer).unwrap();
-    serde_json::from_str(&buffer).unwrap()
+///
+/// The input is read as it is parsed rather than buffered up front, so wrap unbuffered readers like files in a [`std::io::BufReader`].
+/// # Errors
+/// Will return an error if the input is not well-formed Bril JSON, or if it does not convert to a valid [Program]
+pub fn load_program_from_read<R: io::Read>(input: R) -> Result<Program, LoadError> {
+    Ok(load_abstract_program_from_read(input)?.try_into()?)
 }
 
 /// A wrapper of [`load_program_from_read`] which assumes [`std::io::Stdin`]
+/// # Errors
+/// See [`load_program_from_read`]
+pub fn load_program_from_stdin() -> Result<Program, LoadError> {
+    load_program_from_read(io::stdin().lock())
+}
+
+/// A wrapper of [`load_program_from_stdin`] which panics on failure
+/// # Panics
+/// Will panic if the input JSON is not well-formed bril JSON or is not a valid [Program]
 #[must_use]
 pub fn load_program() -> Program {
-    load_program_from_read(std::io::stdin())
+    load_program_from_stdin().unwrap()
 }
 
 /// Outputs a [Program] to [`std::io::Stdout`]
@@ -41,19 +90,83 @@ pub fn output_program(p: &Program) {
     io::stdout().write_all(b"\n").unwrap();
 }
 
+/// Serializes ```program``` as JSON which stays the same byte for byte however this crate orders its fields
+///
+/// The keys of every object are sorted, so those of an instruction come in the order `args`, `dest`, `funcs`, `labels`, `op`, `pos`, `type`, `value`, and a position is `{"col": .., "row": ..}`.
+/// The output is indented by two spaces and ends with a newline. This is the key order and layout of the reference `bril2json`, which prints with Python's `json.dumps(..., indent=2, sort_keys=True)`, though non-ASCII characters are written as they are rather than escaped.
+#[must_use]
+pub fn to_canonical_json(program: &Program) -> String {
+    canonical_json(program)
+}
+
+/// Serializes ```program``` as JSON in the same form as [`to_canonical_json`]
+#[must_use]
+pub fn abstract_program_to_canonical_json(program: &AbstractProgram) -> String {
+    canonical_json(program)
+}
+
+fn canonical_json<T: serde::Serialize>(program: &T) -> String {
+    fn sorted(value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => {
+                let mut entries: Vec<_> = map.into_iter().collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                serde_json::Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (key, sorted(value)))
+                        .collect(),
+                )
+            }
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.into_iter().map(sorted).collect())
+            }
+            value => value,
+        }
+    }
+    // Programs only hold strings, numbers, and maps with string keys, which always serialize
+    let value = sorted(serde_json::to_value(program).unwrap());
+    let mut json = serde_json::to_string_pretty(&value).unwrap();
+    json.push('\n');
+    json
+}
+
 /// A helper function for parsing a Bril program from ```input``` in JSON format to [`AbstractProgram`]
-/// # Panics
-/// Will panic if the input JSON is not well-formed bril JSON
-pub fn load_abstract_program_from_read<R: std::io::Read>(mut input: R) -> AbstractProgram {
-    let mut buffer = String::new();
-    input.read_to_string(&mut buffer).unwrap();
-    serde_json::from_str(&buffer).unwrap()
+///
+/// The input is read as it is parsed rather than buffered up front, so wrap unbuffered readers like files in a [`std::io::BufReader`].
+/// # Errors
+/// Will return an error if the input is not well-formed Bril JSON
+pub fn load_abstract_program_from_read<R: io::Read>(
+    input: R,
+) -> Result<AbstractProgram, LoadError> {
+    Ok(serde_json::from_reader(input)?)
+}
+
+/// Parses ```input``` like [`load_abstract_program_from_read`], but rejects any key which this build would ignore
+///
+/// Every object of the program, its functions, and their instructions is checked. This catches misspelled keys like `"opp"`, which are otherwise silently dropped. Keys belonging to features which are disabled, such as `"pos"` without the `position` feature, are rejected too. With the `metadata` feature any key of a label or instruction is accepted, since instructions keep the keys they do not know.
+/// # Errors
+/// Returns a [`serde_json::Error`] naming the first unknown key, whose [`serde_json::Error::line`] and [`serde_json::Error::column`] point just past it, or any error the usual parse would give
+pub fn load_abstract_program_strict(input: &str) -> Result<AbstractProgram, serde_json::Error> {
+    use serde::de::DeserializeSeed;
+    abstract_program::KnownKeys::Program
+        .deserialize(&mut serde_json::Deserializer::from_str(input))?;
+    serde_json::from_str(input)
 }
 
 /// A wrapper of [`load_abstract_program_from_read`] which assumes [`std::io::Stdin`]
+/// # Errors
+/// See [`load_abstract_program_from_read`]
+pub fn load_abstract_program_from_stdin() -> Result<AbstractProgram, LoadError> {
+    load_abstract_program_from_read(io::stdin().lock())
+}
+
+/// A wrapper of [`load_abstract_program_from_stdin`] which panics on failure
+/// # Panics
+/// Will panic if the input JSON is not well-formed bril JSON
 #[must_use]
 pub fn load_abstract_program() -> AbstractProgram {
-    load_abstract_program_from_read(std::io::stdin())
+    load_abstract_program_from_stdin().unwrap()
 }
 
 /// Outputs an [`AbstractProgram`] to [`std::io::Stdout`]
@@ -63,3 +176,66 @@ pub fn output_abstract_program(p: &AbstractProgram) {
     serde_json::to_writer_pretty(io::stdout(), p).unwrap();
     io::stdout().write_all(b"\n").unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::text::{parse_and_convert, parse_program};
+    use crate::{
+        abstract_program_to_canonical_json, load_abstract_program_from_read,
+        load_abstract_program_strict, to_canonical_json,
+    };
+
+    #[test]
+    fn canonical_json() {
+        let src = "@main(n: int) {\n  x: int = add n n;\n  print x;\n}\n";
+        let mut program = parse_and_convert(src).unwrap();
+        program.strip_positions();
+        let json = to_canonical_json(&program);
+        assert_eq!(
+            json,
+            r#"{
+  "functions": [
+    {
+      "args": [
+        {
+          "name": "n",
+          "type": "int"
+        }
+      ],
+      "instrs": [
+        {
+          "args": [
+            "n",
+            "n"
+          ],
+          "dest": "x",
+          "op": "add",
+          "type": "int"
+        },
+        {
+          "args": [
+            "x"
+          ],
+          "op": "print"
+        }
+      ],
+      "name": "main"
+    }
+  ]
+}
+"#
+        );
+        let mut abstract_program = parse_program(src).unwrap();
+        abstract_program.strip_positions();
+        assert_eq!(abstract_program_to_canonical_json(&abstract_program), json);
+    }
+
+    #[test]
+    fn strict_rejects_misspelled_key() {
+        let json = r#"{"functions": [{"name": "main", "instrs": [], "argz": []}]}"#;
+        assert!(load_abstract_program_from_read(json.as_bytes()).is_ok());
+        let error = load_abstract_program_strict(json).unwrap_err();
+        assert!(error.to_string().contains("argz"), "{error}");
+        assert!(load_abstract_program_strict(r#"{"functions": [], "fuctions": []}"#).is_err());
+    }
+}
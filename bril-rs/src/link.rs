@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+
+/// The ways that renaming or combining functions across a [Program] can fail
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum LinkError {
+    /// No function of this name exists
+    #[error("no function of name `{0}` found")]
+    UnknownFunction(String),
+    /// A function of this name already exists
+    #[error("a function named `{0}` is already defined")]
+    DuplicateFunction(String),
+}
+
+impl Program {
+    /// Renames the function ```from``` to ```to```, along with every value and effect `call` of it
+    /// # Errors
+    /// Returns [`LinkError::UnknownFunction`] if ```from``` is not defined, or [`LinkError::DuplicateFunction`] if ```to``` already is. The program is unchanged on error.
+    pub fn rename_function(&mut self, from: &str, to: &str) -> Result<(), LinkError> {
+        let index = self
+            .functions
+            .iter()
+            .position(|f| f.name == from)
+            .ok_or_else(|| LinkError::UnknownFunction(from.to_string()))?;
+        if from == to {
+            return Ok(());
+        }
+        if self.functions.iter().any(|f| f.name == to) {
+            return Err(LinkError::DuplicateFunction(to.to_string()));
+        }
+        self.functions[index].name = to.to_string();
+        for code in self.functions.iter_mut().flat_map(|f| &mut f.instrs) {
+            if let Code::Instruction(
+                Instruction::Value {
+                    op: ValueOps::Call,
+                    funcs,
+                    ..
+                }
+                | Instruction::Effect {
+                    op: EffectOps::Call,
+                    funcs,
+                    ..
+                },
+            ) = code
+            {
+                for f in funcs.iter_mut().filter(|f| *f == from) {
+                    *f = to.to_string();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the functions and imports of ```other``` to the end of this program
+    ///
+    /// The result declares the newer of the two `version`s.
+    /// # Errors
+    /// Returns [`LinkError::DuplicateFunction`] for the first function of ```other``` whose name is already defined here, including `main`.
+    pub fn merge(mut self, other: Self) -> Result<Self, LinkError> {
+        let index = self.function_index();
+        if let Some(f) = other
+            .functions
+            .iter()
+            .find(|f| index.contains_key(f.name.as_str()))
+        {
+            return Err(LinkError::DuplicateFunction(f.name.clone()));
+        }
+        self.functions.extend(other.functions);
+        #[cfg(feature = "import")]
+        for import in other.imports {
+            if !self.imports.contains(&import) {
+                self.imports.push(import);
+            }
+        }
+        self.version = self.version.max(other.version);
+        Ok(self)
+    }
+
+    /// Like [`Program::merge`], but first renames every function of ```other``` to start with ```prefix```, along with its calls to them
+    ///
+    /// This includes the `main` of ```other```, so this program's `main` stays the entry point. Calls in ```other``` to imported functions are left alone.
+    /// # Errors
+    /// Returns [`LinkError::DuplicateFunction`] if a prefixed name is still defined here, such as when this program was already merged with the same prefix.
+    pub fn merge_namespaced(self, mut other: Self, prefix: &str) -> Result<Self, LinkError> {
+        let renamed: HashMap<String, String> = other
+            .functions
+            .iter()
+            .map(|f| (f.name.clone(), format!("{prefix}{}", f.name)))
+            .collect();
+        for func in &mut other.functions {
+            func.name.clone_from(&renamed[&func.name]);
+            for code in &mut func.instrs {
+                if let Code::Instruction(
+                    Instruction::Value {
+                        op: ValueOps::Call,
+                        funcs,
+                        ..
+                    }
+                    | Instruction::Effect {
+                        op: EffectOps::Call,
+                        funcs,
+                        ..
+                    },
+                ) = code
+                {
+                    for f in funcs.iter_mut() {
+                        if let Some(new) = renamed.get(f) {
+                            f.clone_from(new);
+                        }
+                    }
+                }
+            }
+        }
+        self.merge(other)
+    }
+}
+
+/// Prepends ```prefix``` to the name of every parameter, variable, and label of ```func```, at both their definitions and their uses
+///
+/// Labels are renamed wherever they appear, including the targets of `jmp`, `br`, and `guard` and the labels of a `phi`. The name of the function and the functions it calls are left alone, so this only keeps the code of functions from different programs apart, as with [`Program::merge_namespaced`] for function names.
+pub fn alpha_rename(func: &mut Function, prefix: &str) {
+    let rename = |name: &mut String| name.insert_str(0, prefix);
+    for arg in &mut func.args {
+        rename(&mut arg.name);
+    }
+    for code in &mut func.instrs {
+        match code {
+            Code::Label { label, .. } => rename(label),
+            Code::Instruction(Instruction::Constant { dest, .. }) => rename(dest),
+            Code::Instruction(Instruction::Value {
+                args, dest, labels, ..
+            }) => {
+                rename(dest);
+                args.iter_mut().chain(labels).for_each(rename);
+            }
+            Code::Instruction(Instruction::Effect { args, labels, .. }) => {
+                args.iter_mut().chain(labels).for_each(rename);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{alpha_rename, LinkError};
+    use crate::interp::run;
+    use crate::text::parse_and_convert;
+
+    #[test]
+    fn rename_called_helper() {
+        let mut program = parse_and_convert(
+            "@helper(x: int): int {
+  ret x;
+}
+@other {
+}
+@main {
+  one: int = const 1;
+  a: int = call @helper one;
+  call @helper one;
+  print a;
+}",
+        )
+        .unwrap();
+        let before = program.to_string();
+        assert_eq!(
+            program.rename_function("helper", "other"),
+            Err(LinkError::DuplicateFunction("other".to_string()))
+        );
+        assert_eq!(
+            program.rename_function("missing", "new"),
+            Err(LinkError::UnknownFunction("missing".to_string()))
+        );
+        assert_eq!(program.to_string(), before);
+        program.rename_function("helper", "inc").unwrap();
+        assert_eq!(program.to_string(), before.replace("@helper", "@inc"));
+    }
+
+    #[test]
+    fn merge_two_helpers() {
+        let program = |value: i64| {
+            parse_and_convert(&format!(
+                "@helper: int {{
+  x: int = const {value};
+  ret x;
+}}
+@main {{
+  x: int = call @helper;
+  print x;
+}}"
+            ))
+            .unwrap()
+        };
+        let merged = program(1).merge_namespaced(program(2), "lib.").unwrap();
+        let names: Vec<_> = merged.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["helper", "main", "lib.helper", "lib.main"]);
+        assert!(merged.functions[3]
+            .to_string()
+            .contains("call @lib.helper;"));
+        let mut out = Vec::new();
+        run(&merged, vec![], &mut out).unwrap();
+        assert_eq!(out, b"1\n");
+        assert_eq!(
+            program(1).merge(program(2)),
+            Err(LinkError::DuplicateFunction("helper".to_string()))
+        );
+    }
+
+    #[test]
+    fn alpha_rename_keeps_behavior() {
+        let src = "@main {
+  i: int = const 3;
+  one: int = const 1;
+  zero: int = const 0;
+.loop:
+  print i;
+  i: int = sub i one;
+  done: bool = eq i zero;
+  br done .end .loop;
+.end:
+  x: int = call @id i;
+}
+@id(v: int): int {
+  ret v;
+}";
+        let mut program = parse_and_convert(src).unwrap();
+        let mut before = Vec::new();
+        run(&program, vec![], &mut before).unwrap();
+        alpha_rename(&mut program.functions[0], "a.");
+        assert_eq!(
+            program.functions[0].to_string().trim(),
+            "@main {
+  a.i: int = const 3;
+  a.one: int = const 1;
+  a.zero: int = const 0;
+.a.loop:
+  print a.i;
+  a.i: int = sub a.i a.one;
+  a.done: bool = eq a.i a.zero;
+  br a.done .a.end .a.loop;
+.a.end:
+  a.x: int = call @id a.i;
+}"
+        );
+        let mut after = Vec::new();
+        run(&program, vec![], &mut after).unwrap();
+        assert_eq!(after, before);
+        assert_eq!(before, b"3\n2\n1\n");
+    }
+}
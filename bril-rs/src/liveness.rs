@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cfg::{BlockId, Cfg};
+#[cfg(feature = "ssa")]
+use crate::ValueOps;
+use crate::{Code, Function, Instruction};
+
+/// The variables of a [Function] which are live at the start and end of each block of its [`Cfg`]
+///
+/// A variable is live at a point when some path from there reads it before assigning it. Under the `ssa` feature, an argument of a `phi` is live at the end of the predecessor it is chosen for rather than at the start of the `phi`'s block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Liveness {
+    live_in: Vec<HashSet<String>>,
+    live_out: Vec<HashSet<String>>,
+}
+
+/// The variables read by ```instr``` and the one it assigns. The arguments of a `phi` are not counted as reads
+fn uses_and_def(instr: &Instruction) -> (&[String], Option<&str>) {
+    match instr {
+        Instruction::Constant { dest, .. } => (&[], Some(dest)),
+        #[cfg(feature = "ssa")]
+        Instruction::Value {
+            op: ValueOps::Phi,
+            dest,
+            ..
+        } => (&[], Some(dest)),
+        Instruction::Value { args, dest, .. } => (args, Some(dest)),
+        Instruction::Effect { args, .. } => (args, None),
+    }
+}
+
+/// Updates ```live``` from after ```code``` to before it
+fn step_back(live: &mut HashSet<String>, code: &Code) {
+    if let Code::Instruction(instr) = code {
+        let (uses, def) = uses_and_def(instr);
+        if let Some(def) = def {
+            live.remove(def);
+        }
+        live.extend(uses.iter().cloned());
+    }
+}
+
+impl Liveness {
+    /// The variables live when control enters ```block```
+    #[must_use]
+    pub fn live_in(&self, block: BlockId) -> &HashSet<String> {
+        &self.live_in[block]
+    }
+
+    /// The variables live when control leaves ```block```
+    #[must_use]
+    pub fn live_out(&self, block: BlockId) -> &HashSet<String> {
+        &self.live_out[block]
+    }
+
+    /// The variables live just before the code at ```index``` of ```func```, or at the end of ```func``` when ```index``` is its length
+    ///
+    /// ```func``` and ```cfg``` must be the ones this analysis was computed from.
+    #[must_use]
+    pub fn live_before(&self, func: &Function, cfg: &Cfg, index: usize) -> HashSet<String> {
+        if index >= func.instrs.len() {
+            return HashSet::new();
+        }
+        let block = cfg.block_of(index);
+        let mut live = self.live_out[block].clone();
+        for code in func.instrs[index..cfg.block_range(block).end].iter().rev() {
+            step_back(&mut live, code);
+        }
+        live
+    }
+
+    /// The variables live just after the code at ```index``` of ```func```, which for the last code of a block are those live when control leaves it
+    ///
+    /// ```func``` and ```cfg``` must be the ones this analysis was computed from.
+    #[must_use]
+    pub fn live_after(&self, func: &Function, cfg: &Cfg, index: usize) -> HashSet<String> {
+        if index >= func.instrs.len() {
+            return HashSet::new();
+        }
+        let block = cfg.block_of(index);
+        if index + 1 == cfg.block_range(block).end {
+            self.live_out[block].clone()
+        } else {
+            self.live_before(func, cfg, index + 1)
+        }
+    }
+}
+
+/// Computes which variables of ```func``` are live at the boundaries of each block of ```cfg```
+#[must_use]
+pub fn liveness(func: &Function, cfg: &Cfg) -> Liveness {
+    // The `phi` arguments chosen when control leaves each block
+    #[allow(unused_mut)]
+    let mut phi_uses: Vec<Vec<&str>> = vec![Vec::new(); cfg.len()];
+    #[cfg(feature = "ssa")]
+    for code in &func.instrs {
+        if let Code::Instruction(Instruction::Value {
+            op: ValueOps::Phi,
+            args,
+            labels,
+            ..
+        }) = code
+        {
+            for (arg, label) in args.iter().zip(labels) {
+                if let Some(pred) = cfg.block_by_label(label) {
+                    phi_uses[pred].push(arg);
+                }
+            }
+        }
+    }
+
+    let mut live_in = vec![HashSet::new(); cfg.len()];
+    let mut live_out = vec![HashSet::new(); cfg.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..cfg.len()).rev() {
+            let mut out: HashSet<String> = phi_uses[b].iter().map(|v| (*v).to_string()).collect();
+            for &s in cfg.successors(b) {
+                out.extend(live_in[s].iter().cloned());
+            }
+            let mut live = out.clone();
+            for code in func.instrs[cfg.block_range(b)].iter().rev() {
+                step_back(&mut live, code);
+            }
+            if out != live_out[b] || live != live_in[b] {
+                live_out[b] = out;
+                live_in[b] = live;
+                changed = true;
+            }
+        }
+    }
+    Liveness { live_in, live_out }
+}
+
+/// Which variables of ```func``` interfere, meaning one is live just after the other is assigned so the two cannot share a name, as a symmetric map from each variable to the others it interferes with
+///
+/// Parameters interfere with each other and with everything live at the entry. A variable which interferes with nothing may be missing from the map.
+/// ```live``` must be the [`liveness`] of ```func``` and ```cfg```.
+#[must_use]
+pub fn interference(
+    func: &Function,
+    cfg: &Cfg,
+    live: &Liveness,
+) -> HashMap<String, HashSet<String>> {
+    let mut graph: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut add = |def: &str, others: &HashSet<String>| {
+        for other in others.iter().filter(|o| *o != def) {
+            graph
+                .entry(def.to_string())
+                .or_default()
+                .insert(other.clone());
+            graph
+                .entry(other.clone())
+                .or_default()
+                .insert(def.to_string());
+        }
+    };
+    let mut at_entry: HashSet<String> = func.args.iter().map(|a| a.name.clone()).collect();
+    if !cfg.is_empty() {
+        at_entry.extend(live.live_in(cfg.entry).iter().cloned());
+    }
+    for a in &func.args {
+        add(&a.name, &at_entry);
+    }
+    for b in 0..cfg.len() {
+        let mut after = live.live_out(b).clone();
+        for code in func.instrs[cfg.block_range(b)].iter().rev() {
+            if let Code::Instruction(instr) = code {
+                if let (_, Some(def)) = uses_and_def(instr) {
+                    add(def, &after);
+                }
+            }
+            step_back(&mut after, code);
+        }
+    }
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::liveness;
+    use crate::cfg::build_cfg;
+    use crate::text::parse_and_convert;
+
+    /// The variables of ```live``` in order
+    fn sorted(live: &HashSet<String>) -> Vec<&str> {
+        let mut names: Vec<_> = live.iter().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn straight_line() {
+        let func = parse_and_convert(
+            "@main(a: int, b: int) {
+  x: int = add a b;
+  y: int = add x a;
+  print y;
+}",
+        )
+        .unwrap()
+        .functions
+        .remove(0);
+        let cfg = build_cfg(&func);
+        let live = liveness(&func, &cfg);
+        assert_eq!(sorted(live.live_in(0)), ["a", "b"]);
+        assert_eq!(sorted(&live.live_after(&func, &cfg, 0)), ["a", "x"]);
+        assert_eq!(sorted(&live.live_after(&func, &cfg, 1)), ["y"]);
+        assert!(live.live_out(0).is_empty());
+    }
+
+    #[test]
+    fn live_across_back_edge() {
+        let func = parse_and_convert(
+            "@main(n: int) {
+.entry:
+  i: int = const 0;
+  one: int = const 1;
+  jmp .loop;
+.loop:
+  i: int = add i one;
+  c: bool = lt i n;
+  br c .loop .exit;
+.exit:
+  print n;
+}",
+        )
+        .unwrap()
+        .functions
+        .remove(0);
+        let cfg = build_cfg(&func);
+        let live = liveness(&func, &cfg);
+        let header = cfg.block_of(5);
+        assert_eq!(sorted(live.live_in(0)), ["n"]);
+        assert_eq!(sorted(live.live_in(header)), ["i", "n", "one"]);
+        assert_eq!(sorted(live.live_out(header)), ["i", "n", "one"]);
+        assert_eq!(sorted(live.live_in(cfg.block_of(9))), ["n"]);
+    }
+}
@@ -0,0 +1,722 @@
+use std::collections::HashSet;
+
+use crate::cfg::{BlockId, Cfg};
+use crate::dominators::Dominators;
+#[cfg(feature = "format")]
+use crate::FormatHints;
+#[cfg(feature = "ssa")]
+use crate::Type;
+use crate::{Code, EffectOps, Function, Instruction, Literal, ValueOps};
+
+/// A natural loop of a [`Cfg`]
+///
+/// All back edges into the same header are combined into one loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    /// The block which dominates every block of the loop
+    pub header: BlockId,
+    /// The blocks with a back edge to `header`
+    pub latches: Vec<BlockId>,
+    /// Every block of the loop, including `header`, in ascending order
+    pub blocks: Vec<BlockId>,
+}
+
+impl Loop {
+    /// Whether ```block``` is part of this loop
+    #[must_use]
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.blocks.binary_search(&block).is_ok()
+    }
+}
+
+/// Finds the natural loops of ```cfg```, ordered by header
+///
+/// An edge is a back edge when its target dominates its source. The loop of a back edge contains its header and every block which can reach the latch without passing through the header.
+/// Cycles with more than one entry have no back edge and so are not found; see [`is_reducible`].
+#[must_use]
+pub fn natural_loops(cfg: &Cfg, dom: &Dominators) -> Vec<Loop> {
+    let mut loops: Vec<Loop> = Vec::new();
+    for header in 0..cfg.len() {
+        let latches: Vec<BlockId> = cfg
+            .predecessors(header)
+            .iter()
+            .copied()
+            .filter(|&p| dom.dominates(header, p))
+            .collect();
+        if latches.is_empty() {
+            continue;
+        }
+        let mut blocks = HashSet::from([header]);
+        let mut worklist = latches.clone();
+        while let Some(b) = worklist.pop() {
+            if blocks.insert(b) {
+                worklist.extend(cfg.predecessors(b).iter().filter(|&&p| dom.is_reachable(p)));
+            }
+        }
+        let mut blocks: Vec<BlockId> = blocks.into_iter().collect();
+        blocks.sort_unstable();
+        loops.push(Loop {
+            header,
+            latches,
+            blocks,
+        });
+    }
+    loops
+}
+
+/// Whether every cycle of ```cfg``` is part of a natural loop, so that [`natural_loops`] describes all of its repetition
+///
+/// A walk from the entry classifies an edge as retreating when it goes back to a block still being visited. ```cfg``` is reducible when each retreating edge is a back edge, that is its target dominates its source. An irreducible cycle can be entered at more than one block, so transformations which rely on loops having a single header should check this first. Unreachable blocks are ignored.
+#[must_use]
+pub fn is_reducible(cfg: &Cfg, dom: &Dominators) -> bool {
+    if cfg.is_empty() {
+        return true;
+    }
+    let mut visited = vec![false; cfg.len()];
+    let mut on_stack = vec![false; cfg.len()];
+    // Each entry is a block and how many of its successors have been followed
+    let mut stack = vec![(cfg.entry, 0)];
+    visited[cfg.entry] = true;
+    on_stack[cfg.entry] = true;
+    while let Some((b, next)) = stack.last_mut() {
+        let b = *b;
+        let Some(&s) = cfg.successors(b).get(*next) else {
+            on_stack[b] = false;
+            stack.pop();
+            continue;
+        };
+        *next += 1;
+        if on_stack[s] {
+            if !dom.dominates(s, b) {
+                return false;
+            }
+        } else if !visited[s] {
+            visited[s] = true;
+            on_stack[s] = true;
+            stack.push((s, 0));
+        }
+    }
+    true
+}
+
+/// The index of a [Loop] within a [`LoopForest`]
+pub type LoopId = usize;
+
+/// The natural loops of a [`Cfg`] arranged by nesting
+///
+/// Loops are stored in the order of [`natural_loops`]. A loop's parent is the smallest other loop containing all of its blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopForest {
+    /// Every natural loop, ordered by header
+    pub loops: Vec<Loop>,
+    parents: Vec<Option<LoopId>>,
+    children: Vec<Vec<LoopId>>,
+}
+
+impl LoopForest {
+    /// The loop immediately enclosing ```lp```, if any
+    #[must_use]
+    pub fn parent(&self, lp: LoopId) -> Option<LoopId> {
+        self.parents[lp]
+    }
+
+    /// The loops immediately nested inside ```lp```
+    #[must_use]
+    pub fn children(&self, lp: LoopId) -> &[LoopId] {
+        &self.children[lp]
+    }
+
+    /// The loops which are not nested in any other loop
+    pub fn roots(&self) -> impl Iterator<Item = LoopId> + '_ {
+        (0..self.loops.len()).filter(|&l| self.parents[l].is_none())
+    }
+
+    /// How many loops enclose ```lp```. Outermost loops have depth 0
+    #[must_use]
+    pub fn depth(&self, lp: LoopId) -> usize {
+        std::iter::successors(self.parents[lp], |&p| self.parents[p]).count()
+    }
+
+    /// The innermost loop containing ```block```, if any
+    #[must_use]
+    pub fn innermost_loop_of(&self, block: BlockId) -> Option<LoopId> {
+        (0..self.loops.len())
+            .filter(|&l| self.loops[l].contains(block))
+            .max_by_key(|&l| self.depth(l))
+    }
+
+    /// Every loop, with each loop before the loops nested inside it
+    #[must_use]
+    pub fn outermost_first(&self) -> Vec<LoopId> {
+        let mut order = Vec::with_capacity(self.loops.len());
+        let mut stack: Vec<LoopId> = self.roots().collect();
+        stack.reverse();
+        while let Some(l) = stack.pop() {
+            order.push(l);
+            stack.extend(self.children[l].iter().rev());
+        }
+        order
+    }
+
+    /// Every loop, with each loop after the loops nested inside it
+    #[must_use]
+    pub fn innermost_first(&self) -> Vec<LoopId> {
+        let mut order = self.outermost_first();
+        order.reverse();
+        order
+    }
+}
+
+/// Finds the natural loops of ```cfg``` and how they nest
+#[must_use]
+pub fn loop_forest(cfg: &Cfg, dom: &Dominators) -> LoopForest {
+    let loops = natural_loops(cfg, dom);
+    let parents: Vec<Option<LoopId>> = (0..loops.len())
+        .map(|l| {
+            (0..loops.len())
+                .filter(|&o| {
+                    o != l
+                        && loops[o].blocks.len() > loops[l].blocks.len()
+                        && loops[l].blocks.iter().all(|&b| loops[o].contains(b))
+                })
+                .min_by_key(|&o| loops[o].blocks.len())
+        })
+        .collect();
+    let mut children = vec![Vec::new(); loops.len()];
+    for (l, p) in parents.iter().enumerate() {
+        if let Some(p) = p {
+            children[*p].push(l);
+        }
+    }
+    LoopForest {
+        loops,
+        parents,
+        children,
+    }
+}
+
+pub(crate) fn fresh(base: &str, taken: &HashSet<&str>) -> String {
+    if !taken.contains(base) {
+        return base.to_string();
+    }
+    let mut i = 1;
+    loop {
+        let name = format!("{base}.{i}");
+        if !taken.contains(name.as_str()) {
+            return name;
+        }
+        i += 1;
+    }
+}
+
+impl Function {
+    /// Inserts a preheader for ```lp```: a new block placed directly before the loop header which all edges entering the loop from outside are redirected through
+    ///
+    /// Back edges still target the header directly. Under the `ssa` feature, header `phi`s are rewritten so that the values entering from outside the loop are merged in the preheader.
+    /// ```cfg``` is stale after this call; the returned id is the preheader's [`BlockId`] in a [`Cfg`] rebuilt from the modified function.
+    pub fn insert_preheader(&mut self, lp: &Loop, cfg: &Cfg) -> BlockId {
+        let header = &cfg.blocks[lp.header];
+        let insert_at = header.range.start;
+        let labels: HashSet<&str> = self
+            .instrs
+            .iter()
+            .filter_map(|c| match c {
+                Code::Label { label, .. } => Some(label.as_str()),
+                Code::Instruction(_) => None,
+            })
+            .collect();
+        let header_label = header
+            .label
+            .clone()
+            .unwrap_or_else(|| fresh(&format!("{}.header", self.name), &labels));
+        let preheader_label = fresh(&format!("{header_label}.preheader"), &labels);
+
+        let entering: Vec<BlockId> = cfg
+            .predecessors(lp.header)
+            .iter()
+            .copied()
+            .filter(|&p| !lp.contains(p))
+            .collect();
+
+        // Redirect entering jumps and branches to the preheader
+        for &p in &entering {
+            if let Some(Code::Instruction(Instruction::Effect {
+                op: EffectOps::Jump | EffectOps::Branch,
+                labels,
+                ..
+            })) = self.instrs[cfg.block_range(p)].last_mut()
+            {
+                for l in labels.iter_mut().filter(|l| **l == header_label) {
+                    l.clone_from(&preheader_label);
+                }
+            }
+        }
+
+        let mut new_code: Vec<Code> = Vec::new();
+
+        // A latch which falls through into the header now needs to jump over the preheader
+        if lp.header > 0
+            && lp.latches.contains(&(lp.header - 1))
+            && !matches!(
+                self.instrs[cfg.block_range(lp.header - 1)].last(),
+                Some(Code::Instruction(Instruction::Effect {
+                    op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+                    ..
+                }))
+            )
+        {
+            new_code.push(Code::Instruction(Instruction::Effect {
+                args: Vec::new(),
+                funcs: Vec::new(),
+                labels: vec![header_label.clone()],
+                op: EffectOps::Jump,
+                #[cfg(feature = "position")]
+                pos: None,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+                #[cfg(feature = "metadata")]
+                extra: serde_json::Map::new(),
+            }));
+        }
+        new_code.push(Code::Label {
+            // The `phi`s merged in the preheader are chosen by its label too
+            #[cfg(feature = "ssa")]
+            label: preheader_label.clone(),
+            #[cfg(not(feature = "ssa"))]
+            label: preheader_label,
+            #[cfg(feature = "position")]
+            pos: None,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+        });
+
+        #[cfg(feature = "ssa")]
+        {
+            let entering_labels: Vec<&str> = entering
+                .iter()
+                .filter_map(|&p| cfg.blocks[p].label.as_deref())
+                .collect();
+            let mut vars: HashSet<String> = self.args.iter().map(|a| a.name.clone()).collect();
+            for code in &self.instrs {
+                if let Code::Instruction(
+                    Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+                ) = code
+                {
+                    vars.insert(dest.clone());
+                }
+            }
+            let mut phis: Vec<(String, Type, Vec<String>, Vec<String>)> = Vec::new();
+            for code in &mut self.instrs[header.range.clone()] {
+                if let Code::Instruction(Instruction::Value {
+                    op: ValueOps::Phi,
+                    dest,
+                    op_type,
+                    args,
+                    labels,
+                    ..
+                }) = code
+                {
+                    let (outside, inside): (Vec<_>, Vec<_>) = args
+                        .drain(..)
+                        .zip(labels.drain(..))
+                        .partition(|(_, l)| entering_labels.contains(&l.as_str()));
+                    let (mut new_args, mut new_labels): (Vec<_>, Vec<_>) =
+                        inside.into_iter().unzip();
+                    match outside.len() {
+                        0 => {}
+                        1 => {
+                            new_args.push(outside[0].0.clone());
+                            new_labels.push(preheader_label.clone());
+                        }
+                        _ => {
+                            let taken: HashSet<&str> = vars.iter().map(String::as_str).collect();
+                            let merged = fresh(&format!("{dest}.{preheader_label}"), &taken);
+                            vars.insert(merged.clone());
+                            let (out_args, out_labels) = outside.into_iter().unzip();
+                            phis.push((merged.clone(), op_type.clone(), out_args, out_labels));
+                            new_args.push(merged);
+                            new_labels.push(preheader_label.clone());
+                        }
+                    }
+                    *args = new_args;
+                    *labels = new_labels;
+                }
+            }
+            new_code.extend(phis.into_iter().map(|(dest, op_type, args, labels)| {
+                Code::Instruction(Instruction::Value {
+                    args,
+                    dest,
+                    funcs: Vec::new(),
+                    labels,
+                    op: ValueOps::Phi,
+                    #[cfg(feature = "position")]
+                    pos: None,
+                    #[cfg(feature = "format")]
+                    format: FormatHints::default(),
+                    #[cfg(feature = "metadata")]
+                    extra: serde_json::Map::new(),
+                    op_type,
+                })
+            }));
+        }
+
+        if header.label.is_none() {
+            new_code.push(Code::Label {
+                label: header_label,
+                #[cfg(feature = "position")]
+                pos: None,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+            });
+        }
+        self.instrs.splice(insert_at..insert_at, new_code);
+        lp.header
+    }
+}
+
+/// The value of ```var``` wherever it is read, if its only assignment in ```func``` is an `int` constant
+fn int_constant(func: &Function, var: &str) -> Option<i64> {
+    if func.args.iter().any(|a| a.name == var) {
+        return None;
+    }
+    let mut defs = func.instrs.iter().filter_map(|c| match c {
+        Code::Instruction(
+            i @ (Instruction::Constant { dest, .. } | Instruction::Value { dest, .. }),
+        ) if dest == var => Some(i),
+        _ => None,
+    });
+    match (defs.next(), defs.next()) {
+        (
+            Some(Instruction::Constant {
+                value: Literal::Int(n),
+                ..
+            }),
+            None,
+        ) => Some(*n),
+        _ => None,
+    }
+}
+
+/// How many times the back edge of ```lp``` is taken before the loop exits, when that is known without running it
+///
+/// This recognizes counted loops: a variable `i` is assigned an `int` constant at the end of the one block entering the loop, assigned exactly once inside it by an `add` or `sub` of a constant, and compared to a constant with `lt`, `le`, `gt`, `ge`, or `eq` just before the only `br` leaving the loop. The increment and the test must each run once per iteration, so both must dominate the loop's only latch without being part of a nested loop, and the test may see `i` either before or after it is incremented. A constant here is a variable whose only assignment in the function is a `const`.
+/// Returns [None] when the loop does not have this form, or when it would only stop after `i` overflows.
+#[must_use]
+pub fn trip_count(lp: &Loop, func: &Function, cfg: &Cfg, dom: &Dominators) -> Option<u64> {
+    let &[latch] = lp.latches.as_slice() else {
+        return None;
+    };
+    let once_per_iteration = |b: BlockId| {
+        dom.dominates(b, latch)
+            && !natural_loops(cfg, dom)
+                .iter()
+                .any(|l| l.header != lp.header && lp.contains(l.header) && l.contains(b))
+    };
+
+    // The only exit from the loop is a `br` on a comparison computed in the same block
+    let mut exits = lp
+        .blocks
+        .iter()
+        .filter(|&&b| cfg.successors(b).iter().any(|&s| !lp.contains(s)));
+    let (&exiting, None) = (exits.next()?, exits.next()) else {
+        return None;
+    };
+    let range = cfg.block_range(exiting);
+    let Code::Instruction(Instruction::Effect {
+        op: EffectOps::Branch,
+        args,
+        labels,
+        ..
+    }) = &func.instrs[range.end - 1]
+    else {
+        return None;
+    };
+    let stays = |l: &str| cfg.block_by_label(l).is_some_and(|b| lp.contains(b));
+    let continue_when = match (stays(&labels[0]), stays(&labels[1])) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => return None,
+    };
+    let cond = &args[0];
+    let test = range.rev().find(|&i| {
+        matches!(
+            &func.instrs[i],
+            Code::Instruction(Instruction::Constant { dest, .. } | Instruction::Value { dest, .. })
+                if dest == cond
+        )
+    })?;
+    let Code::Instruction(Instruction::Value {
+        op, args: compared, ..
+    }) = &func.instrs[test]
+    else {
+        return None;
+    };
+    let op = *op;
+    if !matches!(
+        op,
+        ValueOps::Lt | ValueOps::Le | ValueOps::Gt | ValueOps::Ge | ValueOps::Eq
+    ) || compared.len() != 2
+    {
+        return None;
+    }
+
+    // One side of the comparison is stepped by a constant once in the loop, and the other is constant
+    let increment_of = |var: &str| {
+        let mut defs = lp.blocks.iter().flat_map(|&b| cfg.block_range(b)).filter(|&i| {
+            matches!(
+                &func.instrs[i],
+                Code::Instruction(Instruction::Constant { dest, .. } | Instruction::Value { dest, .. })
+                    if dest == var
+            )
+        });
+        let (Some(i), None) = (defs.next(), defs.next()) else {
+            return None;
+        };
+        let Code::Instruction(Instruction::Value { op, args, .. }) = &func.instrs[i] else {
+            return None;
+        };
+        let step = match (op, args.as_slice()) {
+            (ValueOps::Add, [a, b]) if a == var => int_constant(func, b)?,
+            (ValueOps::Add, [a, b]) if b == var => int_constant(func, a)?,
+            (ValueOps::Sub, [a, b]) if a == var => int_constant(func, b)?.checked_neg()?,
+            _ => return None,
+        };
+        Some((i, step))
+    };
+    let (side, (increment, step), bound) = match increment_of(&compared[0]) {
+        Some(inc) => (0, inc, int_constant(func, &compared[1])?),
+        None => (
+            1,
+            increment_of(&compared[1])?,
+            int_constant(func, &compared[0])?,
+        ),
+    };
+    let var = &compared[side];
+    let inc_block = cfg.block_of(increment);
+    if !once_per_iteration(inc_block) || !once_per_iteration(exiting) {
+        return None;
+    }
+
+    // The variable starts the same way each time the loop is entered
+    let mut entering = cfg
+        .predecessors(lp.header)
+        .iter()
+        .filter(|&&p| !lp.contains(p));
+    let (&pre, None) = (entering.next()?, entering.next()) else {
+        return None;
+    };
+    let init = cfg
+        .block_range(pre)
+        .rev()
+        .find_map(|i| match &func.instrs[i] {
+            Code::Instruction(Instruction::Constant {
+                dest,
+                value: Literal::Int(n),
+                ..
+            }) if dest == var => Some(Some(*n)),
+            Code::Instruction(
+                Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+            ) if dest == var => Some(None),
+            _ => None,
+        })??;
+
+    // The test of iteration `k` sees `init + (k + before) * step`, which cannot overflow an i128 for `k` up to 2^63
+    let before = if inc_block == exiting {
+        increment < test
+    } else {
+        dom.dominates(inc_block, exiting)
+    };
+    let value = |k: i128| i128::from(init) + (k + i128::from(before)) * i128::from(step);
+    let holds = |v: i128| {
+        let (a, b) = if side == 0 {
+            (v, i128::from(bound))
+        } else {
+            (i128::from(bound), v)
+        };
+        match op {
+            ValueOps::Lt => a < b,
+            ValueOps::Le => a <= b,
+            ValueOps::Gt => a > b,
+            ValueOps::Ge => a >= b,
+            _ => a == b,
+        }
+    };
+    let exits_at = |k: i128| holds(value(k)) != continue_when;
+    let k = if op == ValueOps::Eq && !continue_when {
+        // The loop runs until the variable lands exactly on the bound
+        let distance = i128::from(bound) - i128::from(init);
+        if step == 0 || distance % i128::from(step) != 0 {
+            return None;
+        }
+        distance / i128::from(step) - i128::from(before)
+    } else {
+        // Whether the loop exits only changes once as the variable moves in one direction
+        let (mut lo, mut hi) = (0, 1_i128 << 63);
+        if exits_at(0) {
+            hi = 0;
+        } else if !exits_at(hi) {
+            return None;
+        }
+        while lo < hi {
+            let mid = i128::midpoint(lo, hi);
+            if exits_at(mid) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        lo
+    };
+    i64::try_from(value(k)).ok()?;
+    u64::try_from(k).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_reducible, loop_forest, natural_loops, trip_count};
+    use crate::cfg::build_cfg;
+    use crate::dominators::dominators;
+    use crate::interp::{run, Value};
+    use crate::text::parse_and_convert;
+    use crate::Function;
+
+    #[test]
+    fn preheader_with_two_entering_blocks() {
+        let mut program = parse_and_convert(
+            "@main(c: bool) {
+  i: int = const 0;
+  br c .a .b;
+.a:
+  jmp .loop;
+.b:
+  jmp .loop;
+.loop:
+  one: int = const 1;
+  i: int = add i one;
+  ten: int = const 10;
+  done: bool = ge i ten;
+  br done .exit .loop;
+.exit:
+  print i;
+}",
+        )
+        .unwrap();
+        let func = &mut program.functions[0];
+        let cfg = build_cfg(func);
+        let lp = natural_loops(&cfg, &dominators(&cfg)).remove(0);
+        let preheader = func.insert_preheader(&lp, &cfg);
+
+        let cfg = build_cfg(func);
+        let header = cfg.block_by_label("loop").unwrap();
+        let entering: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|l| cfg.block_by_label(l).unwrap())
+            .collect();
+        assert_eq!(cfg.predecessors(preheader), entering);
+        assert_eq!(cfg.successors(preheader), [header]);
+        assert_eq!(cfg.predecessors(header), [preheader, header]);
+
+        let mut out = Vec::new();
+        run(&program, vec![Value::Bool(false)], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "10\n");
+    }
+
+    /// The [`trip_count`] of the only loop of the `@main` of ```src```
+    fn trip_count_of(src: &str) -> Option<u64> {
+        let program = parse_and_convert(src).unwrap();
+        let func = &program.functions[0];
+        let cfg = build_cfg(func);
+        let dom = dominators(&cfg);
+        let lp = natural_loops(&cfg, &dom).remove(0);
+        trip_count(&lp, func, &cfg, &dom)
+    }
+
+    #[test]
+    fn counted_trip_count() {
+        // `i` is 1 through 9 when the back edge is taken
+        let src = "@main {
+  i: int = const 0;
+  one: int = const 1;
+  ten: int = const 10;
+.loop:
+  i: int = add i one;
+  go: bool = lt i ten;
+  br go .loop .exit;
+.exit:
+  print i;
+}";
+        assert_eq!(trip_count_of(src), Some(9));
+    }
+
+    #[test]
+    fn data_dependent_trip_count() {
+        let src = "@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+.loop:
+  i: int = add i one;
+  go: bool = lt i n;
+  br go .loop .exit;
+.exit:
+  print i;
+}";
+        assert_eq!(trip_count_of(src), None);
+    }
+
+    /// The `@main` of ```src```, which is in the text format
+    fn main_of(src: &str) -> Function {
+        parse_and_convert(src).unwrap().functions.remove(0)
+    }
+
+    const TRIPLE_NEST: &str = "@main(c: bool) {
+  jmp .a;
+.a:
+  br c .b .x;
+.b:
+  br c .c .a;
+.c:
+  br c .c .b;
+.x:
+  ret;
+}";
+
+    #[test]
+    fn triply_nested_forest() {
+        let cfg = build_cfg(&main_of(TRIPLE_NEST));
+        let forest = loop_forest(&cfg, &dominators(&cfg));
+        let headers: Vec<_> = forest.loops.iter().map(|l| l.header).collect();
+        assert_eq!(headers, [1, 2, 3]);
+        assert_eq!(
+            (0..3).map(|l| forest.parent(l)).collect::<Vec<_>>(),
+            [None, Some(0), Some(1)]
+        );
+        assert_eq!(
+            (forest.children(0), forest.children(2)),
+            (&[1][..], &[][..])
+        );
+        assert_eq!(forest.roots().collect::<Vec<_>>(), [0]);
+        assert_eq!(forest.depth(2), 2);
+        assert_eq!(forest.innermost_loop_of(3), Some(2));
+        assert_eq!(forest.innermost_loop_of(4), None);
+        assert_eq!(forest.outermost_first(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn reducible_and_irreducible() {
+        let cfg = build_cfg(&main_of(TRIPLE_NEST));
+        assert!(is_reducible(&cfg, &dominators(&cfg)));
+        // A loop entered at both `.x` and `.y`, so neither dominates the other
+        let cfg = build_cfg(&main_of(
+            "@main(c: bool) {
+  br c .x .y;
+.x:
+  jmp .y;
+.y:
+  jmp .x;
+}",
+        ));
+        let dom = dominators(&cfg);
+        assert!(!is_reducible(&cfg, &dom));
+        assert!(natural_loops(&cfg, &dom).is_empty());
+    }
+}
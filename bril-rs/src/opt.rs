@@ -0,0 +1,2088 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::callgraph::call_graph;
+use crate::cfg::{build_cfg, BlockId, Cfg};
+use crate::dominators::Dominators;
+#[cfg(feature = "ssa")]
+use crate::liveness::interference;
+use crate::liveness::liveness;
+#[cfg(feature = "format")]
+use crate::FormatHints;
+use crate::{
+    Argument, Code, ConstOps, EffectOps, Function, Instruction, Literal, Program, Type, ValueOps,
+};
+
+/// Whether ```op``` always produces the same result from the same arguments without touching any other state
+const fn is_pure(op: ValueOps) -> bool {
+    match op {
+        ValueOps::Call | ValueOps::Id => false,
+        #[cfg(feature = "ssa")]
+        ValueOps::Phi => false,
+        #[cfg(feature = "memory")]
+        ValueOps::Alloc | ValueOps::Load => false,
+        _ => true,
+    }
+}
+
+/// Whether the order of the two arguments of ```op``` does not affect its result
+const fn is_commutative(op: ValueOps) -> bool {
+    match op {
+        ValueOps::Add | ValueOps::Mul | ValueOps::Eq | ValueOps::And | ValueOps::Or => true,
+        #[cfg(feature = "float")]
+        ValueOps::Fadd | ValueOps::Fmul | ValueOps::Feq => true,
+        #[cfg(feature = "char")]
+        ValueOps::Ceq => true,
+        _ => false,
+    }
+}
+
+/// Two instructions with the same key compute the same value when their arguments hold the same values
+#[derive(PartialEq, Eq, Hash)]
+struct Expr<'a> {
+    op: ValueOps,
+    op_type: &'a Type,
+    args: Vec<&'a str>,
+    funcs: &'a [String],
+    labels: &'a [String],
+}
+
+/// Eliminates common subexpressions of ```func``` whose earlier occurrence dominates the later one, returning how many instructions were replaced
+///
+/// A pure value operation is replaced by an `id` of an identical operation which dominates it, with the arguments of commutative operations compared in either order.
+/// This does not require SSA form and so is conservative: an operation is only reused when its destination is assigned nowhere else and each of its arguments is either a parameter which is never reassigned or a variable with a single assignment that dominates it.
+/// ```cfg``` and ```dom``` must describe ```func```; they remain valid afterwards since no control flow is changed.
+pub fn cse(func: &mut Function, cfg: &Cfg, dom: &Dominators) -> usize {
+    let mut defs: HashMap<&str, Vec<usize>> = HashMap::new();
+    for a in &func.args {
+        defs.entry(a.name.as_str()).or_default();
+    }
+    for (i, code) in func.instrs.iter().enumerate() {
+        if let Code::Instruction(
+            Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+        ) = code
+        {
+            defs.entry(dest.as_str()).or_default().push(i);
+        }
+    }
+    let is_param = |v: &str| func.args.iter().any(|a| a.name == v);
+    let dominates = |def: usize, at: usize| {
+        let (db, ab) = (cfg.block_of(def), cfg.block_of(at));
+        if db == ab {
+            def < at
+        } else {
+            dom.strictly_dominates(db, ab)
+        }
+    };
+    // Whether argument ```var``` of ```at``` holds the same value everywhere that ```at``` dominates
+    let is_stable = |var: &str, at: usize| match defs.get(var).map(Vec::as_slice) {
+        Some([]) => is_param(var),
+        Some(&[d]) => !is_param(var) && dominates(d, at),
+        _ => false,
+    };
+
+    let mut available: HashMap<Expr, Vec<(usize, &str)>> = HashMap::new();
+    let mut replacements: Vec<(usize, String)> = Vec::new();
+    let mut stack: Vec<BlockId> = dom.root().into_iter().collect();
+    while let Some(b) = stack.pop() {
+        stack.extend(dom.children(b).iter().rev());
+        for i in cfg.block_range(b) {
+            let Code::Instruction(Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op,
+                op_type,
+                ..
+            }) = &func.instrs[i]
+            else {
+                continue;
+            };
+            if !is_pure(*op) {
+                continue;
+            }
+            let mut key_args: Vec<&str> = args.iter().map(String::as_str).collect();
+            if is_commutative(*op) {
+                key_args.sort_unstable();
+            }
+            let key = Expr {
+                op: *op,
+                op_type,
+                args: key_args,
+                funcs,
+                labels,
+            };
+            let candidates = available.entry(key).or_default();
+            if let Some(&(_, source)) = candidates.iter().find(|&&(p, _)| dominates(p, i)) {
+                replacements.push((i, source.to_string()));
+            } else if defs[dest.as_str()] == [i]
+                && !is_param(dest)
+                && args.iter().all(|a| is_stable(a, i))
+            {
+                candidates.push((i, dest.as_str()));
+            }
+        }
+    }
+
+    let count = replacements.len();
+    for (i, source) in replacements {
+        if let Code::Instruction(Instruction::Value {
+            args,
+            funcs,
+            labels,
+            op,
+            ..
+        }) = &mut func.instrs[i]
+        {
+            *op = ValueOps::Id;
+            *args = vec![source];
+            funcs.clear();
+            labels.clear();
+        }
+    }
+    count
+}
+
+/// The name of the function called by ```code```, if it is a `call`
+fn callee_of(code: &Code) -> Option<&str> {
+    match code {
+        Code::Instruction(
+            Instruction::Value {
+                op: ValueOps::Call,
+                funcs,
+                ..
+            }
+            | Instruction::Effect {
+                op: EffectOps::Call,
+                funcs,
+                ..
+            },
+        ) => funcs.first().map(String::as_str),
+        _ => None,
+    }
+}
+
+const fn is_return(code: &Code) -> bool {
+    matches!(
+        code,
+        Code::Instruction(Instruction::Effect {
+            op: EffectOps::Return,
+            ..
+        })
+    )
+}
+
+/// Picks `name.k` for the smallest `k` such that no name in ```taken``` is it or starts with it followed by a `.`
+fn fresh_prefix(name: &str, taken: &HashSet<&str>) -> String {
+    let mut k = 0;
+    loop {
+        let prefix = format!("{name}.{k}");
+        if !taken.iter().any(|t| {
+            t.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        }) {
+            return prefix;
+        }
+        k += 1;
+    }
+}
+
+fn id(dest: String, op_type: Type, arg: String) -> Code {
+    Code::Instruction(Instruction::value(ValueOps::Id, dest, op_type, [arg]))
+}
+
+/// The number of instructions of ```func```, not counting labels
+fn size(func: &Function) -> usize {
+    func.instrs
+        .iter()
+        .filter(|c| matches!(c, Code::Instruction(_)))
+        .count()
+}
+
+impl Function {
+    /// Every variable and label named in this function
+    fn names(&self) -> HashSet<&str> {
+        let mut names: HashSet<&str> = self.args.iter().map(|a| a.name.as_str()).collect();
+        for code in &self.instrs {
+            match code {
+                Code::Label { label, .. } => {
+                    names.insert(label);
+                }
+                Code::Instruction(Instruction::Constant { dest, .. }) => {
+                    names.insert(dest);
+                }
+                Code::Instruction(Instruction::Value {
+                    args, dest, labels, ..
+                }) => {
+                    names.insert(dest);
+                    names.extend(args.iter().chain(labels).map(String::as_str));
+                }
+                Code::Instruction(Instruction::Effect { args, labels, .. }) => {
+                    names.extend(args.iter().chain(labels).map(String::as_str));
+                }
+            }
+        }
+        names
+    }
+
+    /// The code replacing the call of ```callee``` at ```index```, and the label it ends with if it needs one
+    fn inlined_body(&self, index: usize, callee: &Self) -> Option<(Vec<Code>, Option<String>)> {
+        let (dest, call_args) = match self.instrs.get(index)? {
+            Code::Instruction(Instruction::Value {
+                op: ValueOps::Call,
+                dest,
+                op_type,
+                args,
+                ..
+            }) => (Some((dest, op_type)), args),
+            Code::Instruction(Instruction::Effect {
+                op: EffectOps::Call,
+                args,
+                ..
+            }) => (None, args),
+            _ => return None,
+        };
+        if callee_of(&self.instrs[index]) != Some(callee.name.as_str())
+            || call_args.len() != callee.args.len()
+            || (dest.is_some() && callee.return_type.is_none())
+        {
+            return None;
+        }
+        let prefix = fresh_prefix(&callee.name, &self.names());
+        let rename = |name: &mut String| *name = format!("{prefix}.{name}");
+        // Without labels, the only `ret` that can run is the last instruction, after which control falls through anyway
+        let straight_line = callee.instrs.iter().enumerate().all(|(i, c)| match c {
+            Code::Label { .. } => false,
+            Code::Instruction(_) => !is_return(c) || i + 1 == callee.instrs.len(),
+        });
+        let exit = (!straight_line).then(|| prefix.clone());
+
+        let mut body: Vec<Code> = callee
+            .args
+            .iter()
+            .zip(call_args)
+            .map(|(param, arg)| {
+                let mut name = param.name.clone();
+                rename(&mut name);
+                id(name, param.arg_type.clone(), arg.clone())
+            })
+            .collect();
+        for code in &callee.instrs {
+            let mut code = code.clone();
+            match &mut code {
+                Code::Label { label, .. } => rename(label),
+                Code::Instruction(Instruction::Effect {
+                    op: EffectOps::Return,
+                    args,
+                    ..
+                }) => {
+                    if let (Some((dest, op_type)), Some(value)) = (dest, args.first_mut()) {
+                        rename(value);
+                        body.push(id(dest.clone(), op_type.clone(), value.clone()));
+                    }
+                    if let Some(exit) = &exit {
+                        body.push(Code::Instruction(Instruction::Effect {
+                            args: Vec::new(),
+                            funcs: Vec::new(),
+                            labels: vec![exit.clone()],
+                            op: EffectOps::Jump,
+                            #[cfg(feature = "position")]
+                            pos: None,
+                            #[cfg(feature = "format")]
+                            format: FormatHints::default(),
+                            #[cfg(feature = "metadata")]
+                            extra: serde_json::Map::new(),
+                        }));
+                    }
+                    continue;
+                }
+                Code::Instruction(Instruction::Constant { dest, .. }) => rename(dest),
+                Code::Instruction(Instruction::Value {
+                    args, dest, labels, ..
+                }) => {
+                    rename(dest);
+                    args.iter_mut().chain(labels).for_each(rename);
+                }
+                Code::Instruction(Instruction::Effect { args, labels, .. }) => {
+                    args.iter_mut().chain(labels).for_each(rename);
+                }
+            }
+            body.push(code);
+        }
+        if let Some(exit) = &exit {
+            body.push(Code::Label {
+                label: exit.clone(),
+                #[cfg(feature = "position")]
+                pos: None,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+            });
+        }
+        Some((body, exit))
+    }
+
+    /// Replaces the call at ```index``` with ```body``` from [`Function::inlined_body`]
+    fn splice_inlined(&mut self, index: usize, body: Vec<Code>, exit: Option<String>) {
+        // The rest of the calling block now starts at `exit`, so `phi`s must name it instead of the block the call was in
+        #[cfg(feature = "ssa")]
+        let block_label = exit.as_ref().and_then(|_| {
+            self.instrs[..index]
+                .iter()
+                .rev()
+                .take_while(|c| {
+                    !matches!(
+                        c,
+                        Code::Instruction(Instruction::Effect {
+                            op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+                            ..
+                        })
+                    )
+                })
+                .find_map(|c| match c {
+                    Code::Label { label, .. } => Some(label.clone()),
+                    Code::Instruction(_) => None,
+                })
+        });
+        let len = body.len();
+        self.instrs.splice(index..=index, body);
+        #[cfg(feature = "ssa")]
+        if let (Some(block_label), Some(exit)) = (block_label, exit) {
+            let (before, after) = self.instrs.split_at_mut(index + len);
+            for code in before[..index].iter_mut().chain(after) {
+                if let Code::Instruction(Instruction::Value {
+                    op: ValueOps::Phi,
+                    labels,
+                    ..
+                }) = code
+                {
+                    for l in labels.iter_mut().filter(|l| **l == block_label) {
+                        l.clone_from(&exit);
+                    }
+                }
+            }
+        }
+        #[cfg(not(feature = "ssa"))]
+        let _ = (exit, len);
+    }
+
+    /// Replaces the `call` of ```callee``` at ```index``` with a copy of the body of ```callee```, returning whether it did
+    ///
+    /// Every variable and label of the copy is prefixed with a name this function does not use. Each argument is copied into its renamed parameter with `id`, and each `ret` becomes an `id` into the call's destination and a `jmp` to a new label after the copy, which is left out for a callee whose only `ret` is its last instruction and which has no labels.
+    /// Nothing changes if ```index``` is not a call of ```callee``` with the right number of arguments.
+    pub fn inline_call(&mut self, index: usize, callee: &Self) -> bool {
+        match self.inlined_body(index, callee) {
+            Some((body, exit)) => {
+                self.splice_inlined(index, body, exit);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Program {
+    /// Inlines every call of a function with at most ```max_size``` instructions, not counting labels, which cannot call itself, returning how many calls were inlined
+    ///
+    /// Calls in inlined code are inlined in turn, and since callers grow as calls are inlined into them, the size of each function is rechecked until no more calls can be inlined.
+    /// Inlining stops adding code once the program would grow by more than ```budget``` instructions and labels in total. Functions are kept even when nothing calls them anymore.
+    pub fn inline_all_trivial(&mut self, max_size: usize, budget: usize) -> usize {
+        let mut spent = 0;
+        let mut inlined = 0;
+        loop {
+            let graph = call_graph(self);
+            let trivial: Vec<bool> = self
+                .functions
+                .iter()
+                .enumerate()
+                .map(|(i, f)| {
+                    #[cfg(feature = "external")]
+                    if f.external {
+                        return false;
+                    }
+                    size(f) <= max_size && !graph.is_recursive(i)
+                })
+                .collect();
+            let before = inlined;
+            for caller in 0..self.functions.len() {
+                let mut index = 0;
+                while index < self.functions[caller].instrs.len() {
+                    let callee = callee_of(&self.functions[caller].instrs[index])
+                        .and_then(|name| graph.function_id(name))
+                        .filter(|&c| c != caller && trivial[c]);
+                    let inlined_body = callee.and_then(|c| {
+                        self.functions[caller].inlined_body(index, &self.functions[c])
+                    });
+                    match inlined_body {
+                        // An empty body removes the call, so `index` is already at the next instruction
+                        Some((body, exit)) if spent + body.len().saturating_sub(1) <= budget => {
+                            spent += body.len().saturating_sub(1);
+                            index += body.len();
+                            inlined += 1;
+                            self.functions[caller].splice_inlined(index - body.len(), body, exit);
+                        }
+                        _ => index += 1,
+                    }
+                }
+            }
+            if inlined == before {
+                return inlined;
+            }
+        }
+    }
+}
+
+/// Whether ```a``` and ```b``` are the same instruction, wherever in the source they came from
+fn same_instruction(a: &Instruction, b: &Instruction) -> bool {
+    #[cfg(feature = "position")]
+    {
+        let (mut a, mut b) = (a.clone(), b.clone());
+        for instr in [&mut a, &mut b] {
+            match instr {
+                Instruction::Constant { pos, .. }
+                | Instruction::Value { pos, .. }
+                | Instruction::Effect { pos, .. } => *pos = None,
+            }
+        }
+        a == b
+    }
+    #[cfg(not(feature = "position"))]
+    {
+        a == b
+    }
+}
+
+/// Rewrites each `br` whose two arms lead to the same place into a `jmp`, returning how many were rewritten
+///
+/// The arms lead to the same place when they name the same label, or labels of blocks with the same instructions ending in a `jmp`, `br`, or `ret`, in which case the `jmp` goes to the first label. The condition is left for dead code elimination to remove.
+/// Blocks that fall through are never compared, so `br c .l .r; .l: print x; .r: print x;` is left unchanged, and so is a `br` to an empty block which falls into the other arm, even though both arms then lead to the same place.
+/// Under the `ssa` feature, a block named by some `phi` is never treated as the same as another block, since the `phi` could tell which one ran.
+pub fn coalesce_branches(func: &mut Function) -> usize {
+    let cfg = build_cfg(func);
+    #[allow(unused_mut)]
+    let mut phi_labels: HashSet<&str> = HashSet::new();
+    #[cfg(feature = "ssa")]
+    for code in &func.instrs {
+        if let Code::Instruction(Instruction::Value {
+            op: ValueOps::Phi,
+            labels,
+            ..
+        }) = code
+        {
+            phi_labels.extend(labels.iter().map(String::as_str));
+        }
+    }
+    let body = |label: &str| -> Option<Vec<&Instruction>> {
+        let block = cfg.block_by_label(label)?;
+        let instrs: Vec<&Instruction> = func.instrs[cfg.block_range(block)]
+            .iter()
+            .filter_map(|c| match c {
+                Code::Instruction(instr) => Some(instr),
+                Code::Label { .. } => None,
+            })
+            .collect();
+        matches!(
+            instrs.last(),
+            Some(Instruction::Effect {
+                op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+                ..
+            })
+        )
+        .then_some(instrs)
+    };
+    let same_block = |a: &str, b: &str| {
+        if phi_labels.contains(a) || phi_labels.contains(b) {
+            return false;
+        }
+        match (body(a), body(b)) {
+            (Some(a), Some(b)) => {
+                a.len() == b.len() && a.iter().zip(&b).all(|(x, y)| same_instruction(x, y))
+            }
+            _ => false,
+        }
+    };
+
+    let targets: Vec<(usize, String)> = func
+        .instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, code)| match code {
+            Code::Instruction(Instruction::Effect {
+                op: EffectOps::Branch,
+                labels,
+                ..
+            }) => match labels.as_slice() {
+                [t, f] if t == f || same_block(t, f) => Some((i, t.clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let count = targets.len();
+    for (i, target) in targets {
+        if let Code::Instruction(Instruction::Effect {
+            args, labels, op, ..
+        }) = &mut func.instrs[i]
+        {
+            *op = EffectOps::Jump;
+            args.clear();
+            *labels = vec![target];
+        }
+    }
+    count
+}
+
+/// The ways that [outline] can fail, in which case the function is unchanged
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum OutlineError {
+    /// The range is empty or goes past the end of the function
+    #[error("`{0}..{1}` is not a non-empty range of instructions in the function")]
+    BadRange(usize, usize),
+    /// A label inside the region is the target of a jump, branch, or `phi` outside it
+    #[error("label `{0}` inside the region is referenced from outside it")]
+    EnteredFromOutside(String),
+    /// The region refers to a label outside it other than the one where it ends
+    #[error("the region refers to label `{0}` outside it")]
+    ExitsElsewhere(String),
+    /// The region contains a `ret`
+    #[error("the region contains a `ret`")]
+    ContainsReturn,
+    /// More than one variable assigned in the region is used after it
+    #[error("the region assigns {0:?}, which are all used after it, but a function can only return one value")]
+    MultipleLiveOuts(Vec<String>),
+    /// A variable passed into or out of the region is assigned values of different types
+    #[error("the type of `{0}` is not known")]
+    UnknownType(String),
+}
+
+/// The labels ```code``` refers to, both as jump targets and `phi` predecessors
+fn referenced_labels(code: &Code) -> &[String] {
+    match code {
+        Code::Instruction(
+            Instruction::Effect { labels, .. } | Instruction::Value { labels, .. },
+        ) => labels,
+        _ => &[],
+    }
+}
+
+/// Moves the code at ```range``` of ```func``` into a new function named ```new_name```, replacing it with a `call`, and returns the new function
+///
+/// The region must be entered only at its start and left only by falling through its end or jumping to a label right after it, and it may not `ret`. Its parameters are the variables it may read before assigning them, in sorted order, and it returns the one variable it assigns which may be read afterwards, if there is one.
+/// The caller is responsible for adding the new function to the program under a name which is not taken yet.
+/// # Errors
+/// Returns an [`OutlineError`] if the region cannot be outlined, which leaves ```func``` unchanged
+pub fn outline(
+    func: &mut Function,
+    range: Range<usize>,
+    new_name: &str,
+) -> Result<Function, OutlineError> {
+    if range.start >= range.end || range.end > func.instrs.len() {
+        return Err(OutlineError::BadRange(range.start, range.end));
+    }
+    let region = &func.instrs[range.clone()];
+    let exit_labels: Vec<&String> = func.instrs[range.end..]
+        .iter()
+        .map_while(|c| match c {
+            Code::Label { label, .. } => Some(label),
+            Code::Instruction(_) => None,
+        })
+        .collect();
+    let inside_labels: HashSet<&String> = region
+        .iter()
+        .filter_map(|c| match c {
+            Code::Label { label, .. } => Some(label),
+            Code::Instruction(_) => None,
+        })
+        .collect();
+    for (i, code) in func.instrs.iter().enumerate() {
+        for label in referenced_labels(code) {
+            let referenced_inside = inside_labels.contains(label);
+            if range.contains(&i) {
+                if !referenced_inside && !exit_labels.contains(&label) {
+                    return Err(OutlineError::ExitsElsewhere(label.clone()));
+                }
+            } else if referenced_inside {
+                return Err(OutlineError::EnteredFromOutside(label.clone()));
+            }
+        }
+    }
+    if region.iter().any(is_return) {
+        return Err(OutlineError::ContainsReturn);
+    }
+
+    let types = func.known_types();
+    let type_of = |var: &str| {
+        types
+            .get(var)
+            .map(|&t| t.clone())
+            .ok_or_else(|| OutlineError::UnknownType(var.to_string()))
+    };
+
+    let cfg = build_cfg(func);
+    let live_after = liveness(func, &cfg).live_before(func, &cfg, range.end);
+    let mut live_outs: Vec<&String> = region
+        .iter()
+        .filter_map(|c| match c {
+            Code::Instruction(
+                Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+            ) if live_after.contains(dest) => Some(dest),
+            _ => None,
+        })
+        .collect();
+    live_outs.sort_unstable();
+    live_outs.dedup();
+    let result = match live_outs.as_slice() {
+        [] => None,
+        [var] => Some(((*var).clone(), type_of(var)?)),
+        _ => {
+            return Err(OutlineError::MultipleLiveOuts(
+                live_outs.into_iter().cloned().collect(),
+            ))
+        }
+    };
+
+    let mut body = region.to_vec();
+    // Jumps to the end of the region now jump to the end of the new function
+    let exits_used = exit_labels
+        .into_iter()
+        .filter(|l| region.iter().any(|c| referenced_labels(c).contains(l)));
+    body.extend(exits_used.map(|label| Code::Label {
+        label: label.clone(),
+        #[cfg(feature = "position")]
+        pos: None,
+        #[cfg(feature = "format")]
+        format: FormatHints::default(),
+    }));
+    if let Some((var, _)) = &result {
+        body.push(Code::Instruction(Instruction::Effect {
+            args: vec![var.clone()],
+            funcs: Vec::new(),
+            labels: Vec::new(),
+            op: EffectOps::Return,
+            #[cfg(feature = "position")]
+            pos: None,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }));
+    }
+    let mut new_func = Function {
+        args: Vec::new(),
+        instrs: body,
+        name: new_name.to_string(),
+        #[cfg(feature = "position")]
+        pos: None,
+        return_type: result.as_ref().map(|(_, t)| t.clone()),
+        #[cfg(feature = "external")]
+        external: false,
+    };
+    // Whatever the body may read before assigning it must be passed in, including a result which is only assigned on some paths
+    let body_cfg = build_cfg(&new_func);
+    let mut params: Vec<String> = liveness(&new_func, &body_cfg)
+        .live_in(body_cfg.entry)
+        .iter()
+        .cloned()
+        .collect();
+    params.sort_unstable();
+    new_func.args = params
+        .iter()
+        .map(|name| {
+            Ok(Argument {
+                name: name.clone(),
+                arg_type: type_of(name)?,
+            })
+        })
+        .collect::<Result<_, OutlineError>>()?;
+
+    let call = match result {
+        Some((dest, op_type)) => Instruction::Value {
+            args: params,
+            dest,
+            funcs: vec![new_name.to_string()],
+            labels: Vec::new(),
+            op: ValueOps::Call,
+            #[cfg(feature = "position")]
+            pos: None,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+            op_type,
+        },
+        None => Instruction::Effect {
+            args: params,
+            funcs: vec![new_name.to_string()],
+            labels: Vec::new(),
+            op: EffectOps::Call,
+            #[cfg(feature = "position")]
+            pos: None,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        },
+    };
+    drop(types);
+    func.instrs.splice(range, [Code::Instruction(call)]);
+    Ok(new_func)
+}
+
+/// Where a copy into a `phi` of a successor of ```block``` goes when leaving SSA: before its terminator, or at its end if it falls through
+#[cfg(feature = "ssa")]
+fn copy_point(func: &Function, cfg: &Cfg, block: BlockId) -> usize {
+    let range = cfg.block_range(block);
+    match func.instrs[range.clone()].last() {
+        Some(Code::Instruction(Instruction::Effect {
+            op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+            ..
+        })) => range.end - 1,
+        _ => range.end,
+    }
+}
+
+/// Gives each `phi` and the arguments it is connected to the same name wherever that cannot change what the function computes, returning how many variables were renamed
+///
+/// Variables are merged greedily, one `phi` argument at a time, while no two variables with the same name would interfere, and only when the argument is always assigned the `phi`'s type. A `phi`'s destination also may not share a name with anything live where [`from_ssa`] copies into it at the end of each predecessor, other than that predecessor's argument, so the copies it still needs are correct, nor with that argument when the destination is live into another successor of the predecessor. A parameter keeps its own name, and a `phi` left with only its own destination as arguments is removed.
+/// The result is generally no longer in SSA form, but remaining `phi`s still behave the same.
+#[cfg(feature = "ssa")]
+pub fn coalesce_phi_webs(func: &mut Function) -> usize {
+    let cfg = build_cfg(func);
+    let live = liveness(func, &cfg);
+    let mut conflicts = interference(func, &cfg, &live);
+    let types = func.known_types();
+    let mut phis = Vec::new();
+    for (i, code) in func.instrs.iter().enumerate() {
+        if let Code::Instruction(Instruction::Value {
+            op: ValueOps::Phi,
+            dest,
+            args,
+            labels,
+            op_type,
+            ..
+        }) = code
+        {
+            for (arg, label) in args.iter().zip(labels) {
+                if let Some(pred) = cfg.block_by_label(label) {
+                    let point = copy_point(func, &cfg, pred);
+                    let mut at_copy = if point < cfg.block_range(pred).end {
+                        live.live_before(func, &cfg, point)
+                    } else {
+                        live.live_out(pred).clone()
+                    };
+                    at_copy.remove(arg);
+                    at_copy.remove(dest);
+                    // Once merged with the argument, the destination would also change along the predecessor's other edges
+                    let phi_block = cfg.block_of(i);
+                    if cfg
+                        .successors(pred)
+                        .iter()
+                        .any(|&s| s != phi_block && live.live_in(s).contains(dest))
+                    {
+                        at_copy.insert(arg.clone());
+                    }
+                    for other in at_copy {
+                        conflicts
+                            .entry(dest.clone())
+                            .or_default()
+                            .insert(other.clone());
+                        conflicts.entry(other).or_default().insert(dest.clone());
+                    }
+                }
+            }
+            // Only variables which are always assigned this type can join the web, which leaves out ones never assigned at all like `__undefined`
+            let mergeable = args
+                .iter()
+                .filter(|a| types.get(a.as_str()) == Some(&op_type))
+                .cloned()
+                .collect::<Vec<_>>();
+            phis.push((dest.clone(), mergeable));
+        }
+    }
+
+    let params: HashSet<&str> = func.args.iter().map(|a| a.name.as_str()).collect();
+    // The name each variable is merged into, and the variables merged into each name
+    let mut name_of: HashMap<String, String> = HashMap::new();
+    let mut members: HashMap<String, Vec<String>> = HashMap::new();
+    let find = |name_of: &HashMap<String, String>, v: &str| {
+        name_of.get(v).cloned().unwrap_or_else(|| v.to_string())
+    };
+    let mut renamed = 0;
+    for (dest, args) in phis {
+        for arg in args {
+            let (mut keep, mut merge) = (find(&name_of, &dest), find(&name_of, &arg));
+            if keep == merge {
+                continue;
+            }
+            if params.contains(merge.as_str()) {
+                std::mem::swap(&mut keep, &mut merge);
+            }
+            let kept = members.remove(&keep).unwrap_or_else(|| vec![keep.clone()]);
+            let merged = members
+                .remove(&merge)
+                .unwrap_or_else(|| vec![merge.clone()]);
+            let interferes = kept.iter().any(|k| {
+                conflicts
+                    .get(k)
+                    .is_some_and(|c| merged.iter().any(|m| c.contains(m)))
+            });
+            if interferes {
+                members.insert(keep, kept);
+                members.insert(merge, merged);
+                continue;
+            }
+            for m in &merged {
+                name_of.insert(m.clone(), keep.clone());
+            }
+            renamed += 1;
+            members.insert(keep, kept.into_iter().chain(merged).collect());
+        }
+    }
+    if renamed == 0 {
+        return 0;
+    }
+
+    let rename = |v: &mut String| {
+        if let Some(n) = name_of.get(v.as_str()) {
+            v.clone_from(n);
+        }
+    };
+    for code in &mut func.instrs {
+        match code {
+            Code::Instruction(Instruction::Constant { dest, .. }) => rename(dest),
+            Code::Instruction(Instruction::Value { dest, args, .. }) => {
+                rename(dest);
+                args.iter_mut().for_each(rename);
+            }
+            Code::Instruction(Instruction::Effect { args, .. }) => args.iter_mut().for_each(rename),
+            Code::Label { .. } => {}
+        }
+    }
+    func.instrs.retain(|c| {
+        !matches!(
+            c,
+            Code::Instruction(Instruction::Value {
+                op: ValueOps::Phi,
+                dest,
+                args,
+                ..
+            }) if args.iter().all(|a| a == dest)
+        )
+    });
+    renamed
+}
+
+/// Takes ```func``` out of SSA form by replacing each `phi` with copies into its destination on the edges from its predecessors
+///
+/// Names are first merged with [`coalesce_phi_webs`] so fewer copies are needed. Each copy is an `id` placed before the predecessor's terminator, if it has one, and copies whose argument already has the destination's name, or which copy a variable that is never assigned like the `__undefined` of the reference `to_ssa`, are left out. When the predecessor has other successors the edge is critical, so its copies instead go in a new block placed after the predecessor, which its branch is redirected to and which then jumps to the `phi`'s block.
+#[cfg(feature = "ssa")]
+pub fn from_ssa(func: &mut Function) {
+    coalesce_phi_webs(func);
+    let cfg = build_cfg(func);
+    let mut assigned: HashSet<String> = func.args.iter().map(|a| a.name.clone()).collect();
+    assigned.extend(func.instrs.iter().filter_map(|c| match c {
+        Code::Instruction(Instruction::Constant { dest, .. } | Instruction::Value { dest, .. }) => {
+            Some(dest.clone())
+        }
+        _ => None,
+    }));
+    let mut copies: Vec<(usize, Code)> = Vec::new();
+    // The copies for each critical edge, by predecessor and `phi` block
+    let mut split: Vec<((BlockId, BlockId), Vec<Code>)> = Vec::new();
+    for (i, code) in func.instrs.iter().enumerate() {
+        if let Code::Instruction(Instruction::Value {
+            op: ValueOps::Phi,
+            dest,
+            args,
+            labels,
+            op_type,
+            ..
+        }) = code
+        {
+            for (arg, label) in args.iter().zip(labels) {
+                if arg == dest || !assigned.contains(arg) {
+                    continue;
+                }
+                if let Some(pred) = cfg.block_by_label(label) {
+                    let copy = id(dest.clone(), op_type.clone(), arg.clone());
+                    let edge = (pred, cfg.block_of(i));
+                    if cfg.successors(pred).len() < 2 {
+                        copies.push((copy_point(func, &cfg, pred), copy));
+                    } else if let Some((_, edge_copies)) =
+                        split.iter_mut().find(|(e, _)| *e == edge)
+                    {
+                        edge_copies.push(copy);
+                    } else {
+                        split.push((edge, vec![copy]));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut taken: HashSet<String> = func.labels().map(ToString::to_string).collect();
+    // New blocks go before the copies at the same point, which belong to the block after them
+    let mut blocks: Vec<(usize, Vec<Code>)> = Vec::new();
+    for ((pred, target), edge_copies) in split {
+        let Some(target_label) = cfg.blocks[target].label.clone() else {
+            continue;
+        };
+        let from = cfg.blocks[pred].label.as_deref().unwrap_or(&func.name);
+        let label = {
+            let taken = taken.iter().map(String::as_str).collect();
+            crate::loops::fresh(&format!("{from}.{target_label}"), &taken)
+        };
+        let range = cfg.block_range(pred);
+        if let Some(Code::Instruction(Instruction::Effect { labels, .. })) =
+            func.instrs.get_mut(range.end - 1)
+        {
+            for l in labels.iter_mut().filter(|l| **l == target_label) {
+                l.clone_from(&label);
+            }
+        }
+        let mut block = vec![Code::Label {
+            label: label.clone(),
+            #[cfg(feature = "position")]
+            pos: None,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+        }];
+        block.extend(edge_copies);
+        block.push(Code::Instruction(Instruction::effect(
+            EffectOps::Jump,
+            Vec::<String>::new(),
+            [target_label],
+            Vec::<String>::new(),
+        )));
+        blocks.push((range.end, block));
+        taken.insert(label);
+    }
+    blocks.sort_by_key(|(i, _)| *i);
+    copies.sort_by_key(|(i, _)| *i);
+    let mut blocks = blocks.into_iter().peekable();
+    let mut copies = copies.into_iter().peekable();
+    let mut instrs = Vec::with_capacity(func.instrs.len());
+    for (i, code) in std::mem::take(&mut func.instrs).into_iter().enumerate() {
+        while let Some((_, block)) = blocks.next_if(|(point, _)| *point == i) {
+            instrs.extend(block);
+        }
+        while let Some((_, copy)) = copies.next_if(|(point, _)| *point == i) {
+            instrs.push(copy);
+        }
+        if !matches!(
+            code,
+            Code::Instruction(Instruction::Value {
+                op: ValueOps::Phi,
+                ..
+            })
+        ) {
+            instrs.push(code);
+        }
+    }
+    instrs.extend(blocks.flat_map(|(_, block)| block));
+    instrs.extend(copies.map(|(_, copy)| copy));
+    func.instrs = instrs;
+}
+
+/// Folds away each `phi` whose arguments are all the same variable, along with the `id` copies this leaves behind, returning how many instructions were removed
+///
+/// A `phi` is trivial when its arguments other than its own destination all name one variable, and either it or an `id` is only folded when its destination and the variable it copies are each assigned exactly once, counting parameters, and not when a `phi` would then read the destination of another `phi`. The destination of a folded instruction is read as the variable it copies everywhere instead, which in SSA form always holds the same value. Folding repeats until no more apply, since removing one can make another `phi` trivial.
+#[cfg(feature = "ssa")]
+pub fn fold_trivial_phis(func: &mut Function) -> usize {
+    let mut removed = 0;
+    loop {
+        let mut assignments: HashMap<&str, usize> = HashMap::new();
+        for name in func
+            .args
+            .iter()
+            .map(|a| a.name.as_str())
+            .chain(func.instrs.iter().filter_map(|c| match c {
+                Code::Instruction(
+                    Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+                ) => Some(dest.as_str()),
+                _ => None,
+            }))
+        {
+            *assignments.entry(name).or_default() += 1;
+        }
+        let single = |v: &str| assignments.get(v) == Some(&1);
+        let (mut phi_dests, mut phi_reads) = (HashSet::new(), HashSet::new());
+        for code in &func.instrs {
+            if let Code::Instruction(Instruction::Value {
+                op: ValueOps::Phi,
+                dest,
+                args,
+                ..
+            }) = code
+            {
+                phi_dests.insert(dest.as_str());
+                phi_reads.extend(args.iter().map(String::as_str));
+            }
+        }
+        let fold = func.instrs.iter().enumerate().find_map(|(i, c)| {
+            let Code::Instruction(Instruction::Value { op, dest, args, .. }) = c else {
+                return None;
+            };
+            let mut incoming = args.iter().filter(|a| *a != dest);
+            let first = incoming.next()?;
+            let trivial = match op {
+                ValueOps::Phi => incoming.all(|a| a == first),
+                ValueOps::Id => args.len() == 1,
+                _ => false,
+            };
+            // `phi`s run one after another, so one must not come to read another's destination in place of a copy of its old value
+            let reorders = phi_reads.contains(dest.as_str()) && phi_dests.contains(first.as_str());
+            (trivial && single(dest) && single(first) && !reorders)
+                .then(|| (i, dest.clone(), first.clone()))
+        });
+        let Some((index, from, to)) = fold else {
+            return removed;
+        };
+        func.instrs.remove(index);
+        removed += 1;
+        for code in &mut func.instrs {
+            if let Code::Instruction(
+                Instruction::Value { args, .. } | Instruction::Effect { args, .. },
+            ) = code
+            {
+                for a in args.iter_mut().filter(|a| **a == from) {
+                    a.clone_from(&to);
+                }
+            }
+        }
+    }
+}
+
+/// Whether ```code``` can run on a path which did not run it before without changing what the function does, which rules out effects and operations which may fail
+#[cfg(all(feature = "ssa", feature = "select"))]
+const fn can_speculate(code: &Code) -> bool {
+    match code {
+        Code::Instruction(Instruction::Constant { .. }) => true,
+        Code::Instruction(Instruction::Value { op, .. }) => match op {
+            ValueOps::Id => true,
+            ValueOps::Div => false,
+            #[cfg(feature = "char")]
+            ValueOps::Int2char => false,
+            op => is_pure(*op),
+        },
+        _ => false,
+    }
+}
+
+/// The variables of ```func``` which hold a value whenever they are read, assuming it is in SSA form
+///
+/// This is every variable except those assigned only by `phi`s which may read a variable that is never assigned, like the `__undefined` of the reference `to_ssa`, and so may leave their destination undefined.
+#[cfg(all(feature = "ssa", feature = "select"))]
+fn always_defined(func: &Function) -> HashSet<&str> {
+    let mut defined: HashSet<&str> = func.args.iter().map(|a| a.name.as_str()).collect();
+    let mut phis = Vec::new();
+    for code in &func.instrs {
+        match code {
+            Code::Instruction(Instruction::Value {
+                op: ValueOps::Phi,
+                dest,
+                args,
+                ..
+            }) => phis.push((dest.as_str(), args)),
+            Code::Instruction(
+                Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+            ) => {
+                defined.insert(dest);
+            }
+            _ => {}
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (dest, args) in &phis {
+            if !defined.contains(dest) && args.iter().all(|a| defined.contains(a.as_str())) {
+                defined.insert(dest);
+                changed = true;
+            }
+        }
+    }
+    defined
+}
+
+/// Finds a diamond closed by ```join```, returning the index of its `br` and the blocks it branches to when `true` and `false`
+///
+/// Both arms must be reached only from the block ending in the `br`, lead only to ```join```, and contain nothing but ```can_speculate``` instructions besides a final `jmp`.
+#[cfg(all(feature = "ssa", feature = "select"))]
+fn diamond(func: &Function, cfg: &Cfg, join: BlockId) -> Option<(usize, BlockId, BlockId)> {
+    let &[p1, p2] = cfg.predecessors(join) else {
+        return None;
+    };
+    let &[head] = cfg.predecessors(p1) else {
+        return None;
+    };
+    let branch = cfg.block_range(head).end - 1;
+    let Code::Instruction(Instruction::Effect {
+        op: EffectOps::Branch,
+        labels,
+        ..
+    }) = &func.instrs[branch]
+    else {
+        return None;
+    };
+    let (t, f) = (
+        cfg.block_by_label(&labels[0])?,
+        cfg.block_by_label(&labels[1])?,
+    );
+    if p1 == p2 || cfg.predecessors(p2) != [head] || ![(p1, p2), (p2, p1)].contains(&(t, f)) {
+        return None;
+    }
+    let speculable = [t, f].into_iter().all(|arm| {
+        cfg.successors(arm) == [join]
+            && func.instrs[cfg.block_range(arm)].iter().all(|c| {
+                matches!(
+                    c,
+                    Code::Label { .. }
+                        | Code::Instruction(Instruction::Effect {
+                            op: EffectOps::Jump,
+                            ..
+                        })
+                ) || can_speculate(c)
+            })
+    });
+    speculable.then_some((branch, t, f))
+}
+
+/// Replaces each `phi` which merges the two arms of an `if`/`else` with a `select` on the branch's condition, returning how many were replaced
+///
+/// This only handles a diamond: a `br` to two blocks which are reached from nowhere else and both lead only to the block of the `phi`s. The instructions of both arms are moved before the `br`, which becomes a `jmp` to the join, so they must be free of effects and of operations which may fail, like `div`. Every `phi` of the join must merge the two arms and, since a `select` reads both of its arguments, neither may be left undefined by a `phi` of a variable that is never assigned, and neither may any variable the arms read. The arms are then removed.
+#[cfg(all(feature = "ssa", feature = "select"))]
+pub fn phis_to_selects(func: &mut Function) -> usize {
+    let mut replaced = 0;
+    'lower: loop {
+        let cfg = build_cfg(func);
+        let defined = always_defined(func);
+        'join: for join in 0..cfg.blocks.len() {
+            let Some(join_label) = cfg.blocks[join].label.clone() else {
+                continue;
+            };
+            let Some((branch, t, f)) = diamond(func, &cfg, join) else {
+                continue;
+            };
+            let Code::Instruction(Instruction::Effect { args, .. }) = &func.instrs[branch] else {
+                unreachable!("`diamond` found a `br`");
+            };
+            let cond = args[0].clone();
+            let mut selects = Vec::new();
+            for i in cfg.block_range(join) {
+                let Code::Instruction(Instruction::Value {
+                    op: ValueOps::Phi,
+                    dest,
+                    args,
+                    labels,
+                    op_type,
+                    ..
+                }) = &func.instrs[i]
+                else {
+                    continue;
+                };
+                let arm_arg = |arm| {
+                    labels
+                        .iter()
+                        .position(|l| cfg.block_by_label(l) == Some(arm))
+                        .map(|k| args[k].clone())
+                };
+                let (Some(a), Some(b)) = (arm_arg(t), arm_arg(f)) else {
+                    continue 'join;
+                };
+                if labels.len() != 2
+                    || !defined.contains(a.as_str())
+                    || !defined.contains(b.as_str())
+                {
+                    continue 'join;
+                }
+                let select = Instruction::Value {
+                    args: vec![cond.clone(), a, b],
+                    dest: dest.clone(),
+                    funcs: Vec::new(),
+                    labels: Vec::new(),
+                    op: ValueOps::Select,
+                    #[cfg(feature = "position")]
+                    pos: None,
+                    #[cfg(feature = "format")]
+                    format: FormatHints::default(),
+                    #[cfg(feature = "metadata")]
+                    extra: serde_json::Map::new(),
+                    op_type: op_type.clone(),
+                };
+                selects.push((i, Code::Instruction(select)));
+            }
+            let arms = [cfg.block_range(t), cfg.block_range(f)];
+            let reads_undefined = arms.iter().flat_map(|arm| &func.instrs[arm.clone()]).any(|c| {
+                matches!(c, Code::Instruction(Instruction::Value { args, .. }) if args.iter().any(|a| !defined.contains(a.as_str())))
+            });
+            if selects.is_empty() || reads_undefined {
+                continue;
+            }
+
+            replaced += selects.len();
+            for (i, select) in selects {
+                func.instrs[i] = select;
+            }
+            let old = std::mem::take(&mut func.instrs);
+            for (i, code) in old.iter().enumerate() {
+                if arms.iter().any(|arm| arm.contains(&i)) {
+                    continue;
+                }
+                if i != branch {
+                    func.instrs.push(code.clone());
+                    continue;
+                }
+                let hoisted = arms.iter().flat_map(|arm| &old[arm.clone()]);
+                func.instrs
+                    .extend(hoisted.filter(|c| can_speculate(c)).cloned());
+                func.instrs.push(Code::Instruction(Instruction::Effect {
+                    args: Vec::new(),
+                    funcs: Vec::new(),
+                    labels: vec![join_label.clone()],
+                    op: EffectOps::Jump,
+                    #[cfg(feature = "position")]
+                    pos: None,
+                    #[cfg(feature = "format")]
+                    format: FormatHints::default(),
+                    #[cfg(feature = "metadata")]
+                    extra: serde_json::Map::new(),
+                }));
+            }
+            continue 'lower;
+        }
+        return replaced;
+    }
+}
+
+/// Whether the `const` at ```index``` assigning ```dest``` can be removed by reading ```source``` instead for the rest of its block, returning the instructions that read ```dest``` if so
+fn reads_until_reassigned(
+    func: &Function,
+    cfg: &Cfg,
+    live_out: &HashSet<String>,
+    index: usize,
+    dest: &str,
+    source: &str,
+) -> Option<Vec<usize>> {
+    let mut reads = Vec::new();
+    let mut source_reassigned = false;
+    for j in index + 1..cfg.block_range(cfg.block_of(index)).end {
+        let Code::Instruction(instr) = &func.instrs[j] else {
+            continue;
+        };
+        let (args, assigned) = match instr {
+            Instruction::Constant { dest, .. } => (&[][..], Some(dest)),
+            Instruction::Value { args, dest, .. } => (&args[..], Some(dest)),
+            Instruction::Effect { args, .. } => (&args[..], None),
+        };
+        if args.iter().any(|a| a == dest) {
+            if source_reassigned {
+                return None;
+            }
+            reads.push(j);
+        }
+        match assigned {
+            Some(d) if d == dest => return Some(reads),
+            Some(d) if d == source => source_reassigned = true,
+            _ => {}
+        }
+    }
+    (!live_out.contains(dest)).then_some(reads)
+}
+
+/// Removes each `const` whose value another variable already holds, returning how many were removed
+///
+/// Only constants assigned earlier in the same block are reused, and the reads of the removed destination are rewritten to the variable which holds the value. A `const` is kept when its destination is read after the other variable is reassigned, or may be read after the block ends. Constants are the same when they have the same type and value.
+pub fn dedupe_constants(func: &mut Function) -> usize {
+    let cfg = build_cfg(func);
+    let live = liveness(func, &cfg);
+    let mut removed = Vec::new();
+    let mut renames: Vec<(usize, String, String)> = Vec::new();
+    for b in 0..cfg.len() {
+        // The variables which hold a constant at this point in the block, and the constant
+        let mut holding: Vec<(&Type, &Literal, &str)> = Vec::new();
+        for i in cfg.block_range(b) {
+            let dest = match &func.instrs[i] {
+                Code::Instruction(Instruction::Constant {
+                    dest,
+                    const_type,
+                    value,
+                    ..
+                }) => {
+                    let source = holding
+                        .iter()
+                        .find(|&&(t, v, var)| t == const_type && v == value && var != dest)
+                        .map(|&(_, _, var)| var);
+                    if let Some(source) = source {
+                        if let Some(reads) =
+                            reads_until_reassigned(func, &cfg, live.live_out(b), i, dest, source)
+                        {
+                            removed.push(i);
+                            renames.extend(
+                                reads
+                                    .into_iter()
+                                    .map(|j| (j, dest.clone(), source.to_string())),
+                            );
+                            holding.retain(|&(_, _, var)| var != dest);
+                            continue;
+                        }
+                    }
+                    holding.retain(|&(_, _, var)| var != dest);
+                    holding.push((const_type, value, dest));
+                    continue;
+                }
+                Code::Instruction(Instruction::Value { dest, .. }) => dest,
+                _ => continue,
+            };
+            holding.retain(|&(_, _, var)| var != dest);
+        }
+    }
+
+    for (j, from, to) in renames {
+        if let Code::Instruction(
+            Instruction::Value { args, .. } | Instruction::Effect { args, .. },
+        ) = &mut func.instrs[j]
+        {
+            for a in args.iter_mut().filter(|a| **a == from) {
+                a.clone_from(&to);
+            }
+        }
+    }
+    let count = removed.len();
+    let removed: HashSet<usize> = removed.into_iter().collect();
+    let mut i = 0;
+    func.instrs.retain(|_| {
+        i += 1;
+        !removed.contains(&(i - 1))
+    });
+    count
+}
+
+/// What an operation is known to produce without being run
+enum Identity {
+    /// Always this value
+    Constant(Literal),
+    /// Always the value of its argument
+    Copy,
+}
+
+/// What an operation of ```op``` with the same variable as both arguments always produces, if it is simpler
+///
+/// `float` operations are never simplified, since `NaN` is not equal to itself and `x - x` is `NaN` for infinite `x`. Neither is `div`, which fails when the variable is zero.
+const fn identity(op: ValueOps) -> Option<Identity> {
+    match op {
+        ValueOps::Sub => Some(Identity::Constant(Literal::Int(0))),
+        ValueOps::And | ValueOps::Or => Some(Identity::Copy),
+        ValueOps::Eq | ValueOps::Le | ValueOps::Ge => Some(Identity::Constant(Literal::Bool(true))),
+        ValueOps::Lt | ValueOps::Gt => Some(Identity::Constant(Literal::Bool(false))),
+        #[cfg(feature = "char")]
+        ValueOps::Ceq | ValueOps::Cle | ValueOps::Cge => {
+            Some(Identity::Constant(Literal::Bool(true)))
+        }
+        #[cfg(feature = "char")]
+        ValueOps::Clt | ValueOps::Cgt => Some(Identity::Constant(Literal::Bool(false))),
+        _ => None,
+    }
+}
+
+/// Rewrites each operation whose two arguments are the same variable and whose result is then known, returning how many were rewritten
+///
+/// `sub x x` becomes `const 0`, `and x x` and `or x x` become `id x`, and comparisons like `eq x x` or `lt x x` become `const true` or `const false`. The `float` operations are left alone since `NaN` breaks these identities.
+pub fn simplify_identities(func: &mut Function) -> usize {
+    let mut rewritten = 0;
+    for code in &mut func.instrs {
+        let Code::Instruction(Instruction::Value {
+            op,
+            args,
+            dest,
+            op_type,
+            #[cfg(feature = "position")]
+            pos,
+            ..
+        }) = code
+        else {
+            continue;
+        };
+        let (Some(identity), [a, b]) = (identity(*op), args.as_slice()) else {
+            continue;
+        };
+        if a != b {
+            continue;
+        }
+        *code = match identity {
+            Identity::Constant(value) => Code::Instruction(Instruction::Constant {
+                dest: dest.clone(),
+                op: ConstOps::Const,
+                #[cfg(feature = "position")]
+                pos: pos.clone(),
+                const_type: op_type.clone(),
+                value,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+                #[cfg(feature = "metadata")]
+                extra: serde_json::Map::new(),
+            }),
+            Identity::Copy => id(dest.clone(), op_type.clone(), a.clone()),
+        };
+        rewritten += 1;
+    }
+    rewritten
+}
+
+/// The value of ```lit``` as a `float`, reading an `int` literal the way a `float` constant does
+#[cfg(feature = "float")]
+#[allow(clippy::cast_precision_loss)]
+const fn float_of(lit: &Literal) -> Option<f64> {
+    match lit {
+        Literal::Float(x) => Some(*x),
+        Literal::Int(i) => Some(*i as f64),
+        Literal::Bool(_) => None,
+        #[cfg(feature = "char")]
+        Literal::Char(_) => None,
+    }
+}
+
+/// What ```op``` produces from the constant arguments ```args```, following the semantics of the interpreter
+///
+/// This is [None] for operations which are not pure, like `call`, `alloc`, and `load`, which are never folded, and for those which would fail at run time, like `div` by zero, so that they are kept for the program to fail on. `fdiv` by zero is also left alone, although it only produces an infinity or `NaN`.
+#[allow(clippy::float_cmp)]
+fn fold_op(op: ValueOps, args: &[&Literal]) -> Option<Literal> {
+    use Literal::{Bool, Int};
+    let value = match (op, args) {
+        (ValueOps::Id, [a]) => (*a).clone(),
+        (ValueOps::Add, [Int(a), Int(b)]) => Int(a.wrapping_add(*b)),
+        (ValueOps::Sub, [Int(a), Int(b)]) => Int(a.wrapping_sub(*b)),
+        (ValueOps::Mul, [Int(a), Int(b)]) => Int(a.wrapping_mul(*b)),
+        (ValueOps::Div, [Int(a), Int(b)]) if *b != 0 => Int(a.wrapping_div(*b)),
+        (ValueOps::Eq, [Int(a), Int(b)]) => Bool(a == b),
+        (ValueOps::Lt, [Int(a), Int(b)]) => Bool(a < b),
+        (ValueOps::Gt, [Int(a), Int(b)]) => Bool(a > b),
+        (ValueOps::Le, [Int(a), Int(b)]) => Bool(a <= b),
+        (ValueOps::Ge, [Int(a), Int(b)]) => Bool(a >= b),
+        (ValueOps::Not, [Bool(a)]) => Bool(!a),
+        (ValueOps::And, [Bool(a), Bool(b)]) => Bool(*a && *b),
+        (ValueOps::Or, [Bool(a), Bool(b)]) => Bool(*a || *b),
+        #[cfg(feature = "select")]
+        (ValueOps::Select, [Bool(c), a, b]) => (if *c { *a } else { *b }).clone(),
+        #[cfg(feature = "char")]
+        (ValueOps::Ceq, [Literal::Char(a), Literal::Char(b)]) => Bool(a == b),
+        #[cfg(feature = "char")]
+        (ValueOps::Clt, [Literal::Char(a), Literal::Char(b)]) => Bool(a < b),
+        #[cfg(feature = "char")]
+        (ValueOps::Cgt, [Literal::Char(a), Literal::Char(b)]) => Bool(a > b),
+        #[cfg(feature = "char")]
+        (ValueOps::Cle, [Literal::Char(a), Literal::Char(b)]) => Bool(a <= b),
+        #[cfg(feature = "char")]
+        (ValueOps::Cge, [Literal::Char(a), Literal::Char(b)]) => Bool(a >= b),
+        #[cfg(feature = "char")]
+        (ValueOps::Char2int, [Literal::Char(c)]) => Int(i64::from(u32::from(*c))),
+        #[cfg(feature = "char")]
+        (ValueOps::Int2char, [Int(i)]) => {
+            Literal::Char(u32::try_from(*i).ok().and_then(char::from_u32)?)
+        }
+        #[cfg(feature = "convert")]
+        #[allow(clippy::cast_precision_loss)]
+        (ValueOps::Int2float, [Int(i)]) => Literal::Float(*i as f64),
+        #[cfg(feature = "convert")]
+        #[allow(clippy::cast_possible_truncation)]
+        (ValueOps::Float2int, [a]) => Int(float_of(a)? as i64),
+        #[cfg(feature = "float")]
+        (op, [a, b]) => {
+            let (a, b) = (float_of(a)?, float_of(b)?);
+            match op {
+                ValueOps::Fadd => Literal::Float(a + b),
+                ValueOps::Fsub => Literal::Float(a - b),
+                ValueOps::Fmul => Literal::Float(a * b),
+                ValueOps::Fdiv if b != 0.0 => Literal::Float(a / b),
+                ValueOps::Feq => Bool(a == b),
+                ValueOps::Flt => Bool(a < b),
+                ValueOps::Fgt => Bool(a > b),
+                ValueOps::Fle => Bool(a <= b),
+                ValueOps::Fge => Bool(a >= b),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The value held by a `const` of type ```t``` and value ```lit```, which is a float for an `int` literal typed `float`
+#[cfg_attr(not(feature = "float"), allow(unused_variables))]
+fn const_literal(lit: &Literal, t: &Type) -> Literal {
+    #[cfg(feature = "float")]
+    if let (Type::Float, Some(x)) = (t, float_of(lit)) {
+        return Literal::Float(x);
+    }
+    lit.clone()
+}
+
+/// Whether ```lit``` can be the value of a `const` of type ```t```
+const fn literal_has_type(lit: &Literal, t: &Type) -> bool {
+    match (lit, t) {
+        (Literal::Int(_), Type::Int) | (Literal::Bool(_), Type::Bool) => true,
+        #[cfg(feature = "float")]
+        (Literal::Int(_) | Literal::Float(_), Type::Float) => true,
+        #[cfg(feature = "char")]
+        (Literal::Char(_), Type::Char) => true,
+        _ => false,
+    }
+}
+
+/// Replaces each value operation whose arguments all hold constants with a `const` of its result, returning how many were replaced
+///
+/// A variable holds a constant after a `const` or a folded operation earlier in the same block, until it is reassigned. Operations with effects or which read memory, like `call`, `alloc`, and `load`, are never folded and their results are not known, and neither are `phi`s. Operations which would fail, like `div` by zero, are kept, as is `fdiv` by zero.
+/// Each instruction is replaced in place, so effects like `print` keep their order.
+pub fn constant_fold(func: &mut Function) -> usize {
+    let cfg = build_cfg(func);
+    let mut folded = 0;
+    for b in 0..cfg.len() {
+        let mut known: HashMap<String, Literal> = HashMap::new();
+        for i in cfg.block_range(b) {
+            let code = &mut func.instrs[i];
+            let Code::Instruction(instr) = code else {
+                continue;
+            };
+            let value = match instr {
+                Instruction::Constant {
+                    dest,
+                    const_type,
+                    value,
+                    ..
+                } => {
+                    known.insert(dest.clone(), const_literal(value, const_type));
+                    continue;
+                }
+                Instruction::Value {
+                    op,
+                    args,
+                    dest,
+                    op_type,
+                    ..
+                } => {
+                    let args: Option<Vec<&Literal>> = args.iter().map(|a| known.get(a)).collect();
+                    let value = args
+                        .and_then(|args| fold_op(*op, &args))
+                        .filter(|v| literal_has_type(v, op_type));
+                    known.remove(dest.as_str());
+                    value
+                }
+                Instruction::Effect { .. } => continue,
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            let Instruction::Value {
+                dest,
+                op_type,
+                #[cfg(feature = "position")]
+                pos,
+                ..
+            } = instr
+            else {
+                continue;
+            };
+            known.insert(dest.clone(), value.clone());
+            *code = Code::Instruction(Instruction::Constant {
+                dest: dest.clone(),
+                op: ConstOps::Const,
+                #[cfg(feature = "position")]
+                pos: pos.clone(),
+                const_type: op_type.clone(),
+                value,
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+                #[cfg(feature = "metadata")]
+                extra: serde_json::Map::new(),
+            });
+            folded += 1;
+        }
+    }
+    folded
+}
+
+/// Deletes every constant and value instruction of ```func``` whose destination is never read, repeating until none are left, and returns whether anything was deleted
+///
+/// Effects, labels, and `call`s are always kept. A `load` whose result is unused is deleted, but an `alloc` is kept even then, since deleting it would hide the allocation from the check that all memory is freed. Like other passes, this assumes the program does not fail at runtime, so an unused `div` is deleted even if its divisor could be zero.
+pub fn remove_dead_code(func: &mut Function) -> bool {
+    let before = func.instrs.len();
+    loop {
+        let used: HashSet<String> = func
+            .instrs
+            .iter()
+            .filter_map(|c| match c {
+                Code::Instruction(instr) => Some(instr.args()),
+                Code::Label { .. } => None,
+            })
+            .flatten()
+            .cloned()
+            .collect();
+        let len = func.instrs.len();
+        func.instrs.retain(|code| match code {
+            Code::Instruction(Instruction::Constant { dest, .. }) => used.contains(dest),
+            Code::Instruction(Instruction::Value { op, dest, .. }) => {
+                used.contains(dest) || !is_removable(*op)
+            }
+            _ => true,
+        });
+        if func.instrs.len() == len {
+            return func.instrs.len() != before;
+        }
+    }
+}
+
+/// Whether an instruction with ```op``` can be deleted when its result is unused
+const fn is_removable(op: ValueOps) -> bool {
+    match op {
+        ValueOps::Call => false,
+        #[cfg(feature = "memory")]
+        ValueOps::Alloc => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(all(feature = "ssa", feature = "select"))]
+    use super::phis_to_selects;
+    use super::{
+        coalesce_branches, constant_fold, cse, dedupe_constants, outline, remove_dead_code,
+        simplify_identities, OutlineError,
+    };
+    #[cfg(feature = "ssa")]
+    use super::{fold_trivial_phis, from_ssa};
+    use crate::cfg::build_cfg;
+    use crate::dominators::dominators;
+    use crate::interp::run;
+    #[cfg(all(feature = "ssa", feature = "select"))]
+    use crate::interp::{assert_same_behavior, Value};
+    use crate::text::parse_and_convert;
+    use crate::{Function, Program};
+
+    /// The `@main` of ```src```, which is in the text format
+    fn main_of(src: &str) -> Function {
+        parse_and_convert(src).unwrap().functions.remove(0)
+    }
+
+    /// The code of ```func``` as text without its indentation, one line for each
+    fn code_of(func: &Function) -> Vec<String> {
+        func.instrs
+            .iter()
+            .map(|code| code.to_string().trim().to_string())
+            .collect()
+    }
+
+    /// What running ```program``` with no arguments prints
+    fn output_of(program: &Program) -> String {
+        let mut out = Vec::new();
+        run(program, Vec::new(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Checks that ```src``` prints ```expected``` both before and after its `@main` is taken out of SSA form, which leaves no `phi`s behind
+    #[cfg(feature = "ssa")]
+    fn assert_from_ssa_keeps(src: &str, expected: &str) -> Function {
+        let mut program = parse_and_convert(src).unwrap();
+        assert_eq!(output_of(&program), expected);
+        from_ssa(&mut program.functions[0]);
+        assert!(!code_of(&program.functions[0])
+            .iter()
+            .any(|line| line.contains("phi")));
+        assert_eq!(output_of(&program), expected);
+        program.functions.remove(0)
+    }
+
+    #[test]
+    fn constant_fold_constant_arguments() {
+        let mut func = main_of(
+            "@main(a: int) {
+  one: int = const 1;
+  two: int = const 2;
+  x: int = add one two;
+  y: int = add a two;
+  print x y;
+}",
+        );
+        assert_eq!(constant_fold(&mut func), 1);
+        assert_eq!(
+            code_of(&func),
+            [
+                "one: int = const 1;",
+                "two: int = const 2;",
+                "x: int = const 3;",
+                "y: int = add a two;",
+                "print x y;",
+            ]
+        );
+    }
+
+    #[test]
+    fn dedupe_equal_constants() {
+        let mut func = main_of(
+            "@main {
+  a: int = const 5;
+  b: int = const 5;
+  c: bool = const true;
+  s: int = add a b;
+  print s c;
+}",
+        );
+        assert_eq!(dedupe_constants(&mut func), 1);
+        assert_eq!(
+            code_of(&func),
+            [
+                "a: int = const 5;",
+                "c: bool = const true;",
+                "s: int = add a a;",
+                "print s c;",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "float")]
+    fn simplify_sub_but_not_fsub() {
+        let mut func = main_of(
+            "@main(a: int, f: float) {
+  x: int = sub a a;
+  y: float = fsub f f;
+  print x y;
+}",
+        );
+        assert_eq!(simplify_identities(&mut func), 1);
+        assert_eq!(
+            code_of(&func),
+            ["x: int = const 0;", "y: float = fsub f f;", "print x y;"]
+        );
+    }
+
+    #[test]
+    fn remove_unused_add() {
+        let mut func = main_of(
+            "@main(a: int) {
+  one: int = const 1;
+  used: int = add a one;
+  unused: int = add used one;
+  print used;
+}",
+        );
+        assert!(remove_dead_code(&mut func));
+        assert_eq!(
+            code_of(&func),
+            [
+                "one: int = const 1;",
+                "used: int = add a one;",
+                "print used;"
+            ]
+        );
+        assert!(!remove_dead_code(&mut func));
+    }
+
+    #[test]
+    #[cfg(feature = "ssa")]
+    fn from_ssa_loop_carried_phis() {
+        assert_from_ssa_keeps(
+            "@main {
+.entry:
+  zero: int = const 0;
+  one: int = const 1;
+  n: int = const 5;
+  jmp .h;
+.h:
+  i: int = phi zero i1 .entry .body;
+  s: int = phi zero s1 .entry .body;
+  c: bool = lt i n;
+  br c .body .x;
+.body:
+  s1: int = add s i;
+  i1: int = add i one;
+  jmp .h;
+.x:
+  print s;
+}",
+            "10\n",
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ssa")]
+    fn from_ssa_exit_reads_phi() {
+        let func = assert_from_ssa_keeps(
+            "@main {
+.entry:
+  i0: int = const 0;
+  one: int = const 1;
+  lim: int = const 3;
+.h:
+  i: int = phi i0 i1 .entry .h;
+  i1: int = add i one;
+  c: bool = lt i1 lim;
+  br c .h .x;
+.x:
+  print i;
+}",
+            "2\n",
+        );
+        // The copy into `i` only happens on the back edge, which now goes through its own block
+        let code = code_of(&func);
+        assert!(code.contains(&"br c .h.h .x;".to_string()));
+        assert!(code.contains(&".h.h:".to_string()));
+    }
+
+    /// How many calls [`Program::inline_all_trivial`] inlines in ```src``` and the code of `@main` afterwards, checking that it prints the same as before
+    fn inlined(src: &str, max_size: usize, budget: usize) -> (usize, String) {
+        let mut program = parse_and_convert(src).unwrap();
+        let expected = output_of(&program);
+        let inlined = program.inline_all_trivial(max_size, budget);
+        assert_eq!(output_of(&program), expected);
+        let main = program.functions.iter().find(|f| f.name == "main").unwrap();
+        (inlined, code_of(main).join("\n"))
+    }
+
+    #[test]
+    fn inline_small_but_not_large() {
+        let src = "@inc(x: int): int {
+  one: int = const 1;
+  y: int = add x one;
+  ret y;
+}
+@big(x: int): int {
+  y: int = add x x;
+  y: int = add y y;
+  y: int = add y y;
+  y: int = add y y;
+  ret y;
+}
+@main {
+  a: int = const 1;
+  b: int = call @inc a;
+  c: int = call @big b;
+  print c;
+}";
+        let (count, main) = inlined(src, 3, 100);
+        assert_eq!(count, 1);
+        assert!(!main.contains("call @inc"));
+        assert!(main.contains("c: int = call @big b;"));
+    }
+
+    #[test]
+    fn inline_within_budget() {
+        let src = "@inc(x: int): int {
+  one: int = const 1;
+  y: int = add x one;
+  ret y;
+}
+@main {
+  a: int = const 1;
+  b: int = call @inc a;
+  c: int = call @inc b;
+  print c;
+}";
+        // Each inlined call replaces one instruction with four
+        assert_eq!(inlined(src, 3, 3).0, 1);
+        assert_eq!(inlined(src, 3, 5).0, 1);
+        assert_eq!(inlined(src, 3, 6).0, 2);
+    }
+
+    #[test]
+    fn inline_empty_callee() {
+        let (count, main) = inlined("@f {}\n@main {\n  call @f;\n  print;\n}", 10, 100);
+        assert_eq!(count, 1);
+        assert_eq!(main, "print;");
+    }
+
+    #[test]
+    fn cse_in_dominated_block() {
+        let mut func = main_of(
+            "@main(a: int, b: int, c: bool) {
+  x: int = add a b;
+  d: int = sub a b;
+  br c .l .r;
+.l:
+  y: int = add a b;
+  z: int = add b a;
+  w: int = sub b a;
+  print y z w;
+.r:
+  print x d;
+}",
+        );
+        let cfg = build_cfg(&func);
+        assert_eq!(cse(&mut func, &cfg, &dominators(&cfg)), 2);
+        assert_eq!(
+            code_of(&func)[4..7],
+            ["y: int = id x;", "z: int = id x;", "w: int = sub b a;"]
+        );
+    }
+
+    #[test]
+    fn coalesce_same_label() {
+        let mut func = main_of(
+            "@main(c: bool) {
+  br c .l .l;
+.l:
+  print c;
+}",
+        );
+        assert_eq!(coalesce_branches(&mut func), 1);
+        assert_eq!(code_of(&func), ["jmp .l;", ".l:", "print c;"]);
+    }
+
+    #[test]
+    fn coalesce_identical_blocks() {
+        let src = "@main(c: bool) {
+  x: int = const 1;
+  br c .l .r;
+.l:
+  print x;
+  jmp .end;
+.r:
+  print x;
+  jmp .end;
+.end:
+}";
+        let mut func = main_of(src);
+        assert_eq!(coalesce_branches(&mut func), 1);
+        assert_eq!(code_of(&func)[1], "jmp .l;");
+        // Arms which fall through are left alone
+        let mut func = main_of(&src.replace("  jmp .end;\n", ""));
+        assert_eq!(coalesce_branches(&mut func), 0);
+        let mut func = main_of(&src.replace("  print x;\n  jmp .end;\n.r:", ".r:"));
+        assert_eq!(coalesce_branches(&mut func), 0);
+    }
+
+    #[test]
+    fn outline_region() {
+        let mut program = parse_and_convert(
+            "@main {
+  a: int = const 2;
+  b: int = const 3;
+  one: int = const 1;
+  x: int = add a one;
+  y: int = mul x b;
+  print y;
+}",
+        )
+        .unwrap();
+        let expected = output_of(&program);
+        let outlined = outline(&mut program.functions[0], 3..5, "f").unwrap();
+        assert_eq!(
+            outlined.to_string().lines().next(),
+            Some("@f(a: int, b: int, one: int): int {")
+        );
+        assert_eq!(
+            code_of(&outlined),
+            ["x: int = add a one;", "y: int = mul x b;", "ret y;"]
+        );
+        assert_eq!(
+            code_of(&program.functions[0])[3],
+            "y: int = call @f a b one;"
+        );
+        program.functions.push(outlined);
+        assert_eq!(output_of(&program), expected);
+    }
+
+    #[test]
+    fn outline_errors() {
+        let error_of = |src: &str, range| outline(&mut main_of(src), range, "f").unwrap_err();
+        let src = "@main(c: bool) {
+  x: int = const 1;
+  y: int = add x x;
+  print x y;
+}";
+        assert_eq!(error_of(src, 1..1), OutlineError::BadRange(1, 1));
+        assert_eq!(error_of(src, 1..9), OutlineError::BadRange(1, 9));
+        assert_eq!(
+            error_of(src, 0..2),
+            OutlineError::MultipleLiveOuts(vec!["x".to_string(), "y".to_string()])
+        );
+        let src = "@main(c: bool) {
+  br c .in .out;
+.in:
+  print c;
+  jmp .out;
+  ret;
+.out:
+}";
+        assert_eq!(
+            error_of(src, 1..3),
+            OutlineError::EnteredFromOutside("in".to_string())
+        );
+        assert_eq!(
+            error_of(src, 2..4),
+            OutlineError::ExitsElsewhere("out".to_string())
+        );
+        assert_eq!(error_of(src, 2..5), OutlineError::ContainsReturn);
+        let src = "@main(c: bool) {
+  v: int = const 1;
+  v: bool = const true;
+  print v;
+}";
+        assert_eq!(
+            error_of(src, 2..3),
+            OutlineError::UnknownType("v".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ssa")]
+    fn fold_phi_of_one_variable() {
+        let mut func = main_of(
+            "@main(c: bool) {
+.entry:
+  x: int = const 4;
+  br c .l .r;
+.l:
+  jmp .j;
+.r:
+  jmp .j;
+.j:
+  y: int = phi x x .l .r;
+  z: int = id y;
+  print z;
+}",
+        );
+        assert_eq!(fold_trivial_phis(&mut func), 2);
+        assert!(!code_of(&func)
+            .iter()
+            .any(|l| l.contains("phi") || l.contains("id")));
+        assert_eq!(code_of(&func).last().unwrap(), "print x;");
+    }
+
+    #[test]
+    #[cfg(all(feature = "ssa", feature = "select"))]
+    fn phi_of_diamond_to_select() {
+        let src = "@main(c: bool, a: int) {
+.entry:
+  br c .l .r;
+.l:
+  one: int = const 1;
+  x: int = add a one;
+  jmp .j;
+.r:
+  two: int = const 2;
+  y: int = mul a two;
+  jmp .j;
+.j:
+  z: int = phi x y .l .r;
+  print z;
+}";
+        let before = parse_and_convert(src).unwrap();
+        let mut after = before.clone();
+        assert_eq!(phis_to_selects(&mut after.functions[0]), 1);
+        let code = code_of(&after.functions[0]);
+        assert!(code.contains(&"z: int = select c x y;".to_string()));
+        assert!(!code.iter().any(|l| l.contains("phi") || l.contains("br ")));
+        assert_same_behavior(
+            &before,
+            &after,
+            &[
+                vec![Value::Bool(true), Value::Int(5)],
+                vec![Value::Bool(false), Value::Int(5)],
+            ],
+        );
+    }
+}
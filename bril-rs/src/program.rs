@@ -1,13 +1,27 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt::{self, Display, Formatter},
     hash::Hash,
+    str::FromStr,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(feature = "metadata")]
+use serde_json::Map;
+use thiserror::Error;
+
+use crate::conversion::ConversionError;
+
+/// The version of the Bril specification targeted by this crate
+///
+/// Programs which declare a newer `version` are rejected when converting to a [Program].
+pub const SPEC_VERSION: u32 = 1;
 
 /// Equivalent to a file of bril code
-#[cfg_attr(not(feature = "float"), derive(Eq))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+///
+/// Keys of the top-level object other than the fields below, such as experimental additions from other producers, are ignored when deserializing and so are not written back out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Program {
     /// A list of functions declared in the program
     pub functions: Vec<Function>,
@@ -15,16 +29,273 @@ pub struct Program {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     /// A list of imports for this program
     pub imports: Vec<Import>,
+    /// The version of the Bril specification this program was written against, if it declares one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+impl Program {
+    /// Serializes this program as pretty-printed JSON with every source position left out, whether or not the `position` feature is enabled
+    /// # Panics
+    /// Never in practice: `serde_json` only fails on maps with non-string keys, and a program has none
+    #[must_use]
+    pub fn to_json_no_pos(&self) -> String {
+        #[cfg(feature = "position")]
+        {
+            let mut p = self.clone();
+            p.strip_positions();
+            serde_json::to_string_pretty(&p).unwrap()
+        }
+        #[cfg(not(feature = "position"))]
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Maps the name of each function to its index in [`Program::functions`]. If several functions share a name, the first one is used
+    #[must_use]
+    pub fn function_index(&self) -> HashMap<&str, usize> {
+        let mut index = HashMap::with_capacity(self.functions.len());
+        for (i, f) in self.functions.iter().enumerate() {
+            index.entry(f.name.as_str()).or_insert(i);
+        }
+        index
+    }
+
+    /// Maps the name of each function to its parameters and return type, which is what checking a `call` of it needs
+    /// # Errors
+    /// Returns a [`DuplicateFunctionError`] for the first function whose name is already taken by an earlier one
+    pub fn signatures(&self) -> Result<HashMap<&str, Signature<'_>>, DuplicateFunctionError> {
+        let mut signatures = HashMap::with_capacity(self.functions.len());
+        for f in &self.functions {
+            if signatures
+                .insert(f.name.as_str(), (f.args.as_slice(), f.return_type.as_ref()))
+                .is_some()
+            {
+                return Err(DuplicateFunctionError(f.name.clone()));
+            }
+        }
+        Ok(signatures)
+    }
+
+    /// The `@main` function which running this program starts from, if it has one
+    #[must_use]
+    pub fn main(&self) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == "main")
+    }
+
+    /// Like [`Program::main`], but allows changing the function
+    #[must_use]
+    pub fn main_mut(&mut self) -> Option<&mut Function> {
+        self.functions.iter_mut().find(|f| f.name == "main")
+    }
+
+    /// Checks that this program can be run from the command line: it has a single `@main`, no function calls it, and it returns nothing, an `int`, or a `bool`
+    /// # Errors
+    /// Returns the first [`EntryError`] found
+    pub fn validate_entry(&self) -> Result<(), EntryError> {
+        let mut mains = self.functions.iter().filter(|f| f.name == "main");
+        let main = mains.next().ok_or(EntryError::NoMain)?;
+        if mains.next().is_some() {
+            return Err(EntryError::DuplicateMain);
+        }
+        if let Some(t) = main
+            .return_type
+            .as_ref()
+            .filter(|t| !matches!(t, Type::Int | Type::Bool))
+        {
+            return Err(EntryError::BadReturnType(t.clone()));
+        }
+        for f in &self.functions {
+            for code in &f.instrs {
+                if let Code::Instruction(
+                    Instruction::Value {
+                        op: ValueOps::Call,
+                        funcs,
+                        ..
+                    }
+                    | Instruction::Effect {
+                        op: EffectOps::Call,
+                        funcs,
+                        ..
+                    },
+                ) = code
+                {
+                    if funcs.iter().any(|callee| callee == "main") {
+                        return Err(EntryError::CalledMain(f.name.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses a program from ```input``` in JSON format like [`serde_json::from_str`], but rejects any object which has the same key more than once instead of keeping the last value
+    /// # Errors
+    /// Returns a [`serde_json::Error`] whose [`serde_json::Error::line`] and [`serde_json::Error::column`] point at the second occurrence of the repeated key, or any error the usual parse would give
+    pub fn from_str_strict(input: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str::<NoDuplicateKeys>(input)?;
+        serde_json::from_str(input)
+    }
+
+    /// Whether this program and ```other``` are the same apart from source positions, which are ignored entirely
+    ///
+    /// This is like `==`, which does compare positions when the `position` feature is enabled, but without having to copy and strip both programs.
+    #[must_use]
+    pub fn structurally_equal(&self, other: &Self) -> bool {
+        #[cfg(feature = "import")]
+        if self.imports != other.imports {
+            return false;
+        }
+        self.version == other.version
+            && self.functions.len() == other.functions.len()
+            && self
+                .functions
+                .iter()
+                .zip(&other.functions)
+                .all(|(a, b)| a.structurally_equal(b))
+    }
+
+    /// Removes the source positions of every function, label, and instruction in this program
+    #[cfg(feature = "position")]
+    pub fn strip_positions(&mut self) {
+        for func in &mut self.functions {
+            func.pos = None;
+            for code in &mut func.instrs {
+                match code {
+                    Code::Label { pos, .. }
+                    | Code::Instruction(
+                        Instruction::Constant { pos, .. }
+                        | Instruction::Value { pos, .. }
+                        | Instruction::Effect { pos, .. },
+                    ) => *pos = None,
+                }
+            }
+        }
+    }
+}
+
+/// Deserializes any JSON value, failing on the first object with a repeated key
+struct NoDuplicateKeys;
+
+impl<'de> Deserialize<'de> for NoDuplicateKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NoDuplicateKeysVisitor)
+    }
+}
+
+struct NoDuplicateKeysVisitor;
+
+impl<'de> Visitor<'de> for NoDuplicateKeysVisitor {
+    type Value = NoDuplicateKeys;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while seq.next_element::<NoDuplicateKeys>()?.is_some() {}
+        Ok(NoDuplicateKeys)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut keys = HashSet::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if !keys.insert(key.clone()) {
+                return Err(de::Error::custom(format!("duplicate key `{key}`")));
+            }
+            map.next_value::<NoDuplicateKeys>()?;
+        }
+        Ok(NoDuplicateKeys)
+    }
+}
+
+/// The parameters and return type of a function, as given by [`Program::signatures`]
+pub type Signature<'a> = (&'a [Argument], Option<&'a Type>);
+
+/// More than one function of a [Program] has this name
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("a function named `{0}` is defined more than once")]
+pub struct DuplicateFunctionError(pub String);
+
+/// The reasons [`Program::validate_entry`] can reject a [Program]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum EntryError {
+    /// There is no `@main` function
+    #[error("there is no `@main` function")]
+    NoMain,
+    /// More than one function is named `@main`
+    #[error("a function named `main` is defined more than once")]
+    DuplicateMain,
+    /// `@main` is called from this function, rather than only being the entry
+    #[error("`@main` is called from `@{0}`")]
+    CalledMain(String),
+    /// `@main` returns a value of this type, which is not an `int` or `bool`
+    #[error("`@main` must return nothing, an `int`, or a `bool`, not `{0}`")]
+    BadReturnType(Type),
 }
 
 impl Display for Program {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, PrettyOptions::default())
+    }
+}
+
+/// Settings for printing a [Program] as text with [`Program::to_pretty_string`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrettyOptions {
+    /// Follow each effect instruction with a comment giving the type of each of its arguments, as far as it is known
+    ///
+    /// Value and constant instructions always include their type. The type of an argument is known when it is a parameter or every instruction assigning it has the same type, and arguments of unknown type are left out. Since the annotations are comments, the text still parses to the same program.
+    pub always_annotate_types: bool,
+    /// Follow each label and instruction with a comment giving its source position, if it has one
+    #[cfg(feature = "position")]
+    pub annotate_positions: bool,
+}
+
+impl Program {
+    /// Prints this program as Bril text, like its [Display] implementation but with the given [`PrettyOptions`]
+    #[must_use]
+    pub fn to_pretty_string(&self, options: PrettyOptions) -> String {
+        struct Pretty<'a>(&'a Program, PrettyOptions);
+        impl Display for Pretty<'_> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                self.0.fmt_with(f, self.1)
+            }
+        }
+        Pretty(self, options).to_string()
+    }
+
+    fn fmt_with(&self, f: &mut Formatter<'_>, options: PrettyOptions) -> fmt::Result {
         #[cfg(feature = "import")]
         for i in &self.imports {
             writeln!(f, "{i}")?;
         }
         for func in &self.functions {
-            writeln!(f, "{func}")?;
+            func.fmt_with(f, options)?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -32,10 +303,10 @@ impl Display for Program {
 
 /// <https://capra.cs.cornell.edu/bril/lang/import.html#syntax>
 #[cfg(feature = "import")]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Import {
     /// A list of functions to be imported
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub functions: Vec<ImportedFunction>,
     /// The relative path of the file from some lib directory specified by the user
     pub path: std::path::PathBuf,
@@ -44,15 +315,12 @@ pub struct Import {
 #[cfg(feature = "import")]
 impl Display for Import {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "from {}", self.path.display())?;
-        if !self.functions.is_empty() {
-            write!(f, " import ")?;
-            for (i, name) in self.functions.iter().enumerate() {
-                if i != 0 {
-                    write!(f, ", ")?;
-                }
-                write!(f, "{name}")?;
+        write!(f, "from \"{}\" import", self.path.display())?;
+        for (i, name) in self.functions.iter().enumerate() {
+            if i != 0 {
+                write!(f, ",")?;
             }
+            write!(f, " {name}")?;
         }
         write!(f, ";")?;
         Ok(())
@@ -61,7 +329,7 @@ impl Display for Import {
 
 /// <https://capra.cs.cornell.edu/bril/lang/import.html#syntax>
 #[cfg(feature = "import")]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ImportedFunction {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// A function can be optionally aliased with a different name for use in the rest of the program
@@ -70,20 +338,28 @@ pub struct ImportedFunction {
     pub name: String,
 }
 
+#[cfg(feature = "import")]
+impl ImportedFunction {
+    /// The name this function is called by in the importing program, which is its alias if it has one
+    #[must_use]
+    pub fn local_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
 #[cfg(feature = "import")]
 impl Display for ImportedFunction {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.name)?;
+        write!(f, "@{}", self.name)?;
         if let Some(a) = self.alias.as_ref() {
-            write!(f, " as {a}")?;
+            write!(f, " as @{a}")?;
         }
         Ok(())
     }
 }
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#function>
-#[cfg_attr(not(feature = "float"), derive(Eq))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Function {
     /// Any arguments the function accepts
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -101,10 +377,108 @@ pub struct Function {
     #[serde(rename = "type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub return_type: Option<Type>,
+
+    /// Whether this function is only a declaration whose body is provided outside of Bril, in which case it has no instructions
+    #[cfg(feature = "external")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub external: bool,
 }
 
 impl Display for Function {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, PrettyOptions::default())
+    }
+}
+
+impl Function {
+    /// Whether this function and ```other``` are the same apart from source positions, as with [`Program::structurally_equal`]
+    #[must_use]
+    pub fn structurally_equal(&self, other: &Self) -> bool {
+        #[cfg(feature = "external")]
+        if self.external != other.external {
+            return false;
+        }
+        self.name == other.name
+            && self.args == other.args
+            && self.return_type == other.return_type
+            && self.instrs.len() == other.instrs.len()
+            && self
+                .instrs
+                .iter()
+                .zip(&other.instrs)
+                .all(|(a, b)| a.structurally_equal(b))
+    }
+
+    /// The labels and instructions of this function in order, the same as [`Function::instrs`]
+    #[must_use]
+    pub fn code(&self) -> &[Code] {
+        &self.instrs
+    }
+
+    /// The instructions of this function in order, skipping labels
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction> + '_ {
+        self.instrs.iter().filter_map(|c| match c {
+            Code::Instruction(instr) => Some(instr),
+            Code::Label { .. } => None,
+        })
+    }
+
+    /// Like [`Function::instructions`], but allows changing each instruction in place
+    pub fn instructions_mut(&mut self) -> impl Iterator<Item = &mut Instruction> + '_ {
+        self.instrs.iter_mut().filter_map(|c| match c {
+            Code::Instruction(instr) => Some(instr),
+            Code::Label { .. } => None,
+        })
+    }
+
+    /// The labels defined in this function in order, without their leading `.`
+    pub fn labels(&self) -> impl Iterator<Item = &str> + '_ {
+        self.instrs.iter().filter_map(|c| match c {
+            Code::Label { label, .. } => Some(label.as_str()),
+            Code::Instruction(_) => None,
+        })
+    }
+
+    /// The type of every parameter and of every variable whose assignments all have the same type
+    pub(crate) fn known_types(&self) -> HashMap<&str, &Type> {
+        let mut types: HashMap<&str, Option<&Type>> = self
+            .args
+            .iter()
+            .map(|a| (a.name.as_str(), Some(&a.arg_type)))
+            .collect();
+        for code in &self.instrs {
+            if let Code::Instruction(
+                Instruction::Constant {
+                    dest,
+                    const_type: t,
+                    ..
+                }
+                | Instruction::Value {
+                    dest, op_type: t, ..
+                },
+            ) = code
+            {
+                types
+                    .entry(dest)
+                    .and_modify(|old| {
+                        if *old != Some(t) {
+                            *old = None;
+                        }
+                    })
+                    .or_insert(Some(t));
+            }
+        }
+        types
+            .into_iter()
+            .filter_map(|(var, t)| Some((var, t?)))
+            .collect()
+    }
+
+    fn fmt_with(&self, f: &mut Formatter<'_>, options: PrettyOptions) -> fmt::Result {
+        #[cfg(feature = "external")]
+        if self.external {
+            write!(f, "extern ")?;
+        }
         write!(f, "@{}", self.name)?;
         if !self.args.is_empty() {
             write!(f, "(")?;
@@ -119,9 +493,39 @@ impl Display for Function {
         if let Some(tpe) = self.return_type.as_ref() {
             write!(f, ": {tpe}")?;
         }
+        #[cfg(feature = "external")]
+        if self.external {
+            return write!(f, ";");
+        }
         writeln!(f, " {{")?;
-        for instr in &self.instrs {
-            writeln!(f, "{instr}")?;
+        let types = if options.always_annotate_types {
+            self.known_types()
+        } else {
+            HashMap::new()
+        };
+        for code in &self.instrs {
+            #[allow(unused_mut)]
+            let mut notes = Vec::new();
+            #[cfg(feature = "position")]
+            if options.annotate_positions {
+                let pos = match code {
+                    Code::Label { pos, .. } => pos.clone(),
+                    Code::Instruction(instr) => instr.get_pos(),
+                };
+                notes.extend(pos.map(|pos| pos.to_string()));
+            }
+            if let Code::Instruction(Instruction::Effect { args, .. }) = code {
+                let annotated: Vec<String> = args
+                    .iter()
+                    .filter_map(|a| Some(format!("{a}: {}", types.get(a.as_str())?)))
+                    .collect();
+                if !annotated.is_empty() {
+                    notes.push(annotated.join(", "));
+                }
+            }
+            let note = (!notes.is_empty()).then(|| notes.join("; "));
+            code.fmt_with_note(f, note.as_deref())?;
+            writeln!(f)?;
         }
         write!(f, "}}")?;
         Ok(())
@@ -148,8 +552,7 @@ impl Display for Argument {
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#function>
 /// Code is a Label or an Instruction
-#[cfg_attr(not(feature = "float"), derive(Eq))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(untagged)]
 pub enum Code {
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#label>
@@ -160,6 +563,10 @@ pub enum Code {
         #[cfg(feature = "position")]
         #[serde(flatten, skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
     },
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#instruction>
     Instruction(Instruction),
@@ -167,23 +574,111 @@ pub enum Code {
 
 impl Display for Code {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_with_note(f, None)
+    }
+}
+
+impl Code {
+    /// Prints this code followed by a comment holding its recorded comment, if any, and then ```note```
+    fn fmt_with_note(&self, f: &mut Formatter<'_>, note: Option<&str>) -> fmt::Result {
+        #[cfg(feature = "format")]
+        for _ in 0..self.format_hints().blank_lines_before {
+            writeln!(f)?;
+        }
         match self {
             Self::Label {
                 label,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
             } => write!(f, ".{label}:"),
             Self::Instruction(instr) => write!(f, "  {instr}"),
+        }?;
+        #[cfg(feature = "format")]
+        let comment = self.format_hints().comment.as_deref();
+        #[cfg(not(feature = "format"))]
+        let comment = None;
+        match (comment, note) {
+            (Some(comment), Some(note)) => write!(f, "  # {comment}; {note}"),
+            (Some(text), None) | (None, Some(text)) => write!(f, "  # {text}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "format")]
+impl Code {
+    /// The [`FormatHints`] recorded for this code
+    #[must_use]
+    pub const fn format_hints(&self) -> &FormatHints {
+        match self {
+            Self::Label { format, .. }
+            | Self::Instruction(
+                Instruction::Constant { format, .. }
+                | Instruction::Value { format, .. }
+                | Instruction::Effect { format, .. },
+            ) => format,
+        }
+    }
+
+    /// Mutable access to the [`FormatHints`] recorded for this code
+    pub const fn format_hints_mut(&mut self) -> &mut FormatHints {
+        match self {
+            Self::Label { format, .. }
+            | Self::Instruction(
+                Instruction::Constant { format, .. }
+                | Instruction::Value { format, .. }
+                | Instruction::Effect { format, .. },
+            ) => format,
         }
     }
 }
 
+/// Layout of the source text which a printer can reproduce, such as blank lines and comments
+///
+/// Hints never affect the meaning of a program: all hints compare equal and hash the same, so programs which differ only in layout are equal.
+/// They are also left out of JSON, so that other tools never see them. The [binary](crate::binary) encoding keeps them.
+#[cfg(feature = "format")]
+#[derive(Debug, Clone, Default)]
+pub struct FormatHints {
+    /// The number of blank lines before this code
+    pub blank_lines_before: usize,
+    /// A comment at the end of this code's line, without the leading `#`
+    pub comment: Option<String>,
+}
+
+#[cfg(feature = "format")]
+impl FormatHints {
+    /// Whether there is no layout to reproduce
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.blank_lines_before == 0 && self.comment.is_none()
+    }
+}
+
+#[cfg(feature = "format")]
+impl PartialEq for FormatHints {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "format")]
+impl Eq for FormatHints {}
+
+#[cfg(feature = "format")]
+impl Hash for FormatHints {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#instruction>
-#[cfg_attr(not(feature = "float"), derive(Eq))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(untagged)]
 pub enum Instruction {
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#constant>
+    // With floats, `serialize_constant` writes this variant and skips the empty fields and the format hints itself
+    #[cfg_attr(feature = "float", serde(serialize_with = "serialize_constant"))]
     Constant {
         /// destination variable
         dest: String,
@@ -191,13 +686,23 @@ pub enum Instruction {
         op: ConstOps,
         #[cfg(feature = "position")]
         /// The source position of the instruction if provided
-        #[serde(flatten, skip_serializing_if = "Option::is_none")]
+        #[serde(flatten)]
+        #[cfg_attr(not(feature = "float"), serde(skip_serializing_if = "Option::is_none"))]
         pos: Option<Position>,
         /// Type of variable
         #[serde(rename = "type")]
         const_type: Type,
         /// The literal being stored in the variable
         value: Literal,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip_deserializing)]
+        #[cfg_attr(not(feature = "float"), serde(skip_serializing))]
+        format: FormatHints,
+        /// Keys of the instruction which Bril does not define, kept so they are written back out unchanged
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: Map<String, serde_json::Value>,
     },
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#value-operation>
     Value {
@@ -221,6 +726,14 @@ pub enum Instruction {
         /// Type of variable
         #[serde(rename = "type")]
         op_type: Type,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
+        /// Keys of the instruction which Bril does not define, kept so they are written back out unchanged
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: Map<String, serde_json::Value>,
     },
     /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#effect-operation>
     Effect {
@@ -239,9 +752,98 @@ pub enum Instruction {
         #[cfg(feature = "position")]
         #[serde(flatten, skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        /// Layout of the source text around this code, which is ignored by equality
+        #[cfg(feature = "format")]
+        #[serde(skip)]
+        format: FormatHints,
+        /// Keys of the instruction which Bril does not define, kept so they are written back out unchanged
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: Map<String, serde_json::Value>,
     },
 }
 
+impl Code {
+    /// Whether this code and ```other``` are the same apart from source positions, as with [`Program::structurally_equal`]
+    #[must_use]
+    pub fn structurally_equal(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Label { label: a, .. }, Self::Label { label: b, .. }) => a == b,
+            (Self::Instruction(a), Self::Instruction(b)) => a.structurally_equal(b),
+            _ => false,
+        }
+    }
+}
+
+impl Instruction {
+    /// Whether this instruction and ```other``` are the same apart from source positions, as with [`Program::structurally_equal`]
+    #[must_use]
+    pub fn structurally_equal(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Constant {
+                    dest,
+                    op,
+                    const_type,
+                    value,
+                    ..
+                },
+                Self::Constant {
+                    dest: dest2,
+                    op: op2,
+                    const_type: const_type2,
+                    value: value2,
+                    ..
+                },
+            ) => dest == dest2 && op == op2 && const_type == const_type2 && value == value2,
+            (
+                Self::Value {
+                    args,
+                    dest,
+                    funcs,
+                    labels,
+                    op,
+                    op_type,
+                    ..
+                },
+                Self::Value {
+                    args: args2,
+                    dest: dest2,
+                    funcs: funcs2,
+                    labels: labels2,
+                    op: op2,
+                    op_type: op_type2,
+                    ..
+                },
+            ) => {
+                op == op2
+                    && dest == dest2
+                    && op_type == op_type2
+                    && args == args2
+                    && funcs == funcs2
+                    && labels == labels2
+            }
+            (
+                Self::Effect {
+                    args,
+                    funcs,
+                    labels,
+                    op,
+                    ..
+                },
+                Self::Effect {
+                    args: args2,
+                    funcs: funcs2,
+                    labels: labels2,
+                    op: op2,
+                    ..
+                },
+            ) => op == op2 && args == args2 && funcs == funcs2 && labels == labels2,
+            _ => false,
+        }
+    }
+}
+
 #[cfg(feature = "position")]
 impl Instruction {
     /// A helper function to extract the position value if it exists from an instruction
@@ -255,6 +857,120 @@ impl Instruction {
     }
 }
 
+impl Instruction {
+    /// A `const` instruction with no source position
+    #[must_use]
+    pub fn constant(dest: impl Into<String>, const_type: Type, value: Literal) -> Self {
+        Self::Constant {
+            dest: dest.into(),
+            op: ConstOps::Const,
+            #[cfg(feature = "position")]
+            pos: None,
+            const_type,
+            value,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    /// A value operation with no functions, labels, or source position
+    ///
+    /// `Instruction::value(ValueOps::Add, "x", Type::Int, ["a", "b"])` is `x: int = add a b;`. Calls and phis can have their `funcs` and `labels` filled in afterwards.
+    #[must_use]
+    pub fn value<S: Into<String>>(
+        op: ValueOps,
+        dest: impl Into<String>,
+        op_type: Type,
+        args: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self::Value {
+            args: args.into_iter().map(Into::into).collect(),
+            dest: dest.into(),
+            funcs: Vec::new(),
+            labels: Vec::new(),
+            op,
+            #[cfg(feature = "position")]
+            pos: None,
+            op_type,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    /// An effect operation with no source position
+    ///
+    /// Labels are given without their leading `.` and functions without their leading `@`.
+    #[must_use]
+    pub fn effect<A: Into<String>, L: Into<String>, F: Into<String>>(
+        op: EffectOps,
+        args: impl IntoIterator<Item = A>,
+        labels: impl IntoIterator<Item = L>,
+        funcs: impl IntoIterator<Item = F>,
+    ) -> Self {
+        Self::Effect {
+            args: args.into_iter().map(Into::into).collect(),
+            funcs: funcs.into_iter().map(Into::into).collect(),
+            labels: labels.into_iter().map(Into::into).collect(),
+            op,
+            #[cfg(feature = "position")]
+            pos: None,
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    /// The variable this instruction writes, which is [None] for effect operations
+    #[must_use]
+    pub fn dest(&self) -> Option<&str> {
+        match self {
+            Self::Constant { dest, .. } | Self::Value { dest, .. } => Some(dest),
+            Self::Effect { .. } => None,
+        }
+    }
+
+    /// The variables this instruction reads, in order. Constants read none
+    #[must_use]
+    pub fn args(&self) -> &[String] {
+        match self {
+            Self::Constant { .. } => &[],
+            Self::Value { args, .. } | Self::Effect { args, .. } => args,
+        }
+    }
+
+    /// Renames every argument of this instruction which is ```from``` to ```to```. Its destination, functions, and labels are left alone
+    pub fn replace_arg(&mut self, from: &str, to: &str) {
+        self.map_args(|arg| if arg == from { to } else { arg }.to_string());
+    }
+
+    /// Replaces every argument of this instruction with the result of ```f``` on it, in order. Its destination, functions, and labels are left alone
+    pub fn map_args<F: FnMut(&str) -> String>(&mut self, mut f: F) {
+        match self {
+            Self::Constant { .. } => {}
+            Self::Value { args, .. } | Self::Effect { args, .. } => {
+                for arg in args {
+                    *arg = f(arg);
+                }
+            }
+        }
+    }
+
+    /// The type of the variable this instruction writes, which is [None] for effect operations
+    #[must_use]
+    pub const fn op_type(&self) -> Option<&Type> {
+        match self {
+            Self::Constant { const_type, .. } => Some(const_type),
+            Self::Value { op_type, .. } => Some(op_type),
+            Self::Effect { .. } => None,
+        }
+    }
+}
+
 impl Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -265,6 +981,10 @@ impl Display for Instruction {
                 value,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
+                #[cfg(feature = "metadata")]
+                    extra: _,
             } => {
                 write!(f, "{dest}: {const_type} = {op} {value};")
             }
@@ -277,6 +997,10 @@ impl Display for Instruction {
                 labels,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
+                #[cfg(feature = "metadata")]
+                    extra: _,
             } => {
                 write!(f, "{dest}: {op_type} = {op}")?;
                 for func in funcs {
@@ -297,6 +1021,10 @@ impl Display for Instruction {
                 labels,
                 #[cfg(feature = "position")]
                     pos: _,
+                #[cfg(feature = "format")]
+                    format: _,
+                #[cfg(feature = "metadata")]
+                    extra: _,
             } => {
                 write!(f, "{op}")?;
                 for func in funcs {
@@ -330,8 +1058,38 @@ impl Display for ConstOps {
     }
 }
 
+/// Implements [Display] and [`FromStr`] for an enum of operations, and lists the names of its variants as `NAMES`, from one table of names and variants
+///
+/// A name which is not in the table is parsed as the given [`ConversionError`].
+macro_rules! op_names {
+    ($ops:ident, $error:ident { $($(#[$attr:meta])* $name:literal => $variant:ident,)* }) => {
+        impl Display for $ops {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str(match self {
+                    $($(#[$attr])* Self::$variant => $name,)*
+                })
+            }
+        }
+
+        impl FromStr for $ops {
+            type Err = ConversionError;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Ok(match s {
+                    $($(#[$attr])* $name => Self::$variant,)*
+                    op => return Err(ConversionError::$error(op.to_string())),
+                })
+            }
+        }
+
+        impl $ops {
+            /// The name of every variant, in the order they are declared
+            pub(crate) const NAMES: &'static [&'static str] = &[$($(#[$attr])* $name,)*];
+        }
+    };
+}
+
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#effect-operation>
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum EffectOps {
     /// <https://capra.cs.cornell.edu/bril/lang/core.html#control>
@@ -366,31 +1124,27 @@ pub enum EffectOps {
     Guard,
 }
 
-impl Display for EffectOps {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Jump => write!(f, "jmp"),
-            Self::Branch => write!(f, "br"),
-            Self::Call => write!(f, "call"),
-            Self::Return => write!(f, "ret"),
-            Self::Print => write!(f, "print"),
-            Self::Nop => write!(f, "nop"),
-            #[cfg(feature = "memory")]
-            Self::Store => write!(f, "store"),
-            #[cfg(feature = "memory")]
-            Self::Free => write!(f, "free"),
-            #[cfg(feature = "speculate")]
-            Self::Speculate => write!(f, "speculate"),
-            #[cfg(feature = "speculate")]
-            Self::Commit => write!(f, "commit"),
-            #[cfg(feature = "speculate")]
-            Self::Guard => write!(f, "guard"),
-        }
-    }
-}
+op_names!(EffectOps, InvalidEffectOps {
+    "jmp" => Jump,
+    "br" => Branch,
+    "call" => Call,
+    "ret" => Return,
+    "print" => Print,
+    "nop" => Nop,
+    #[cfg(feature = "memory")]
+    "store" => Store,
+    #[cfg(feature = "memory")]
+    "free" => Free,
+    #[cfg(feature = "speculate")]
+    "speculate" => Speculate,
+    #[cfg(feature = "speculate")]
+    "commit" => Commit,
+    #[cfg(feature = "speculate")]
+    "guard" => Guard,
+});
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#value-operation>
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum ValueOps {
     /// <https://capra.cs.cornell.edu/bril/lang/core.html#arithmetic>
@@ -472,6 +1226,15 @@ pub enum ValueOps {
     /// <https://capra.cs.cornell.edu/bril/lang/char.html#operations>
     #[cfg(feature = "char")]
     Int2char,
+    /// Converts an `int` to the nearest `float`
+    #[cfg(feature = "convert")]
+    Int2float,
+    /// Converts a `float` to an `int` by truncating toward zero. `NaN` becomes 0 and out-of-range values saturate
+    #[cfg(feature = "convert")]
+    Float2int,
+    /// Produces its second argument when its first is `true` and its third otherwise, reading all three
+    #[cfg(feature = "select")]
+    Select,
     /// <https://capra.cs.cornell.edu/bril/lang/memory.html#operations>
     #[cfg(feature = "memory")]
     Alloc,
@@ -483,66 +1246,68 @@ pub enum ValueOps {
     PtrAdd,
 }
 
-impl Display for ValueOps {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Add => write!(f, "add"),
-            Self::Sub => write!(f, "sub"),
-            Self::Mul => write!(f, "mul"),
-            Self::Div => write!(f, "div"),
-            Self::Eq => write!(f, "eq"),
-            Self::Lt => write!(f, "lt"),
-            Self::Gt => write!(f, "gt"),
-            Self::Le => write!(f, "le"),
-            Self::Ge => write!(f, "ge"),
-            Self::Not => write!(f, "not"),
-            Self::And => write!(f, "and"),
-            Self::Or => write!(f, "or"),
-            Self::Call => write!(f, "call"),
-            Self::Id => write!(f, "id"),
-            #[cfg(feature = "ssa")]
-            Self::Phi => write!(f, "phi"),
-            #[cfg(feature = "float")]
-            Self::Fadd => write!(f, "fadd"),
-            #[cfg(feature = "float")]
-            Self::Fsub => write!(f, "fsub"),
-            #[cfg(feature = "float")]
-            Self::Fmul => write!(f, "fmul"),
-            #[cfg(feature = "float")]
-            Self::Fdiv => write!(f, "fdiv"),
-            #[cfg(feature = "float")]
-            Self::Feq => write!(f, "feq"),
-            #[cfg(feature = "float")]
-            Self::Flt => write!(f, "flt"),
-            #[cfg(feature = "float")]
-            Self::Fgt => write!(f, "fgt"),
-            #[cfg(feature = "float")]
-            Self::Fle => write!(f, "fle"),
-            #[cfg(feature = "float")]
-            Self::Fge => write!(f, "fge"),
-            #[cfg(feature = "char")]
-            Self::Ceq => write!(f, "ceq"),
-            #[cfg(feature = "char")]
-            Self::Clt => write!(f, "clt"),
-            #[cfg(feature = "char")]
-            Self::Cgt => write!(f, "cgt"),
-            #[cfg(feature = "char")]
-            Self::Cle => write!(f, "cle"),
-            #[cfg(feature = "char")]
-            Self::Cge => write!(f, "cge"),
-            #[cfg(feature = "char")]
-            Self::Char2int => write!(f, "char2int"),
-            #[cfg(feature = "char")]
-            Self::Int2char => write!(f, "int2char"),
-            #[cfg(feature = "memory")]
-            Self::Alloc => write!(f, "alloc"),
-            #[cfg(feature = "memory")]
-            Self::Load => write!(f, "load"),
-            #[cfg(feature = "memory")]
-            Self::PtrAdd => write!(f, "ptradd"),
-        }
-    }
-}
+op_names!(ValueOps, InvalidValueOps {
+    "add" => Add,
+    "sub" => Sub,
+    "mul" => Mul,
+    "div" => Div,
+    "eq" => Eq,
+    "lt" => Lt,
+    "gt" => Gt,
+    "le" => Le,
+    "ge" => Ge,
+    "not" => Not,
+    "and" => And,
+    "or" => Or,
+    "call" => Call,
+    "id" => Id,
+    #[cfg(feature = "ssa")]
+    "phi" => Phi,
+    #[cfg(feature = "float")]
+    "fadd" => Fadd,
+    #[cfg(feature = "float")]
+    "fsub" => Fsub,
+    #[cfg(feature = "float")]
+    "fmul" => Fmul,
+    #[cfg(feature = "float")]
+    "fdiv" => Fdiv,
+    #[cfg(feature = "float")]
+    "feq" => Feq,
+    #[cfg(feature = "float")]
+    "flt" => Flt,
+    #[cfg(feature = "float")]
+    "fgt" => Fgt,
+    #[cfg(feature = "float")]
+    "fle" => Fle,
+    #[cfg(feature = "float")]
+    "fge" => Fge,
+    #[cfg(feature = "char")]
+    "ceq" => Ceq,
+    #[cfg(feature = "char")]
+    "clt" => Clt,
+    #[cfg(feature = "char")]
+    "cgt" => Cgt,
+    #[cfg(feature = "char")]
+    "cle" => Cle,
+    #[cfg(feature = "char")]
+    "cge" => Cge,
+    #[cfg(feature = "char")]
+    "char2int" => Char2int,
+    #[cfg(feature = "char")]
+    "int2char" => Int2char,
+    #[cfg(feature = "convert")]
+    "int2float" => Int2float,
+    #[cfg(feature = "convert")]
+    "float2int" => Float2int,
+    #[cfg(feature = "select")]
+    "select" => Select,
+    #[cfg(feature = "memory")]
+    "alloc" => Alloc,
+    #[cfg(feature = "memory")]
+    "load" => Load,
+    #[cfg(feature = "memory")]
+    "ptradd" => PtrAdd,
+});
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#type>
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -562,6 +1327,111 @@ pub enum Type {
     #[cfg(feature = "memory")]
     #[serde(rename = "ptr")]
     Pointer(Box<Self>),
+    /// An array of elements of the given type, with a fixed number of them if the size is known
+    ///
+    /// This is `array<int>` or `array<int, 8>` in text, and `{"array": "int"}` or `{"array": ["int", 8]}` in JSON.
+    #[cfg(feature = "array")]
+    #[serde(
+        rename = "array",
+        serialize_with = "serialize_array",
+        deserialize_with = "deserialize_array"
+    )]
+    Array(Box<Self>, Option<usize>),
+}
+
+#[cfg(feature = "array")]
+#[allow(clippy::borrowed_box, clippy::ref_option)]
+fn serialize_array<S: serde::Serializer>(
+    element: &Box<Type>,
+    size: &Option<usize>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match size {
+        None => element.serialize(serializer),
+        Some(size) => (element, size).serialize(serializer),
+    }
+}
+
+#[cfg(feature = "array")]
+fn deserialize_array<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(Box<Type>, Option<usize>), D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Array {
+        Unsized(Box<Type>),
+        Sized(Box<Type>, usize),
+    }
+    Ok(match Array::deserialize(deserializer)? {
+        Array::Unsized(element) => (element, None),
+        Array::Sized(element, size) => (element, Some(size)),
+    })
+}
+
+impl Type {
+    /// Rebuilds this type bottom-up, replacing every type inside it, including itself, with the result of ```f```
+    ///
+    /// Under the `memory` feature the pointee of a `ptr` is mapped before the `ptr` that contains it, and likewise for the elements of an `array`, so ```f``` always sees already mapped children.
+    #[must_use]
+    pub fn map(&self, f: impl Fn(&Self) -> Self) -> Self {
+        self.map_with(&f)
+    }
+
+    /// The type a `ptr` points to, one level down, or [None] for any other type
+    ///
+    /// For `ptr<ptr<int>>` this is `ptr<int>`.
+    #[cfg(feature = "memory")]
+    #[must_use]
+    pub const fn pointee(&self) -> Option<&Self> {
+        match self {
+            Self::Pointer(inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// How many bytes a value of this type takes up in memory when Bril is lowered to a machine target
+    ///
+    /// This assumes a 64-bit target: an `int` is 8 bytes, a `bool` is 1, a `float` is an 8-byte IEEE double, a `char` is a 4-byte Unicode scalar value, and every `ptr` is 8 bytes whatever it points to.
+    /// An `array` with a size holds its elements one after another, and one without a size is referred to by an 8-byte pointer to its first element.
+    #[must_use]
+    pub const fn size_in_bytes(&self) -> usize {
+        match self {
+            Self::Int => 8,
+            Self::Bool => 1,
+            #[cfg(feature = "float")]
+            Self::Float => 8,
+            #[cfg(feature = "char")]
+            Self::Char => 4,
+            #[cfg(feature = "memory")]
+            Self::Pointer(_) => 8,
+            #[cfg(feature = "array")]
+            Self::Array(element, Some(size)) => element.size_in_bytes().saturating_mul(*size),
+            #[cfg(feature = "array")]
+            Self::Array(_, None) => 8,
+        }
+    }
+
+    /// The alignment in bytes that a value of this type needs, under the same assumptions as [`Type::size_in_bytes`]
+    ///
+    /// Every type is aligned to its own size, except that an `array` with a size is aligned like its elements.
+    #[must_use]
+    pub const fn alignment(&self) -> usize {
+        match self {
+            #[cfg(feature = "array")]
+            Self::Array(element, Some(_)) => element.alignment(),
+            _ => self.size_in_bytes(),
+        }
+    }
+
+    fn map_with(&self, f: &dyn Fn(&Self) -> Self) -> Self {
+        match self {
+            #[cfg(feature = "memory")]
+            Self::Pointer(inner) => f(&Self::Pointer(Box::new(inner.map_with(f)))),
+            #[cfg(feature = "array")]
+            Self::Array(element, size) => f(&Self::Array(Box::new(element.map_with(f)), *size)),
+            t => f(t),
+        }
+    }
 }
 
 impl Display for Type {
@@ -575,13 +1445,20 @@ impl Display for Type {
             Self::Char => write!(f, "char"),
             #[cfg(feature = "memory")]
             Self::Pointer(tpe) => write!(f, "ptr<{tpe}>"),
+            #[cfg(feature = "array")]
+            Self::Array(tpe, None) => write!(f, "array<{tpe}>"),
+            #[cfg(feature = "array")]
+            Self::Array(tpe, Some(size)) => write!(f, "array<{tpe}, {size}>"),
         }
     }
 }
 
 /// A JSON number/value
-#[cfg_attr(not(feature = "float"), derive(Eq, Hash))]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+///
+/// Under the `float` feature, floats are compared and hashed by their bits so that equality is total: `NaN` equals itself, while `0.0` and `-0.0` are different, unlike `==` on [f64].
+/// This is what lets [Instruction], [Function], and [Program] implement [Eq] and [Hash].
+#[cfg_attr(not(feature = "float"), derive(PartialEq, Eq, Hash))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Literal {
     /// Integers
@@ -589,26 +1466,148 @@ pub enum Literal {
     /// Booleans
     Bool(bool),
     /// Floating Points
+    ///
+    /// Non-finite values are written as the strings `NaN`, `Infinity`, and `-Infinity`, as the reference interpreter prints them.
     #[cfg(feature = "float")]
+    #[serde(
+        serialize_with = "serialize_float",
+        deserialize_with = "deserialize_float"
+    )]
     Float(f64),
     /// UTF-16 Characters
     #[cfg(feature = "char")]
     Char(char),
 }
 
+#[cfg(feature = "float")]
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a.to_bits() == b.to_bits(),
+            #[cfg(feature = "char")]
+            (Self::Char(a), Self::Char(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "float")]
+impl Eq for Literal {}
+
+#[cfg(feature = "float")]
+impl Hash for Literal {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Int(i) => i.hash(state),
+            Self::Bool(b) => b.hash(state),
+            Self::Float(x) => x.to_bits().hash(state),
+            #[cfg(feature = "char")]
+            Self::Char(c) => c.hash(state),
+        }
+    }
+}
+
 impl Display for Literal {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::Int(i) => write!(f, "{i}"),
             Self::Bool(b) => write!(f, "{b}"),
             #[cfg(feature = "float")]
-            Self::Float(x) => write!(f, "{x}"),
+            // `Debug` keeps the decimal point or exponent so the text still parses as a float
+            Self::Float(x) => write!(f, "{x:?}"),
             #[cfg(feature = "char")]
             Self::Char(c) => write!(f, "\'{}\'", escape_char(*c)),
         }
     }
 }
 
+#[cfg(feature = "float")]
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn serialize_float<S: serde::Serializer>(x: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if x.is_nan() {
+        serializer.serialize_str("NaN")
+    } else if x.is_infinite() {
+        serializer.serialize_str(if *x > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        serializer.serialize_f64(*x)
+    }
+}
+
+#[cfg(feature = "float")]
+fn deserialize_float<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FloatOrName {
+        Float(f64),
+        Name(String),
+    }
+    match FloatOrName::deserialize(deserializer)? {
+        FloatOrName::Float(x) => Ok(x),
+        FloatOrName::Name(n) => match n.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => Err(de::Error::invalid_value(
+                de::Unexpected::Str(&n),
+                &"a float, `NaN`, `Infinity`, or `-Infinity`",
+            )),
+        },
+    }
+}
+
+/// Writes a constant like the derived implementation would, except that an integer stored as a `float` is written as a float so it keeps its type when read back
+#[cfg(feature = "float")]
+// serde passes every field of the variant separately
+#[allow(
+    clippy::ref_option,
+    clippy::trivially_copy_pass_by_ref,
+    clippy::too_many_arguments
+)]
+fn serialize_constant<S: serde::Serializer>(
+    dest: &String,
+    op: &ConstOps,
+    #[cfg(feature = "position")] pos: &Option<Position>,
+    const_type: &Type,
+    value: &Literal,
+    #[cfg(feature = "format")] _format: &FormatHints,
+    #[cfg(feature = "metadata")] extra: &Map<String, serde_json::Value>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Constant<'a> {
+        dest: &'a String,
+        op: &'a ConstOps,
+        #[cfg(feature = "position")]
+        #[serde(flatten, skip_serializing_if = "Option::is_none")]
+        pos: &'a Option<Position>,
+        #[serde(rename = "type")]
+        const_type: &'a Type,
+        value: Literal,
+        #[cfg(feature = "metadata")]
+        #[serde(flatten)]
+        extra: &'a Map<String, serde_json::Value>,
+    }
+    let value = match (const_type, value) {
+        #[allow(clippy::cast_precision_loss)]
+        (Type::Float, Literal::Int(i)) => Literal::Float(*i as f64),
+        (_, v) => v.clone(),
+    };
+    Constant {
+        dest,
+        op,
+        #[cfg(feature = "position")]
+        pos,
+        const_type,
+        value,
+        #[cfg(feature = "metadata")]
+        extra,
+    }
+    .serialize(serializer)
+}
+
 #[cfg(feature = "char")]
 fn escape_char(c: char) -> String {
     match c {
@@ -640,7 +1639,7 @@ impl Literal {
 }
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#source-positions>
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Position {
     /// Starting position
     pub pos: ColRow,
@@ -653,10 +1652,512 @@ pub struct Position {
 }
 
 /// <https://capra.cs.cornell.edu/bril/lang/syntax.html#source-positions>
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ColRow {
     /// Column
     pub col: u64,
     /// Row
     pub row: u64,
 }
+
+/// Writes `src:row:col-row:col`, leaving out the source file and the end when they are not known
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(src) = &self.src {
+            write!(f, "{src}:")?;
+        }
+        write!(f, "{}:{}", self.pos.row, self.pos.col)?;
+        if let Some(end) = &self.pos_end {
+            write!(f, "-{}:{}", end.row, end.col)?;
+        }
+        Ok(())
+    }
+}
+
+impl Position {
+    /// The position of ```row``` and ```col``` with no end or source file
+    #[must_use]
+    pub const fn new(row: u64, col: u64) -> Self {
+        Self {
+            pos: ColRow { col, row },
+            pos_end: None,
+            src: None,
+        }
+    }
+
+    /// This position ending at ```row``` and ```col```
+    #[must_use]
+    pub const fn with_end(mut self, row: u64, col: u64) -> Self {
+        self.pos_end = Some(ColRow { col, row });
+        self
+    }
+
+    /// This position in the source file ```src```
+    #[must_use]
+    pub fn with_src(mut self, src: impl Into<String>) -> Self {
+        self.src = Some(src.into());
+        self
+    }
+}
+
+/// Attaches source positions to code as a code generator builds it, so that the emitted program points back into the generator's input
+///
+/// Move the map along with [`SourceMap::at`] while walking the input, and pass each label, instruction, and function through [`SourceMap::place`] or [`SourceMap::place_function`] as it is made.
+/// Without the `position` feature there is nowhere to keep positions, so placing hands everything back unchanged and the same generator builds either way.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    src: Option<String>,
+    current: Option<Position>,
+}
+
+impl SourceMap {
+    /// A map which places nothing until it is moved with [`SourceMap::at`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A map whose positions are all in the source file ```src```
+    #[must_use]
+    pub fn for_file(src: impl Into<String>) -> Self {
+        Self {
+            src: Some(src.into()),
+            current: None,
+        }
+    }
+
+    /// Places code from now on at ```row``` and ```col```
+    pub fn at(&mut self, row: u64, col: u64) -> &mut Self {
+        self.set(Position::new(row, col))
+    }
+
+    /// Places code from now on at the span from ```start``` to ```end```, each a row and column
+    pub fn span(&mut self, start: (u64, u64), end: (u64, u64)) -> &mut Self {
+        self.set(Position::new(start.0, start.1).with_end(end.0, end.1))
+    }
+
+    /// Places code from now on at ```pos```, in the file of this map if ```pos``` names none
+    pub fn set(&mut self, mut pos: Position) -> &mut Self {
+        if pos.src.is_none() {
+            pos.src.clone_from(&self.src);
+        }
+        self.current = Some(pos);
+        self
+    }
+
+    /// Stops placing code, so that it is emitted without a position
+    pub fn clear(&mut self) -> &mut Self {
+        self.current = None;
+        self
+    }
+
+    /// The position code is currently placed at
+    #[must_use]
+    pub const fn current(&self) -> Option<&Position> {
+        self.current.as_ref()
+    }
+
+    /// ```code``` at the current position, replacing any it had
+    #[must_use]
+    #[cfg_attr(
+        not(feature = "position"),
+        allow(unused_mut, clippy::missing_const_for_fn)
+    )]
+    pub fn place(&self, mut code: Code) -> Code {
+        #[cfg(feature = "position")]
+        match &mut code {
+            Code::Label { pos, .. }
+            | Code::Instruction(
+                Instruction::Constant { pos, .. }
+                | Instruction::Value { pos, .. }
+                | Instruction::Effect { pos, .. },
+            ) => pos.clone_from(&self.current),
+        }
+        code
+    }
+
+    /// ```func``` at the current position, replacing any it had. Its code is left as it is
+    #[must_use]
+    #[cfg_attr(
+        not(feature = "position"),
+        allow(unused_mut, clippy::missing_const_for_fn)
+    )]
+    pub fn place_function(&self, mut func: Function) -> Function {
+        #[cfg(feature = "position")]
+        func.pos.clone_from(&self.current);
+        func
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::text::parse_and_convert;
+    use crate::{
+        Code, DuplicateFunctionError, EffectOps, EntryError, Instruction, Literal, PrettyOptions,
+        Program, SourceMap, Type, ValueOps,
+    };
+
+    /// The instructions of the `@main` of ```src```
+    fn instrs_of(src: &str) -> Vec<Instruction> {
+        parse_and_convert(src).unwrap().functions[0]
+            .instrs
+            .iter()
+            .filter_map(|code| match code {
+                Code::Instruction(instr) => Some(instr.clone()),
+                Code::Label { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replace_and_map_args() {
+        let mut instrs = instrs_of(
+            "@main(a: int, b: int) {
+  a: int = const 1;
+  a: int = add a b;
+  r: int = call @a a a;
+  br c .a .b;
+.a:
+.b:
+}",
+        );
+        for instr in &mut instrs {
+            instr.replace_arg("a", "x");
+        }
+        instrs[3].map_args(str::to_uppercase);
+        let text: Vec<_> = instrs.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            text,
+            [
+                "a: int = const 1;",
+                "a: int = add x b;",
+                "r: int = call @a x x;",
+                "br C .a .b;",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "memory", feature = "float"))]
+    fn map_inside_pointers() {
+        let ptr = |t| Type::Pointer(Box::new(t));
+        let int_to_float = |t: &Type| match t {
+            Type::Int => Type::Float,
+            t => t.clone(),
+        };
+        assert_eq!(ptr(ptr(Type::Int)).map(int_to_float), ptr(ptr(Type::Float)));
+        // Children are mapped first, so `f` sees `ptr<float>` rather than `ptr<int>`
+        let mapped = ptr(Type::Int).map(|t| match t {
+            Type::Int => Type::Float,
+            Type::Pointer(inner) if **inner == Type::Float => Type::Bool,
+            t => t.clone(),
+        });
+        assert_eq!(mapped, Type::Bool);
+    }
+
+    #[test]
+    fn strict_rejects_duplicate_op() {
+        let json = r#"{"functions": [{"name": "main", "instrs": [
+  {"op": "print", "args": [], "op": "nop"}
+]}]}"#;
+        let error = Program::from_str_strict(json).unwrap_err();
+        assert_eq!(error.to_string(), "duplicate key `op` at line 2 column 34");
+        assert!(Program::from_str_strict(&json.replace(r#", "op": "nop""#, "")).is_ok());
+    }
+
+    #[test]
+    fn annotated_text_round_trips() {
+        let program = parse_and_convert(
+            "@main(n: int) {
+  b: bool = const true;
+  print n b;
+  br b .x .x;
+.x:
+}",
+        )
+        .unwrap();
+        let text = program.to_pretty_string(PrettyOptions {
+            always_annotate_types: true,
+            ..PrettyOptions::default()
+        });
+        assert_eq!(
+            text,
+            "@main(n: int) {
+  b: bool = const true;
+  print n b;  # n: int, b: bool
+  br b .x .x;  # b: bool
+.x:
+}
+"
+        );
+        let reparsed = parse_and_convert(&text).unwrap();
+        assert!(reparsed.structurally_equal(&program));
+    }
+
+    #[test]
+    fn canonical_json_shape() {
+        let canonical = r#"{"functions":[{"instrs":[{"label":"start"},{"dest":"x","op":"const","type":"int","value":1},{"args":["x"],"op":"print"},{"labels":["start"],"op":"jmp"},{"op":"ret"}],"name":"main"}]}"#;
+        let program = crate::load_program_from_read(canonical.as_bytes()).unwrap();
+        let written = serde_json::to_string(&program).unwrap();
+        assert_eq!(written, canonical);
+        let reread = crate::load_program_from_read(written.as_bytes()).unwrap();
+        assert_eq!(reread, program);
+    }
+
+    #[test]
+    fn reads_and_writes() {
+        let instrs = instrs_of(
+            "@main {
+  a: int = const 1;
+  b: int = add a a;
+  print b;
+}",
+        );
+        let uses: Vec<_> = instrs
+            .iter()
+            .map(|instr| (instr.dest(), instr.args(), instr.op_type()))
+            .collect();
+        let args = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            uses,
+            [
+                (Some("a"), &[][..], Some(&Type::Int)),
+                (Some("b"), &args(&["a", "a"])[..], Some(&Type::Int)),
+                (None, &args(&["b"])[..], None),
+            ]
+        );
+    }
+
+    #[test]
+    fn build_instructions() {
+        let built = [
+            Instruction::constant("a", Type::Int, Literal::Int(1)),
+            Instruction::value(ValueOps::Add, "b", Type::Int, ["a", "a"]),
+            Instruction::effect(EffectOps::Branch, ["c"], ["then", "else"], None::<&str>),
+            Instruction::effect(EffectOps::Call, ["b"], None::<&str>, ["f"]),
+        ];
+        let text: Vec<_> = built.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            text,
+            [
+                "a: int = const 1;",
+                "b: int = add a a;",
+                "br c .then .else;",
+                "call @f b;"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "float")]
+    fn float_literals_compare_by_bits() {
+        let nan = Literal::Float(f64::NAN);
+        assert_eq!(nan, nan.clone());
+        assert_ne!(Literal::Float(0.0), Literal::Float(-0.0));
+        let programs: HashSet<_> = ["1.5", "1.5", "NaN", "NaN"]
+            .iter()
+            .map(|x| {
+                let mut program = parse_and_convert(&format!(
+                    "@main {{\n  x: float = const {x};\n  print x;\n}}"
+                ))
+                .unwrap();
+                program.strip_positions();
+                program
+            })
+            .collect();
+        assert_eq!(programs.len(), 2);
+        let types: HashMap<Type, &str> = [(Type::Int, "int"), (Type::Float, "float")].into();
+        assert_eq!(types[&Type::Float], "float");
+    }
+
+    #[test]
+    fn signatures_of_two_functions() {
+        let program = parse_and_convert(
+            "@add(a: int, b: int): int {
+  c: int = add a b;
+  ret c;
+}
+@main {
+  x: int = const 1;
+  y: int = call @add x x;
+  print y;
+}",
+        )
+        .unwrap();
+        let signatures = program.signatures().unwrap();
+        let (args, ret) = signatures["add"];
+        let params: Vec<_> = args
+            .iter()
+            .map(|a| (a.name.as_str(), &a.arg_type))
+            .collect();
+        assert_eq!(params, [("a", &Type::Int), ("b", &Type::Int)]);
+        assert_eq!(ret, Some(&Type::Int));
+        assert_eq!(signatures["main"], (&[][..], None));
+        let mut twice = program.clone();
+        twice.functions.push(program.functions[0].clone());
+        assert_eq!(
+            twice.signatures(),
+            Err(DuplicateFunctionError("add".to_string()))
+        );
+    }
+
+    #[test]
+    fn entry_with_and_without_main() {
+        let program = |src: &str| parse_and_convert(src).unwrap();
+        let with_main = program("@main {\n  x: int = call @helper;\n  print x;\n}\n@helper: int {\n  one: int = const 1;\n  ret one;\n}");
+        assert_eq!(with_main.main().map(|f| f.name.as_str()), Some("main"));
+        assert_eq!(with_main.validate_entry(), Ok(()));
+        let without_main = program("@helper {\n  ret;\n}");
+        assert!(without_main.main().is_none());
+        assert_eq!(without_main.validate_entry(), Err(EntryError::NoMain));
+        assert_eq!(
+            program("@main {\n  call @again;\n}\n@again {\n  call @main;\n}").validate_entry(),
+            Err(EntryError::CalledMain("again".to_string()))
+        );
+        assert_eq!(
+            program("@main: float {\n  x: float = const 1.5;\n  ret x;\n}").validate_entry(),
+            Err(EntryError::BadReturnType(Type::Float))
+        );
+    }
+
+    #[test]
+    fn instructions_and_labels() {
+        let mut program = parse_and_convert(
+            "@main {
+.a:
+  x: int = const 1;
+.b:
+.c:
+  print x;
+  y: int = const 2;
+}",
+        )
+        .unwrap();
+        let main = &mut program.functions[0];
+        assert_eq!(main.code().len(), 6);
+        assert_eq!(main.instructions().count(), 3);
+        assert_eq!(main.labels().collect::<Vec<_>>(), ["a", "b", "c"]);
+        for instr in main.instructions_mut() {
+            instr.replace_arg("x", "z");
+        }
+        assert_eq!(main.instructions().nth(1).unwrap().to_string(), "print z;");
+    }
+
+    #[test]
+    fn sizes_and_alignments() {
+        let layout = |t: &Type| (t.size_in_bytes(), t.alignment());
+        assert_eq!(layout(&Type::Int), (8, 8));
+        assert_eq!(layout(&Type::Bool), (1, 1));
+        #[cfg(feature = "float")]
+        assert_eq!(layout(&Type::Float), (8, 8));
+        #[cfg(feature = "char")]
+        assert_eq!(layout(&Type::Char), (4, 4));
+        #[cfg(feature = "memory")]
+        assert_eq!(layout(&Type::Pointer(Box::new(Type::Bool))), (8, 8));
+        #[cfg(feature = "array")]
+        {
+            assert_eq!(layout(&Type::Array(Box::new(Type::Bool), Some(3))), (3, 1));
+            assert_eq!(layout(&Type::Array(Box::new(Type::Int), None)), (8, 8));
+        }
+    }
+
+    #[test]
+    fn structural_equality_ignores_positions() {
+        let program = |src: &str| parse_and_convert(src).unwrap();
+        let spread = program("@main(a: int) {\n  x: int = add a a;\n.l:\n  print x;\n}");
+        let moved = program("\n\n@main(a: int) {\n    x: int = add a a;\n  .l:\n      print x;\n}");
+        #[cfg(feature = "position")]
+        assert_ne!(spread, moved);
+        assert!(spread.structurally_equal(&moved));
+        let other_op = program("@main(a: int) {\n  x: int = mul a a;\n.l:\n  print x;\n}");
+        assert!(!spread.structurally_equal(&other_op));
+    }
+
+    #[test]
+    #[cfg(feature = "metadata")]
+    fn extra_instruction_keys_round_trip() {
+        let json = r#"{"functions":[{"instrs":[{"analysis":{"count":3,"hot":true},"dest":"x","op":"const","type":"int","value":1},{"args":["x"],"op":"print"}],"name":"main"}]}"#;
+        let program = crate::load_program_from_read(json.as_bytes()).unwrap();
+        let Code::Instruction(Instruction::Constant { extra, .. }) =
+            &program.functions[0].instrs[0]
+        else {
+            panic!("the first code should be a constant");
+        };
+        assert_eq!(extra["analysis"]["count"], 3);
+        assert_eq!(
+            serde_json::to_value(&program).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "position")]
+    fn source_map_positions_are_serialized() {
+        let mut map = SourceMap::for_file("gen.src");
+        map.at(1, 1);
+        let mut instrs = vec![map.place(Code::Instruction(Instruction::constant(
+            "x",
+            Type::Int,
+            Literal::Int(1),
+        )))];
+        map.span((2, 3), (2, 9));
+        instrs.push(map.place(Code::Instruction(Instruction::effect(
+            EffectOps::Print,
+            ["x"],
+            None::<&str>,
+            None::<&str>,
+        ))));
+        map.clear();
+        instrs.push(map.place(Code::Instruction(Instruction::effect(
+            EffectOps::Return,
+            None::<&str>,
+            None::<&str>,
+            None::<&str>,
+        ))));
+        let main = map.at(1, 1).place_function(crate::Function {
+            args: Vec::new(),
+            instrs,
+            name: "main".to_string(),
+            pos: None,
+            return_type: None,
+            #[cfg(feature = "external")]
+            external: false,
+        });
+        let json = serde_json::to_value(&main).unwrap();
+        assert_eq!(json["pos"], serde_json::json!({"row": 1, "col": 1}));
+        assert_eq!(json["src"], "gen.src");
+        assert_eq!(
+            json["instrs"][1]["pos"],
+            serde_json::json!({"row": 2, "col": 3})
+        );
+        assert_eq!(
+            json["instrs"][1]["pos_end"],
+            serde_json::json!({"row": 2, "col": 9})
+        );
+        assert!(json["instrs"][2].get("pos").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "float")]
+    fn non_finite_and_integral_float_json() {
+        for (value, json) in [
+            (f64::NAN, r#""NaN""#),
+            (f64::INFINITY, r#""Infinity""#),
+            (f64::NEG_INFINITY, r#""-Infinity""#),
+            (2.0, "2.0"),
+        ] {
+            let literal = Literal::Float(value);
+            assert_eq!(serde_json::to_string(&literal).unwrap(), json);
+            let program = format!(
+                r#"{{"functions": [{{"name": "main", "instrs": [{{"op": "const", "dest": "x", "type": "float", "value": {json}}}]}}]}}"#
+            );
+            let program = crate::load_program_from_read(program.as_bytes()).unwrap();
+            assert!(matches!(
+                &program.functions[0].instrs[0],
+                Code::Instruction(Instruction::Constant { value, .. }) if *value == literal
+            ));
+        }
+    }
+}
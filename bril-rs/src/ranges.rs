@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use crate::cfg::{BlockId, Cfg};
+use crate::{Code, EffectOps, Function, Instruction, Literal, Type, ValueOps};
+
+/// The integers from `lo` to `hi`, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Interval {
+    /// The smallest value in the range
+    pub lo: i64,
+    /// The largest value in the range
+    pub hi: i64,
+}
+
+impl Interval {
+    /// Every `int`, which is what is known about a variable nothing can be said about
+    pub const FULL: Self = Self {
+        lo: i64::MIN,
+        hi: i64::MAX,
+    };
+
+    /// The range holding only ```n```
+    #[must_use]
+    pub const fn constant(n: i64) -> Self {
+        Self { lo: n, hi: n }
+    }
+
+    /// Whether ```n``` is in this range
+    #[must_use]
+    pub const fn contains(&self, n: i64) -> bool {
+        self.lo <= n && n <= self.hi
+    }
+
+    /// The smallest range containing both ```self``` and ```other```
+    #[must_use]
+    pub fn join(self, other: Self) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// The values in both ranges, if there are any
+    fn meet(self, other: Self) -> Option<Self> {
+        let lo = self.lo.max(other.lo);
+        let hi = self.hi.min(other.hi);
+        (lo <= hi).then_some(Self { lo, hi })
+    }
+
+    /// `add` wraps on overflow, so any sum which might overflow could be anything
+    const fn add(self, other: Self) -> Self {
+        match (self.lo.checked_add(other.lo), self.hi.checked_add(other.hi)) {
+            (Some(lo), Some(hi)) => Self { lo, hi },
+            _ => Self::FULL,
+        }
+    }
+
+    const fn sub(self, other: Self) -> Self {
+        match (self.lo.checked_sub(other.hi), self.hi.checked_sub(other.lo)) {
+            (Some(lo), Some(hi)) => Self { lo, hi },
+            _ => Self::FULL,
+        }
+    }
+}
+
+type State<'a> = HashMap<&'a str, Interval>;
+
+/// A loop header's range may grow this many times before growing bounds are widened to the extremes, which guarantees the analysis terminates
+const WIDEN_AFTER: usize = 3;
+
+/// How many times ranges are narrowed again after widening
+const NARROW_PASSES: usize = 3;
+
+fn get(state: &State, var: &str) -> Interval {
+    state.get(var).copied().unwrap_or(Interval::FULL)
+}
+
+fn join_states<'a>(old: &State<'a>, new: &State<'a>, widen: bool) -> State<'a> {
+    let mut joined = old.clone();
+    for (&var, &i) in new {
+        joined
+            .entry(var)
+            .and_modify(|o| {
+                let j = o.join(i);
+                *o = if widen {
+                    Interval {
+                        lo: if j.lo < o.lo { i64::MIN } else { j.lo },
+                        hi: if j.hi > o.hi { i64::MAX } else { j.hi },
+                    }
+                } else {
+                    j
+                };
+            })
+            .or_insert(i);
+    }
+    joined
+}
+
+/// Narrows ```state``` to the values for which `op a b` is ```taken```, returning [None] if there are none
+fn refine<'a>(
+    mut state: State<'a>,
+    op: ValueOps,
+    a: &'a str,
+    b: &'a str,
+    taken: bool,
+) -> Option<State<'a>> {
+    // Rewrite every condition as `x < y`, `x <= y`, or `x == y`
+    let (strict, x, y) = match (op, taken) {
+        (ValueOps::Lt, true) | (ValueOps::Ge, false) => (Some(true), a, b),
+        (ValueOps::Le, true) | (ValueOps::Gt, false) => (Some(false), a, b),
+        (ValueOps::Gt, true) | (ValueOps::Le, false) => (Some(true), b, a),
+        (ValueOps::Ge, true) | (ValueOps::Lt, false) => (Some(false), b, a),
+        (ValueOps::Eq, true) => (None, a, b),
+        _ => return Some(state),
+    };
+    let (xi, yi) = (get(&state, x), get(&state, y));
+    let (new_x, new_y) = match strict {
+        None => {
+            let both = xi.meet(yi)?;
+            (both, both)
+        }
+        Some(strict) => {
+            let gap = i64::from(strict);
+            let x_hi = yi.hi.checked_sub(gap)?;
+            let y_lo = xi.lo.checked_add(gap)?;
+            (
+                xi.meet(Interval {
+                    lo: i64::MIN,
+                    hi: x_hi,
+                })?,
+                yi.meet(Interval {
+                    lo: y_lo,
+                    hi: i64::MAX,
+                })?,
+            )
+        }
+    };
+    state.insert(x, new_x);
+    state.insert(y, new_y);
+    Some(state)
+}
+
+/// Runs ```block``` from ```state```, returning the state along each outgoing edge which may be taken
+fn transfer<'a>(
+    func: &'a Function,
+    cfg: &Cfg,
+    block: BlockId,
+    mut state: State<'a>,
+) -> Vec<(BlockId, State<'a>)> {
+    // The comparison which decided the variable it was stored in, while its arguments are unchanged
+    let mut compares: HashMap<&str, (ValueOps, &str, &str)> = HashMap::new();
+    for i in cfg.block_range(block) {
+        let Code::Instruction(instr) = &func.instrs[i] else {
+            continue;
+        };
+        let (dest, range) = match instr {
+            Instruction::Constant {
+                dest,
+                const_type: Type::Int,
+                value: Literal::Int(n),
+                ..
+            } => (dest, Some(Interval::constant(*n))),
+            Instruction::Constant { dest, .. } => (dest, None),
+            Instruction::Value {
+                dest,
+                op,
+                op_type,
+                args,
+                ..
+            } => {
+                let range = (*op_type == Type::Int).then(|| match (op, args.as_slice()) {
+                    (ValueOps::Id, [a]) => get(&state, a),
+                    (ValueOps::Add, [a, b]) => get(&state, a).add(get(&state, b)),
+                    (ValueOps::Sub, [a, b]) => get(&state, a).sub(get(&state, b)),
+                    #[cfg(feature = "ssa")]
+                    (ValueOps::Phi, _) => args
+                        .iter()
+                        .filter_map(|a| state.get(a.as_str()).copied())
+                        .reduce(Interval::join)
+                        .unwrap_or(Interval::FULL),
+                    _ => Interval::FULL,
+                });
+                (dest, range)
+            }
+            Instruction::Effect {
+                op: EffectOps::Branch,
+                args,
+                labels,
+                ..
+            } => {
+                let (Some(cond), [t, f]) = (args.first(), labels.as_slice()) else {
+                    return Vec::new();
+                };
+                return [(t, true), (f, false)]
+                    .into_iter()
+                    .filter_map(|(label, taken)| {
+                        let target = cfg.block_by_label(label)?;
+                        let edge = match compares.get(cond.as_str()) {
+                            Some(&(op, a, b)) => refine(state.clone(), op, a, b, taken)?,
+                            None => state.clone(),
+                        };
+                        Some((target, edge))
+                    })
+                    .collect();
+            }
+            Instruction::Effect { .. } => continue,
+        };
+        compares.retain(|c, (_, a, b)| c != dest && a != dest && b != dest);
+        if let Instruction::Value {
+            op: op @ (ValueOps::Lt | ValueOps::Le | ValueOps::Gt | ValueOps::Ge | ValueOps::Eq),
+            args,
+            ..
+        } = instr
+        {
+            if let [a, b] = args.as_slice() {
+                if a != b && a != dest && b != dest {
+                    compares.insert(dest, (*op, a, b));
+                }
+            }
+        }
+        match range {
+            Some(r) => state.insert(dest, r),
+            None => state.remove(dest.as_str()),
+        };
+    }
+    cfg.successors(block)
+        .iter()
+        .map(|&s| (s, state.clone()))
+        .collect()
+}
+
+/// Computes the range of every `int` variable of ```func``` at the start of each block of ```cfg``` which is reachable
+///
+/// Ranges come from constants, `id`, `add`, and `sub`, and are narrowed along each edge of a `br` on an integer comparison computed in the same block. Any other operation, and any sum that could overflow, may produce any value.
+/// A variable is present for a block when it is an `int` on some path from the entry, so a range is only meaningful where the variable is defined. Ranges in loops are widened so the analysis terminates and then narrowed again, which can still lose precision.
+#[must_use]
+pub fn value_ranges(func: &Function, cfg: &Cfg) -> HashMap<(BlockId, String), Interval> {
+    let params: State = func
+        .args
+        .iter()
+        .filter(|a| a.arg_type == Type::Int)
+        .map(|a| (a.name.as_str(), Interval::FULL))
+        .collect();
+    let mut entry: Vec<Option<State>> = vec![None; cfg.len()];
+    entry[cfg.entry] = Some(params.clone());
+    // Every cycle has an edge which does not go forward in program order, so widening at their targets is enough to terminate
+    let headers: Vec<bool> = (0..cfg.len())
+        .map(|b| cfg.predecessors(b).iter().any(|&p| p >= b))
+        .collect();
+    let mut updates = vec![0; cfg.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 0..cfg.len() {
+            let Some(state) = entry[b].clone() else {
+                continue;
+            };
+            for (s, edge) in transfer(func, cfg, b, state) {
+                let new = match &entry[s] {
+                    None => edge,
+                    Some(old) => join_states(old, &edge, headers[s] && updates[s] >= WIDEN_AFTER),
+                };
+                if entry[s].as_ref() != Some(&new) {
+                    updates[s] += 1;
+                    entry[s] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Widening overshoots, so recover precision by recomputing every block from its predecessors a few more times
+    for _ in 0..NARROW_PASSES {
+        let mut next: Vec<Option<State>> = vec![None; cfg.len()];
+        next[cfg.entry] = Some(params.clone());
+        for (b, state) in entry.iter().enumerate() {
+            let Some(state) = state.clone() else {
+                continue;
+            };
+            for (s, edge) in transfer(func, cfg, b, state) {
+                next[s] = Some(match next[s].take() {
+                    None => edge,
+                    Some(old) => join_states(&old, &edge, false),
+                });
+            }
+        }
+        if next == entry {
+            break;
+        }
+        entry = next;
+    }
+
+    entry
+        .into_iter()
+        .enumerate()
+        .filter_map(|(b, state)| Some((b, state?)))
+        .flat_map(|(b, state)| {
+            state
+                .into_iter()
+                .map(move |(var, i)| ((b, var.to_string()), i))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{value_ranges, Interval};
+    use crate::cfg::build_cfg;
+    use crate::text::parse_and_convert;
+
+    #[test]
+    fn narrowed_on_lt() {
+        let program = parse_and_convert(
+            "@main(x: int) {
+  ten: int = const 10;
+  small: bool = lt x ten;
+  br small .then .else;
+.then:
+  print x;
+  jmp .end;
+.else:
+  print x;
+.end:
+}",
+        )
+        .unwrap();
+        let func = &program.functions[0];
+        let cfg = build_cfg(func);
+        let ranges = value_ranges(func, &cfg);
+        let then = cfg.block_by_label("then").unwrap();
+        let other = cfg.block_by_label("else").unwrap();
+        assert_eq!(
+            ranges[&(then, "x".to_string())],
+            Interval {
+                lo: i64::MIN,
+                hi: 9
+            }
+        );
+        assert_eq!(ranges[&(then, "ten".to_string())], Interval::constant(10));
+        assert_eq!(
+            ranges[&(other, "x".to_string())],
+            Interval {
+                lo: 10,
+                hi: i64::MAX
+            }
+        );
+    }
+}
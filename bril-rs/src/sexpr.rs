@@ -0,0 +1,677 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+use thiserror::Error;
+
+use crate::conversion::PositionalConversionError;
+use crate::{
+    AbstractArgument, AbstractCode, AbstractFunction, AbstractInstruction, AbstractProgram,
+    AbstractType, Code, ConstOps, Function, Instruction, Literal, Program, Type,
+};
+#[cfg(feature = "position")]
+use crate::{ColRow, Position};
+#[cfg(feature = "import")]
+use crate::{Import, ImportedFunction};
+
+/// The ways that reading a [Program] from an S-expression with [`Program::from_sexpr`] can fail
+#[derive(Error, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum SexprError {
+    /// The input ended inside a list or before the program
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    /// A `)` closes no list
+    #[error("unexpected `)`")]
+    UnexpectedClose,
+    /// A string has no closing `"`
+    #[error("unterminated string")]
+    UnterminatedString,
+    /// There is more input after the program
+    #[error("unexpected `{0}` after the program")]
+    TrailingInput(String),
+    /// Something other than {0} was found
+    #[error("expected {0}, found `{1}`")]
+    Expected(&'static str, String),
+    /// The program is well formed but not a valid Bril program, such as one with an unknown operation
+    #[error(transparent)]
+    Conversion(Box<PositionalConversionError>),
+}
+
+impl From<PositionalConversionError> for SexprError {
+    fn from(e: PositionalConversionError) -> Self {
+        Self::Conversion(Box::new(e))
+    }
+}
+
+/// An atom, a string, or a list of S-expressions
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Sexpr {
+    Atom(String),
+    Str(String),
+    List(Vec<Self>),
+}
+
+impl Display for Sexpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Atom(a) => write!(f, "{a}"),
+            Self::Str(s) => write_str(f, s),
+            Self::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn write_str(f: &mut impl Write, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{c}")?;
+    }
+    write!(f, "\"")
+}
+
+const fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"')
+}
+
+/// Writes ```name``` as a bare atom when it can be read back as one, and as a string otherwise
+fn write_name(f: &mut impl Write, name: &str) -> fmt::Result {
+    if name.is_empty() || name.contains(is_delimiter) {
+        write_str(f, name)
+    } else {
+        write!(f, "{name}")
+    }
+}
+
+/// Writes ```(head items...)```, or nothing if there are no items
+fn write_list(f: &mut impl Write, head: &str, items: &[String]) -> fmt::Result {
+    if items.is_empty() {
+        return Ok(());
+    }
+    write!(f, " ({head}")?;
+    for item in items {
+        write!(f, " ")?;
+        write_name(f, item)?;
+    }
+    write!(f, ")")
+}
+
+fn write_type(f: &mut impl Write, t: &Type) -> fmt::Result {
+    match t {
+        #[cfg(feature = "memory")]
+        Type::Pointer(inner) => {
+            write!(f, "(ptr ")?;
+            write_type(f, inner)?;
+            write!(f, ")")
+        }
+        #[cfg(feature = "array")]
+        Type::Array(element, size) => {
+            write!(f, "(array ")?;
+            write_type(f, element)?;
+            if let Some(size) = size {
+                write!(f, " {size}")?;
+            }
+            write!(f, ")")
+        }
+        t => write!(f, "{t}"),
+    }
+}
+
+fn write_literal(f: &mut impl Write, value: &Literal) -> fmt::Result {
+    match value {
+        Literal::Int(i) => write!(f, "{i}"),
+        Literal::Bool(b) => write!(f, "{b}"),
+        // The debug form always has a `.` or an exponent, so it is not read back as an `int`
+        #[cfg(feature = "float")]
+        Literal::Float(x) => write!(f, "{x:?}"),
+        #[cfg(feature = "char")]
+        Literal::Char(c) => write_str(f, &c.to_string()),
+    }
+}
+
+#[cfg(feature = "position")]
+fn write_pos(f: &mut impl Write, pos: Option<&Position>) -> fmt::Result {
+    let Some(Position { pos, pos_end, src }) = pos else {
+        return Ok(());
+    };
+    write!(f, " (pos {} {})", pos.row, pos.col)?;
+    if let Some(end) = pos_end {
+        write!(f, " (pos_end {} {})", end.row, end.col)?;
+    }
+    if let Some(src) = src {
+        write!(f, " (src ")?;
+        write_str(f, src)?;
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+fn write_code(f: &mut impl Write, code: &Code) -> fmt::Result {
+    match code {
+        Code::Label { label, .. } => {
+            write!(f, "(label ")?;
+            write_name(f, label)?;
+        }
+        Code::Instruction(Instruction::Constant {
+            dest,
+            const_type,
+            value,
+            ..
+        }) => {
+            write!(f, "(const ")?;
+            write_name(f, dest)?;
+            write!(f, " ")?;
+            write_type(f, const_type)?;
+            write!(f, " ")?;
+            write_literal(f, value)?;
+        }
+        Code::Instruction(Instruction::Value {
+            args,
+            dest,
+            funcs,
+            labels,
+            op,
+            op_type,
+            ..
+        }) => {
+            write!(f, "(value ")?;
+            write_name(f, dest)?;
+            write!(f, " ")?;
+            write_type(f, op_type)?;
+            write!(f, " {op}")?;
+            write_list(f, "args", args)?;
+            write_list(f, "funcs", funcs)?;
+            write_list(f, "labels", labels)?;
+        }
+        Code::Instruction(Instruction::Effect {
+            args,
+            funcs,
+            labels,
+            op,
+            ..
+        }) => {
+            write!(f, "(effect {op}")?;
+            write_list(f, "args", args)?;
+            write_list(f, "funcs", funcs)?;
+            write_list(f, "labels", labels)?;
+        }
+    }
+    #[cfg(feature = "position")]
+    {
+        let pos = match code {
+            Code::Label { pos, .. } => pos.clone(),
+            Code::Instruction(instr) => crate::check::pos_of(instr),
+        };
+        write_pos(f, pos.as_ref())?;
+    }
+    write!(f, ")")
+}
+
+fn write_function(f: &mut impl Write, func: &Function) -> fmt::Result {
+    write!(f, "  (function ")?;
+    write_name(f, &func.name)?;
+    if !func.args.is_empty() {
+        write!(f, " (args")?;
+        for a in &func.args {
+            write!(f, " (")?;
+            write_name(f, &a.name)?;
+            write!(f, " ")?;
+            write_type(f, &a.arg_type)?;
+            write!(f, ")")?;
+        }
+        write!(f, ")")?;
+    }
+    if let Some(t) = &func.return_type {
+        write!(f, " (returns ")?;
+        write_type(f, t)?;
+        write!(f, ")")?;
+    }
+    #[cfg(feature = "external")]
+    if func.external {
+        write!(f, " (external)")?;
+    }
+    #[cfg(feature = "position")]
+    write_pos(f, func.pos.as_ref())?;
+    for code in &func.instrs {
+        write!(f, "\n    ")?;
+        write_code(f, code)?;
+    }
+    write!(f, ")")
+}
+
+/// Splits ```src``` into the S-expressions it contains
+fn parse_all(src: &str) -> Result<Vec<Sexpr>, SexprError> {
+    let mut stack: Vec<Vec<Sexpr>> = vec![Vec::new()];
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => stack.push(Vec::new()),
+            ')' => {
+                if stack.len() == 1 {
+                    return Err(SexprError::UnexpectedClose);
+                }
+                let list = stack.pop().unwrap();
+                stack.last_mut().unwrap().push(Sexpr::List(list));
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(SexprError::UnterminatedString),
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => s.push(c),
+                            None => return Err(SexprError::UnterminatedString),
+                        },
+                        Some((_, c)) => s.push(c),
+                    }
+                }
+                stack.last_mut().unwrap().push(Sexpr::Str(s));
+            }
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if is_delimiter(c) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .push(Sexpr::Atom(src[start..end].to_string()));
+            }
+        }
+    }
+    if stack.len() != 1 {
+        return Err(SexprError::UnexpectedEnd);
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn name(e: &Sexpr) -> Result<String, SexprError> {
+    match e {
+        Sexpr::Atom(s) | Sexpr::Str(s) => Ok(s.clone()),
+        Sexpr::List(_) => Err(SexprError::Expected("a name", e.to_string())),
+    }
+}
+
+fn number<T: std::str::FromStr>(e: Option<&Sexpr>) -> Result<T, SexprError> {
+    match e {
+        Some(Sexpr::Atom(a)) => a
+            .parse()
+            .map_err(|_| SexprError::Expected("a number", a.clone())),
+        Some(e) => Err(SexprError::Expected("a number", e.to_string())),
+        None => Err(SexprError::UnexpectedEnd),
+    }
+}
+
+fn parse_type(e: &Sexpr) -> Result<AbstractType, SexprError> {
+    match e {
+        Sexpr::Atom(t) => Ok(AbstractType::Primitive(t.clone())),
+        Sexpr::List(items) => match items.as_slice() {
+            [Sexpr::Atom(t), inner] => Ok(AbstractType::Parameterized(
+                t.clone(),
+                Box::new(parse_type(inner)?),
+            )),
+            #[cfg(feature = "array")]
+            [Sexpr::Atom(t), inner, Sexpr::Atom(size)] => Ok(AbstractType::Sized(
+                t.clone(),
+                Box::new(parse_type(inner)?),
+                size.clone(),
+            )),
+            _ => Err(SexprError::Expected("a type", e.to_string())),
+        },
+        Sexpr::Str(_) => Err(SexprError::Expected("a type", e.to_string())),
+    }
+}
+
+fn parse_literal(e: &Sexpr) -> Result<Literal, SexprError> {
+    match e {
+        Sexpr::Atom(a) if a == "true" => Ok(Literal::Bool(true)),
+        Sexpr::Atom(a) if a == "false" => Ok(Literal::Bool(false)),
+        Sexpr::Atom(a) => {
+            if let Ok(i) = a.parse() {
+                return Ok(Literal::Int(i));
+            }
+            #[cfg(feature = "float")]
+            if let Ok(x) = a.parse() {
+                return Ok(Literal::Float(x));
+            }
+            Err(SexprError::Expected("a literal", a.clone()))
+        }
+        #[cfg(feature = "char")]
+        Sexpr::Str(s) => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Literal::Char(c)),
+                _ => Err(SexprError::Expected("a single character", e.to_string())),
+            }
+        }
+        _ => Err(SexprError::Expected("a literal", e.to_string())),
+    }
+}
+
+/// The trailing parts of a list which are not `(args ...)`, `(funcs ...)`, `(labels ...)`, or a position, and the contents of those
+#[derive(Default)]
+struct Fields {
+    args: Vec<String>,
+    funcs: Vec<String>,
+    labels: Vec<String>,
+    #[cfg(feature = "position")]
+    pos: Option<Position>,
+    rest: Vec<Sexpr>,
+}
+
+impl Fields {
+    fn parse(items: &[Sexpr]) -> Result<Self, SexprError> {
+        let mut fields = Self::default();
+        for item in items {
+            let Sexpr::List(list) = item else {
+                fields.rest.push(item.clone());
+                continue;
+            };
+            let names = || list[1..].iter().map(name).collect::<Result<Vec<_>, _>>();
+            match list.first() {
+                Some(Sexpr::Atom(h)) if h == "args" => fields.args = names()?,
+                Some(Sexpr::Atom(h)) if h == "funcs" => fields.funcs = names()?,
+                Some(Sexpr::Atom(h)) if h == "labels" => fields.labels = names()?,
+                #[cfg(feature = "position")]
+                Some(Sexpr::Atom(h)) if h == "pos" => {
+                    fields.pos = Some(Position {
+                        pos: ColRow {
+                            row: number(list.get(1))?,
+                            col: number(list.get(2))?,
+                        },
+                        pos_end: None,
+                        src: None,
+                    });
+                }
+                #[cfg(feature = "position")]
+                Some(Sexpr::Atom(h)) if h == "pos_end" => {
+                    let end = ColRow {
+                        row: number(list.get(1))?,
+                        col: number(list.get(2))?,
+                    };
+                    fields
+                        .pos
+                        .as_mut()
+                        .ok_or_else(|| {
+                            SexprError::Expected("`pos` before `pos_end`", item.to_string())
+                        })?
+                        .pos_end = Some(end);
+                }
+                #[cfg(feature = "position")]
+                Some(Sexpr::Atom(h)) if h == "src" => {
+                    let src = list
+                        .get(1)
+                        .ok_or(SexprError::UnexpectedEnd)
+                        .and_then(name)?;
+                    fields
+                        .pos
+                        .as_mut()
+                        .ok_or_else(|| {
+                            SexprError::Expected("`pos` before `src`", item.to_string())
+                        })?
+                        .src = Some(src);
+                }
+                _ => fields.rest.push(item.clone()),
+            }
+        }
+        Ok(fields)
+    }
+}
+
+/// Splits ```e``` into the atom at its head and the rest of the list
+fn head<'a>(e: &'a Sexpr, what: &'static str) -> Result<(&'a str, &'a [Sexpr]), SexprError> {
+    match e {
+        Sexpr::List(items) => match items.split_first() {
+            Some((Sexpr::Atom(h), rest)) => Ok((h, rest)),
+            _ => Err(SexprError::Expected(what, e.to_string())),
+        },
+        _ => Err(SexprError::Expected(what, e.to_string())),
+    }
+}
+
+fn parse_code(e: &Sexpr) -> Result<AbstractCode, SexprError> {
+    let (kind, items) = head(e, "`label`, `const`, `value`, or `effect`")?;
+    let Fields {
+        args,
+        funcs,
+        labels,
+        #[cfg(feature = "position")]
+        pos,
+        rest,
+    } = Fields::parse(items)?;
+    let bad = || SexprError::Expected("the parts of an instruction", e.to_string());
+    Ok(match (kind, rest.as_slice()) {
+        ("label", [label]) => AbstractCode::Label {
+            label: name(label)?,
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "format")]
+            format: crate::FormatHints::default(),
+        },
+        ("const", [dest, t, value]) => AbstractCode::Instruction(AbstractInstruction::Constant {
+            dest: name(dest)?,
+            op: ConstOps::Const,
+            const_type: Some(parse_type(t)?),
+            value: parse_literal(value)?,
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "format")]
+            format: crate::FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }),
+        ("value", [dest, t, op]) => AbstractCode::Instruction(AbstractInstruction::Value {
+            args,
+            dest: name(dest)?,
+            funcs,
+            labels,
+            op: name(op)?,
+            op_type: Some(parse_type(t)?),
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "format")]
+            format: crate::FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }),
+        ("effect", [op]) => AbstractCode::Instruction(AbstractInstruction::Effect {
+            args,
+            funcs,
+            labels,
+            op: name(op)?,
+            #[cfg(feature = "position")]
+            pos,
+            #[cfg(feature = "format")]
+            format: crate::FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }),
+        _ => return Err(bad()),
+    })
+}
+
+fn parse_function(items: &[Sexpr]) -> Result<AbstractFunction, SexprError> {
+    let (fn_name, items) = items.split_first().ok_or(SexprError::UnexpectedEnd)?;
+    let mut func = AbstractFunction {
+        args: Vec::new(),
+        instrs: Vec::new(),
+        name: name(fn_name)?,
+        return_type: None,
+        #[cfg(feature = "position")]
+        pos: None,
+        #[cfg(feature = "external")]
+        external: false,
+        #[cfg(feature = "alias")]
+        aliases: Vec::new(),
+    };
+    #[cfg(feature = "position")]
+    let mut position = Vec::new();
+    for item in items {
+        match head(item, "a part of a function")? {
+            ("args", params) => {
+                for p in params {
+                    match p {
+                        Sexpr::List(pair) if pair.len() == 2 => func.args.push(AbstractArgument {
+                            name: name(&pair[0])?,
+                            arg_type: parse_type(&pair[1])?,
+                        }),
+                        _ => return Err(SexprError::Expected("a parameter", p.to_string())),
+                    }
+                }
+            }
+            ("returns", [t]) => func.return_type = Some(parse_type(t)?),
+            #[cfg(feature = "external")]
+            ("external", []) => func.external = true,
+            #[cfg(feature = "position")]
+            ("pos" | "pos_end" | "src", _) => position.push(item.clone()),
+            _ => func.instrs.push(parse_code(item)?),
+        }
+    }
+    #[cfg(feature = "position")]
+    {
+        func.pos = Fields::parse(&position)?.pos;
+    }
+    Ok(func)
+}
+
+impl Program {
+    /// Writes this program as an S-expression, with a list for each function and each of its labels and instructions
+    ///
+    /// A program is `(program (version 3) (import "f.bril" g (h renamed)) (function ...) ...)`, where the version and imports are optional. A function is `(function name (args (a int) (p (ptr int))) (returns int) (external) code...)`, leaving out the parts that do not apply.
+    /// Code is `(label name)`, `(const dest type value)`, `(value dest type op ...)`, or `(effect op ...)`, where an operation ends with the nonempty lists among `(args ...)`, `(funcs ...)`, and `(labels ...)`, and any code may end with a source position as `(pos row col)`, `(pos_end row col)`, and `(src "path")`.
+    /// Names are written as bare atoms unless they contain whitespace, parentheses, or quotes, and `char` constants as strings. Format hints are not written. [`Program::from_sexpr`] reads the result back into an equal program.
+    #[must_use]
+    pub fn to_sexpr(&self) -> String {
+        let mut s = "(program".to_string();
+        // Writing to a String never fails
+        if let Some(v) = self.version {
+            let _ = write!(s, "\n  (version {v})");
+        }
+        #[cfg(feature = "import")]
+        for Import { functions, path } in &self.imports {
+            s.push_str("\n  (import ");
+            let _ = write_str(&mut s, &path.to_string_lossy());
+            for ImportedFunction { alias, name } in functions {
+                s.push(' ');
+                if let Some(alias) = alias {
+                    s.push('(');
+                    let _ = write_name(&mut s, name);
+                    s.push(' ');
+                    let _ = write_name(&mut s, alias);
+                    s.push(')');
+                } else {
+                    let _ = write_name(&mut s, name);
+                }
+            }
+            s.push(')');
+        }
+        for func in &self.functions {
+            s.push('\n');
+            let _ = write_function(&mut s, func);
+        }
+        s.push_str(")\n");
+        s
+    }
+
+    /// Reads a program written in the form produced by [`Program::to_sexpr`]
+    /// # Errors
+    /// Returns a [`SexprError`] if ```src``` is not a single well-formed `program` list or does not describe a valid program
+    pub fn from_sexpr(src: &str) -> Result<Self, SexprError> {
+        let mut all = parse_all(src)?.into_iter();
+        let program = all.next().ok_or(SexprError::UnexpectedEnd)?;
+        if let Some(extra) = all.next() {
+            return Err(SexprError::TrailingInput(extra.to_string()));
+        }
+        let ("program", items) = head(&program, "`(program ...)`")? else {
+            return Err(SexprError::Expected("`(program ...)`", program.to_string()));
+        };
+        let mut abs = AbstractProgram {
+            functions: Vec::new(),
+            #[cfg(feature = "import")]
+            imports: Vec::new(),
+            version: None,
+        };
+        for item in items {
+            match head(item, "`version`, `import`, or `function`")? {
+                ("version", [v]) => abs.version = Some(number(Some(v))?),
+                #[cfg(feature = "import")]
+                ("import", [path, names @ ..]) => abs.imports.push(Import {
+                    path: name(path)?.into(),
+                    functions: names
+                        .iter()
+                        .map(|n| match n {
+                            Sexpr::List(pair) if pair.len() == 2 => Ok(ImportedFunction {
+                                name: name(&pair[0])?,
+                                alias: Some(name(&pair[1])?),
+                            }),
+                            _ => Ok(ImportedFunction {
+                                name: name(n)?,
+                                alias: None,
+                            }),
+                        })
+                        .collect::<Result<_, SexprError>>()?,
+                }),
+                ("function", rest) => abs.functions.push(parse_function(rest)?),
+                _ => {
+                    return Err(SexprError::Expected(
+                        "`version`, `import`, or `function`",
+                        item.to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(abs.try_into()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text::parse_and_convert;
+    use crate::Program;
+
+    #[test]
+    #[cfg(all(feature = "char", feature = "float", feature = "position"))]
+    fn round_trip() {
+        let mut program = parse_and_convert(
+            "@main(n: int) {
+  c: char = const 'a';
+  f: float = const 1.5;
+  b: bool = call @even n;
+  br b .yes .no;
+.yes:
+  print c f;
+.no:
+}
+@even(n: int): bool {
+  two: int = const 2;
+  r: int = div n two;
+  r: int = mul r two;
+  b: bool = eq r n;
+  ret b;
+}",
+        )
+        .unwrap();
+        // A name which has to be quoted
+        program.functions[1].args[0].name = "the (n)".to_string();
+        let sexpr = program.to_sexpr();
+        assert!(sexpr.contains(r#"(const c char "a" (pos 2 3) (pos_end 2 23))"#));
+        assert!(sexpr.contains(r#"(args ("the (n)" int))"#));
+        assert_eq!(Program::from_sexpr(&sexpr).unwrap(), program);
+    }
+}
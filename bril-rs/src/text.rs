@@ -0,0 +1,646 @@
+use thiserror::Error;
+
+use crate::conversion::PositionalConversionError;
+
+#[cfg(feature = "alias")]
+use crate::AbstractTypeAlias;
+#[cfg(feature = "format")]
+use crate::FormatHints;
+#[cfg(feature = "position")]
+use crate::Position;
+use crate::{
+    AbstractArgument, AbstractCode, AbstractFunction, AbstractInstruction, AbstractProgram,
+    AbstractType, ColRow, ConstOps, Literal, Program,
+};
+#[cfg(feature = "import")]
+use crate::{Import, ImportedFunction};
+
+/// A problem with the Bril text given to [`parse_program`]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Line {}, Column {}: expected {expected}, found {}", pos.row, pos.col, found.as_ref().map_or_else(|| "the end of the input".to_string(), |f| format!("`{f}`")))]
+#[allow(clippy::module_name_repetitions)]
+pub struct ParseError {
+    /// Where the problem starts
+    pub pos: ColRow,
+    /// What the parser was looking for, such as `a type`
+    pub expected: &'static str,
+    /// The text found instead, or [None] at the end of the input
+    pub found: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tok<'a> {
+    /// A run of characters which can make up a name, number, label, or function name
+    Word(&'a str),
+    Punct(char),
+    /// The text between the quotes of a string, which has no escapes
+    Str(&'a str),
+    /// The text between the quotes of a character literal, which may be an escape
+    Char(&'a str),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    tok: Tok<'a>,
+    /// Byte offsets of the first character and just past the last
+    start: usize,
+    end: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '%' | '.' | '@' | '+' | '-')
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || matches!(c, '_' | '%'))
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '%' | '.'))
+}
+
+#[derive(Default)]
+struct Operands {
+    args: Vec<String>,
+    funcs: Vec<String>,
+    labels: Vec<String>,
+}
+
+/// The byte offset of each function and of each of its labels and instructions
+type Offsets = Vec<(usize, Vec<usize>)>;
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token<'a>>,
+    next: usize,
+    /// The byte offset where each line starts
+    line_starts: Vec<usize>,
+    /// The byte offset of each comment's `#` and its text after the `#`
+    #[cfg_attr(not(feature = "format"), allow(dead_code))]
+    comments: Vec<(usize, &'a str)>,
+    offsets: Offsets,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Result<Self, ParseError> {
+        let mut parser = Self {
+            src,
+            tokens: Vec::new(),
+            next: 0,
+            line_starts: std::iter::once(0)
+                .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+            comments: Vec::new(),
+            offsets: Vec::new(),
+        };
+        let mut chars = src.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let tok = match c {
+                c if c.is_whitespace() => continue,
+                '#' => {
+                    let end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+                    parser.comments.push((start, &src[start + 1..end]));
+                    while chars.next_if(|&(i, _)| i < end).is_some() {}
+                    continue;
+                }
+                '"' | '\'' => {
+                    let mut end = None;
+                    while let Some((i, d)) = chars.next() {
+                        if d == '\\' && c == '\'' {
+                            chars.next();
+                        } else if d == c {
+                            end = Some(i);
+                            break;
+                        }
+                    }
+                    let Some(end) = end else {
+                        return Err(parser.error_at(
+                            start,
+                            if c == '"' {
+                                "a closing `\"`"
+                            } else {
+                                "a closing `'`"
+                            },
+                            None,
+                        ));
+                    };
+                    let inner = &src[start + 1..end];
+                    parser.tokens.push(Token {
+                        tok: if c == '"' {
+                            Tok::Str(inner)
+                        } else {
+                            Tok::Char(inner)
+                        },
+                        start,
+                        end: end + 1,
+                    });
+                    continue;
+                }
+                c if is_word_char(c) => {
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, d)) = chars.next_if(|&(_, d)| is_word_char(d)) {
+                        end = i + d.len_utf8();
+                    }
+                    parser.tokens.push(Token {
+                        tok: Tok::Word(&src[start..end]),
+                        start,
+                        end,
+                    });
+                    continue;
+                }
+                c @ (';' | ':' | '=' | ',' | '(' | ')' | '{' | '}' | '<' | '>') => Tok::Punct(c),
+                c => return Err(parser.error_at(start, "a token", Some(c.to_string()))),
+            };
+            parser.tokens.push(Token {
+                tok,
+                start,
+                end: start + c.len_utf8(),
+            });
+        }
+        Ok(parser)
+    }
+
+    /// The 1-based row and column, counted in characters, of the byte offset ```index```
+    fn col_row(&self, index: usize) -> ColRow {
+        let line = self.line_starts.partition_point(|&s| s <= index) - 1;
+        let col = self.src[self.line_starts[line]..index].chars().count() + 1;
+        ColRow {
+            col: col as u64,
+            row: line as u64 + 1,
+        }
+    }
+
+    #[cfg(feature = "position")]
+    fn position(&self, start: usize, end: usize) -> Position {
+        Position {
+            pos: self.col_row(start),
+            pos_end: Some(self.col_row(end)),
+            src: None,
+        }
+    }
+
+    fn error_at(&self, index: usize, expected: &'static str, found: Option<String>) -> ParseError {
+        ParseError {
+            pos: self.col_row(index),
+            expected,
+            found,
+        }
+    }
+
+    /// An error at the next token, or at the end of the input if there is none
+    fn error(&self, expected: &'static str) -> ParseError {
+        self.tokens.get(self.next).map_or_else(
+            || self.error_at(self.src.len(), expected, None),
+            |t| {
+                self.error_at(
+                    t.start,
+                    expected,
+                    Some(self.src[t.start..t.end].to_string()),
+                )
+            },
+        )
+    }
+
+    fn peek(&self) -> Option<Tok<'a>> {
+        self.tokens.get(self.next).map(|t| t.tok)
+    }
+
+    #[cfg(feature = "alias")]
+    fn peek_at(&self, ahead: usize) -> Option<Tok<'a>> {
+        self.tokens.get(self.next + ahead).map(|t| t.tok)
+    }
+
+    /// The byte offset of the next token, or the end of the input
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.src.len(), |t| t.start)
+    }
+
+    /// The byte offset just past the last token consumed
+    #[cfg(any(feature = "position", feature = "format"))]
+    fn last_end(&self) -> usize {
+        self.next.checked_sub(1).map_or(0, |i| self.tokens[i].end)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(Tok::Punct(c));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char, expected: &'static str) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    fn word(&mut self, expected: &'static str) -> Result<&'a str, ParseError> {
+        match self.peek() {
+            Some(Tok::Word(w)) => {
+                self.next += 1;
+                Ok(w)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// A word which is ```prefix``` followed by a name
+    fn prefixed(&mut self, prefix: &str, expected: &'static str) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Tok::Word(w)) if w.strip_prefix(prefix).is_some_and(is_ident) => {
+                self.next += 1;
+                Ok(w[prefix.len()..].to_string())
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        self.prefixed("", "a name")
+    }
+
+    /// Items separated by `,` up to ```close```, allowing a trailing `,`
+    fn comma_list<T>(
+        &mut self,
+        close: char,
+        expected: &'static str,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(item(self)?);
+            if !self.eat(',') {
+                self.expect(close, expected)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn parse_type(&mut self) -> Result<AbstractType, ParseError> {
+        let name = self.prefixed("", "a type")?;
+        if self.eat('<') {
+            let inner = self.parse_type()?;
+            // The size is kept as written, so that conversion can say what is wrong with it
+            #[cfg(feature = "array")]
+            if self.eat(',') {
+                let size = self.word("a size")?.to_string();
+                self.expect('>', "`>`")?;
+                return Ok(AbstractType::Sized(name, Box::new(inner), size));
+            }
+            self.expect('>', "`>`")?;
+            Ok(AbstractType::Parameterized(name, Box::new(inner)))
+        } else {
+            Ok(AbstractType::Primitive(name))
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, ParseError> {
+        let literal = match self.peek() {
+            Some(Tok::Word("true")) => Some(Literal::Bool(true)),
+            Some(Tok::Word("false")) => Some(Literal::Bool(false)),
+            // Integers too large for an `int` are kept as floats so that conversion can report them against the constant's type
+            #[cfg(feature = "float")]
+            Some(Tok::Word(w)) => w
+                .parse()
+                .ok()
+                .map(Literal::Int)
+                .or_else(|| w.parse().ok().map(Literal::Float)),
+            #[cfg(not(feature = "float"))]
+            Some(Tok::Word(w)) => w.parse().ok().map(Literal::Int),
+            #[cfg(feature = "char")]
+            Some(Tok::Char(c)) => unescape_char(c).map(Literal::Char),
+            _ => None,
+        };
+        let literal = literal.ok_or_else(|| self.error("a literal"))?;
+        self.next += 1;
+        Ok(literal)
+    }
+
+    /// The function names, labels, and arguments of an operation, up to and including its `;`
+    fn operands(&mut self) -> Result<Operands, ParseError> {
+        let mut operands = Operands::default();
+        while !self.eat(';') {
+            match self.peek() {
+                Some(Tok::Word(w)) if w.starts_with('@') => {
+                    operands.funcs.push(self.prefixed("@", "a function name")?);
+                }
+                Some(Tok::Word(w)) if w.starts_with('.') => {
+                    operands.labels.push(self.prefixed(".", "a label")?);
+                }
+                Some(Tok::Word(_)) => operands.args.push(self.ident()?),
+                _ => return Err(self.error("an argument or `;`")),
+            }
+        }
+        Ok(operands)
+    }
+
+    fn parse_code(&mut self) -> Result<AbstractCode, ParseError> {
+        #[cfg_attr(not(feature = "position"), allow(unused_variables))]
+        let start = self.offset();
+        if let Some(Tok::Word(w)) = self.peek() {
+            if w.starts_with('.') {
+                let label = self.prefixed(".", "a label")?;
+                self.expect(':', "`:` after a label")?;
+                return Ok(AbstractCode::Label {
+                    label,
+                    #[cfg(feature = "position")]
+                    pos: Some(self.position(start, self.last_end())),
+                    #[cfg(feature = "format")]
+                    format: FormatHints::default(),
+                });
+            }
+        }
+        let first = self.ident()?;
+        if !matches!(self.peek(), Some(Tok::Punct(':' | '='))) {
+            let Operands {
+                args,
+                funcs,
+                labels,
+            } = self.operands()?;
+            return Ok(AbstractCode::Instruction(AbstractInstruction::Effect {
+                args,
+                funcs,
+                labels,
+                op: first,
+                #[cfg(feature = "position")]
+                pos: Some(self.position(start, self.last_end())),
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+                #[cfg(feature = "metadata")]
+                extra: serde_json::Map::new(),
+            }));
+        }
+        let op_type = if self.eat(':') {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        self.expect('=', "`=`")?;
+        let op = self.word("an operation")?;
+        if op == "const" {
+            let value = self.parse_literal()?;
+            // A whole number is written without a `.` even when it is typed `float`, as the reference `bril2json` reads it
+            #[cfg(feature = "float")]
+            #[allow(clippy::cast_precision_loss)]
+            let value = match (&op_type, value) {
+                (Some(AbstractType::Primitive(t)), Literal::Int(i)) if t == "float" => {
+                    Literal::Float(i as f64)
+                }
+                (_, value) => value,
+            };
+            self.expect(';', "`;`")?;
+            return Ok(AbstractCode::Instruction(AbstractInstruction::Constant {
+                dest: first,
+                op: ConstOps::Const,
+                const_type: op_type,
+                value,
+                #[cfg(feature = "position")]
+                pos: Some(self.position(start, self.last_end())),
+                #[cfg(feature = "format")]
+                format: FormatHints::default(),
+                #[cfg(feature = "metadata")]
+                extra: serde_json::Map::new(),
+            }));
+        }
+        if !is_ident(op) {
+            self.next -= 1;
+            return Err(self.error("an operation"));
+        }
+        let Operands {
+            args,
+            funcs,
+            labels,
+        } = self.operands()?;
+        Ok(AbstractCode::Instruction(AbstractInstruction::Value {
+            args,
+            dest: first,
+            funcs,
+            labels,
+            op: op.to_string(),
+            op_type,
+            #[cfg(feature = "position")]
+            pos: Some(self.position(start, self.last_end())),
+            #[cfg(feature = "format")]
+            format: FormatHints::default(),
+            #[cfg(feature = "metadata")]
+            extra: serde_json::Map::new(),
+        }))
+    }
+
+    /// The blank lines between the byte offsets ```from``` and ```to``` and the comment after ```to``` on its line
+    #[cfg(feature = "format")]
+    fn format_hints(&self, from: usize, to: usize, code_end: usize) -> FormatHints {
+        let between: Vec<&str> = self.src[from..to].split('\n').collect();
+        let blank_lines_before = between
+            .get(1..between.len().saturating_sub(1))
+            .map_or(0, |lines| {
+                lines.iter().filter(|l| l.trim().is_empty()).count()
+            });
+        let comment = self
+            .comments
+            .iter()
+            .find(|&&(at, _)| at >= code_end && at <= self.offset())
+            .filter(|&&(at, _)| !self.src[code_end..at].contains('\n'))
+            .map(|(_, text)| text.trim().to_string());
+        FormatHints {
+            blank_lines_before,
+            comment,
+        }
+    }
+
+    fn parse_function(&mut self) -> Result<AbstractFunction, ParseError> {
+        let start = self.offset();
+        self.offsets.push((start, Vec::new()));
+        #[cfg(feature = "external")]
+        let external = self.peek() == Some(Tok::Word("extern"));
+        #[cfg(feature = "external")]
+        if external {
+            self.next += 1;
+        }
+        let name = self.prefixed("@", "a function")?;
+        let args = if self.eat('(') {
+            self.comma_list(')', "`,` or `)`", |p| {
+                let name = p.ident()?;
+                p.expect(':', "`:` and the type of the parameter")?;
+                Ok(AbstractArgument {
+                    name,
+                    arg_type: p.parse_type()?,
+                })
+            })?
+        } else {
+            Vec::new()
+        };
+        let return_type = if self.eat(':') {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        let mut func = AbstractFunction {
+            args,
+            instrs: Vec::new(),
+            name,
+            #[cfg(feature = "position")]
+            pos: Some(self.position(start, self.last_end())),
+            return_type,
+            #[cfg(feature = "external")]
+            external,
+            #[cfg(feature = "alias")]
+            aliases: Vec::new(),
+        };
+        #[cfg(feature = "external")]
+        if external {
+            self.expect(';', "`;` after an external function")?;
+            return Ok(func);
+        }
+        self.expect('{', "`{`")?;
+        #[cfg(feature = "format")]
+        let mut prev_end = self.last_end();
+        while !self.eat('}') {
+            if self.peek().is_none() {
+                return Err(self.error("`}`"));
+            }
+            #[cfg(feature = "alias")]
+            if self.peek() == Some(Tok::Word("type"))
+                && matches!(self.peek_at(1), Some(Tok::Word(_)))
+                && self.peek_at(2) == Some(Tok::Punct('='))
+            {
+                self.next += 1;
+                let name = self.ident()?;
+                self.next += 1;
+                let alias_type = self.parse_type()?;
+                self.expect(';', "`;`")?;
+                func.aliases.push(AbstractTypeAlias { name, alias_type });
+                #[cfg(feature = "format")]
+                {
+                    prev_end = self.last_end();
+                }
+                continue;
+            }
+            let start = self.offset();
+            if let Some((_, codes)) = self.offsets.last_mut() {
+                codes.push(start);
+            }
+            #[cfg_attr(not(feature = "format"), allow(unused_mut))]
+            let mut code = self.parse_code()?;
+            #[cfg(feature = "format")]
+            {
+                let end = self.last_end();
+                *code.format_hints_mut() = self.format_hints(prev_end, start, end);
+                prev_end = end;
+            }
+            func.instrs.push(code);
+        }
+        Ok(func)
+    }
+
+    #[cfg(feature = "import")]
+    fn parse_import(&mut self) -> Result<Import, ParseError> {
+        self.next += 1;
+        let path = match self.peek() {
+            Some(Tok::Str(p)) => p.into(),
+            _ => return Err(self.error("the path of the imported file")),
+        };
+        self.next += 1;
+        if self.word("`import`")? != "import" {
+            self.next -= 1;
+            return Err(self.error("`import`"));
+        }
+        let functions = self.comma_list(';', "`,` or `;`", |p| {
+            let name = p.prefixed("@", "a function name")?;
+            let alias = if p.peek() == Some(Tok::Word("as")) {
+                p.next += 1;
+                Some(p.prefixed("@", "a function name")?)
+            } else {
+                None
+            };
+            Ok(ImportedFunction { alias, name })
+        })?;
+        Ok(Import { functions, path })
+    }
+}
+
+#[cfg(feature = "char")]
+fn unescape_char(s: &str) -> Option<char> {
+    match s {
+        "\\0" => Some('\u{0000}'),
+        "\\a" => Some('\u{0007}'),
+        "\\b" => Some('\u{0008}'),
+        "\\t" => Some('\u{0009}'),
+        "\\n" => Some('\u{000A}'),
+        "\\v" => Some('\u{000B}'),
+        "\\f" => Some('\u{000C}'),
+        "\\r" => Some('\u{000D}'),
+        s => {
+            let mut chars = s.chars();
+            chars.next().filter(|_| chars.next().is_none())
+        }
+    }
+}
+
+/// The ways that reading a [Program] from the Bril text format with [`parse_and_convert`] can fail
+#[derive(Error, Debug)]
+pub enum TextError {
+    /// The text does not fit the grammar
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    /// The text parses but is not a valid Bril program. The error carries the byte offset of the code it was found at, and the text, even without the `position` feature
+    #[error(transparent)]
+    Conversion(Box<PositionalConversionError>),
+}
+
+/// Parses a program written in the Bril text format, as [`AbstractProgram`]'s [`std::fmt::Display`] writes it
+///
+/// This covers imports, function headers with parameters and a return type, labels, and constant, value, and effect instructions, with `#` comments anywhere.
+/// External functions and type aliases are read when their features are enabled. Under the `position` feature every function, label, and instruction gets its start and end position, and under the `format` feature blank lines and trailing comments are kept as [`crate::FormatHints`].
+/// The result can be turned into a [Program] with [`TryFrom`], or with [`parse_and_convert`] for errors which point into ```input```.
+/// # Errors
+/// Returns a [`ParseError`] at the first token which does not fit the grammar
+pub fn parse_program(input: &str) -> Result<AbstractProgram, ParseError> {
+    parse_with_offsets(input).map(|(program, _)| program)
+}
+
+/// Parses a program written in the Bril text format, as with [`parse_program`], and converts it to a [Program]
+///
+/// A conversion error, such as an unknown operation, is given the byte offset of the function, label, or instruction it was found in with [`PositionalConversionError::with_byte_offset`], along with ```input``` itself, so that it can point at the offending line whether or not the `position` feature is enabled.
+/// # Errors
+/// Returns a [`TextError::Parse`] at the first token which does not fit the grammar, or a [`TextError::Conversion`] for the first part of the program which does not convert
+pub fn parse_and_convert(input: &str) -> Result<Program, TextError> {
+    let (program, offsets) = parse_with_offsets(input)?;
+    Program::try_from(program).map_err(|e| {
+        let offset = e.function.and_then(|f| {
+            let (start, codes) = offsets.get(f)?;
+            Some(e.code.and_then(|c| codes.get(c).copied()).unwrap_or(*start))
+        });
+        let e = e.with_source(input);
+        TextError::Conversion(Box::new(match offset {
+            Some(offset) => e.with_byte_offset(offset),
+            None => e,
+        }))
+    })
+}
+
+/// Parses ```input``` along with the byte offset of each function and of each of its labels and instructions
+fn parse_with_offsets(input: &str) -> Result<(AbstractProgram, Offsets), ParseError> {
+    let mut parser = Parser::new(input)?;
+    let mut program = AbstractProgram {
+        functions: Vec::new(),
+        #[cfg(feature = "import")]
+        imports: Vec::new(),
+        version: None,
+    };
+    while parser.peek().is_some() {
+        #[cfg(feature = "import")]
+        if parser.peek() == Some(Tok::Word("from")) {
+            program.imports.push(parser.parse_import()?);
+            continue;
+        }
+        program.functions.push(parser.parse_function()?);
+    }
+    Ok((program, parser.offsets))
+}
@@ -0,0 +1,707 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::check::pos_of;
+#[cfg(feature = "import")]
+use crate::ImportedFunction;
+use crate::{
+    Code, EffectOps, Function, Instruction, Literal, Position, Program, Signature, Type, ValueOps,
+};
+
+/// A problem found by [`type_check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct TypeError {
+    /// The name of the function containing the problem
+    pub func: String,
+    /// The index into [`Function::instrs`] of the offending code, or [None] for a problem with the function's signature
+    pub index: Option<usize>,
+    /// The offending instruction itself, or [None] for a problem with the function's signature
+    pub instruction: Option<Instruction>,
+    /// The source position of the offending code, if known
+    pub pos: Option<Position>,
+    /// A description of the problem
+    pub message: String,
+    /// The type that was required, when the problem is a mismatch
+    pub expected: Option<Type>,
+    /// The type that was given instead of [`TypeError::expected`]
+    pub found: Option<Type>,
+    /// A change which would likely fix the problem, when there is an obvious one
+    pub suggestion: Option<String>,
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.pos, self.index) {
+            (Some(p), _) => write!(f, "Line {}, Column {}: ", p.pos.row, p.pos.col)?,
+            (None, Some(i)) => write!(f, "@{}, instruction {i}: ", self.func)?,
+            (None, None) => write!(f, "@{}: ", self.func)?,
+        }
+        write!(f, "{}", self.message)?;
+        if let (Some(expected), Some(found)) = (&self.expected, &self.found) {
+            write!(f, " (expected `{expected}`, found `{found}`)")?;
+        }
+        if let Some(s) = &self.suggestion {
+            write!(f, "; {s}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// The integer operation corresponding to ```op```, if it is an arithmetic or comparison operation
+const fn int_version(op: ValueOps) -> Option<ValueOps> {
+    match op {
+        ValueOps::Add
+        | ValueOps::Sub
+        | ValueOps::Mul
+        | ValueOps::Div
+        | ValueOps::Eq
+        | ValueOps::Lt
+        | ValueOps::Gt
+        | ValueOps::Le
+        | ValueOps::Ge => Some(op),
+        #[cfg(feature = "float")]
+        ValueOps::Fadd => Some(ValueOps::Add),
+        #[cfg(feature = "float")]
+        ValueOps::Fsub => Some(ValueOps::Sub),
+        #[cfg(feature = "float")]
+        ValueOps::Fmul => Some(ValueOps::Mul),
+        #[cfg(feature = "float")]
+        ValueOps::Fdiv => Some(ValueOps::Div),
+        #[cfg(feature = "float")]
+        ValueOps::Feq => Some(ValueOps::Eq),
+        #[cfg(feature = "float")]
+        ValueOps::Flt => Some(ValueOps::Lt),
+        #[cfg(feature = "float")]
+        ValueOps::Fgt => Some(ValueOps::Gt),
+        #[cfg(feature = "float")]
+        ValueOps::Fle => Some(ValueOps::Le),
+        #[cfg(feature = "float")]
+        ValueOps::Fge => Some(ValueOps::Ge),
+        #[cfg(feature = "char")]
+        ValueOps::Ceq => Some(ValueOps::Eq),
+        #[cfg(feature = "char")]
+        ValueOps::Clt => Some(ValueOps::Lt),
+        #[cfg(feature = "char")]
+        ValueOps::Cgt => Some(ValueOps::Gt),
+        #[cfg(feature = "char")]
+        ValueOps::Cle => Some(ValueOps::Le),
+        #[cfg(feature = "char")]
+        ValueOps::Cge => Some(ValueOps::Ge),
+        _ => None,
+    }
+}
+
+/// The operation which does the same thing as ```op``` on arguments of type ```t```, if there is a different one
+fn counterpart(op: ValueOps, t: &Type) -> Option<ValueOps> {
+    let int_op = int_version(op)?;
+    let version = match (t, int_op) {
+        (Type::Int, _) => Some(int_op),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Add) => Some(ValueOps::Fadd),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Sub) => Some(ValueOps::Fsub),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Mul) => Some(ValueOps::Fmul),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Div) => Some(ValueOps::Fdiv),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Eq) => Some(ValueOps::Feq),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Lt) => Some(ValueOps::Flt),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Gt) => Some(ValueOps::Fgt),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Le) => Some(ValueOps::Fle),
+        #[cfg(feature = "float")]
+        (Type::Float, ValueOps::Ge) => Some(ValueOps::Fge),
+        #[cfg(feature = "char")]
+        (Type::Char, ValueOps::Eq) => Some(ValueOps::Ceq),
+        #[cfg(feature = "char")]
+        (Type::Char, ValueOps::Lt) => Some(ValueOps::Clt),
+        #[cfg(feature = "char")]
+        (Type::Char, ValueOps::Gt) => Some(ValueOps::Cgt),
+        #[cfg(feature = "char")]
+        (Type::Char, ValueOps::Le) => Some(ValueOps::Cle),
+        #[cfg(feature = "char")]
+        (Type::Char, ValueOps::Ge) => Some(ValueOps::Cge),
+        _ => None,
+    };
+    version.filter(|&v| v != op)
+}
+
+/// The operation which converts a value of type ```from``` to type ```to```, if there is one
+const fn conversion(from: &Type, to: &Type) -> Option<ValueOps> {
+    match (from, to) {
+        #[cfg(feature = "convert")]
+        (Type::Int, Type::Float) => Some(ValueOps::Int2float),
+        #[cfg(feature = "convert")]
+        (Type::Float, Type::Int) => Some(ValueOps::Float2int),
+        #[cfg(feature = "char")]
+        (Type::Int, Type::Char) => Some(ValueOps::Int2char),
+        #[cfg(feature = "char")]
+        (Type::Char, Type::Int) => Some(ValueOps::Char2int),
+        _ => None,
+    }
+}
+
+/// The type of the values that ```lit``` can always be stored as
+const fn literal_type(lit: &Literal) -> Type {
+    match lit {
+        Literal::Int(_) => Type::Int,
+        Literal::Bool(_) => Type::Bool,
+        #[cfg(feature = "float")]
+        Literal::Float(_) => Type::Float,
+        #[cfg(feature = "char")]
+        Literal::Char(_) => Type::Char,
+    }
+}
+
+struct Checker<'a> {
+    functions: HashMap<&'a str, Signature<'a>>,
+    #[cfg_attr(not(feature = "import"), allow(dead_code))]
+    imported: HashSet<&'a str>,
+    func: &'a Function,
+    env: HashMap<&'a str, &'a Type>,
+    labels: HashSet<&'a str>,
+    index: Option<usize>,
+    instr: Option<&'a Instruction>,
+    pos: Option<Position>,
+    errors: Vec<TypeError>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(
+        &mut self,
+        message: String,
+        expected: Option<Type>,
+        found: Option<Type>,
+        suggestion: Option<String>,
+    ) {
+        self.errors.push(TypeError {
+            func: self.func.name.clone(),
+            index: self.index,
+            instruction: self.instr.cloned(),
+            pos: self.pos.clone(),
+            message,
+            expected,
+            found,
+            suggestion,
+        });
+    }
+
+    fn lookup(&mut self, var: &str) -> Option<&'a Type> {
+        let t = self.env.get(var).copied();
+        if t.is_none() {
+            self.error(format!("undefined variable `{var}`"), None, None, None);
+        }
+        t
+    }
+
+    /// Checks that ```var``` has type ```expected```
+    fn expect_var(&mut self, var: &str, expected: &Type) {
+        let Some(found) = self.lookup(var) else {
+            return;
+        };
+        if found != expected {
+            self.error(
+                format!("`{var}` has the wrong type"),
+                Some(expected.clone()),
+                Some(found.clone()),
+                conversion(found, expected)
+                    .map(|c| format!("insert `{c}` to convert `{var}` to `{expected}`")),
+            );
+        }
+    }
+
+    /// Checks that ```args``` has ```expected``` entries, returning whether it does
+    fn expect_count(&mut self, what: &str, expected: usize, args: &[String]) -> bool {
+        if args.len() == expected {
+            return true;
+        }
+        let suggestion = if args.len() == expected + 1 {
+            Some(format!("remove the extra {what} `{}`", args[expected]))
+        } else if args.len() + 1 == expected {
+            Some(format!("add the missing {what}"))
+        } else {
+            None
+        };
+        self.error(
+            format!("expected {expected} {what}s, found {}", args.len()),
+            None,
+            None,
+            suggestion,
+        );
+        false
+    }
+
+    /// Checks that ```args``` have ```types```. When every argument of ```op``` has the same wrong type, a version of the operation for that type is suggested instead
+    fn expect_args(&mut self, args: &[String], types: &[&Type], op: Option<ValueOps>) {
+        if !self.expect_count("argument", types.len(), args) {
+            return;
+        }
+        let shared = args.first().and_then(|a| self.env.get(a.as_str()).copied());
+        if let (Some(op), Some(found)) = (op, shared) {
+            if args
+                .iter()
+                .all(|a| self.env.get(a.as_str()) == Some(&found))
+                && types.iter().all(|&t| t != found)
+            {
+                if let Some(c) = counterpart(op, found) {
+                    self.error(
+                        format!("`{op}` cannot be applied to `{found}` arguments"),
+                        Some(types[0].clone()),
+                        Some(found.clone()),
+                        Some(format!("use `{c}` instead")),
+                    );
+                    return;
+                }
+            }
+        }
+        for (a, t) in args.iter().zip(types) {
+            self.expect_var(a, t);
+        }
+    }
+
+    fn expect_labels(&mut self, labels: &[String], count: usize) {
+        if self.expect_count("label", count, labels) {
+            for l in labels {
+                if !self.labels.contains(l.as_str()) {
+                    self.error(format!("could not find label `{l}`"), None, None, None);
+                }
+            }
+        }
+    }
+
+    /// The pointee type of ```var```, reporting an error if it is not a pointer
+    #[cfg(feature = "memory")]
+    fn pointee(&mut self, var: &str) -> Option<&'a Type> {
+        match self.lookup(var)? {
+            Type::Pointer(t) => Some(t),
+            found => {
+                self.error(
+                    format!("`{var}` must be a pointer"),
+                    None,
+                    Some(found.clone()),
+                    None,
+                );
+                None
+            }
+        }
+    }
+
+    /// Checks a call of ```funcs``` with ```args```, returning the callee's return type if it is known
+    ///
+    /// Calls to imported functions are trusted, since their signatures are not known. When ```value``` is set the callee must return something.
+    fn check_call(&mut self, funcs: &[String], args: &[String], value: bool) -> Option<Type> {
+        if !self.expect_count("function", 1, funcs) {
+            return None;
+        }
+        let Some(&(callee_args, return_type)) = self.functions.get(funcs[0].as_str()) else {
+            #[cfg(feature = "import")]
+            if self.imported.contains(funcs[0].as_str()) {
+                return None;
+            }
+            self.error(
+                format!("no function of name `{}` found", funcs[0]),
+                None,
+                None,
+                None,
+            );
+            return None;
+        };
+        let params: Vec<&Type> = callee_args.iter().map(|a| &a.arg_type).collect();
+        self.expect_args(args, &params, None);
+        if value && return_type.is_none() {
+            self.error(
+                format!("`@{}` does not return a value", funcs[0]),
+                None,
+                None,
+                Some(format!("call it as an effect with `call @{}`", funcs[0])),
+            );
+        }
+        return_type.cloned()
+    }
+
+    /// The type produced by ```op``` after checking its arguments, or [None] if it is unknown
+    #[allow(clippy::too_many_lines)]
+    fn check_value(
+        &mut self,
+        op: ValueOps,
+        op_type: &'a Type,
+        args: &[String],
+        funcs: &[String],
+        #[cfg_attr(not(feature = "ssa"), allow(unused_variables))] labels: &[String],
+    ) -> Option<Type> {
+        let int = &Type::Int;
+        let bool = &Type::Bool;
+        #[cfg(feature = "float")]
+        let float = &Type::Float;
+        #[cfg(feature = "char")]
+        let char = &Type::Char;
+        let (params, result): (Vec<&Type>, &Type) = match op {
+            ValueOps::Add | ValueOps::Sub | ValueOps::Mul | ValueOps::Div => (vec![int, int], int),
+            ValueOps::Eq | ValueOps::Lt | ValueOps::Gt | ValueOps::Le | ValueOps::Ge => {
+                (vec![int, int], bool)
+            }
+            ValueOps::Not => (vec![bool], bool),
+            ValueOps::And | ValueOps::Or => (vec![bool, bool], bool),
+            ValueOps::Id => (vec![op_type], op_type),
+            ValueOps::Call => return self.check_call(funcs, args, true),
+            #[cfg(feature = "ssa")]
+            ValueOps::Phi => {
+                if self.expect_count("argument", labels.len(), args) {
+                    // An argument may be undefined along the path of its label
+                    for a in args {
+                        if self.env.contains_key(a.as_str()) {
+                            self.expect_var(a, op_type);
+                        }
+                    }
+                }
+                return Some(op_type.clone());
+            }
+            #[cfg(feature = "float")]
+            ValueOps::Fadd | ValueOps::Fsub | ValueOps::Fmul | ValueOps::Fdiv => {
+                (vec![float, float], float)
+            }
+            #[cfg(feature = "float")]
+            ValueOps::Feq | ValueOps::Flt | ValueOps::Fgt | ValueOps::Fle | ValueOps::Fge => {
+                (vec![float, float], bool)
+            }
+            #[cfg(feature = "char")]
+            ValueOps::Ceq | ValueOps::Clt | ValueOps::Cgt | ValueOps::Cle | ValueOps::Cge => {
+                (vec![char, char], bool)
+            }
+            #[cfg(feature = "char")]
+            ValueOps::Char2int => (vec![char], int),
+            #[cfg(feature = "char")]
+            ValueOps::Int2char => (vec![int], char),
+            #[cfg(feature = "convert")]
+            ValueOps::Int2float => (vec![int], float),
+            #[cfg(feature = "convert")]
+            ValueOps::Float2int => (vec![float], int),
+            #[cfg(feature = "select")]
+            ValueOps::Select => (vec![bool, op_type, op_type], op_type),
+            #[cfg(feature = "memory")]
+            ValueOps::Alloc => {
+                self.expect_args(args, &[int], None);
+                if !matches!(op_type, Type::Pointer(_)) {
+                    self.error(
+                        "`alloc` must produce a pointer".to_string(),
+                        None,
+                        Some(op_type.clone()),
+                        Some(format!("declare the result as `ptr<{op_type}>`")),
+                    );
+                }
+                return Some(op_type.clone());
+            }
+            #[cfg(feature = "memory")]
+            ValueOps::Load => {
+                if self.expect_count("argument", 1, args) {
+                    return self.pointee(&args[0]).cloned();
+                }
+                return None;
+            }
+            #[cfg(feature = "memory")]
+            ValueOps::PtrAdd => {
+                if self.expect_count("argument", 2, args) {
+                    self.expect_var(&args[1], int);
+                    return self
+                        .pointee(&args[0])
+                        .map(|t| Type::Pointer(Box::new(t.clone())));
+                }
+                return None;
+            }
+        };
+        self.expect_args(args, &params, Some(op));
+        Some(result.clone())
+    }
+
+    fn check_effect(
+        &mut self,
+        op: EffectOps,
+        args: &[String],
+        funcs: &[String],
+        labels: &[String],
+    ) {
+        match op {
+            EffectOps::Jump => self.expect_labels(labels, 1),
+            EffectOps::Branch => {
+                self.expect_args(args, &[&Type::Bool], None);
+                self.expect_labels(labels, 2);
+            }
+            EffectOps::Call => {
+                self.check_call(funcs, args, false);
+            }
+            EffectOps::Return => match &self.func.return_type {
+                None => {
+                    if !args.is_empty() {
+                        self.error(
+                            format!("`@{}` does not return a value", self.func.name),
+                            None,
+                            None,
+                            Some("remove the returned value".to_string()),
+                        );
+                    }
+                }
+                Some(t) => {
+                    if args.is_empty() {
+                        self.error(
+                            format!("`@{}` must return a value", self.func.name),
+                            Some(t.clone()),
+                            None,
+                            Some(format!("return a value of type `{t}`")),
+                        );
+                    } else {
+                        self.expect_args(args, &[t], None);
+                    }
+                }
+            },
+            EffectOps::Print => {
+                for a in args {
+                    self.lookup(a);
+                }
+            }
+            EffectOps::Nop => {}
+            #[cfg(feature = "memory")]
+            EffectOps::Store => {
+                if self.expect_count("argument", 2, args) {
+                    if let Some(t) = self.pointee(&args[0]) {
+                        self.expect_var(&args[1], t);
+                    }
+                }
+            }
+            #[cfg(feature = "memory")]
+            EffectOps::Free => {
+                if self.expect_count("argument", 1, args) {
+                    self.pointee(&args[0]);
+                }
+            }
+            #[cfg(feature = "speculate")]
+            EffectOps::Speculate | EffectOps::Commit => {
+                self.expect_args(args, &[], None);
+            }
+            #[cfg(feature = "speculate")]
+            EffectOps::Guard => {
+                self.expect_args(args, &[&Type::Bool], None);
+                self.expect_labels(labels, 1);
+            }
+        }
+    }
+
+    fn check_instruction(&mut self, instr: &'a Instruction) {
+        match instr {
+            Instruction::Constant {
+                dest,
+                const_type,
+                value,
+                ..
+            } => {
+                let found = literal_type(value);
+                let fits = found == *const_type || {
+                    #[cfg(feature = "float")]
+                    {
+                        matches!((value, const_type), (Literal::Int(_), Type::Float))
+                    }
+                    #[cfg(not(feature = "float"))]
+                    {
+                        false
+                    }
+                };
+                if !fits {
+                    self.error(
+                        format!("constant `{value}` cannot be stored in `{dest}`"),
+                        Some(const_type.clone()),
+                        Some(found.clone()),
+                        Some(format!("declare `{dest}` as `{found}`")),
+                    );
+                }
+            }
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op,
+                op_type,
+                ..
+            } => {
+                if let Some(result) = self.check_value(*op, op_type, args, funcs, labels) {
+                    if result != *op_type {
+                        self.error(
+                            format!("`{dest}` cannot hold the result of `{op}`"),
+                            Some(result.clone()),
+                            Some(op_type.clone()),
+                            Some(format!("declare `{dest}` as `{result}`")),
+                        );
+                    }
+                }
+            }
+            Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                ..
+            } => self.check_effect(*op, args, funcs, labels),
+        }
+    }
+
+    fn check_function(&mut self, func: &'a Function) {
+        let start = self.errors.len();
+        self.func = func;
+        self.index = None;
+        self.instr = None;
+        self.pos = None;
+        self.env.clear();
+        self.labels.clear();
+        for a in &func.args {
+            if self.env.insert(&a.name, &a.arg_type).is_some() {
+                self.error(
+                    format!("duplicate parameter `{}`", a.name),
+                    None,
+                    None,
+                    None,
+                );
+            }
+        }
+        for (i, code) in func.instrs.iter().enumerate() {
+            match code {
+                Code::Label { label, .. } => {
+                    self.labels.insert(label);
+                }
+                Code::Instruction(
+                    instr @ (Instruction::Constant {
+                        dest,
+                        const_type: t,
+                        ..
+                    }
+                    | Instruction::Value {
+                        dest, op_type: t, ..
+                    }),
+                ) => match self.env.get(dest.as_str()) {
+                    Some(&old) if old != t => {
+                        self.index = Some(i);
+                        self.instr = Some(instr);
+                        self.pos = pos_of(instr);
+                        self.error(
+                            format!("`{dest}` is assigned values of type `{old}` and `{t}`"),
+                            Some(old.clone()),
+                            Some(t.clone()),
+                            Some(format!("use a new variable for the `{t}` value")),
+                        );
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.env.insert(dest, t);
+                    }
+                },
+                Code::Instruction(Instruction::Effect { .. }) => {}
+            }
+        }
+        for (i, code) in func.instrs.iter().enumerate() {
+            if let Code::Instruction(instr) = code {
+                self.index = Some(i);
+                self.instr = Some(instr);
+                self.pos = pos_of(instr);
+                self.check_instruction(instr);
+            }
+        }
+        self.errors[start..].sort_by_key(|e| e.index);
+    }
+}
+
+/// Checks that every instruction of ```program``` uses its arguments, labels, and functions with the right types and counts
+///
+/// Each variable must be assigned values of a single type throughout its function. Problems are collected across the whole program rather than stopping at the first, and come with a [`TypeError::suggestion`] for common mistakes such as mixing up `int` and `float`.
+/// The signatures of external functions are trusted, as are calls to imported functions, whose signatures are not known.
+/// # Errors
+/// Returns every [`TypeError`] found, in program order, or just one at the second definition of a function whose name is already taken, since calls of it cannot be checked
+pub fn type_check(program: &Program) -> Result<(), Vec<TypeError>> {
+    #[cfg(feature = "import")]
+    let imported = program
+        .imports
+        .iter()
+        .flat_map(|i| &i.functions)
+        .map(ImportedFunction::local_name)
+        .collect();
+    #[cfg(not(feature = "import"))]
+    let imported = HashSet::new();
+    let Some(first) = program.functions.first() else {
+        return Ok(());
+    };
+    let functions = match program.signatures() {
+        Ok(functions) => functions,
+        Err(e) => {
+            #[cfg_attr(not(feature = "position"), allow(unused_variables))]
+            let second = program.functions.iter().filter(|f| f.name == e.0).nth(1);
+            #[cfg(feature = "position")]
+            let pos = second.and_then(|f| f.pos.clone());
+            #[cfg(not(feature = "position"))]
+            let pos = None;
+            return Err(vec![TypeError {
+                func: e.0,
+                index: None,
+                instruction: None,
+                pos,
+                message: "function is defined more than once".to_string(),
+                expected: None,
+                found: None,
+                suggestion: None,
+            }]);
+        }
+    };
+    let mut checker = Checker {
+        functions,
+        imported,
+        func: first,
+        env: HashMap::new(),
+        labels: HashSet::new(),
+        index: None,
+        instr: None,
+        pos: None,
+        errors: Vec::new(),
+    };
+    for func in &program.functions {
+        checker.check_function(func);
+    }
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::type_check;
+    use crate::text::parse_and_convert;
+    use crate::Type;
+
+    #[test]
+    #[cfg(feature = "convert")]
+    fn suggest_conversion_for_int_as_float() {
+        let program = parse_and_convert(
+            "@main {
+  x: int = const 1;
+  f: float = const 0.5;
+  y: float = fadd x f;
+}",
+        )
+        .unwrap();
+        let errors = type_check(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!(error.index, Some(2));
+        assert_eq!(
+            (error.expected.as_ref(), error.found.as_ref()),
+            (Some(&Type::Float), Some(&Type::Int))
+        );
+        assert_eq!(
+            error.suggestion.as_deref(),
+            Some("insert `int2float` to convert `x` to `float`")
+        );
+    }
+}
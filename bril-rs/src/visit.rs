@@ -0,0 +1,215 @@
+use crate::{Code, Function, Instruction, Program};
+
+/// Hooks for reading every function, label, and instruction of a [Program] in order with [`walk_program`]
+///
+/// Every method does nothing by default, so a visitor only overrides the kinds of code it cares about.
+pub trait Visitor {
+    /// Called on each function before any of its code
+    fn visit_function(&mut self, _func: &Function) {}
+
+    /// Called on each label, without its leading `.`
+    fn visit_label(&mut self, _label: &str) {}
+
+    /// Called on each [`Instruction::Constant`]
+    fn visit_constant(&mut self, _instr: &Instruction) {}
+
+    /// Called on each [`Instruction::Value`]
+    fn visit_value(&mut self, _instr: &Instruction) {}
+
+    /// Called on each [`Instruction::Effect`]
+    fn visit_effect(&mut self, _instr: &Instruction) {}
+}
+
+/// What a [`VisitorMut`] wants done with the code it was just given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Leave the code in the function, along with any changes made to it
+    Keep,
+    /// Delete the code from the function
+    Remove,
+}
+
+/// Hooks like [Visitor] which may change code in place or delete it, driven by [`walk_program_mut`]
+///
+/// Every method keeps the code unchanged by default.
+pub trait VisitorMut {
+    /// Called on each function before any of its code. Changes to [`Function::instrs`] made here are walked over afterwards
+    fn visit_function(&mut self, _func: &mut Function) {}
+
+    /// Called on each label, without its leading `.`
+    fn visit_label(&mut self, _label: &mut String) -> Action {
+        Action::Keep
+    }
+
+    /// Called on each [`Instruction::Constant`]
+    fn visit_constant(&mut self, _instr: &mut Instruction) -> Action {
+        Action::Keep
+    }
+
+    /// Called on each [`Instruction::Value`]
+    fn visit_value(&mut self, _instr: &mut Instruction) -> Action {
+        Action::Keep
+    }
+
+    /// Called on each [`Instruction::Effect`]
+    fn visit_effect(&mut self, _instr: &mut Instruction) -> Action {
+        Action::Keep
+    }
+}
+
+/// Calls ```visitor``` on every function of ```program``` in order, as with [`walk_function`]
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for func in &program.functions {
+        walk_function(visitor, func);
+    }
+}
+
+/// Calls ```visitor``` on ```func``` and then on each of its labels and instructions in order
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, func: &Function) {
+    visitor.visit_function(func);
+    for code in &func.instrs {
+        match code {
+            Code::Label { label, .. } => visitor.visit_label(label),
+            Code::Instruction(instr @ Instruction::Constant { .. }) => {
+                visitor.visit_constant(instr);
+            }
+            Code::Instruction(instr @ Instruction::Value { .. }) => visitor.visit_value(instr),
+            Code::Instruction(instr @ Instruction::Effect { .. }) => visitor.visit_effect(instr),
+        }
+    }
+}
+
+/// Calls ```visitor``` on every function of ```program``` in order, as with [`walk_function_mut`], returning how much code was removed
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) -> usize {
+    program
+        .functions
+        .iter_mut()
+        .map(|func| walk_function_mut(visitor, func))
+        .sum()
+}
+
+/// Calls ```visitor``` on ```func``` and then on each of its labels and instructions in order, deleting the code it returns [`Action::Remove`] for
+///
+/// Returns how much code was removed.
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func: &mut Function) -> usize {
+    visitor.visit_function(func);
+    let before = func.instrs.len();
+    func.instrs.retain_mut(|code| {
+        let action = match code {
+            Code::Label { label, .. } => visitor.visit_label(label),
+            Code::Instruction(instr @ Instruction::Constant { .. }) => {
+                visitor.visit_constant(instr)
+            }
+            Code::Instruction(instr @ Instruction::Value { .. }) => visitor.visit_value(instr),
+            Code::Instruction(instr @ Instruction::Effect { .. }) => visitor.visit_effect(instr),
+        };
+        action == Action::Keep
+    });
+    before - func.instrs.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk_program, walk_program_mut, Action, Visitor, VisitorMut};
+    use crate::text::parse_and_convert;
+    use crate::{Instruction, ValueOps};
+
+    const PROGRAM: &str = "@main(a: int) {
+  x: int = add a a;
+  y: int = mul x a;
+  z: int = add y x;
+  print z;
+}";
+
+    struct CountAdds(usize);
+
+    impl Visitor for CountAdds {
+        fn visit_value(&mut self, instr: &Instruction) {
+            if matches!(
+                instr,
+                Instruction::Value {
+                    op: ValueOps::Add,
+                    ..
+                }
+            ) {
+                self.0 += 1;
+            }
+        }
+    }
+
+    /// Renames ```from``` to ```to``` wherever it is written or read
+    struct Rename {
+        from: &'static str,
+        to: &'static str,
+    }
+
+    impl Rename {
+        fn rename(&self, instr: &mut Instruction) -> Action {
+            instr.replace_arg(self.from, self.to);
+            if let Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } = instr {
+                if dest == self.from {
+                    *dest = self.to.to_string();
+                }
+            }
+            Action::Keep
+        }
+    }
+
+    impl VisitorMut for Rename {
+        fn visit_constant(&mut self, instr: &mut Instruction) -> Action {
+            self.rename(instr)
+        }
+
+        fn visit_value(&mut self, instr: &mut Instruction) -> Action {
+            self.rename(instr)
+        }
+
+        fn visit_effect(&mut self, instr: &mut Instruction) -> Action {
+            self.rename(instr)
+        }
+    }
+
+    #[test]
+    fn count_adds() {
+        let mut count = CountAdds(0);
+        walk_program(&mut count, &parse_and_convert(PROGRAM).unwrap());
+        assert_eq!(count.0, 2);
+    }
+
+    #[test]
+    fn rename_destination() {
+        let mut program = parse_and_convert(PROGRAM).unwrap();
+        let removed = walk_program_mut(
+            &mut Rename {
+                from: "x",
+                to: "sum",
+            },
+            &mut program,
+        );
+        assert_eq!(removed, 0);
+        assert_eq!(
+            program.to_string().trim(),
+            "@main(a: int) {
+  sum: int = add a a;
+  y: int = mul sum a;
+  z: int = add y sum;
+  print z;
+}"
+        );
+    }
+
+    struct DropPrints;
+
+    impl VisitorMut for DropPrints {
+        fn visit_effect(&mut self, _instr: &mut Instruction) -> Action {
+            Action::Remove
+        }
+    }
+
+    #[test]
+    fn remove_effects() {
+        let mut program = parse_and_convert(PROGRAM).unwrap();
+        assert_eq!(walk_program_mut(&mut DropPrints, &mut program), 1);
+        assert_eq!(program.functions[0].instrs.len(), 3);
+    }
+}
@@ -1,6 +1,9 @@
 use std::fmt::Display;
 
-use bril_rs::{conversion::PositionalConversionError, Position};
+use bril_rs::{
+  conversion::{LoadError, PositionalConversionError},
+  Position,
+};
 use std::error::Error;
 use thiserror::Error;
 
@@ -154,10 +157,22 @@ impl From<InterpError> for PositionalInterpError {
 }
 
 impl From<PositionalConversionError> for PositionalInterpError {
-  fn from(PositionalConversionError { e, pos }: PositionalConversionError) -> Self {
+  fn from(PositionalConversionError { e, pos, .. }: PositionalConversionError) -> Self {
     Self {
       e: Box::new(e),
       pos,
     }
   }
 }
+
+impl From<LoadError> for PositionalInterpError {
+  fn from(e: LoadError) -> Self {
+    match e {
+      LoadError::Json(e) => Self {
+        e: Box::new(e),
+        pos: None,
+      },
+      LoadError::Conversion(e) => (*e).into(),
+    }
+  }
+}
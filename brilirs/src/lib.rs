@@ -39,7 +39,7 @@ pub fn run_input<T: std::io::Write, U: std::io::Write>(
   let prog: Program = if text {
     bril2json::parse_abstract_program_from_read(input, true, true, src_name).try_into()?
   } else {
-    bril_rs::load_abstract_program_from_read(input).try_into()?
+    bril_rs::load_program_from_read(input)?
   };
   let bbprog: BBProgram = prog.try_into()?;
   check::type_check(&bbprog)?;